@@ -0,0 +1,195 @@
+// A stand-in for a Mastodon-style server, for trying federation against a
+// local replica. It publishes one actor, logs what BlockVerse delivers to
+// its inbox and sends signed activities on request:
+//
+//   node scripts/fake-remote.js serve
+//   node scripts/fake-remote.js follow <actor-url>
+//   node scripts/fake-remote.js unfollow <actor-url>
+//   node scripts/fake-remote.js reply <post-url> <text>
+//   node scripts/fake-remote.js delete <post-url> <note-id>
+//
+// The server has to be running for the canister to fetch the actor's key,
+// and the commands sign with the key it generated. See the README.
+
+import { createHash, createSign, generateKeyPairSync, createPublicKey, randomBytes } from 'node:crypto';
+import { existsSync, readFileSync, writeFileSync } from 'node:fs';
+import http from 'node:http';
+import { tmpdir } from 'node:os';
+import { join } from 'node:path';
+
+const PORT = Number(process.env.FAKE_REMOTE_PORT || 8081);
+const ORIGIN = `http://localhost:${PORT}`;
+const ACTOR = `${ORIGIN}/actor`;
+const KEY_FILE = join(tmpdir(), `blockverse-fake-remote-${PORT}.pem`);
+const ACTIVITY_JSON = 'application/activity+json';
+const ACTIVITY_STREAMS = 'https://www.w3.org/ns/activitystreams';
+
+function privateKey() {
+  if (!existsSync(KEY_FILE)) {
+    const { privateKey } = generateKeyPairSync('rsa', { modulusLength: 2048 });
+    writeFileSync(KEY_FILE, privateKey.export({ type: 'pkcs8', format: 'pem' }));
+  }
+  return readFileSync(KEY_FILE, 'utf8');
+}
+
+function actorDocument() {
+  return {
+    '@context': [ACTIVITY_STREAMS, 'https://w3id.org/security/v1'],
+    id: ACTOR,
+    type: 'Person',
+    preferredUsername: 'tester',
+    name: 'Fake remote tester',
+    summary: '<p>Lives on a <em>fake</em> server</p>',
+    inbox: `${ORIGIN}/inbox`,
+    outbox: `${ORIGIN}/outbox`,
+    endpoints: { sharedInbox: `${ORIGIN}/inbox` },
+    publicKey: {
+      id: `${ACTOR}#main-key`,
+      owner: ACTOR,
+      publicKeyPem: createPublicKey(privateKey()).export({ type: 'spki', format: 'pem' }),
+    },
+  };
+}
+
+// `*.localhost` always means this machine, but not every resolver knows it.
+function connectTo(url) {
+  return url.hostname.endsWith('.localhost') ? '127.0.0.1' : url.hostname;
+}
+
+function request(method, target, headers = {}, body = null) {
+  const url = new URL(target);
+  return new Promise((resolve, reject) => {
+    const req = http.request(
+      { method, host: connectTo(url), port: url.port || 80, path: url.pathname + url.search, headers: { Host: url.host, ...headers } },
+      (res) => {
+        const chunks = [];
+        res.on('data', (chunk) => chunks.push(chunk));
+        res.on('end', () => resolve({ status: res.statusCode, body: Buffer.concat(chunks).toString('utf8') }));
+      },
+    );
+    req.on('error', reject);
+    if (body) req.write(body);
+    req.end();
+  });
+}
+
+// Signs the way Mastodon does: (request-target), host, date and digest.
+async function deliver(inbox, activity) {
+  const url = new URL(inbox);
+  const body = JSON.stringify(activity);
+  const headers = {
+    Date: new Date().toUTCString(),
+    Digest: `SHA-256=${createHash('sha256').update(body).digest('base64')}`,
+    'Content-Type': ACTIVITY_JSON,
+    'Content-Length': Buffer.byteLength(body),
+  };
+  const signingString = [
+    `(request-target): post ${url.pathname}${url.search}`,
+    `host: ${url.host}`,
+    `date: ${headers.Date}`,
+    `digest: ${headers.Digest}`,
+  ].join('\n');
+  const signature = createSign('RSA-SHA256').update(signingString).sign(privateKey(), 'base64');
+  headers.Signature =
+    `keyId="${ACTOR}#main-key",algorithm="rsa-sha256",headers="(request-target) host date digest",signature="${signature}"`;
+
+  const response = await request('POST', inbox, headers, body);
+  console.log(`${activity.type} -> ${inbox}: ${response.status} ${response.body}`);
+}
+
+async function inboxOf(actorUrl) {
+  const response = await request('GET', actorUrl, { Accept: ACTIVITY_JSON });
+  if (response.status !== 200) throw new Error(`Fetching ${actorUrl} failed: ${response.status} ${response.body}`);
+  return JSON.parse(response.body).inbox;
+}
+
+function sharedInboxOf(objectUrl) {
+  return `${new URL(objectUrl).origin}/inbox`;
+}
+
+function follow(actorUrl) {
+  const digest = createHash('sha256').update(actorUrl).digest('hex').slice(0, 16);
+  return { id: `${ACTOR}#follows/${digest}`, type: 'Follow', actor: ACTOR, object: actorUrl };
+}
+
+function serve() {
+  privateKey();
+  http
+    .createServer((req, res) => {
+      const chunks = [];
+      req.on('data', (chunk) => chunks.push(chunk));
+      req.on('end', () => {
+        if (req.method === 'GET' && req.url === '/actor') {
+          res.writeHead(200, { 'Content-Type': ACTIVITY_JSON });
+          res.end(JSON.stringify(actorDocument()));
+        } else if (req.method === 'POST' && req.url === '/inbox') {
+          // Deliveries aren't verified; this only shows what arrives
+          const body = Buffer.concat(chunks).toString('utf8');
+          console.log(`${new Date().toISOString()} inbox, signed by ${req.headers.signature ? 'yes' : 'nobody'}:`);
+          console.log(JSON.stringify(JSON.parse(body), null, 2));
+          res.writeHead(202);
+          res.end();
+        } else {
+          res.writeHead(404);
+          res.end();
+        }
+      });
+    })
+    .listen(PORT, () => console.log(`Fake remote server at ${ACTOR}`));
+}
+
+const [command, ...args] = process.argv.slice(2);
+const commands = {
+  serve,
+  async follow(actorUrl) {
+    await deliver(await inboxOf(actorUrl), { '@context': ACTIVITY_STREAMS, ...follow(actorUrl) });
+  },
+  async unfollow(actorUrl) {
+    const undo = follow(actorUrl);
+    await deliver(await inboxOf(actorUrl), {
+      '@context': ACTIVITY_STREAMS,
+      id: `${undo.id}/undo`,
+      type: 'Undo',
+      actor: ACTOR,
+      object: undo,
+    });
+  },
+  async reply(postUrl, ...words) {
+    const id = `${ORIGIN}/notes/${randomBytes(8).toString('hex')}`;
+    const note = {
+      id,
+      type: 'Note',
+      attributedTo: ACTOR,
+      inReplyTo: postUrl,
+      content: `<p>${words.join(' ')}</p>`,
+      published: new Date().toISOString(),
+      to: ['https://www.w3.org/ns/activitystreams#Public'],
+    };
+    await deliver(sharedInboxOf(postUrl), {
+      '@context': ACTIVITY_STREAMS,
+      id: `${id}/activity`,
+      type: 'Create',
+      actor: ACTOR,
+      object: note,
+    });
+    console.log(`Note id: ${id}`);
+  },
+  async delete(postUrl, noteId) {
+    await deliver(sharedInboxOf(postUrl), {
+      '@context': ACTIVITY_STREAMS,
+      id: `${noteId}#delete`,
+      type: 'Delete',
+      actor: ACTOR,
+      object: { id: noteId, type: 'Tombstone' },
+    });
+  },
+};
+
+if (!commands[command]) {
+  console.error('Usage: fake-remote.js serve | follow <actor> | unfollow <actor> | reply <post> <text> | delete <post> <note>');
+  process.exit(1);
+}
+Promise.resolve(commands[command](...args)).catch((error) => {
+  console.error(error.message);
+  process.exit(1);
+});
@@ -0,0 +1,121 @@
+#!/usr/bin/env bash
+# Integration tests for tips, deposits and withdrawals against the local
+# ledger stand-in. Needs a running replica (`dfx start --background`).
+#
+#   scripts/test-payments.sh
+#
+# Both canisters are reinstalled, so any local BlockVerse state is lost.
+# The test users are dfx identities created on the first run.
+
+set -euo pipefail
+
+ALICE=blockverse-test-alice
+BOB=blockverse-test-bob
+FAILURES=0
+
+for identity in "$ALICE" "$BOB"; do
+  dfx identity new "$identity" --storage-mode plaintext >/dev/null 2>&1 || true
+done
+
+principal_of() {
+  dfx identity get-principal --identity "$1"
+}
+
+# Calls a canister as an identity. The output is flattened to one line
+# without digit separators, so `990_000` can be matched as `990000`.
+call() {
+  local identity=$1
+  shift
+  dfx canister call --identity "$identity" "$@" 2>&1 | tr -d '_' | tr -s '[:space:]' ' '
+}
+
+check() {
+  local description=$1 expected=$2 actual=$3
+  if grep -qF -- "$expected" <<<"$actual"; then
+    echo "ok   - $description"
+  else
+    echo "FAIL - $description: expected '$expected', got '$actual'"
+    FAILURES=$((FAILURES + 1))
+  fi
+}
+
+account() {
+  echo "record { owner = principal \"$1\"; subaccount = null }"
+}
+
+balance_of() {
+  call "$ALICE" BlockVerse_backend get_user_balance "(principal \"$1\")"
+}
+
+wallet_of() {
+  call "$ALICE" ledger icrc1_balance_of "($(account "$1"))"
+}
+
+dfx deploy ledger --mode reinstall --yes >/dev/null
+LEDGER=$(dfx canister id ledger)
+dfx deploy BlockVerse_backend --mode reinstall --yes \
+  --argument "(record { admin = principal \"$(dfx identity get-principal)\"; ledger_canister_id = principal \"$LEDGER\" })" \
+  >/dev/null
+BACKEND=$(dfx canister id BlockVerse_backend)
+ALICE_ID=$(principal_of "$ALICE")
+BOB_ID=$(principal_of "$BOB")
+
+call "$ALICE" BlockVerse_backend create_user '("alice", "", "")' >/dev/null
+call "$BOB" BlockVerse_backend create_user '("bob", "", "")' >/dev/null
+
+echo "# claim_deposit"
+# Unwrap the `(record { ... },)` reply into the record itself
+deposit=$(call "$ALICE" BlockVerse_backend get_deposit_account | sed -E 's/^ *\( *(record \{.*\}) *,? *\) *$/\1/')
+call "$ALICE" ledger mint "($deposit, 1_000_000)" >/dev/null
+check "claims the deposit less the fee" "Ok = 990000" "$(call "$ALICE" BlockVerse_backend claim_deposit)"
+check "credits the claim" "(990000 : nat64)" "$(balance_of "$ALICE_ID")"
+check "sweeps into the main account" "(990000 : nat)" "$(wallet_of "$BACKEND")"
+check "rejects a second claim" "Nothing to claim" "$(call "$ALICE" BlockVerse_backend claim_deposit)"
+check "credits nothing twice" "(990000 : nat64)" "$(balance_of "$ALICE_ID")"
+
+echo "# tip_user from balance"
+check "tips" "variant { Ok }" "$(call "$ALICE" BlockVerse_backend tip_user "(principal \"$BOB_ID\", 100_000)")"
+check "debits the sender" "(890000 : nat64)" "$(balance_of "$ALICE_ID")"
+check "credits the recipient" "(100000 : nat64)" "$(balance_of "$BOB_ID")"
+check "leaves the ledger alone" "(990000 : nat)" "$(wallet_of "$BACKEND")"
+
+echo "# tip_user via icrc2_transfer_from"
+call "$ALICE" ledger mint "($(account "$ALICE_ID"), 2_000_000)" >/dev/null
+check "needs an allowance" "Insufficient allowance" \
+  "$(call "$ALICE" BlockVerse_backend tip_user "(principal \"$BOB_ID\", 1_000_000)")"
+check "credits nothing without one" "(100000 : nat64)" "$(balance_of "$BOB_ID")"
+call "$ALICE" ledger icrc2_approve "(record {
+  from_subaccount = null;
+  spender = $(account "$BACKEND");
+  amount = 1_010_000;
+  expected_allowance = null;
+  expires_at = null;
+  fee = null;
+  memo = null;
+  created_at_time = null;
+})" >/dev/null
+check "tips" "variant { Ok }" "$(call "$ALICE" BlockVerse_backend tip_user "(principal \"$BOB_ID\", 1_000_000)")"
+check "leaves the sender's balance alone" "(890000 : nat64)" "$(balance_of "$ALICE_ID")"
+check "credits the recipient" "(1100000 : nat64)" "$(balance_of "$BOB_ID")"
+check "pulls from the wallet" "(980000 : nat)" "$(wallet_of "$ALICE_ID")"
+check "into the main account" "(1990000 : nat)" "$(wallet_of "$BACKEND")"
+
+echo "# withdraw"
+check "withdraws" "Ok =" "$(call "$BOB" BlockVerse_backend withdraw "($(account "$BOB_ID"), 500_000)")"
+check "debits the balance" "(600000 : nat64)" "$(balance_of "$BOB_ID")"
+check "pays out less the fee" "(490000 : nat)" "$(wallet_of "$BOB_ID")"
+check "from the main account" "(1490000 : nat)" "$(wallet_of "$BACKEND")"
+call "$BOB" ledger fail_next_transfers '(1)' >/dev/null
+check "reports a failed transfer" "TemporarilyUnavailable" \
+  "$(call "$BOB" BlockVerse_backend withdraw "($(account "$BOB_ID"), 100_000)")"
+check "refunds the balance" "(600000 : nat64)" "$(balance_of "$BOB_ID")"
+check "pays out nothing" "(490000 : nat)" "$(wallet_of "$BOB_ID")"
+check "rejects overdrafts" "InsufficientBalance" \
+  "$(call "$BOB" BlockVerse_backend withdraw "($(account "$BOB_ID"), 700_000)")"
+check "debits nothing" "(600000 : nat64)" "$(balance_of "$BOB_ID")"
+
+if [ "$FAILURES" -gt 0 ]; then
+  echo "$FAILURES check(s) failed"
+  exit 1
+fi
+echo "All payment checks passed"
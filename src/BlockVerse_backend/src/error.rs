@@ -0,0 +1,91 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum EntityKind {
+    User,
+    Username,
+    Post,
+    Comment,
+    Like,
+    Role,
+    Report,
+    Decision,
+    Appeal,
+    Conversation,
+    FollowRequest,
+    Asset,
+    Upload,
+    Withdrawal,
+}
+
+/// Error returned by every fallible canister endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum BlockVerseError {
+    NotFound { kind: EntityKind, id: String },
+    AlreadyExists { kind: EntityKind, id: String },
+    /// The caller must sign in before doing this.
+    AnonymousCaller,
+    Unauthorized(String),
+    Validation { field: String, reason: String },
+    /// The request is well-formed but not allowed in the current state,
+    /// e.g. following yourself or unliking a post you never liked.
+    InvalidOperation(String),
+    InsufficientBalance { balance: u64, required: u64 },
+    /// The ledger canister rejected or failed a transfer.
+    Ledger(String),
+    /// A moderator suspended the caller until the given time.
+    Suspended { until: u64 },
+}
+
+impl BlockVerseError {
+    pub fn not_found(kind: EntityKind, id: impl ToString) -> Self {
+        Self::NotFound { kind, id: id.to_string() }
+    }
+
+    pub fn already_exists(kind: EntityKind, id: impl ToString) -> Self {
+        Self::AlreadyExists { kind, id: id.to_string() }
+    }
+
+    pub fn validation(field: &str, reason: &str) -> Self {
+        Self::Validation { field: field.to_string(), reason: reason.to_string() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::User;
+    use crate::services::user_service::UserService;
+    use crate::storage::state::STATE;
+    use crate::utils::testing::principal;
+
+    #[test]
+    fn errors_round_trip_through_candid() {
+        let errors = [
+            BlockVerseError::not_found(EntityKind::Post, "post_1"),
+            BlockVerseError::already_exists(EntityKind::Like, "post_1"),
+            BlockVerseError::validation("content", "Too long"),
+            BlockVerseError::InsufficientBalance { balance: 1, required: 2 },
+            BlockVerseError::Suspended { until: 3 },
+        ];
+        for error in errors {
+            let bytes = candid::encode_one(&error).unwrap();
+            assert_eq!(candid::decode_one::<BlockVerseError>(&bytes).unwrap(), error);
+        }
+    }
+
+    #[test]
+    fn services_name_what_was_missing() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (alice, stranger) = (principal(1), principal(2));
+            state.insert_user(User::new(alice, "alice".to_string(), String::new(), String::new()));
+
+            assert_eq!(
+                UserService::follow(state, alice, stranger),
+                Err(BlockVerseError::NotFound { kind: EntityKind::User, id: stranger.to_text() })
+            );
+        });
+    }
+}
@@ -1,75 +1,78 @@
+#![allow(non_snake_case)]
+
 use candid::{CandidType, Deserialize, Principal};
-use ic_cdk::api::time;
-use ic_cdk_macros::{init, post_upgrade, pre_upgrade, query, update};
-use std::collections::HashMap;
+use ic_cdk_macros::{init, post_upgrade, query, update};
 
+mod error;
 mod models;
 mod services;
 mod storage;
 mod utils;
 
-use models::{user::User, post::Post, comment::Comment};
+use error::BlockVerseError;
+use models::{user::User, post::{Post, PostRevision, Visibility}, comment::Comment, page::Page};
+use ic_cdk::api::management_canister::http_request::{HttpResponse as OutcallHttpResponse, TransformArgs};
+use models::asset::{Asset, MediaUsage};
+use models::federation::RemoteActor;
+use models::http::{HttpRequest, HttpResponse, StreamingCallbackHttpResponse, StreamingToken};
+use models::message::{Conversation, ConversationSummary, DmPolicy, Message, ReadReceipt};
+use models::moderation::{Appeal, Decision, ModerationAction, Report, ReportReason, ReportTarget};
+use models::notification::{Notification, NotificationKind};
+use models::role::{Role, RoleChange};
+use models::tag::{Mention, TrendingHashtag};
+use models::transaction::{PendingWithdrawal, Transaction, TransactionTotals};
 use services::{
     user_service::UserService,
     post_service::PostService,
     comment_service::CommentService,
     payment_service::PaymentService,
+    role_service::RoleService,
+    moderation_service::ModerationService,
+    notification_service::NotificationService,
+    message_service::MessageService,
+    media_service::MediaService,
+    http_service::HttpService,
+    federation_service::FederationService,
+    tag_service::TagService,
+    purge_service::PurgeService,
+    ledger::Account,
 };
-use storage::state::{State, STATE};
+use storage::{migrations, state::STATE};
 
 #[derive(CandidType, Deserialize)]
 pub struct InitArgs {
+    /// Becomes the first owner.
     pub admin: Principal,
+    pub ledger_canister_id: Principal,
 }
 
 #[init]
 fn init(args: InitArgs) {
     STATE.with(|state| {
         let mut state = state.borrow_mut();
-        state.admin = args.admin;
-    });
-}
-
-#[pre_upgrade]
-fn pre_upgrade() {
-    STATE.with(|state| {
-        let state = state.borrow();
-        ic_cdk::storage::stable_save((
-            &state.users,
-            &state.posts,
-            &state.comments,
-            &state.user_posts,
-            &state.user_followers,
-            &state.admin,
-        )).expect("Failed to save state before upgrade");
+        RoleService::bootstrap_owner(&mut state, args.admin);
+        state.set_ledger(args.ledger_canister_id);
+        state.set_schema_version(migrations::CURRENT_SCHEMA_VERSION);
     });
+    MediaService::certify_assets();
+    PurgeService::schedule();
+    FederationService::schedule();
 }
 
+// State lives in stable structures, so there is nothing to save before an
+// upgrade; afterwards we only need to migrate older layouts and restore
+// what lives on the heap: certified responses and timers.
 #[post_upgrade]
 fn post_upgrade() {
-    let (users, posts, comments, user_posts, user_followers, admin): (
-        HashMap<Principal, User>,
-        HashMap<String, Post>,
-        HashMap<String, Comment>,
-        HashMap<Principal, Vec<String>>,
-        HashMap<Principal, Vec<Principal>>,
-        Principal,
-    ) = ic_cdk::storage::stable_restore().expect("Failed to restore state after upgrade");
-
-    STATE.with(|state| {
-        let mut state = state.borrow_mut();
-        state.users = users;
-        state.posts = posts;
-        state.comments = comments;
-        state.user_posts = user_posts;
-        state.user_followers = user_followers;
-        state.admin = admin;
-    });
+    migrations::run();
+    MediaService::certify_assets();
+    PurgeService::schedule();
+    FederationService::schedule();
 }
 
 // User Management
 #[update]
-fn create_user(username: String, bio: String, avatar_url: String) -> Result<User, String> {
+fn create_user(username: String, bio: String, avatar_url: String) -> Result<User, BlockVerseError> {
     UserService::create_user(username, bio, avatar_url)
 }
 
@@ -78,35 +81,122 @@ fn get_user(user_id: Principal) -> Option<User> {
     UserService::get_user(user_id)
 }
 
+#[query]
+fn get_user_by_username(username: String) -> Option<User> {
+    UserService::get_user_by_username(username)
+}
+
 #[update]
-fn update_user(bio: String, avatar_url: String) -> Result<User, String> {
+fn update_user(bio: String, avatar_url: String) -> Result<User, BlockVerseError> {
     UserService::update_user(bio, avatar_url)
 }
 
 #[update]
-fn follow_user(user_to_follow: Principal) -> Result<(), String> {
+fn follow_user(user_to_follow: Principal) -> Result<(), BlockVerseError> {
     UserService::follow_user(user_to_follow)
 }
 
 #[update]
-fn unfollow_user(user_to_unfollow: Principal) -> Result<(), String> {
+fn unfollow_user(user_to_unfollow: Principal) -> Result<(), BlockVerseError> {
     UserService::unfollow_user(user_to_unfollow)
 }
 
 #[query]
-fn get_user_followers(user_id: Principal) -> Vec<Principal> {
-    UserService::get_user_followers(user_id)
+fn get_user_followers(
+    user_id: Principal,
+    cursor: Option<String>,
+    limit: usize,
+) -> Result<Page<Principal>, BlockVerseError> {
+    UserService::get_user_followers(user_id, cursor, limit)
+}
+
+#[query]
+fn get_user_following(
+    user_id: Principal,
+    cursor: Option<String>,
+    limit: usize,
+) -> Result<Page<Principal>, BlockVerseError> {
+    UserService::get_user_following(user_id, cursor, limit)
+}
+
+#[query]
+fn is_following(follower: Principal, followed: Principal) -> bool {
+    UserService::is_following(follower, followed)
+}
+
+#[update]
+fn set_account_private(is_private: bool) -> Result<User, BlockVerseError> {
+    UserService::set_account_private(is_private)
 }
 
 #[query]
-fn get_user_following(user_id: Principal) -> Vec<Principal> {
-    UserService::get_user_following(user_id)
+fn get_follow_requests(cursor: Option<String>, limit: usize) -> Result<Page<Principal>, BlockVerseError> {
+    UserService::get_follow_requests(cursor, limit)
+}
+
+#[query]
+fn get_sent_follow_requests(cursor: Option<String>, limit: usize) -> Result<Page<Principal>, BlockVerseError> {
+    UserService::get_sent_follow_requests(cursor, limit)
+}
+
+#[update]
+fn approve_follow_request(requester: Principal) -> Result<(), BlockVerseError> {
+    UserService::approve_follow_request(requester)
+}
+
+#[update]
+fn reject_follow_request(requester: Principal) -> Result<(), BlockVerseError> {
+    UserService::reject_follow_request(requester)
+}
+
+#[update]
+fn cancel_follow_request(user_id: Principal) -> Result<(), BlockVerseError> {
+    UserService::cancel_follow_request(user_id)
+}
+
+#[update]
+fn block_user(user_id: Principal) -> Result<(), BlockVerseError> {
+    UserService::block_user(user_id)
+}
+
+#[update]
+fn unblock_user(user_id: Principal) -> Result<(), BlockVerseError> {
+    UserService::unblock_user(user_id)
+}
+
+#[query]
+fn get_blocked_users(cursor: Option<String>, limit: usize) -> Result<Page<Principal>, BlockVerseError> {
+    UserService::get_blocked_users(cursor, limit)
+}
+
+#[update]
+fn mute_user(user_id: Principal) -> Result<(), BlockVerseError> {
+    UserService::mute_user(user_id)
+}
+
+#[update]
+fn unmute_user(user_id: Principal) -> Result<(), BlockVerseError> {
+    UserService::unmute_user(user_id)
+}
+
+#[query]
+fn get_muted_users(cursor: Option<String>, limit: usize) -> Result<Page<Principal>, BlockVerseError> {
+    UserService::get_muted_users(cursor, limit)
 }
 
 // Post Management
 #[update]
-fn create_post(content: String, media_url: Option<String>) -> Result<Post, String> {
-    PostService::create_post(content, media_url)
+fn create_post(
+    content: String,
+    media_asset_id: Option<String>,
+    visibility: Option<Visibility>,
+) -> Result<Post, BlockVerseError> {
+    PostService::create_post(content, media_asset_id, visibility)
+}
+
+#[update]
+fn update_post(post_id: String, content: String, media_asset_id: Option<String>) -> Result<Post, BlockVerseError> {
+    PostService::update_post(post_id, content, media_asset_id)
 }
 
 #[query]
@@ -115,50 +205,152 @@ fn get_post(post_id: String) -> Option<Post> {
 }
 
 #[query]
-fn get_user_posts(user_id: Principal) -> Vec<Post> {
-    PostService::get_user_posts(user_id)
+fn get_post_revisions(post_id: String, cursor: Option<String>, limit: usize) -> Result<Page<PostRevision>, BlockVerseError> {
+    PostService::get_post_revisions(post_id, cursor, limit)
+}
+
+#[query]
+fn get_edit_window() -> u64 {
+    PostService::get_edit_window()
+}
+
+#[query]
+fn get_user_posts(user_id: Principal, cursor: Option<String>, limit: usize) -> Result<Page<Post>, BlockVerseError> {
+    PostService::get_user_posts(user_id, cursor, limit)
 }
 
 #[query]
-fn get_feed(user_id: Principal, limit: usize, offset: usize) -> Vec<Post> {
-    PostService::get_feed(user_id, limit, offset)
+fn get_feed(user_id: Principal, cursor: Option<String>, limit: usize) -> Result<Page<Post>, BlockVerseError> {
+    PostService::get_feed(user_id, cursor, limit)
+}
+
+#[update]
+fn delete_post(post_id: String) -> Result<(), BlockVerseError> {
+    PostService::delete_post(post_id)
 }
 
 #[update]
-fn like_post(post_id: String) -> Result<(), String> {
+fn like_post(post_id: String) -> Result<(), BlockVerseError> {
     PostService::like_post(post_id)
 }
 
 #[update]
-fn unlike_post(post_id: String) -> Result<(), String> {
+fn unlike_post(post_id: String) -> Result<(), BlockVerseError> {
     PostService::unlike_post(post_id)
 }
 
 #[update]
-fn share_post(post_id: String, comment: Option<String>) -> Result<Post, String> {
+fn share_post(post_id: String, comment: Option<String>) -> Result<Post, BlockVerseError> {
     PostService::share_post(post_id, comment)
 }
 
 // Comment Management
 #[update]
-fn create_comment(post_id: String, content: String) -> Result<Comment, String> {
-    CommentService::create_comment(post_id, content)
+fn create_comment(
+    post_id: String,
+    content: String,
+    parent_comment_id: Option<String>,
+) -> Result<Comment, BlockVerseError> {
+    CommentService::create_comment(post_id, content, parent_comment_id)
+}
+
+#[update]
+fn update_comment(comment_id: String, content: String) -> Result<Comment, BlockVerseError> {
+    CommentService::update_comment(comment_id, content)
+}
+
+#[update]
+fn delete_comment(comment_id: String) -> Result<(), BlockVerseError> {
+    CommentService::delete_comment(comment_id)
 }
 
 #[query]
-fn get_post_comments(post_id: String) -> Vec<Comment> {
-    CommentService::get_post_comments(post_id)
+fn get_post_comments(post_id: String, cursor: Option<String>, limit: usize) -> Result<Page<Comment>, BlockVerseError> {
+    CommentService::get_post_comments(post_id, cursor, limit)
 }
 
 #[update]
-fn like_comment(comment_id: String) -> Result<(), String> {
+fn like_comment(comment_id: String) -> Result<(), BlockVerseError> {
     CommentService::like_comment(comment_id)
 }
 
+#[update]
+fn unlike_comment(comment_id: String) -> Result<(), BlockVerseError> {
+    CommentService::unlike_comment(comment_id)
+}
+
 // Payment System
 #[update]
-fn tip_user(user_id: Principal, amount: u64) -> Result<(), String> {
-    PaymentService::tip_user(user_id, amount)
+async fn tip_user(user_id: Principal, amount: u64) -> Result<(), BlockVerseError> {
+    PaymentService::tip_user(user_id, amount).await
+}
+
+#[update]
+async fn tip_post(post_id: String, amount: u64) -> Result<(), BlockVerseError> {
+    PaymentService::tip_post(post_id, amount).await
+}
+
+#[query]
+fn get_deposit_account() -> Account {
+    PaymentService::get_deposit_account()
+}
+
+#[update]
+async fn claim_deposit() -> Result<u64, BlockVerseError> {
+    PaymentService::claim_deposit().await
+}
+
+#[update]
+async fn withdraw(to: Account, amount: u64) -> Result<u64, BlockVerseError> {
+    PaymentService::withdraw(to, amount).await
+}
+
+#[update]
+async fn retry_withdrawal(id: u64) -> Result<u64, BlockVerseError> {
+    PaymentService::retry_withdrawal(id).await
+}
+
+#[query]
+fn get_pending_withdrawals(cursor: Option<String>, limit: usize) -> Result<Page<PendingWithdrawal>, BlockVerseError> {
+    PaymentService::get_pending_withdrawals(cursor, limit)
+}
+
+#[query]
+fn get_transactions(user_id: Principal, cursor: Option<String>, limit: usize) -> Result<Page<Transaction>, BlockVerseError> {
+    PaymentService::get_transactions(user_id, cursor, limit)
+}
+
+#[query]
+fn get_sent_transactions(
+    user_id: Principal,
+    cursor: Option<String>,
+    limit: usize,
+) -> Result<Page<Transaction>, BlockVerseError> {
+    PaymentService::get_sent_transactions(user_id, cursor, limit)
+}
+
+#[query]
+fn get_received_transactions(
+    user_id: Principal,
+    cursor: Option<String>,
+    limit: usize,
+) -> Result<Page<Transaction>, BlockVerseError> {
+    PaymentService::get_received_transactions(user_id, cursor, limit)
+}
+
+#[query]
+fn get_post_tips(post_id: String, cursor: Option<String>, limit: usize) -> Result<Page<Transaction>, BlockVerseError> {
+    PaymentService::get_post_tips(post_id, cursor, limit)
+}
+
+#[query]
+fn get_transaction_totals(user_id: Principal) -> TransactionTotals {
+    PaymentService::get_transaction_totals(user_id)
+}
+
+#[query]
+fn get_post_tip_total(post_id: String) -> Result<u64, BlockVerseError> {
+    PaymentService::get_post_tip_total(post_id)
 }
 
 #[query]
@@ -166,34 +358,320 @@ fn get_user_balance(user_id: Principal) -> u64 {
     PaymentService::get_user_balance(user_id)
 }
 
+// Notifications
+#[query]
+fn get_notifications(cursor: Option<String>, limit: usize) -> Result<Page<Notification>, BlockVerseError> {
+    NotificationService::get_notifications(cursor, limit)
+}
+
+#[query]
+fn get_unread_notification_count() -> u64 {
+    NotificationService::get_unread_count()
+}
+
+#[update]
+fn mark_notifications_read(ids: Vec<u64>) -> Result<(), BlockVerseError> {
+    NotificationService::mark_read(ids)
+}
+
+#[update]
+fn mark_all_notifications_read() -> Result<(), BlockVerseError> {
+    NotificationService::mark_all_read()
+}
+
+#[update]
+fn set_notification_muted(kind: NotificationKind, muted: bool) -> Result<(), BlockVerseError> {
+    NotificationService::set_muted(kind, muted)
+}
+
+#[query]
+fn get_muted_notifications() -> Vec<NotificationKind> {
+    NotificationService::get_muted()
+}
+
+// Direct Messages
+#[update]
+fn start_conversation(members: Vec<Principal>) -> Result<Conversation, BlockVerseError> {
+    MessageService::start_conversation(members)
+}
+
+#[update]
+fn send_message(conversation_id: String, content: String) -> Result<Message, BlockVerseError> {
+    MessageService::send_message(conversation_id, content)
+}
+
+#[query]
+fn get_messages(
+    conversation_id: String,
+    cursor: Option<String>,
+    limit: usize,
+) -> Result<Page<Message>, BlockVerseError> {
+    MessageService::get_messages(conversation_id, cursor, limit)
+}
+
+#[query]
+fn get_conversations(cursor: Option<String>, limit: usize) -> Result<Page<ConversationSummary>, BlockVerseError> {
+    MessageService::get_conversations(cursor, limit)
+}
+
+#[update]
+fn mark_conversation_read(conversation_id: String) -> Result<(), BlockVerseError> {
+    MessageService::mark_conversation_read(conversation_id)
+}
+
+#[query]
+fn get_read_receipts(conversation_id: String) -> Result<Vec<ReadReceipt>, BlockVerseError> {
+    MessageService::get_read_receipts(conversation_id)
+}
+
+#[update]
+fn set_conversation_muted(conversation_id: String, muted: bool) -> Result<(), BlockVerseError> {
+    MessageService::set_conversation_muted(conversation_id, muted)
+}
+
+#[update]
+fn leave_conversation(conversation_id: String) -> Result<(), BlockVerseError> {
+    MessageService::leave_conversation(conversation_id)
+}
+
+#[update]
+fn set_dm_policy(policy: DmPolicy) -> Result<(), BlockVerseError> {
+    MessageService::set_dm_policy(policy)
+}
+
+#[query]
+fn get_dm_policy() -> DmPolicy {
+    MessageService::get_dm_policy()
+}
+
+// Media
+#[update]
+fn begin_upload(mime_type: String, size: u64) -> Result<String, BlockVerseError> {
+    MediaService::begin_upload(mime_type, size)
+}
+
+#[update]
+fn put_chunk(upload_id: String, index: u32, data: Vec<u8>) -> Result<(), BlockVerseError> {
+    MediaService::put_chunk(upload_id, index, data)
+}
+
+#[update]
+fn commit_upload(upload_id: String) -> Result<Asset, BlockVerseError> {
+    MediaService::commit_upload(upload_id)
+}
+
+#[update]
+fn abort_upload(upload_id: String) -> Result<(), BlockVerseError> {
+    MediaService::abort_upload(upload_id)
+}
+
+#[query]
+fn get_asset(asset_id: String) -> Option<Asset> {
+    MediaService::get_asset(asset_id)
+}
+
+#[query]
+fn get_asset_chunk(asset_id: String, index: u32) -> Result<Vec<u8>, BlockVerseError> {
+    MediaService::get_asset_chunk(asset_id, index)
+}
+
+#[update]
+fn delete_asset(asset_id: String) -> Result<(), BlockVerseError> {
+    MediaService::delete_asset(asset_id)
+}
+
+#[query]
+fn get_my_assets(cursor: Option<String>, limit: usize) -> Result<Page<Asset>, BlockVerseError> {
+    MediaService::get_my_assets(cursor, limit)
+}
+
+#[query]
+fn get_media_usage() -> MediaUsage {
+    MediaService::get_media_usage()
+}
+
+// HTTP Gateway
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    HttpService::http_request(request)
+}
+
+#[update]
+async fn http_request_update(request: HttpRequest) -> HttpResponse {
+    HttpService::http_request_update(request).await
+}
+
+#[query]
+fn http_request_streaming_callback(token: StreamingToken) -> StreamingCallbackHttpResponse {
+    HttpService::http_request_streaming_callback(token)
+}
+
+// Federation
+#[query]
+fn get_remote_actor(user_id: Principal) -> Option<RemoteActor> {
+    FederationService::get_remote_actor(user_id)
+}
+
+#[query]
+fn get_federation_domain() -> String {
+    FederationService::get_federation_domain()
+}
+
+#[query]
+fn transform_outcall(args: TransformArgs) -> OutcallHttpResponse {
+    FederationService::transform(args)
+}
+
 // Search and Discovery
 #[query]
-fn search_users(query: String) -> Vec<User> {
-    UserService::search_users(query)
+fn search_users(query: String, cursor: Option<String>, limit: usize) -> Result<Page<User>, BlockVerseError> {
+    UserService::search_users(query, cursor, limit)
+}
+
+#[query]
+fn search_posts(query: String, cursor: Option<String>, limit: usize) -> Result<Page<Post>, BlockVerseError> {
+    PostService::search_posts(query, cursor, limit)
+}
+
+#[query]
+fn get_posts_by_hashtag(tag: String, cursor: Option<String>, limit: usize) -> Result<Page<Post>, BlockVerseError> {
+    TagService::get_posts_by_hashtag(tag, cursor, limit)
+}
+
+#[query]
+fn get_mentions(user_id: Principal, cursor: Option<String>, limit: usize) -> Result<Page<Mention>, BlockVerseError> {
+    TagService::get_mentions(user_id, cursor, limit)
+}
+
+#[query]
+fn get_trending_hashtags(window_ns: u64) -> Vec<TrendingHashtag> {
+    TagService::get_trending_hashtags(window_ns)
+}
+
+// Moderation
+#[update]
+fn report(target: ReportTarget, reason: ReportReason, details: String) -> Result<Report, BlockVerseError> {
+    ModerationService::report(target, reason, details)
 }
 
 #[query]
-fn search_posts(query: String) -> Vec<Post> {
-    PostService::search_posts(query)
+fn get_report_queue(cursor: Option<String>, limit: usize) -> Result<Page<Report>, BlockVerseError> {
+    RoleService::require(Role::Support)?;
+    ModerationService::get_report_queue(cursor, limit)
+}
+
+#[update]
+fn resolve_report(report_id: String, action: ModerationAction, note: String) -> Result<Decision, BlockVerseError> {
+    RoleService::require(Role::Moderator)?;
+    ModerationService::resolve_report(report_id, action, note)
+}
+
+#[query]
+fn get_decisions(cursor: Option<String>, limit: usize) -> Result<Page<Decision>, BlockVerseError> {
+    RoleService::require(Role::Support)?;
+    ModerationService::get_decisions(cursor, limit)
+}
+
+#[query]
+fn get_user_decisions(user_id: Principal, cursor: Option<String>, limit: usize) -> Result<Page<Decision>, BlockVerseError> {
+    if ic_cdk::caller() != user_id {
+        RoleService::require(Role::Support)?;
+    }
+    ModerationService::get_user_decisions(user_id, cursor, limit)
+}
+
+#[update]
+fn appeal_decision(decision_id: u64, statement: String) -> Result<Appeal, BlockVerseError> {
+    ModerationService::appeal_decision(decision_id, statement)
+}
+
+#[query]
+fn get_appeal_queue(cursor: Option<String>, limit: usize) -> Result<Page<Appeal>, BlockVerseError> {
+    RoleService::require(Role::Support)?;
+    ModerationService::get_appeal_queue(cursor, limit)
+}
+
+#[update]
+fn resolve_appeal(decision_id: u64, overturn: bool, response: String) -> Result<Appeal, BlockVerseError> {
+    RoleService::require(Role::Moderator)?;
+    ModerationService::resolve_appeal(decision_id, overturn, response)
 }
 
 // Admin Functions
 #[update]
-fn remove_post(post_id: String) -> Result<(), String> {
-    let caller = ic_cdk::caller();
-    STATE.with(|state| {
-        let state = state.borrow();
-        if caller != state.admin {
-            return Err("Unauthorized: Only admin can remove posts".to_string());
-        }
-        PostService::remove_post(post_id)
-    })
+fn remove_post(post_id: String) -> Result<(), BlockVerseError> {
+    RoleService::require(Role::Moderator)?;
+    PostService::remove_post(post_id)
+}
+
+/// Runs a purge batch now instead of waiting for the timer.
+#[update]
+fn purge_tombstones() -> Result<u64, BlockVerseError> {
+    RoleService::require(Role::Admin)?;
+    Ok(PurgeService::run())
+}
+
+/// Delivers a batch of queued activities now instead of waiting for the
+/// timer.
+#[update]
+async fn deliver_activities() -> Result<u64, BlockVerseError> {
+    RoleService::require(Role::Admin)?;
+    Ok(FederationService::deliver_due().await)
+}
+
+#[update]
+fn set_federation_domain(domain: String) -> Result<(), BlockVerseError> {
+    RoleService::require(Role::Admin)?;
+    FederationService::set_federation_domain(domain)
+}
+
+#[update]
+fn set_edit_window(window_ns: u64) -> Result<(), BlockVerseError> {
+    RoleService::require(Role::Admin)?;
+    PostService::set_edit_window(window_ns);
+    Ok(())
+}
+
+/// Settles a pending withdrawal the ledger no longer deduplicates:
+/// `block_index` is where it landed, or `None` to refund it.
+#[update]
+fn settle_withdrawal(user_id: Principal, id: u64, block_index: Option<u64>) -> Result<(), BlockVerseError> {
+    RoleService::require(Role::Admin)?;
+    PaymentService::settle_withdrawal(user_id, id, block_index)
+}
+
+#[update]
+fn set_ledger_canister(ledger: Principal) -> Result<(), BlockVerseError> {
+    RoleService::require(Role::Owner)?;
+    PaymentService::set_ledger_canister(ledger);
+    Ok(())
+}
+
+#[update]
+fn grant_role(user_id: Principal, role: Role) -> Result<(), BlockVerseError> {
+    RoleService::grant_role(user_id, role)
+}
+
+#[update]
+fn revoke_role(user_id: Principal) -> Result<(), BlockVerseError> {
+    RoleService::revoke_role(user_id)
+}
+
+#[query]
+fn get_role(user_id: Principal) -> Option<Role> {
+    RoleService::get_role(user_id)
+}
+
+#[query]
+fn get_role_changes(cursor: Option<String>, limit: usize) -> Result<Page<RoleChange>, BlockVerseError> {
+    RoleService::require(Role::Support)?;
+    RoleService::get_role_changes(cursor, limit)
 }
 
 // WebSocket-like functionality for real-time updates
 #[query]
-fn get_latest_posts(timestamp: u64) -> Vec<Post> {
-    PostService::get_posts_since(timestamp)
+fn get_latest_posts(timestamp: u64, cursor: Option<String>, limit: usize) -> Result<Page<Post>, BlockVerseError> {
+    PostService::get_posts_since(timestamp, cursor, limit)
 }
 
 // Candid export
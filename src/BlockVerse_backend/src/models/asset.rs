@@ -0,0 +1,61 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+/// Uploads are sent in chunks of exactly this many bytes; only the last
+/// chunk may be shorter.
+pub const CHUNK_SIZE: u64 = 256 * 1024;
+
+/// Bytes of media, committed or still uploading, each user may store.
+pub const USER_MEDIA_QUOTA: u64 = 200 * 1024 * 1024;
+
+/// How long an upload may stay uncommitted before it is discarded.
+pub const UPLOAD_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+
+/// An uploaded media file. Posts refer to it by `id`.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Asset {
+    pub id: String,
+    pub owner: Principal,
+    pub mime_type: String,
+    pub size: u64,
+    pub chunk_count: u32,
+    /// Hex SHA-256 of the content
+    pub sha256: String,
+    pub created_at: u64,
+}
+
+impl Asset {
+    /// Where the asset is served over HTTP.
+    pub fn path(&self) -> String {
+        format!("/media/{}", self.id)
+    }
+}
+
+/// An upload in progress, see `MediaService`.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Upload {
+    pub id: String,
+    pub owner: Principal,
+    pub mime_type: String,
+    pub size: u64,
+    pub chunk_count: u32,
+    pub started_at: u64,
+}
+
+impl Upload {
+    /// The exact length chunk `index` must have.
+    pub fn chunk_len(&self, index: u32) -> u64 {
+        if index + 1 < self.chunk_count {
+            CHUNK_SIZE
+        } else {
+            self.size - CHUNK_SIZE * u64::from(index)
+        }
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct MediaUsage {
+    /// Committed assets plus uploads in progress
+    pub used: u64,
+    pub quota: u64,
+}
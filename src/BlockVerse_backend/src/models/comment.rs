@@ -1,6 +1,9 @@
 use candid::{CandidType, Deserialize, Principal};
 use serde::Serialize;
-use std::collections::HashSet;
+use crate::utils::clock;
+
+/// Deepest a reply can nest; top-level comments have depth 0.
+pub const MAX_COMMENT_DEPTH: u32 = 5;
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Comment {
@@ -8,42 +11,60 @@ pub struct Comment {
     pub post_id: String,
     pub author: Principal,
     pub content: String,
-    pub likes: HashSet<Principal>,
+    /// See `Post::mentions`
+    pub mentions: Vec<Principal>,
+    pub hashtags: Vec<String>,
     pub likes_count: u64,
     pub created_at: u64,
+    /// The comment this replies to, if any
+    pub parent_comment_id: Option<String>,
+    pub depth: u32,
+    /// Direct replies that haven't been deleted
+    pub replies_count: u64,
+    /// Set when the author last edited the comment
+    pub edited_at: Option<u64>,
+    /// Set when the comment is deleted; see `Post::deleted_at`.
+    pub deleted_at: Option<u64>,
 }
 
 impl Comment {
-    pub fn new(post_id: String, author: Principal, content: String) -> Self {
-        let now = ic_cdk::api::time();
-        let id = format!("comment_{}_{}_{}", post_id, author.to_text(), now);
-        
+    pub fn new(id: String, post_id: String, author: Principal, content: String) -> Self {
+        let now = clock::now();
+
         Self {
             id,
             post_id,
             author,
             content,
-            likes: HashSet::new(),
+            mentions: Vec::new(),
+            hashtags: Vec::new(),
             likes_count: 0,
             created_at: now,
+            parent_comment_id: None,
+            depth: 0,
+            replies_count: 0,
+            edited_at: None,
+            deleted_at: None,
         }
     }
 
-    pub fn add_like(&mut self, user_id: Principal) -> bool {
-        if self.likes.insert(user_id) {
-            self.likes_count += 1;
-            true
-        } else {
-            false
+    pub fn new_reply(id: String, parent: &Comment, author: Principal, content: String) -> Self {
+        Self {
+            parent_comment_id: Some(parent.id.clone()),
+            depth: parent.depth + 1,
+            ..Self::new(id, parent.post_id.clone(), author, content)
         }
     }
 
-    pub fn remove_like(&mut self, user_id: Principal) -> bool {
-        if self.likes.remove(&user_id) {
-            self.likes_count = self.likes_count.saturating_sub(1);
-            true
-        } else {
-            false
-        }
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
     }
-}
\ No newline at end of file
+
+    /// Strips the comment down to a tombstone.
+    pub fn tombstone(&mut self, now: u64) {
+        self.content.clear();
+        self.mentions.clear();
+        self.hashtags.clear();
+        self.deleted_at = Some(now);
+    }
+}
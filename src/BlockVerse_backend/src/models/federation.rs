@@ -0,0 +1,49 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+/// Longest actor, inbox or key id accepted from another server.
+pub const MAX_URI_LEN: usize = 512;
+
+/// Longest public key accepted from another server.
+pub const MAX_KEY_PEM_LEN: usize = 2048;
+
+/// Longest activity queued for delivery.
+pub const MAX_ACTIVITY_LEN: usize = 16 * 1024;
+
+/// An account on another ActivityPub server. It is mirrored as a `User`
+/// with no usable identity of its own, so its follows and replies go
+/// through the same paths as a local user's.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RemoteActor {
+    /// The local user standing in for the actor
+    pub user_id: Principal,
+    /// The actor's id, which is also where its document is fetched from
+    pub uri: String,
+    pub inbox: String,
+    /// The inbox every actor on the server shares, if it has one
+    pub shared_inbox: Option<String>,
+    /// The id the actor signs requests with
+    pub key_id: String,
+    pub public_key_pem: String,
+    pub fetched_at: u64,
+}
+
+impl RemoteActor {
+    /// Where activities addressed to the actor are delivered. Servers with
+    /// a shared inbox get one copy for all their actors.
+    pub fn delivery_inbox(&self) -> &str {
+        self.shared_inbox.as_deref().unwrap_or(&self.inbox)
+    }
+}
+
+/// An activity waiting to be delivered to a remote inbox.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Delivery {
+    pub inbox: String,
+    /// The key of the local actor the activity is sent as
+    pub key_id: String,
+    /// The activity as JSON
+    pub activity: String,
+    /// Failed attempts so far
+    pub attempts: u32,
+}
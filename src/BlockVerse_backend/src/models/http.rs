@@ -0,0 +1,120 @@
+use candid::{define_function, CandidType, Deserialize};
+use serde_bytes::ByteBuf;
+
+pub type HeaderField = (String, String);
+
+/// A request forwarded by the HTTP gateway.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+    pub certificate_version: Option<u16>,
+}
+
+impl HttpRequest {
+    /// The percent-decoded path, without the query string.
+    pub fn path(&self) -> String {
+        let path = self.url.split(['?', '#']).next().unwrap_or_default();
+        percent_decode(path)
+    }
+
+    /// The percent-decoded value of query parameter `name`.
+    pub fn query(&self, name: &str) -> Option<String> {
+        let query = self.url.split('#').next()?.split_once('?')?.1;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(key, _)| percent_decode(key) == name)
+            .map(|(_, value)| percent_decode(&value.replace('+', " ")))
+    }
+
+    /// The first header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<HeaderField>,
+    pub body: ByteBuf,
+    pub upgrade: Option<bool>,
+    pub streaming_strategy: Option<StreamingStrategy>,
+}
+
+impl HttpResponse {
+    pub fn new(status_code: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self {
+            status_code,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            body: ByteBuf::from(body.into()),
+            upgrade: None,
+            streaming_strategy: None,
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(404, "text/plain; charset=utf-8", "Not found")
+    }
+
+    /// Asks the gateway to repeat the request as an update call, for
+    /// requests that change state.
+    pub fn upgrade() -> Self {
+        Self { upgrade: Some(true), ..Self::new(200, "text/plain; charset=utf-8", "") }
+    }
+
+    pub fn with_header(mut self, name: &str, value: impl ToString) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Lets the gateway fetch a large body in pieces through a callback.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub enum StreamingStrategy {
+    Callback { callback: StreamingCallback, token: StreamingToken },
+}
+
+define_function!(pub StreamingCallback : (StreamingToken) -> (StreamingCallbackHttpResponse) query);
+
+/// Where the next piece of a streamed asset starts.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StreamingToken {
+    pub asset_id: String,
+    pub index: u32,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: ByteBuf,
+    pub token: Option<StreamingToken>,
+}
+
+fn percent_decode(input: &str) -> String {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| input.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
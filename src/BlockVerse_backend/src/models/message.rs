@@ -0,0 +1,66 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+/// Most members a group conversation can have, its creator included.
+pub const MAX_GROUP_MEMBERS: usize = 8;
+
+/// Who may start a conversation with a user.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum DmPolicy {
+    #[default]
+    Everyone,
+    /// Only people the user follows
+    Following,
+}
+
+/// A direct message thread. Direct conversations have exactly two members
+/// and there is at most one per pair; groups are created fresh every time.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Conversation {
+    pub id: String,
+    pub members: Vec<Principal>,
+    pub is_group: bool,
+    pub created_by: Principal,
+    pub created_at: u64,
+    /// When the last message was sent, or `created_at` before the first
+    pub last_message_at: u64,
+}
+
+impl Conversation {
+    pub fn has_member(&self, user_id: Principal) -> bool {
+        self.members.contains(&user_id)
+    }
+
+    /// The other member of a direct conversation.
+    pub fn other_member(&self, user_id: Principal) -> Option<Principal> {
+        self.members.iter().copied().find(|member| *member != user_id)
+    }
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Message {
+    /// Increases with every message, so later messages have higher ids
+    pub id: u64,
+    pub conversation_id: String,
+    pub sender: Principal,
+    pub content: String,
+    pub created_at: u64,
+}
+
+/// How far a member has read a conversation.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ReadReceipt {
+    pub member: Principal,
+    /// Id of the latest message the member has seen
+    pub last_read: u64,
+    pub read_at: u64,
+}
+
+/// A conversation as it appears in the caller's list.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct ConversationSummary {
+    pub conversation: Conversation,
+    pub last_message: Option<Message>,
+    pub unread_count: u64,
+    pub muted: bool,
+}
@@ -1,3 +1,13 @@
 pub mod user;
 pub mod post;
-pub mod comment;
\ No newline at end of file
+pub mod comment;
+pub mod page;
+pub mod transaction;
+pub mod role;
+pub mod moderation;
+pub mod notification;
+pub mod tag;
+pub mod message;
+pub mod asset;
+pub mod http;
+pub mod federation;
@@ -0,0 +1,99 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+/// What a report or moderation decision is about.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ReportTarget {
+    Post(String),
+    Comment(String),
+    User(Principal),
+}
+
+impl ReportTarget {
+    /// Unique string form, used to key the per-target indexes.
+    pub fn key(&self) -> String {
+        match self {
+            Self::Post(post_id) => format!("post:{}", post_id),
+            Self::Comment(comment_id) => format!("comment:{}", comment_id),
+            Self::User(user_id) => format!("user:{}", user_id),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ReportReason {
+    Spam,
+    Harassment,
+    HateSpeech,
+    Violence,
+    SexualContent,
+    Misinformation,
+    Other,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ReportStatus {
+    Open,
+    Resolved,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Report {
+    pub id: String,
+    pub reporter: Principal,
+    pub target: ReportTarget,
+    pub reason: ReportReason,
+    pub details: String,
+    pub status: ReportStatus,
+    pub created_at: u64,
+    /// Set once a moderator has acted on the report.
+    pub decision_id: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum ModerationAction {
+    /// Keep the post or comment but stop showing it to anyone but its author.
+    Hide,
+    Remove,
+    Warn,
+    /// Stop the user from posting, commenting and interacting for a while.
+    Suspend { duration_ns: u64 },
+    /// Close the reports without acting on them.
+    Dismiss,
+}
+
+/// An entry in the moderation log. Decisions are append-only: an appeal
+/// can undo a decision's effect but never changes the entry itself.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Decision {
+    pub id: u64,
+    pub moderator: Principal,
+    pub target: ReportTarget,
+    /// The user the action applies to: the target user, or the author of
+    /// the target post or comment.
+    pub subject: Principal,
+    pub action: ModerationAction,
+    pub note: String,
+    pub created_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum AppealStatus {
+    Pending,
+    /// The decision stands.
+    Upheld,
+    /// The decision was reversed as far as its action allows.
+    Overturned,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Appeal {
+    pub decision_id: u64,
+    pub appellant: Principal,
+    pub statement: String,
+    pub status: AppealStatus,
+    pub reviewer: Option<Principal>,
+    pub response: Option<String>,
+    pub created_at: u64,
+    pub resolved_at: Option<u64>,
+}
@@ -0,0 +1,65 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum NotificationKind {
+    Follow,
+    FollowRequest,
+    Like,
+    Comment,
+    Reply,
+    Share,
+    Mention,
+    Tip,
+    Message,
+}
+
+impl NotificationKind {
+    pub const ALL: [Self; 9] = [
+        Self::Follow,
+        Self::FollowRequest,
+        Self::Like,
+        Self::Comment,
+        Self::Reply,
+        Self::Share,
+        Self::Mention,
+        Self::Tip,
+        Self::Message,
+    ];
+
+    /// Stable string form, used to key mute preferences and groups.
+    pub fn key(self) -> &'static str {
+        match self {
+            Self::Follow => "follow",
+            Self::FollowRequest => "follow_request",
+            Self::Like => "like",
+            Self::Comment => "comment",
+            Self::Reply => "reply",
+            Self::Share => "share",
+            Self::Mention => "mention",
+            Self::Tip => "tip",
+            Self::Message => "message",
+        }
+    }
+}
+
+/// An inbox entry. Unread notifications of the same kind about the same
+/// subject are grouped into one, e.g. "5 people liked your post".
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Notification {
+    /// Changes whenever another event is grouped in, which moves the
+    /// notification back to the top of the inbox.
+    pub id: u64,
+    pub kind: NotificationKind,
+    /// The post, comment or conversation the notification is about
+    pub subject: Option<String>,
+    /// The most recent distinct actors, newest first
+    pub actors: Vec<Principal>,
+    /// How many distinct actors were grouped into this notification
+    pub count: u64,
+    /// Total amount tipped, for tip notifications
+    pub amount: Option<u64>,
+    pub read: bool,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
@@ -0,0 +1,19 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+
+/// One page of a list query. Pass `next_cursor` back to get the following
+/// page; it is `None` once the list is exhausted. A page can come back
+/// short, or empty, with a cursor when most of what it scanned was
+/// filtered out. Cursors are opaque and point just past the last entry
+/// scanned, so inserts elsewhere in the list don't shift later pages.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn empty() -> Self {
+        Self { items: Vec::new(), next_cursor: None }
+    }
+}
@@ -1,82 +1,165 @@
 use candid::{CandidType, Deserialize, Principal};
 use serde::Serialize;
-use std::collections::HashSet;
+use crate::utils::clock;
+
+/// How long after publishing a post its author can still edit it, until an
+/// admin configures otherwise.
+pub const DEFAULT_EDIT_WINDOW_NS: u64 = 60 * 60 * 1_000_000_000;
+
+/// Who can see a post, on top of its author's account being private.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum Visibility {
+    #[default]
+    Public,
+    /// Only the author's followers
+    FollowersOnly,
+    /// Only the users mentioned in it
+    MentionedOnly,
+    /// Anyone it is linked to, but kept out of search, hashtags and the
+    /// latest posts
+    Unlisted,
+}
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct Post {
     pub id: String,
     pub author: Principal,
     pub content: String,
+    /// Media linked by URL. New posts attach uploads via `media_asset_id`
+    /// instead; this is kept for posts that predate them.
     pub media_url: Option<String>,
-    pub likes: HashSet<Principal>,
+    /// The `Asset` shown with the post
+    pub media_asset_id: Option<String>,
     pub likes_count: u64,
     pub comments_count: u64,
     pub shares_count: u64,
     pub is_shared: bool,
+    /// The shared post. Cleared once that post has been purged.
     pub original_post_id: Option<String>,
     pub share_comment: Option<String>,
+    /// Users mentioned as `@username` in `content`
+    pub mentions: Vec<Principal>,
+    /// `#tags` in `content`, lowercased
+    pub hashtags: Vec<String>,
+    /// A share always has its original's visibility
+    pub visibility: Visibility,
     pub created_at: u64,
     pub updated_at: u64,
+    /// Set when the author last edited the post; earlier versions are kept
+    /// as `PostRevision`s.
+    pub edited_at: Option<u64>,
+    /// Set when the post is deleted. Deleted posts stay behind as content-less
+    /// tombstones, so comments and shares still resolve, until they are purged.
+    pub deleted_at: Option<u64>,
 }
 
 impl Post {
-    pub fn new(author: Principal, content: String, media_url: Option<String>) -> Self {
-        let now = ic_cdk::api::time();
-        let id = format!("{}_{}", author.to_text(), now);
-        
+    pub fn new(
+        id: String,
+        author: Principal,
+        content: String,
+        media_asset_id: Option<String>,
+        visibility: Visibility,
+    ) -> Self {
+        let now = clock::now();
+
         Self {
             id,
             author,
             content,
-            media_url,
-            likes: HashSet::new(),
+            media_url: None,
+            media_asset_id,
             likes_count: 0,
             comments_count: 0,
             shares_count: 0,
             is_shared: false,
             original_post_id: None,
             share_comment: None,
+            mentions: Vec::new(),
+            hashtags: Vec::new(),
+            visibility,
             created_at: now,
             updated_at: now,
+            edited_at: None,
+            deleted_at: None,
         }
     }
 
-    pub fn new_share(author: Principal, original_post_id: String, comment: Option<String>) -> Self {
-        let now = ic_cdk::api::time();
-        let id = format!("share_{}_{}", author.to_text(), now);
-        
+    pub fn new_share(id: String, author: Principal, original: &Post, comment: Option<String>) -> Self {
+        let now = clock::now();
+
         Self {
             id,
             author,
             content: String::new(),
             media_url: None,
-            likes: HashSet::new(),
+            media_asset_id: None,
             likes_count: 0,
             comments_count: 0,
             shares_count: 0,
             is_shared: true,
-            original_post_id: Some(original_post_id),
+            original_post_id: Some(original.id.clone()),
             share_comment: comment,
+            mentions: Vec::new(),
+            hashtags: Vec::new(),
+            visibility: original.visibility,
             created_at: now,
             updated_at: now,
+            edited_at: None,
+            deleted_at: None,
         }
     }
 
-    pub fn add_like(&mut self, user_id: Principal) -> bool {
-        if self.likes.insert(user_id) {
-            self.likes_count += 1;
-            true
-        } else {
-            false
-        }
+    /// Replaces the post's content and returns the version it replaced. Any
+    /// URL media is dropped in favour of `media_asset_id`.
+    pub fn edit(&mut self, content: String, media_asset_id: Option<String>, number: u64, now: u64) -> PostRevision {
+        let revision = PostRevision {
+            post_id: self.id.clone(),
+            number,
+            content: std::mem::replace(&mut self.content, content),
+            media_url: self.media_url.take(),
+            media_asset_id: std::mem::replace(&mut self.media_asset_id, media_asset_id),
+            created_at: self.edited_at.unwrap_or(self.created_at),
+            replaced_at: now,
+        };
+        self.updated_at = now;
+        self.edited_at = Some(now);
+        revision
     }
 
-    pub fn remove_like(&mut self, user_id: Principal) -> bool {
-        if self.likes.remove(&user_id) {
-            self.likes_count = self.likes_count.saturating_sub(1);
-            true
-        } else {
-            false
-        }
+    /// Whether the post shows up in search, hashtags and the latest posts.
+    pub fn is_listed(&self) -> bool {
+        self.visibility == Visibility::Public
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
     }
-}
\ No newline at end of file
+
+    /// Strips the post down to a tombstone.
+    pub fn tombstone(&mut self, now: u64) {
+        self.content.clear();
+        self.media_url = None;
+        self.media_asset_id = None;
+        self.share_comment = None;
+        self.mentions.clear();
+        self.hashtags.clear();
+        self.updated_at = now;
+        self.deleted_at = Some(now);
+    }
+}
+
+/// A superseded version of a post. Revisions are numbered from 0, the
+/// version originally published.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PostRevision {
+    pub post_id: String,
+    pub number: u64,
+    pub content: String,
+    pub media_url: Option<String>,
+    pub media_asset_id: Option<String>,
+    /// When this version was published
+    pub created_at: u64,
+    /// When an edit replaced it
+    pub replaced_at: u64,
+}
@@ -0,0 +1,46 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+/// Staff roles, from most to least privileged. Each role includes every
+/// permission of the roles below it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum Role {
+    Owner,
+    Admin,
+    Moderator,
+    Support,
+}
+
+impl Role {
+    fn rank(self) -> u8 {
+        match self {
+            Self::Owner => 3,
+            Self::Admin => 2,
+            Self::Moderator => 1,
+            Self::Support => 0,
+        }
+    }
+
+    /// Whether holding `self` grants everything `required` does.
+    pub fn includes(self, required: Role) -> bool {
+        self.rank() >= required.rank()
+    }
+
+    /// Whether a holder of `self` may grant or revoke `other`. Owners manage
+    /// everyone, including other owners; everyone else only manages roles
+    /// strictly below their own.
+    pub fn manages(self, other: Role) -> bool {
+        self == Self::Owner || self.rank() > other.rank()
+    }
+}
+
+/// One entry in the role audit trail. `previous` and `role` are both set
+/// when a grant replaced an existing role; `role` is `None` for a revoke.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct RoleChange {
+    pub actor: Principal,
+    pub target: Principal,
+    pub previous: Option<Role>,
+    pub role: Option<Role>,
+    pub timestamp: u64,
+}
@@ -0,0 +1,17 @@
+use candid::{CandidType, Deserialize};
+use serde::Serialize;
+use super::{comment::Comment, post::Post};
+
+/// Where a user was mentioned.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub enum Mention {
+    Post(Post),
+    Comment(Comment),
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct TrendingHashtag {
+    pub tag: String,
+    /// Posts using the tag within the requested window
+    pub posts_count: u64,
+}
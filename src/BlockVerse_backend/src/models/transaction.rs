@@ -0,0 +1,65 @@
+use candid::{CandidType, Deserialize, Principal};
+use serde::Serialize;
+
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct Transaction {
+    pub id: String,
+    pub from: Principal,
+    pub to: Principal,
+    pub amount: u64,
+    pub transaction_type: TransactionType,
+    pub timestamp: u64,
+    /// Ledger block that settled this, if it touched the ledger at all.
+    pub block_index: Option<u64>,
+    /// The post a tip was sent for.
+    pub post_id: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize, Serialize)]
+pub enum TransactionType {
+    Tip,
+    Reward,
+    Deposit,
+    Withdrawal,
+}
+
+impl Transaction {
+    /// Whether this moved value out of `user_id`'s hands. Deposits only
+    /// ever count as received.
+    pub fn is_sent_by(&self, user_id: Principal) -> bool {
+        self.from == user_id && self.transaction_type != TransactionType::Deposit
+    }
+
+    /// Whether this moved value into `user_id`'s balance. Withdrawals only
+    /// ever count as sent, whoever owns the target account.
+    pub fn is_received_by(&self, user_id: Principal) -> bool {
+        self.to == user_id && self.transaction_type != TransactionType::Withdrawal
+    }
+}
+
+/// Running sums of everything a user has moved, kept up to date as
+/// transactions are recorded.
+#[derive(Clone, Debug, Default, CandidType, Deserialize, Serialize)]
+pub struct TransactionTotals {
+    pub tips_sent: u64,
+    pub tips_received: u64,
+    pub rewards_received: u64,
+    pub deposited: u64,
+    pub withdrawn: u64,
+}
+
+/// A withdrawal whose ledger call failed without an answer. The amount
+/// stays debited until resubmitting the same transfer settles it: the
+/// ledger deduplicates it by `created_at_time` and `memo`.
+#[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
+pub struct PendingWithdrawal {
+    pub id: u64,
+    pub user_id: Principal,
+    pub to_owner: Principal,
+    pub to_subaccount: Option<Vec<u8>>,
+    /// Debited from the balance; the ledger fee comes out of it
+    pub amount: u64,
+    pub fee: u64,
+    pub memo: Vec<u8>,
+    pub created_at_time: u64,
+}
@@ -1,5 +1,6 @@
 use candid::{CandidType, Deserialize, Principal};
 use serde::Serialize;
+use crate::utils::clock;
 
 #[derive(Clone, Debug, CandidType, Deserialize, Serialize)]
 pub struct User {
@@ -11,13 +12,16 @@ pub struct User {
     pub following_count: u64,
     pub posts_count: u64,
     pub balance: u64,
+    /// Private accounts approve each follower, and only followers see
+    /// their posts.
+    pub is_private: bool,
     pub created_at: u64,
     pub updated_at: u64,
 }
 
 impl User {
     pub fn new(id: Principal, username: String, bio: String, avatar_url: String) -> Self {
-        let now = ic_cdk::api::time();
+        let now = clock::now();
         Self {
             id,
             username,
@@ -27,6 +31,7 @@ impl User {
             following_count: 0,
             posts_count: 0,
             balance: 0,
+            is_private: false,
             created_at: now,
             updated_at: now,
         }
@@ -35,6 +40,6 @@ impl User {
     pub fn update(&mut self, bio: String, avatar_url: String) {
         self.bio = bio;
         self.avatar_url = avatar_url;
-        self.updated_at = ic_cdk::api::time();
+        self.updated_at = clock::now();
     }
 }
\ No newline at end of file
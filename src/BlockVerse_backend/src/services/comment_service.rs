@@ -1,78 +1,470 @@
 use candid::Principal;
-use crate::models::comment::Comment;
-use crate::storage::state::STATE;
+use std::ops::Bound;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::{comment::{Comment, MAX_COMMENT_DEPTH}, page::Page, role::Role};
+use crate::models::notification::NotificationKind;
+use crate::services::moderation_service::ModerationService;
+use crate::services::notification_service::NotificationService;
+use crate::services::role_service::RoleService;
+use crate::services::tag_service::TagService;
+use crate::services::user_service::UserService;
+use crate::storage::pagination;
+use crate::storage::state::{self, State, STATE};
+use crate::storage::storable::{IdKey, PrincipalKey, ThreadKey};
+use crate::utils::{clock, validation};
 
 pub struct CommentService;
 
 impl CommentService {
-    pub fn create_comment(post_id: String, content: String) -> Result<Comment, String> {
+    /// Comments on `post_id`, or replies to `parent_comment_id` on it.
+    pub fn create_comment(
+        post_id: String,
+        content: String,
+        parent_comment_id: Option<String>,
+    ) -> Result<Comment, BlockVerseError> {
         let caller = ic_cdk::caller();
 
         if caller == Principal::anonymous() {
-            return Err("Anonymous users cannot create comments".to_string());
+            return Err(BlockVerseError::AnonymousCaller);
         }
 
-        if content.trim().is_empty() {
-            return Err("Comment cannot be empty".to_string());
+        let content = Self::validate_content(&content)?;
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            Self::add_comment(&mut state, caller, post_id, content, parent_comment_id)
+        })
+    }
+
+    /// Checks and trims the content of a new or edited comment.
+    pub fn validate_content(content: &str) -> Result<String, BlockVerseError> {
+        let content = validation::sanitize_content(content);
+
+        if content.is_empty() {
+            return Err(BlockVerseError::validation("content", "Comment cannot be empty"));
         }
 
+        if !validation::is_valid_content(&content) {
+            return Err(BlockVerseError::validation("content", "Too long"));
+        }
+
+        Ok(content)
+    }
+
+    /// Adds `author`'s comment on `post_id`, or reply to
+    /// `parent_comment_id` on it, with content that passed
+    /// `validate_content`.
+    pub fn add_comment(
+        state: &mut State,
+        author: Principal,
+        post_id: String,
+        content: String,
+        parent_comment_id: Option<String>,
+    ) -> Result<Comment, BlockVerseError> {
+        ModerationService::check_not_suspended(state, author)?;
+
+        // Check if post exists
+        let post_author = state
+            .get_live_post(&post_id)
+            .filter(|post| state.can_view_post(post, author))
+            .map(|post| post.author)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))?;
+        UserService::check_not_blocked(state, author, post_author)?;
+
+        let id = state.next_id("comment");
+        let mut comment = match &parent_comment_id {
+            Some(parent_id) => {
+                let parent = state
+                    .get_live_comment(parent_id)
+                    .filter(|parent| parent.post_id == post_id)
+                    .ok_or_else(|| BlockVerseError::not_found(EntityKind::Comment, parent_id))?;
+                if parent.depth >= MAX_COMMENT_DEPTH {
+                    return Err(BlockVerseError::validation("parent_comment_id", "Thread is too deep"));
+                }
+                UserService::check_not_blocked(state, author, parent.author)?;
+                Comment::new_reply(id, &parent, author, content)
+            }
+            None => Comment::new(id, post_id.clone(), author, content),
+        };
+        TagService::parse_comment(state, &mut comment);
+
+        state.insert_comment(comment.clone());
+        TagService::index_comment(state, &comment);
+
+        // Add to post's comments in thread order
+        Self::place(state, &comment);
+
+        // Update comment counts
+        state.update_post(&post_id, |post| post.comments_count += 1);
+        if let Some(parent_id) = &parent_comment_id {
+            state.update_comment(parent_id, |parent| parent.replies_count += 1);
+        }
+
+        // Notify the post's author, and whoever is being replied to
+        NotificationService::notify(state, post_author, NotificationKind::Comment, author, Some(post_id), None);
+        if let Some(parent_author) = parent_comment_id
+            .as_deref()
+            .and_then(|parent_id| state.get_comment(parent_id))
+            .map(|parent| parent.author)
+            .filter(|parent_author| *parent_author != post_author)
+        {
+            NotificationService::notify(state, parent_author, NotificationKind::Reply, author, parent_comment_id, None);
+        }
+        TagService::notify_mentions(state, author, &comment.id, &comment.mentions, &[]);
+
+        Ok(comment)
+    }
+
+    /// Replaces the content of one of the caller's comments. Comments share
+    /// the edit window of posts.
+    pub fn update_comment(comment_id: String, content: String) -> Result<Comment, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        let content = Self::validate_content(&content)?;
+
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            // Check if post exists
-            if !state.posts.contains_key(&post_id) {
-                return Err("Post not found".to_string());
+
+            ModerationService::check_not_suspended(&state, caller)?;
+
+            let mut comment = state
+                .get_live_comment(&comment_id)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Comment, &comment_id))?;
+            if comment.author != caller {
+                return Err(BlockVerseError::Unauthorized("Only the author can edit this comment".to_string()));
             }
 
-            let comment = Comment::new(post_id.clone(), caller, content);
-            let comment_id = comment.id.clone();
-            
-            state.comments.insert(comment_id.clone(), comment.clone());
-            
-            // Add to post's comments
-            let post_comments = state.post_comments.entry(post_id.clone()).or_insert_with(Vec::new);
-            post_comments.push(comment_id);
-
-            // Update post's comment count
-            if let Some(post) = state.posts.get_mut(&post_id) {
-                post.comments_count += 1;
+            let now = clock::now();
+            if now.saturating_sub(comment.created_at) > state.edit_window() {
+                return Err(BlockVerseError::InvalidOperation(
+                    "The edit window for this comment has closed".to_string(),
+                ));
             }
 
+            TagService::unindex_comment(&mut state, &comment);
+            let previous_mentions = std::mem::take(&mut comment.mentions);
+
+            comment.content = content;
+            comment.edited_at = Some(now);
+            TagService::parse_comment(&state, &mut comment);
+            state.insert_comment(comment.clone());
+
+            TagService::index_comment(&mut state, &comment);
+            TagService::notify_mentions(&mut state, caller, &comment.id, &comment.mentions, &previous_mentions);
             Ok(comment)
         })
     }
 
-    pub fn get_post_comments(post_id: String) -> Vec<Comment> {
+    /// Deletes a comment. Allowed for its author, the author of the post it
+    /// is on, and moderators.
+    pub fn delete_comment(comment_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
         STATE.with(|state| {
-            let state = state.borrow();
-            
-            if let Some(comment_ids) = state.post_comments.get(&post_id) {
-                comment_ids.iter()
-                    .filter_map(|id| state.comments.get(id))
-                    .cloned()
-                    .collect()
-            } else {
-                Vec::new()
+            let mut state = state.borrow_mut();
+
+            let comment = state
+                .get_live_comment(&comment_id)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Comment, &comment_id))?;
+            let post_author = state.get_post(&comment.post_id).map(|post| post.author);
+            if comment.author != caller
+                && post_author != Some(caller)
+                && !RoleService::has_role(&state, caller, Role::Moderator)
+            {
+                return Err(BlockVerseError::Unauthorized("Cannot delete this comment".to_string()));
             }
+
+            Self::delete(&mut state, &comment);
+            Ok(())
         })
     }
 
-    pub fn like_comment(comment_id: String) -> Result<(), String> {
+    /// The post's comments in thread order: each comment is followed by its
+    /// replies, siblings oldest first. Deleted comments only show up, as
+    /// tombstones, while they still have replies.
+    pub fn get_post_comments(
+        post_id: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Comment>, BlockVerseError> {
         let caller = ic_cdk::caller();
+        STATE.with(|state| Self::post_comments(&state.borrow(), caller, post_id, cursor, limit))
+    }
+
+    fn post_comments(
+        state: &State,
+        caller: Principal,
+        post_id: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Comment>, BlockVerseError> {
+        if !state.get_post(&post_id).is_some_and(|post| state.can_view_post(&post, caller)) {
+            return Err(BlockVerseError::not_found(EntityKind::Post, &post_id));
+        }
+
+        let post_key = IdKey(post_id);
+        pagination::page_members(&state.post_comments, post_key.clone(), cursor, limit, |key| {
+            state
+                .get_comment(&key.comment_id)
+                .filter(|comment| !state.is_hidden_from(&comment.id, comment.author, caller))
+                .filter(|comment| !comment.is_deleted() || Self::has_replies_at(state, &post_key, key))
+        })
+    }
+
+    /// Turns `comment` into a tombstone that keeps its place in the thread.
+    pub fn delete(state: &mut State, comment: &Comment) {
+        let now = clock::now();
+        TagService::unindex_comment(state, comment);
+        state.update_comment(&comment.id, |comment| comment.tombstone(now));
+        state.tombstones.insert((now, IdKey(comment.id.clone())), ());
+
+        state.update_post(&comment.post_id, |post| post.comments_count = post.comments_count.saturating_sub(1));
+        if let Some(parent_id) = &comment.parent_comment_id {
+            state.update_comment(parent_id, |parent| parent.replies_count = parent.replies_count.saturating_sub(1));
+        }
+    }
+
+    /// Hard-deletes a comment tombstone along with its likes and its place
+    /// in the thread. Callers must make sure it has no replies left.
+    pub fn purge(state: &mut State, comment: &Comment) {
+        if let Some(key) = state.comment_threads.get(&IdKey(comment.id.clone())) {
+            state.post_comments.remove(&(IdKey(comment.post_id.clone()), key));
+        }
+        Self::purge_record(state, &comment.id);
+    }
+
+    /// Removes the comment and what hangs off it, leaving `post_comments`
+    /// to the caller.
+    pub fn purge_record(state: &mut State, comment_id: &str) {
+        let comment_id = IdKey::from(comment_id);
+        state.comments.remove(&comment_id);
+        state.comment_threads.remove(&comment_id);
+        state.hidden_content.remove(&comment_id);
+        state::remove_members(&mut state.comment_likes, &comment_id);
+    }
+
+    /// Whether any replies to `comment`, deleted or not, are still stored.
+    pub fn has_replies(state: &State, comment: &Comment) -> bool {
+        state
+            .comment_threads
+            .get(&IdKey(comment.id.clone()))
+            .is_some_and(|key| Self::has_replies_at(state, &IdKey(comment.post_id.clone()), &key))
+    }
+
+    /// Gives `comment` its key in `post_comments`, after every comment
+    /// already placed under the same parent. Its parent must have been
+    /// placed first.
+    pub fn place(state: &mut State, comment: &Comment) {
+        let mut path = comment
+            .parent_comment_id
+            .as_ref()
+            .and_then(|parent_id| state.comment_threads.get(&IdKey(parent_id.clone())))
+            .map(|parent| parent.path)
+            .unwrap_or_default();
+        path.push(state.next_sequence());
+
+        let key = ThreadKey { path, comment_id: comment.id.clone() };
+        state.comment_threads.insert(IdKey(comment.id.clone()), key.clone());
+        state.post_comments.insert((IdKey(comment.post_id.clone()), key), ());
+    }
+
+    fn has_replies_at(state: &State, post_key: &IdKey, key: &ThreadKey) -> bool {
+        state
+            .post_comments
+            .range((Bound::Excluded((post_key.clone(), key.clone())), Bound::Unbounded))
+            .next()
+            .is_some_and(|((post, next), _)| post == *post_key && key.is_ancestor_of(&next))
+    }
+
+    pub fn like_comment(comment_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
 
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            match state.comments.get_mut(&comment_id) {
-                Some(comment) => {
-                    if comment.add_like(caller) {
-                        Ok(())
-                    } else {
-                        Err("Already liked this comment".to_string())
-                    }
-                }
-                None => Err("Comment not found".to_string())
+
+            ModerationService::check_not_suspended(&state, caller)?;
+            Self::check_can_react(&state, caller, &comment_id)?;
+
+            let key = (IdKey(comment_id.clone()), PrincipalKey(caller));
+            if state.comment_likes.insert(key, ()).is_some() {
+                return Err(BlockVerseError::already_exists(EntityKind::Like, &comment_id));
             }
+
+            state.update_comment(&comment_id, |comment| comment.likes_count += 1);
+            Ok(())
         })
     }
-}
\ No newline at end of file
+
+    pub fn unlike_comment(comment_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        STATE.with(|state| Self::unlike(&mut state.borrow_mut(), caller, &comment_id))
+    }
+
+    /// Removes `caller`'s like from `comment_id`. Only the like has to
+    /// exist: a block or a hidden post must not leave it stuck in place.
+    fn unlike(state: &mut State, caller: Principal, comment_id: &str) -> Result<(), BlockVerseError> {
+        let key = (IdKey::from(comment_id), PrincipalKey(caller));
+        if state.comment_likes.remove(&key).is_none() {
+            return Err(BlockVerseError::not_found(EntityKind::Like, comment_id));
+        }
+
+        state.update_comment(comment_id, |comment| comment.likes_count = comment.likes_count.saturating_sub(1));
+        Ok(())
+    }
+
+    /// Fails unless `caller` may like `comment_id`, with the checks
+    /// `add_comment` makes for a reply: the comment is live, its post is
+    /// visible to `caller`, and no author involved blocked them or was
+    /// blocked by them.
+    fn check_can_react(state: &State, caller: Principal, comment_id: &str) -> Result<(), BlockVerseError> {
+        let comment = state
+            .get_live_comment(comment_id)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Comment, comment_id))?;
+        let post_author = state
+            .get_live_post(&comment.post_id)
+            .filter(|post| state.can_view_post(post, caller))
+            .map(|post| post.author)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Comment, comment_id))?;
+
+        UserService::check_not_blocked(state, caller, post_author)?;
+        UserService::check_not_blocked(state, caller, comment.author)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::{Post, Visibility};
+    use crate::utils::testing::principal;
+
+    /// Alice's post with Bob's comment on it, visible as `visibility`.
+    fn thread(state: &mut State, visibility: Visibility) {
+        let post = Post::new("post_1".to_string(), principal(1), "hello".to_string(), None, visibility);
+        state.insert_post(post);
+        let comment = Comment::new("comment_1".to_string(), "post_1".to_string(), principal(2), "hi".to_string());
+        state.insert_comment(comment);
+    }
+
+    #[test]
+    fn reacting_needs_a_visible_post() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            thread(state, Visibility::FollowersOnly);
+
+            assert!(CommentService::check_can_react(state, principal(1), "comment_1").is_ok());
+            assert!(matches!(
+                CommentService::check_can_react(state, principal(3), "comment_1"),
+                Err(BlockVerseError::NotFound { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn reacting_is_refused_across_blocks() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            thread(state, Visibility::Public);
+            assert!(CommentService::check_can_react(state, principal(3), "comment_1").is_ok());
+
+            // The comment's author blocked the caller
+            state.user_blocks.insert((PrincipalKey(principal(2)), PrincipalKey(principal(3))), ());
+            assert!(CommentService::check_can_react(state, principal(3), "comment_1").is_err());
+
+            // The caller blocked the post's author
+            state.user_blocks.remove(&(PrincipalKey(principal(2)), PrincipalKey(principal(3))));
+            state.user_blocks.insert((PrincipalKey(principal(3)), PrincipalKey(principal(1))), ());
+            assert!(CommentService::check_can_react(state, principal(3), "comment_1").is_err());
+        });
+    }
+
+    #[test]
+    fn likes_can_be_removed_after_a_block() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            thread(state, Visibility::Public);
+            state.comment_likes.insert((IdKey::from("comment_1"), PrincipalKey(principal(3))), ());
+            state.update_comment("comment_1", |comment| comment.likes_count = 1);
+
+            state.user_blocks.insert((PrincipalKey(principal(2)), PrincipalKey(principal(3))), ());
+            CommentService::unlike(state, principal(3), "comment_1").unwrap();
+            assert_eq!(state.get_comment("comment_1").unwrap().likes_count, 0);
+
+            assert!(matches!(
+                CommentService::unlike(state, principal(3), "comment_1"),
+                Err(BlockVerseError::NotFound { kind: EntityKind::Like, .. })
+            ));
+        });
+    }
+
+    fn reply(state: &mut State, author: u8, parent: Option<&Comment>) -> Result<Comment, BlockVerseError> {
+        let parent_id = parent.map(|parent| parent.id.clone());
+        CommentService::add_comment(state, principal(author), "post_1".to_string(), "hi".to_string(), parent_id)
+    }
+
+    fn listed(state: &State) -> Vec<String> {
+        let page = CommentService::post_comments(state, principal(3), "post_1".to_string(), None, 20).unwrap();
+        page.items.into_iter().map(|comment| comment.id).collect()
+    }
+
+    #[test]
+    fn replies_stop_at_the_maximum_depth() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            thread(state, Visibility::Public);
+            let mut parent = reply(state, 2, None).unwrap();
+            while parent.depth < MAX_COMMENT_DEPTH {
+                parent = reply(state, 2, Some(&parent)).unwrap();
+            }
+
+            assert!(matches!(reply(state, 2, Some(&parent)), Err(BlockVerseError::Validation { .. })));
+            assert_eq!(state.get_comment(&parent.id).unwrap().replies_count, 0);
+        });
+    }
+
+    #[test]
+    fn threads_list_parents_before_replies_and_siblings_oldest_first() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            thread(state, Visibility::Public);
+            let first = reply(state, 2, None).unwrap();
+            let second = reply(state, 3, None).unwrap();
+            let answer = reply(state, 3, Some(&first)).unwrap();
+            let later_answer = reply(state, 1, Some(&first)).unwrap();
+            let nested = reply(state, 2, Some(&answer)).unwrap();
+
+            let order = [&first, &answer, &nested, &later_answer, &second].map(|comment| comment.id.clone());
+            assert_eq!(listed(state), order);
+        });
+    }
+
+    #[test]
+    fn deleted_comments_stay_listed_only_while_they_have_replies() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            thread(state, Visibility::Public);
+            let parent = reply(state, 2, None).unwrap();
+            let answer = reply(state, 3, Some(&parent)).unwrap();
+            let lonely = reply(state, 2, None).unwrap();
+
+            CommentService::delete(state, &parent);
+            CommentService::delete(state, &lonely);
+            assert_eq!(listed(state), [parent.id.clone(), answer.id.clone()]);
+            assert!(state.get_comment(&parent.id).unwrap().is_deleted());
+
+            // A deleted reply still holds its parent's place until it's purged
+            CommentService::delete(state, &answer);
+            assert_eq!(listed(state), [parent.id]);
+            let tombstone = state.get_comment(&answer.id).unwrap();
+            CommentService::purge(state, &tombstone);
+            assert!(listed(state).is_empty());
+        });
+    }
+}
@@ -0,0 +1,1288 @@
+use candid::Principal;
+use ic_cdk::api::management_canister::http_request::{
+    self as outcall, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse as OutcallResponse,
+    TransformArgs, TransformContext,
+};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha224};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::federation::{self, Delivery, RemoteActor};
+use crate::models::http::{HttpRequest, HttpResponse};
+use crate::models::post::{Post, Visibility};
+use crate::models::user::User;
+use crate::services::comment_service::CommentService;
+use crate::services::http_service::HttpService;
+use crate::services::media_service::MediaService;
+use crate::services::post_service::PostService;
+use crate::services::search_service::SearchService;
+use crate::services::user_service::UserService;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::{IdKey, PrincipalKey, MAX_ID_LEN};
+use crate::utils::signature::{self, Signature};
+use crate::utils::{clock, crypto, dates, markup, validation};
+
+const ACTIVITY_JSON: &str = "application/activity+json";
+const JRD_JSON: &str = "application/jrd+json";
+const TEXT: &str = "text/plain; charset=utf-8";
+const JSON: &str = "application/json";
+
+const ACTIVITY_STREAMS: &str = "https://www.w3.org/ns/activitystreams";
+const SECURITY: &str = "https://w3id.org/security/v1";
+const PUBLIC: &str = "https://www.w3.org/ns/activitystreams#Public";
+
+/// Activities per outbox page.
+const OUTBOX_PAGE: usize = 20;
+
+/// How far a signed request's `Date` may be from the canister's clock.
+const MAX_CLOCK_SKEW_NS: u64 = 12 * 60 * 60 * 1_000_000_000;
+
+/// Largest activity accepted by the inbox.
+const MAX_INBOX_BODY: usize = 64 * 1024;
+
+/// Longest host accepted in an actor id, port included.
+const MAX_HOST_LEN: usize = 200;
+
+/// Longest bio copied from a remote actor.
+const REMOTE_BIO_CHARS: usize = 120;
+
+/// Deliveries attempted per run.
+const DELIVERY_BATCH: usize = 10;
+
+/// Attempts before a delivery is given up on.
+const MAX_DELIVERY_ATTEMPTS: u32 = 8;
+
+/// Wait before the first retry; it doubles with every further attempt.
+const RETRY_BASE_NS: u64 = 60 * 1_000_000_000;
+
+#[cfg(target_arch = "wasm32")]
+const DELIVERY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+const MAX_ACTOR_RESPONSE_BYTES: u64 = 64 * 1024;
+const MAX_DELIVERY_RESPONSE_BYTES: u64 = 4 * 1024;
+
+/// Transform context telling `transform` to keep only the status.
+const STATUS_ONLY: &[u8] = b"status";
+
+/// Nodes on the subnet, which HTTP outcall fees scale with.
+const SUBNET_SIZE: u128 = 13;
+
+/// Class byte of opaque principals, see the IC interface spec.
+const OPAQUE_ID_CLASS: u8 = 0x01;
+
+/// Actor fetches allowed per `FETCH_INTERVAL_NS`, to any one host and in
+/// total. Each is a paid outcall, and any inbox request can ask for one.
+const MAX_HOST_FETCHES: u32 = 10;
+const MAX_FETCHES: u32 = 60;
+const FETCH_INTERVAL_NS: u64 = 60 * 1_000_000_000;
+
+thread_local! {
+    /// Actor fetches made in the current interval. Kept on the heap, so an
+    /// upgrade starts a fresh interval.
+    static FETCH_BUDGET: RefCell<FetchBudget> = RefCell::new(FetchBudget::default());
+}
+
+/// Federates BlockVerse with Mastodon and other ActivityPub servers.
+///
+/// Every local user is published as a `Person` at `/u/{username}`, found
+/// through WebFinger, with an outbox of their public posts and follower
+/// counts. Remote accounts that send us activities are mirrored as users
+/// under a principal derived from their actor id (see `RemoteActor`), so
+/// a remote Follow is a `follow_user` by that user, with follow requests
+/// for private accounts, and a reply to a post is a `create_comment`.
+///
+/// Activities about posts are queued and delivered to remote followers by
+/// a timer, signed with a single instance key. Inbox requests arrive as
+/// update calls and are verified against their actor's published key,
+/// which takes an HTTP outcall the first time an actor is seen.
+///
+/// ActivityPub responses aren't certified, so remote servers have to
+/// reach the canister through a gateway that accepts that: its `raw`
+/// domain, or a custom domain set with `set_federation_domain`.
+pub struct FederationService;
+
+impl FederationService {
+    /// Starts delivering queued activities. The first run generates the
+    /// instance key. Like every timer, this runs from both `init` and
+    /// `post_upgrade`.
+    pub fn schedule() {
+        #[cfg(target_arch = "wasm32")]
+        {
+            ic_cdk_timers::set_timer(std::time::Duration::ZERO, || {
+                ic_cdk::spawn(async {
+                    Self::deliver_due().await;
+                })
+            });
+            ic_cdk_timers::set_timer_interval(DELIVERY_INTERVAL, || {
+                ic_cdk::spawn(async {
+                    Self::deliver_due().await;
+                })
+            });
+        }
+    }
+
+    /// Sets the domain actors are published under; an empty domain goes
+    /// back to the canister's `raw` domain. Remote servers know actors by
+    /// their domain, so changing it later breaks existing follows.
+    pub fn set_federation_domain(domain: String) -> Result<(), BlockVerseError> {
+        let domain = domain.trim().to_lowercase();
+        let is_valid = domain.len() <= 253
+            && domain.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':'));
+        if !is_valid {
+            return Err(BlockVerseError::validation("domain", "Must be a host name, optionally with a port"));
+        }
+
+        STATE.with(|state| {
+            state.borrow_mut().set_federation_domain(domain);
+            Ok(())
+        })
+    }
+
+    pub fn get_federation_domain() -> String {
+        STATE.with(|state| Self::domain(&state.borrow()))
+    }
+
+    /// The remote account `user_id` stands in for, if it is one.
+    pub fn get_remote_actor(user_id: Principal) -> Option<RemoteActor> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            state.remote_actors.get(&PrincipalKey(user_id))
+        })
+    }
+
+    /// Whether the request asks for ActivityPub JSON rather than a page.
+    pub fn wants_activity(request: &HttpRequest) -> bool {
+        request
+            .header("Accept")
+            .is_some_and(|accept| accept.contains(ACTIVITY_JSON) || accept.contains("application/ld+json"))
+    }
+
+    /// Resolves `acct:username@domain`, or an actor's URL, to the actor.
+    pub fn webfinger(request: &HttpRequest) -> HttpResponse {
+        let Some(resource) = request.query("resource") else {
+            return HttpResponse::new(400, TEXT, "Missing resource");
+        };
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            let (origin, domain) = (Self::origin(&state), Self::domain(&state));
+
+            let username = match resource.strip_prefix("acct:") {
+                Some(account) => account
+                    .trim_start_matches('@')
+                    .rsplit_once('@')
+                    .filter(|(_, host)| host.eq_ignore_ascii_case(&domain))
+                    .map(|(username, _)| username),
+                None => match Self::local_path(&origin, &resource).as_deref() {
+                    Some(["u", username]) => Some(*username),
+                    _ => None,
+                },
+            };
+            let Some(user) = username.and_then(|username| Self::local_user(&state, username)) else {
+                return HttpResponse::not_found();
+            };
+
+            let actor = Self::actor_url(&origin, &user.username);
+            let body = json!({
+                "subject": format!("acct:{}@{}", user.username, domain),
+                "aliases": [actor],
+                "links": [
+                    { "rel": "self", "type": ACTIVITY_JSON, "href": actor },
+                    { "rel": "http://webfinger.net/rel/profile-page", "type": "text/html", "href": actor },
+                ],
+            });
+            HttpResponse::new(200, JRD_JSON, body.to_string()).with_header("Access-Control-Allow-Origin", "*")
+        })
+    }
+
+    /// A local user as an ActivityPub `Person`.
+    pub fn actor(username: &str) -> HttpResponse {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let Some(user) = Self::local_user(&state, username) else {
+                return HttpResponse::not_found();
+            };
+            let Some(public_key_pem) = state.federation_key().and_then(|pem| signature::public_key_pem(&pem)) else {
+                return HttpResponse::new(503, TEXT, "Federation is starting up").with_header("Retry-After", 60);
+            };
+
+            let origin = Self::origin(&state);
+            let actor = Self::actor_url(&origin, &user.username);
+            let mut person = json!({
+                "@context": [ACTIVITY_STREAMS, SECURITY],
+                "id": actor,
+                "type": "Person",
+                "preferredUsername": user.username,
+                "name": user.username,
+                "summary": format!("<p>{}</p>", markup::escape(&user.bio)),
+                "url": actor,
+                "inbox": format!("{}/inbox", actor),
+                "outbox": format!("{}/outbox", actor),
+                "followers": format!("{}/followers", actor),
+                "following": format!("{}/following", actor),
+                "endpoints": { "sharedInbox": format!("{}/inbox", origin) },
+                "manuallyApprovesFollowers": user.is_private,
+                "discoverable": !user.is_private,
+                "published": dates::rfc3339(user.created_at),
+                "publicKey": {
+                    "id": Self::key_id(&actor),
+                    "owner": actor,
+                    "publicKeyPem": public_key_pem,
+                },
+            });
+            if let Some(avatar_url) = HttpService::avatar_url(&user) {
+                person["icon"] = json!({ "type": "Image", "url": avatar_url });
+            }
+            Self::activity_response(200, &person)
+        })
+    }
+
+    /// A user's posts as Create and Announce activities, newest first.
+    /// `?page=true` and `?cursor=` page through them as in
+    /// `get_user_posts`.
+    pub fn outbox(request: &HttpRequest, username: &str) -> HttpResponse {
+        let Some(user) = STATE.with(|state| Self::local_user(&state.borrow(), username)) else {
+            return HttpResponse::not_found();
+        };
+
+        let origin = STATE.with(|state| Self::origin(&state.borrow()));
+        let outbox = format!("{}/outbox", Self::actor_url(&origin, &user.username));
+        if request.query("page").is_none() {
+            return Self::activity_response(
+                200,
+                &json!({
+                    "@context": ACTIVITY_STREAMS,
+                    "id": outbox,
+                    "type": "OrderedCollection",
+                    "totalItems": user.posts_count,
+                    "first": format!("{}?page=true", outbox),
+                }),
+            );
+        }
+
+        let cursor = request.query("cursor");
+        let page = match PostService::get_user_posts(user.id, cursor.clone(), OUTBOX_PAGE) {
+            Ok(page) => page,
+            Err(BlockVerseError::Validation { reason, .. }) => return HttpResponse::new(400, TEXT, reason),
+            Err(_) => return HttpResponse::new(500, TEXT, "Could not load posts"),
+        };
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            let items: Vec<Value> = page
+                .items
+                .iter()
+                .filter(|post| !post.is_deleted())
+                .filter_map(|post| Self::post_activity(&state, &origin, &user, post))
+                .collect();
+
+            let page_url = |cursor: &Option<String>| match cursor {
+                Some(cursor) => format!("{}?page=true&cursor={}", outbox, cursor),
+                None => format!("{}?page=true", outbox),
+            };
+            let mut collection = json!({
+                "@context": ACTIVITY_STREAMS,
+                "id": page_url(&cursor),
+                "type": "OrderedCollectionPage",
+                "partOf": outbox,
+                "orderedItems": items,
+            });
+            if page.next_cursor.is_some() {
+                collection["next"] = json!(page_url(&page.next_cursor));
+            }
+            Self::activity_response(200, &collection)
+        })
+    }
+
+    /// A user's followers or following. Only the size is published.
+    pub fn collection(username: &str, name: &str) -> HttpResponse {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let Some(user) = Self::local_user(&state, username) else {
+                return HttpResponse::not_found();
+            };
+            let total = if name == "followers" { user.followers_count } else { user.following_count };
+            Self::activity_response(
+                200,
+                &json!({
+                    "@context": ACTIVITY_STREAMS,
+                    "id": format!("{}/{}", Self::actor_url(&Self::origin(&state), &user.username), name),
+                    "type": "OrderedCollection",
+                    "totalItems": total,
+                }),
+            )
+        })
+    }
+
+    /// A post as a `Note`, or a plain share as its `Announce`.
+    pub fn note(post_id: &str) -> HttpResponse {
+        let Some(post) = PostService::get_post(post_id.to_string()) else {
+            return HttpResponse::not_found();
+        };
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            let origin = Self::origin(&state);
+            if post.is_deleted() {
+                let tombstone = json!({
+                    "@context": ACTIVITY_STREAMS,
+                    "id": Self::post_url(&origin, &post.id),
+                    "type": "Tombstone",
+                });
+                return Self::activity_response(410, &tombstone);
+            }
+
+            let Some(author) = state.get_user(post.author).filter(|_| !state.is_remote(post.author)) else {
+                return HttpResponse::not_found();
+            };
+            let object = if Self::is_plain_share(&post) {
+                Self::post_activity(&state, &origin, &author, &post)
+            } else {
+                Self::note_object(&state, &origin, &author, &post)
+            };
+            match object {
+                Some(mut object) => {
+                    object["@context"] = json!(ACTIVITY_STREAMS);
+                    Self::activity_response(200, &object)
+                }
+                None => HttpResponse::not_found(),
+            }
+        })
+    }
+
+    /// Handles an activity posted to an inbox. The request must be signed
+    /// by the activity's actor.
+    pub async fn receive(request: HttpRequest) -> HttpResponse {
+        match Self::handle(&request).await {
+            Ok(()) => HttpResponse::new(202, TEXT, "Accepted"),
+            Err(response) => response,
+        }
+    }
+
+    /// Queues the activity publishing a new post or share for the author's
+    /// remote followers.
+    pub fn publish(state: &mut State, post: &Post) {
+        Self::send_about(state, post, |state, origin, author, post| {
+            if state.hidden_content.contains_key(&IdKey(post.id.clone())) {
+                return None;
+            }
+            Self::post_activity(state, origin, author, post)
+        });
+    }
+
+    /// Queues an `Update` for an edited post.
+    pub fn update(state: &mut State, post: &Post) {
+        Self::send_about(state, post, |state, origin, author, post| {
+            let note = Self::note_object(state, origin, author, post)?;
+            let actor = Self::actor_url(origin, &author.username);
+            Some(json!({
+                "@context": ACTIVITY_STREAMS,
+                "id": format!("{}#updates/{}", Self::post_url(origin, &post.id), post.updated_at),
+                "type": "Update",
+                "actor": actor,
+                "to": note["to"],
+                "cc": note["cc"],
+                "object": note,
+            }))
+        });
+    }
+
+    /// Queues the activity withdrawing a deleted post or share.
+    pub fn retract(state: &mut State, post: &Post) {
+        Self::send_about(state, post, |state, origin, author, post| {
+            let (to, cc) = Self::audience(state, origin, author, post)?;
+            let actor = Self::actor_url(origin, &author.username);
+            let url = Self::post_url(origin, &post.id);
+            let object = if Self::is_plain_share(post) {
+                json!({ "id": format!("{}/activity", url), "type": "Announce", "actor": actor })
+            } else {
+                json!({ "id": url, "type": "Tombstone" })
+            };
+            let kind = if Self::is_plain_share(post) { "Undo" } else { "Delete" };
+            Some(json!({
+                "@context": ACTIVITY_STREAMS,
+                "id": format!("{}#{}", url, kind.to_lowercase()),
+                "type": kind,
+                "actor": actor,
+                "to": to,
+                "cc": cc,
+                "object": object,
+            }))
+        });
+    }
+
+    /// Tells a remote follower their follow of `followed` took effect.
+    pub fn accept_follow(state: &mut State, follower: Principal, followed: Principal) {
+        if let Some(follow_id) = state.remote_follows.get(&(PrincipalKey(followed), PrincipalKey(follower))) {
+            Self::answer_follow(state, follower, followed, &follow_id.0, "Accept");
+        }
+    }
+
+    /// Tells a remote follower their follow of `followed` was refused or
+    /// has ended.
+    pub fn reject_follow(state: &mut State, follower: Principal, followed: Principal) {
+        if let Some(follow_id) = state.remote_follows.remove(&(PrincipalKey(followed), PrincipalKey(follower))) {
+            Self::answer_follow(state, follower, followed, &follow_id.0, "Reject");
+        }
+    }
+
+    /// Delivers one batch of due activities and returns how many were
+    /// accepted. Failed deliveries are retried with exponential backoff.
+    pub async fn deliver_due() -> u64 {
+        let Some(private_key) = STATE.with(|state| state.borrow().federation_key()) else {
+            Self::generate_key().await;
+            return 0;
+        };
+
+        let due: Vec<_> = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let now = clock::now();
+            let due: Vec<_> = state
+                .deliveries
+                .iter()
+                .take_while(|((due_at, _), _)| *due_at <= now)
+                .take(DELIVERY_BATCH)
+                .collect();
+            for (key, _) in &due {
+                state.deliveries.remove(key);
+            }
+            due
+        });
+
+        let mut delivered = 0;
+        for ((_, sequence), mut delivery) in due {
+            match Self::deliver(&private_key, &delivery).await {
+                Ok(()) => delivered += 1,
+                Err(retry) => {
+                    delivery.attempts += 1;
+                    if retry && delivery.attempts < MAX_DELIVERY_ATTEMPTS {
+                        let due_at = clock::now() + (RETRY_BASE_NS << (delivery.attempts - 1));
+                        STATE.with(|state| state.borrow_mut().deliveries.insert((due_at, sequence), delivery));
+                    }
+                }
+            }
+        }
+        delivered
+    }
+
+    /// Strips what differs between replicas from an outcall response, so
+    /// they can agree on it. Delivery responses keep only their status.
+    pub fn transform(args: TransformArgs) -> OutcallResponse {
+        let mut response = args.response;
+        response.headers.clear();
+        if args.context == STATUS_ONLY {
+            response.body.clear();
+        }
+        response
+    }
+
+    async fn handle(request: &HttpRequest) -> Result<(), HttpResponse> {
+        if request.body.len() > MAX_INBOX_BODY {
+            return Err(HttpResponse::new(413, TEXT, "Activity too large"));
+        }
+        let activity: Value =
+            serde_json::from_slice(&request.body).map_err(|_| HttpResponse::new(400, TEXT, "Invalid JSON"))?;
+        let Some(actor_uri) = Self::id_of(&activity["actor"]) else {
+            return Err(HttpResponse::new(400, TEXT, "Missing actor"));
+        };
+        let kind = activity["type"].as_str().unwrap_or_default();
+
+        // Deleted accounts can no longer be fetched to check their
+        // signature; if we never knew them there is nothing to undo
+        let is_known = STATE.with(|state| state.borrow().is_remote(Self::remote_principal(actor_uri)));
+        if kind == "Delete" && Self::id_of(&activity["object"]) == Some(actor_uri) && !is_known {
+            return Ok(());
+        }
+
+        let remote = Self::verify(request, actor_uri).await?;
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            match kind {
+                "Follow" => Self::on_follow(&mut state, &remote, &activity),
+                "Undo" => Self::on_undo(&mut state, &remote, &activity["object"]),
+                "Create" => Self::on_create(&mut state, &remote, &activity["object"]),
+                "Delete" => Self::on_delete(&mut state, &remote, &activity["object"]),
+                _ => Ok(()),
+            }
+        })
+        .map_err(Self::rejected)
+    }
+
+    /// Checks the request's HTTP signature against the key `actor_uri`
+    /// publishes and returns the actor. The actor is fetched if it is new
+    /// or signed with a key we don't have.
+    async fn verify(request: &HttpRequest, actor_uri: &str) -> Result<RemoteActor, HttpResponse> {
+        let unauthorized = |reason: &str| HttpResponse::new(401, TEXT, reason.to_string());
+
+        let signature = request
+            .header("Signature")
+            .and_then(Signature::parse)
+            .ok_or_else(|| unauthorized("Missing or malformed signature"))?;
+        for header in ["(request-target)", "host", "date", "digest"] {
+            if !signature.covers(header) {
+                return Err(unauthorized(&format!("The signature must cover {}", header)));
+            }
+        }
+        // A key served from another host can't belong to the actor, so
+        // there is no point fetching the actor to find out
+        if !Self::same_host(&signature.key_id, actor_uri) {
+            return Err(unauthorized("The signing key is not the actor's"));
+        }
+
+        let date = request
+            .header("Date")
+            .and_then(dates::parse_http_date)
+            .ok_or_else(|| unauthorized("Missing date"))?;
+        if clock::now().abs_diff(date) > MAX_CLOCK_SKEW_NS {
+            return Err(unauthorized("Date out of range"));
+        }
+
+        let digest = signature::digest(&request.body);
+        let digests = request.header("Digest").unwrap_or_default();
+        if !digests.split(',').any(|candidate| candidate.trim() == digest) {
+            return Err(unauthorized("Digest mismatch"));
+        }
+
+        let target = format!("{} {}", request.method.to_lowercase(), request.url);
+        let signing_string = signature::signing_string(&signature.headers, |name| match name {
+            "(request-target)" => Some(target.as_str()),
+            _ => request.header(name),
+        })
+        .ok_or_else(|| unauthorized("A signed header is missing"))?;
+
+        let cached = STATE.with(|state| {
+            let state = state.borrow();
+            state.remote_actors.get(&PrincipalKey(Self::remote_principal(actor_uri)))
+        });
+        if let Some(remote) = cached.filter(|remote| remote.key_id == signature.key_id) {
+            if signature::verify(&remote.public_key_pem, &signing_string, &signature.signature) {
+                return Ok(remote);
+            }
+        }
+
+        let host = Self::split_url(actor_uri).map(|(host, _)| host.to_ascii_lowercase()).unwrap_or_default();
+        if !FETCH_BUDGET.with(|budget| budget.borrow_mut().take(&host, clock::now())) {
+            return Err(HttpResponse::new(429, TEXT, "Too many new actors, try again later".to_string()));
+        }
+        let fetched = Self::fetch_actor(actor_uri).await.map_err(|reason| unauthorized(&reason))?;
+        if fetched.actor.key_id != signature.key_id
+            || !signature::verify(&fetched.actor.public_key_pem, &signing_string, &signature.signature)
+        {
+            return Err(unauthorized("Invalid signature"));
+        }
+        Ok(STATE.with(|state| Self::mirror(&mut state.borrow_mut(), fetched)))
+    }
+
+    /// A Follow of a local user: follows them as `follow_user` would and
+    /// answers once the follow takes effect.
+    fn on_follow(state: &mut State, remote: &RemoteActor, activity: &Value) -> Result<(), BlockVerseError> {
+        let object = Self::id_of(&activity["object"]).unwrap_or_default();
+        let Some(user) = Self::local_actor(state, object) else {
+            return Err(BlockVerseError::not_found(EntityKind::User, object));
+        };
+
+        // Accept and Reject refer back to the Follow by its id
+        let follow_id = Self::id_of(&activity["id"]).filter(|id| id.len() <= MAX_ID_LEN as usize).unwrap_or_default();
+        state.remote_follows.insert((PrincipalKey(user.id), PrincipalKey(remote.user_id)), IdKey::from(follow_id));
+
+        match UserService::follow(state, remote.user_id, user.id) {
+            Ok(true) => Self::accept_follow(state, remote.user_id, user.id),
+            Ok(false) => {}
+            Err(_) => Self::reject_follow(state, remote.user_id, user.id),
+        }
+        Ok(())
+    }
+
+    /// Undoing a Follow unfollows, or withdraws the follow request.
+    fn on_undo(state: &mut State, remote: &RemoteActor, object: &Value) -> Result<(), BlockVerseError> {
+        if object["type"] != "Follow" {
+            return Ok(());
+        }
+        if Self::id_of(&object["actor"]) != Some(remote.uri.as_str()) {
+            return Err(BlockVerseError::Unauthorized("Cannot undo another actor's follow".to_string()));
+        }
+        let Some(user) = Self::id_of(&object["object"]).and_then(|uri| Self::local_actor(state, uri)) else {
+            return Ok(());
+        };
+
+        state.remote_follows.remove(&(PrincipalKey(user.id), PrincipalKey(remote.user_id)));
+        UserService::unfollow(state, remote.user_id, user.id);
+        Ok(())
+    }
+
+    /// A reply to a local post, or to a reply received earlier, becomes a
+    /// comment as `create_comment` would make it. Other notes are ignored.
+    fn on_create(state: &mut State, remote: &RemoteActor, object: &Value) -> Result<(), BlockVerseError> {
+        if object["type"] != "Note" {
+            return Ok(());
+        }
+        let (Some(note_id), Some(in_reply_to)) = (Self::id_of(&object["id"]), Self::id_of(&object["inReplyTo"])) else {
+            return Ok(());
+        };
+        if Self::id_of(&object["attributedTo"]) != Some(remote.uri.as_str()) {
+            return Err(BlockVerseError::Unauthorized("Cannot create another actor's note".to_string()));
+        }
+
+        // Servers retry deliveries, so the same note can arrive twice
+        let key = Self::object_key(note_id);
+        if state.remote_objects.contains_key(&key) {
+            return Ok(());
+        }
+
+        let origin = Self::origin(state);
+        let (post_id, parent_comment_id) = match Self::local_path(&origin, in_reply_to).as_deref() {
+            Some(["post", post_id]) => (post_id.to_string(), None),
+            _ => match state
+                .remote_objects
+                .get(&Self::object_key(in_reply_to))
+                .and_then(|comment_id| state.get_live_comment(&comment_id.0))
+            {
+                Some(parent) => (parent.post_id, Some(parent.id)),
+                None => return Ok(()),
+            },
+        };
+
+        let content = markup::to_text(object["content"].as_str().unwrap_or_default());
+        let content = CommentService::validate_content(&content)?;
+        let comment = CommentService::add_comment(state, remote.user_id, post_id, content, parent_comment_id)?;
+        state.remote_objects.insert(key, IdKey(comment.id));
+        Ok(())
+    }
+
+    /// Deleting a note deletes the comment it became; deleting the actor
+    /// ends its follows.
+    fn on_delete(state: &mut State, remote: &RemoteActor, object: &Value) -> Result<(), BlockVerseError> {
+        let Some(id) = Self::id_of(object) else {
+            return Ok(());
+        };
+
+        if id == remote.uri {
+            let follower = PrincipalKey(remote.user_id);
+            let requested: Vec<Principal> = state
+                .sent_follow_requests
+                .range((follower, PrincipalKey::default())..)
+                .take_while(|((requester, _), _)| *requester == follower)
+                .map(|((_, user_id), _)| user_id.0)
+                .collect();
+            for followed in state.following(remote.user_id).into_iter().chain(requested) {
+                state.remote_follows.remove(&(PrincipalKey(followed), follower));
+                UserService::unfollow(state, remote.user_id, followed);
+            }
+            return Ok(());
+        }
+
+        if let Some(comment_id) = state.remote_objects.remove(&Self::object_key(id)) {
+            if let Some(comment) = state.get_live_comment(&comment_id.0).filter(|comment| comment.author == remote.user_id) {
+                CommentService::delete(state, &comment);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fetches an actor's document and checks it describes `uri`.
+    async fn fetch_actor(uri: &str) -> Result<FetchedActor, String> {
+        if !Self::is_valid_uri(uri) {
+            return Err("Unsupported actor id".to_string());
+        }
+
+        let response = Self::outcall(CanisterHttpRequestArgument {
+            url: uri.to_string(),
+            max_response_bytes: Some(MAX_ACTOR_RESPONSE_BYTES),
+            method: HttpMethod::GET,
+            headers: vec![HttpHeader { name: "Accept".to_string(), value: ACTIVITY_JSON.to_string() }],
+            body: None,
+            transform: Some(TransformContext::from_name("transform_outcall".to_string(), Vec::new())),
+        })
+        .await?;
+        let status = Self::status(&response);
+        if !(200..300).contains(&status) {
+            return Err(format!("Fetching the actor failed with status {}", status));
+        }
+
+        let document: Value =
+            serde_json::from_slice(&response.body).map_err(|_| "Invalid actor document".to_string())?;
+        if document["id"].as_str() != Some(uri) {
+            return Err("The actor document is for another actor".to_string());
+        }
+        let key = &document["publicKey"];
+        if Self::id_of(&key["owner"]).is_some_and(|owner| owner != uri) {
+            return Err("The actor's key belongs to another actor".to_string());
+        }
+
+        let uri_field = |value: &Value| value.as_str().filter(|uri| Self::is_valid_uri(uri)).map(str::to_string);
+        let (Some(inbox), Some(key_id)) = (uri_field(&document["inbox"]), uri_field(&key["id"])) else {
+            return Err("The actor has no inbox or key".to_string());
+        };
+        let public_key_pem = key["publicKeyPem"]
+            .as_str()
+            .filter(|pem| pem.len() <= federation::MAX_KEY_PEM_LEN && signature::is_public_key(pem))
+            .ok_or_else(|| "The actor's key is not a supported public key".to_string())?;
+
+        // The host becomes part of a username, which has to fit a key
+        let host = Self::split_url(uri).map(|(host, _)| host).unwrap_or_default();
+        if host.is_empty() || host.len() > MAX_HOST_LEN || !host.is_ascii() {
+            return Err("Unsupported actor host".to_string());
+        }
+        let name: String = document["preferredUsername"]
+            .as_str()
+            .unwrap_or("unknown")
+            .chars()
+            .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+            .take(30)
+            .collect();
+        let bio = markup::truncate(
+            &markup::to_text(document["summary"].as_str().unwrap_or_default()),
+            REMOTE_BIO_CHARS,
+        );
+        let avatar_url = Self::id_of(&document["icon"]["url"])
+            .or_else(|| document["icon"]["url"]["href"].as_str())
+            .filter(|url| url.starts_with("https://") && validation::is_valid_url(url))
+            .unwrap_or_default();
+
+        Ok(FetchedActor {
+            actor: RemoteActor {
+                user_id: Self::remote_principal(uri),
+                uri: uri.to_string(),
+                inbox,
+                shared_inbox: uri_field(&document["endpoints"]["sharedInbox"]),
+                key_id,
+                public_key_pem: public_key_pem.to_string(),
+                fetched_at: clock::now(),
+            },
+            handle: format!("{}@{}", name, host),
+            bio,
+            avatar_url: avatar_url.to_string(),
+        })
+    }
+
+    /// Stores a fetched actor and creates or refreshes the user standing
+    /// in for it.
+    fn mirror(state: &mut State, fetched: FetchedActor) -> RemoteActor {
+        let FetchedActor { actor, handle, bio, avatar_url } = fetched;
+        let username = Self::unique_handle(state, actor.user_id, &handle);
+        let user = match state.get_user(actor.user_id) {
+            Some(mut user) => {
+                SearchService::unindex_user(state, &user);
+                if user.username != username {
+                    state.usernames.remove(&IdKey(user.username.to_lowercase()));
+                    user.username = username;
+                }
+                user.update(bio, avatar_url);
+                user
+            }
+            None => User::new(actor.user_id, username, bio, avatar_url),
+        };
+        state.insert_user(user.clone());
+        SearchService::index_user(state, &user);
+        state.remote_actors.insert(PrincipalKey(actor.user_id), actor.clone());
+        actor
+    }
+
+    /// `handle`, or if another user already goes by it, the first of
+    /// `name-2@host`, `name-3@host`, ... that is still free. Local usernames
+    /// can't contain `@`, so only remote actors ever collide.
+    fn unique_handle(state: &State, user_id: Principal, handle: &str) -> String {
+        let (name, host) = handle.split_once('@').unwrap_or((handle, ""));
+        let mut username = handle.to_string();
+        let mut suffix = 1;
+        while state.find_username(&username).is_some_and(|owner| owner != user_id) {
+            suffix += 1;
+            username = format!("{}-{}@{}", name, suffix, host);
+        }
+        username
+    }
+
+    /// Sends one delivery. Fails with whether it is worth retrying.
+    async fn deliver(private_key: &str, delivery: &Delivery) -> Result<(), bool> {
+        let (host, path) = Self::split_url(&delivery.inbox).ok_or(false)?;
+        let body = delivery.activity.as_bytes().to_vec();
+
+        let headers = [
+            ("(request-target)", format!("post {}", path)),
+            ("host", host.to_string()),
+            ("date", dates::http_date(clock::now())),
+            ("digest", signature::digest(&body)),
+            ("content-type", ACTIVITY_JSON.to_string()),
+        ];
+        let names: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+        let signing_string = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let signature = signature::sign(private_key, &delivery.key_id, &names, &signing_string).ok_or(false)?;
+
+        // The outcall fills in the host from the URL
+        let mut request_headers: Vec<HttpHeader> = headers[2..]
+            .iter()
+            .map(|(name, value)| HttpHeader { name: name.to_string(), value: value.clone() })
+            .collect();
+        request_headers.push(HttpHeader { name: "signature".to_string(), value: signature });
+        request_headers.push(HttpHeader { name: "user-agent".to_string(), value: "BlockVerse".to_string() });
+
+        let response = Self::outcall(CanisterHttpRequestArgument {
+            url: delivery.inbox.clone(),
+            max_response_bytes: Some(MAX_DELIVERY_RESPONSE_BYTES),
+            method: HttpMethod::POST,
+            headers: request_headers,
+            body: Some(body),
+            transform: Some(TransformContext::from_name("transform_outcall".to_string(), STATUS_ONLY.to_vec())),
+        })
+        .await
+        .map_err(|_| true)?;
+
+        match Self::status(&response) {
+            200..=299 => Ok(()),
+            // The server won't take it, however often we ask
+            status @ 400..=499 => Err(status == 408 || status == 429),
+            _ => Err(true),
+        }
+    }
+
+    async fn outcall(request: CanisterHttpRequestArgument) -> Result<OutcallResponse, String> {
+        let cycles = Self::outcall_cycles(&request);
+        outcall::http_request(request, cycles)
+            .await
+            .map(|(response,)| response)
+            .map_err(|(_, message)| message)
+    }
+
+    /// What an outcall costs, following the published fee schedule.
+    fn outcall_cycles(request: &CanisterHttpRequestArgument) -> u128 {
+        let request_bytes = request.url.len()
+            + request.headers.iter().map(|header| header.name.len() + header.value.len()).sum::<usize>()
+            + request.body.as_ref().map_or(0, Vec::len);
+        let response_bytes = request.max_response_bytes.unwrap_or(2 * 1024 * 1024);
+        (3_000_000 + 60_000 * SUBNET_SIZE) * SUBNET_SIZE
+            + 400 * SUBNET_SIZE * request_bytes as u128
+            + 800 * SUBNET_SIZE * u128::from(response_bytes)
+    }
+
+    fn status(response: &OutcallResponse) -> u64 {
+        u64::try_from(&response.status.0).unwrap_or_default()
+    }
+
+    /// Generates the instance key from the management canister's
+    /// randomness, unless another run got there first.
+    async fn generate_key() {
+        let Ok((seed,)) = ic_cdk::api::management_canister::main::raw_rand().await else {
+            return;
+        };
+        let Some(pem) = <[u8; 32]>::try_from(seed).ok().and_then(signature::generate_key) else {
+            return;
+        };
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if state.federation_key().is_none() {
+                state.set_federation_key(pem);
+            }
+        });
+    }
+
+    /// Queues the activity `build` makes about `post` for the remote
+    /// followers of its author.
+    fn send_about(
+        state: &mut State,
+        post: &Post,
+        build: impl FnOnce(&State, &str, &User, &Post) -> Option<Value>,
+    ) {
+        if state.is_remote(post.author) {
+            return;
+        }
+        let inboxes = Self::follower_inboxes(state, post.author);
+        let Some(author) = state.get_user(post.author).filter(|_| !inboxes.is_empty()) else {
+            return;
+        };
+        let origin = Self::origin(state);
+        if let Some(activity) = build(state, &origin, &author, post) {
+            let key_id = Self::key_id(&Self::actor_url(&origin, &author.username));
+            Self::enqueue(state, &key_id, &activity, inboxes);
+        }
+    }
+
+    fn answer_follow(state: &mut State, follower: Principal, followed: Principal, follow_id: &str, kind: &str) {
+        let (Some(remote), Some(user)) = (state.remote_actors.get(&PrincipalKey(follower)), state.get_user(followed))
+        else {
+            return;
+        };
+
+        let actor = Self::actor_url(&Self::origin(state), &user.username);
+        let mut follow = json!({ "type": "Follow", "actor": remote.uri, "object": actor });
+        if !follow_id.is_empty() {
+            follow["id"] = json!(follow_id);
+        }
+        let sequence = state.next_sequence();
+        let activity = json!({
+            "@context": ACTIVITY_STREAMS,
+            "id": format!("{}#{}s/{:x}", actor, kind.to_lowercase(), sequence),
+            "type": kind,
+            "actor": actor,
+            "object": follow,
+        });
+        Self::enqueue(state, &Self::key_id(&actor), &activity, vec![remote.inbox]);
+    }
+
+    fn enqueue(state: &mut State, key_id: &str, activity: &Value, inboxes: Vec<String>) {
+        let activity = activity.to_string();
+        if activity.len() > federation::MAX_ACTIVITY_LEN {
+            return;
+        }
+        let now = clock::now();
+        for inbox in inboxes {
+            let sequence = state.next_sequence();
+            let delivery = Delivery { inbox, key_id: key_id.to_string(), activity: activity.clone(), attempts: 0 };
+            state.deliveries.insert((now, sequence), delivery);
+        }
+    }
+
+    /// The inboxes reaching every remote follower of `user_id`, one per
+    /// server where servers share one.
+    fn follower_inboxes(state: &State, user_id: Principal) -> Vec<String> {
+        let mut inboxes: Vec<String> = state
+            .followers(user_id)
+            .into_iter()
+            .filter_map(|follower| state.remote_actors.get(&PrincipalKey(follower)))
+            .map(|remote| remote.delivery_inbox().to_string())
+            .collect();
+        inboxes.sort();
+        inboxes.dedup();
+        inboxes
+    }
+
+    /// A post as the activity publishing it: a plain share is an
+    /// `Announce` of what it shares, anything else a `Create` of its note.
+    fn post_activity(state: &State, origin: &str, author: &User, post: &Post) -> Option<Value> {
+        let (to, cc) = Self::audience(state, origin, author, post)?;
+        let actor = Self::actor_url(origin, &author.username);
+        let url = Self::post_url(origin, &post.id);
+
+        if Self::is_plain_share(post) {
+            let original = post.original_post_id.as_deref().and_then(|id| state.get_live_post(id))?;
+            return Some(json!({
+                "@context": ACTIVITY_STREAMS,
+                "id": format!("{}/activity", url),
+                "type": "Announce",
+                "actor": actor,
+                "published": dates::rfc3339(post.created_at),
+                "to": to,
+                "cc": cc,
+                "object": Self::post_url(origin, &original.id),
+            }));
+        }
+
+        let note = Self::note_object(state, origin, author, post)?;
+        Some(json!({
+            "@context": ACTIVITY_STREAMS,
+            "id": format!("{}/activity", url),
+            "type": "Create",
+            "actor": actor,
+            "published": note["published"],
+            "to": to,
+            "cc": cc,
+            "object": note,
+        }))
+    }
+
+    /// A post as a `Note`. A share with a comment becomes a note quoting
+    /// the shared post.
+    fn note_object(state: &State, origin: &str, author: &User, post: &Post) -> Option<Value> {
+        let (to, cc) = Self::audience(state, origin, author, post)?;
+        let url = Self::post_url(origin, &post.id);
+
+        let text = if post.is_shared { post.share_comment.as_deref().unwrap_or_default() } else { &post.content };
+        let mut content = format!("<p>{}</p>", markup::escape(text).replace('\n', "<br>"));
+        let quoted = post.original_post_id.as_deref().filter(|_| post.is_shared).map(|id| Self::post_url(origin, id));
+        if let Some(quoted) = &quoted {
+            content.push_str(&format!(r#"<p>RE: <a href="{0}">{0}</a></p>"#, markup::escape(quoted)));
+        }
+
+        let mut attachments = Vec::new();
+        if let Some(asset) = post.media_asset_id.as_deref().and_then(MediaService::public_asset) {
+            attachments.push(json!({
+                "type": "Document",
+                "mediaType": asset.mime_type,
+                "url": format!("{}{}", origin, asset.path()),
+            }));
+        } else if let Some(media_url) = post.media_url.as_deref().filter(|url| url.starts_with("https://")) {
+            attachments.push(json!({ "type": "Document", "url": media_url }));
+        }
+        let tags: Vec<Value> = post
+            .hashtags
+            .iter()
+            .map(|tag| json!({ "type": "Hashtag", "name": format!("#{}", tag) }))
+            .collect();
+
+        let mut note = json!({
+            "id": url,
+            "type": "Note",
+            "attributedTo": Self::actor_url(origin, &author.username),
+            "content": content,
+            "published": dates::rfc3339(post.created_at),
+            "url": url,
+            "to": to,
+            "cc": cc,
+            "sensitive": false,
+            "attachment": attachments,
+            "tag": tags,
+        });
+        if let Some(edited_at) = post.edited_at {
+            note["updated"] = json!(dates::rfc3339(edited_at));
+        }
+        if let Some(quoted) = quoted {
+            note["quoteUrl"] = json!(quoted);
+        }
+        Some(note)
+    }
+
+    /// Who activities about `post` are addressed to, as `(to, cc)`. Posts
+    /// only for the users they mention aren't federated, and a private
+    /// account's posts only go to its followers.
+    fn audience(state: &State, origin: &str, author: &User, post: &Post) -> Option<(Vec<String>, Vec<String>)> {
+        let followers = format!("{}/followers", Self::actor_url(origin, &author.username));
+        let is_private = state.get_user(author.id).is_some_and(|user| user.is_private);
+        match (post.visibility, is_private) {
+            (Visibility::MentionedOnly, _) => None,
+            (Visibility::FollowersOnly, _) | (_, true) => Some((vec![followers], Vec::new())),
+            (Visibility::Public, false) => Some((vec![PUBLIC.to_string()], vec![followers])),
+            (Visibility::Unlisted, false) => Some((vec![followers], vec![PUBLIC.to_string()])),
+        }
+    }
+
+    fn is_plain_share(post: &Post) -> bool {
+        post.is_shared && post.share_comment.is_none()
+    }
+
+    fn activity_response(status_code: u16, value: &Value) -> HttpResponse {
+        HttpResponse::new(status_code, &format!("{}; charset=utf-8", ACTIVITY_JSON), value.to_string())
+            .with_header("Vary", "Accept")
+            .with_header("Access-Control-Allow-Origin", "*")
+    }
+
+    /// An inbox response for an activity BlockVerse refused.
+    fn rejected(error: BlockVerseError) -> HttpResponse {
+        let status_code = match error {
+            BlockVerseError::NotFound { .. } => 404,
+            BlockVerseError::Validation { .. } => 422,
+            BlockVerseError::Unauthorized(_)
+            | BlockVerseError::Suspended { .. }
+            | BlockVerseError::InvalidOperation(_) => 403,
+            _ => 400,
+        };
+        HttpResponse::new(status_code, JSON, serde_json::to_vec(&error).unwrap_or_default())
+    }
+
+    /// The id of an object given either by reference or embedded.
+    fn id_of(value: &Value) -> Option<&str> {
+        value.as_str().or_else(|| value["id"].as_str())
+    }
+
+    /// The local user `username` names. Remote accounts have no actor
+    /// here.
+    fn local_user(state: &State, username: &str) -> Option<User> {
+        state
+            .find_username(username)
+            .filter(|user_id| !state.is_remote(*user_id))
+            .and_then(|user_id| state.get_user(user_id))
+    }
+
+    /// The local user whose actor is `uri`.
+    fn local_actor(state: &State, uri: &str) -> Option<User> {
+        match Self::local_path(&Self::origin(state), uri).as_deref() {
+            Some(["u", username]) => Self::local_user(state, username),
+            _ => None,
+        }
+    }
+
+    /// The path segments of `uri` if it is one of ours.
+    fn local_path<'a>(origin: &str, uri: &'a str) -> Option<Vec<&'a str>> {
+        let path = uri.strip_prefix(origin)?.strip_prefix('/')?;
+        Some(path.split(['?', '#']).next().unwrap_or_default().split('/').collect())
+    }
+
+    /// The principal of the user standing in for actor `uri`: an opaque id
+    /// derived from it, which nobody holds a key for.
+    fn remote_principal(uri: &str) -> Principal {
+        let mut bytes = Sha224::digest(uri.as_bytes()).to_vec();
+        bytes.push(OPAQUE_ID_CLASS);
+        Principal::from_slice(&bytes)
+    }
+
+    /// Remote object ids are too long for keys, so they are hashed.
+    fn object_key(id: &str) -> IdKey {
+        IdKey(crypto::hash_chunks([id]))
+    }
+
+    fn is_valid_uri(uri: &str) -> bool {
+        uri.len() <= federation::MAX_URI_LEN && (uri.starts_with("https://") || uri.starts_with("http://"))
+    }
+
+    /// The host and path of a URL, without any fragment.
+    fn split_url(url: &str) -> Option<(&str, &str)> {
+        let rest = url.strip_prefix("https://").or_else(|| url.strip_prefix("http://"))?;
+        let rest = rest.split('#').next().unwrap_or_default();
+        match rest.find('/') {
+            Some(slash) => Some((&rest[..slash], &rest[slash..])),
+            None => Some((rest, "/")),
+        }
+    }
+
+    fn domain(state: &State) -> String {
+        state.federation_domain().unwrap_or_else(|| format!("{}.raw.icp0.io", ic_cdk::id()))
+    }
+
+    /// Where actors live. Local development domains are served over plain
+    /// HTTP.
+    fn origin(state: &State) -> String {
+        let domain = Self::domain(state);
+        let host = domain.split(':').next().unwrap_or_default();
+        let scheme = if host == "localhost" || host.ends_with(".localhost") { "http" } else { "https" };
+        format!("{}://{}", scheme, domain)
+    }
+
+    fn actor_url(origin: &str, username: &str) -> String {
+        format!("{}/u/{}", origin, username)
+    }
+
+    fn post_url(origin: &str, post_id: &str) -> String {
+        format!("{}/post/{}", origin, post_id)
+    }
+
+    /// Whether both URLs are on the same host and port.
+    fn same_host(a: &str, b: &str) -> bool {
+        match (Self::split_url(a), Self::split_url(b)) {
+            (Some((a, _)), Some((b, _))) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        }
+    }
+
+    /// Every actor signs with the instance key, under its own key id.
+    fn key_id(actor: &str) -> String {
+        format!("{}#main-key", actor)
+    }
+}
+
+/// Actor fetches made in one interval, see `MAX_FETCHES`.
+#[derive(Default)]
+struct FetchBudget {
+    interval: u64,
+    total: u32,
+    hosts: HashMap<String, u32>,
+}
+
+impl FetchBudget {
+    /// Counts a fetch from `host` at `now`, unless it would go over either
+    /// budget.
+    fn take(&mut self, host: &str, now: u64) -> bool {
+        let interval = now / FETCH_INTERVAL_NS;
+        if interval != self.interval {
+            *self = Self { interval, ..Self::default() };
+        }
+
+        let host_fetches = self.hosts.get(host).copied().unwrap_or(0);
+        if self.total >= MAX_FETCHES || host_fetches >= MAX_HOST_FETCHES {
+            return false;
+        }
+        self.total += 1;
+        self.hosts.insert(host.to_string(), host_fetches + 1);
+        true
+    }
+}
+
+/// A remote actor's document, before it is stored.
+struct FetchedActor {
+    actor: RemoteActor,
+    /// `name@host`, which becomes the username of the standing-in user
+    handle: String,
+    bio: String,
+    avatar_url: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetched(uri: &str, handle: &str, bio: &str) -> FetchedActor {
+        FetchedActor {
+            actor: RemoteActor {
+                user_id: FederationService::remote_principal(uri),
+                uri: uri.to_string(),
+                inbox: format!("{}/inbox", uri),
+                shared_inbox: None,
+                key_id: format!("{}#main-key", uri),
+                public_key_pem: String::new(),
+                fetched_at: 0,
+            },
+            handle: handle.to_string(),
+            bio: bio.to_string(),
+            avatar_url: String::new(),
+        }
+    }
+
+    fn is_indexed(state: &State, term: &str, user_id: Principal) -> bool {
+        state.user_terms.contains_key(&(IdKey::from(term), PrincipalKey(user_id)))
+    }
+
+    #[test]
+    fn mirror_disambiguates_taken_handles() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let first = FederationService::mirror(state, fetched("https://a.example/users/1", "sam@a.example", ""));
+            let second = FederationService::mirror(state, fetched("https://a.example/people/1", "Sam@a.example", ""));
+
+            assert_eq!(state.find_username("sam@a.example"), Some(first.user_id));
+            assert_eq!(state.find_username("Sam-2@a.example"), Some(second.user_id));
+            assert_eq!(state.get_user(second.user_id).unwrap().username, "Sam-2@a.example");
+
+            // Refetching keeps each actor's own handle
+            FederationService::mirror(state, fetched("https://a.example/people/1", "Sam@a.example", ""));
+            assert_eq!(state.get_user(second.user_id).unwrap().username, "Sam-2@a.example");
+            assert_eq!(state.find_username("sam@a.example"), Some(first.user_id));
+        });
+    }
+
+    #[test]
+    fn mirror_reindexes_profiles() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let uri = "https://a.example/users/1";
+            let actor = FederationService::mirror(state, fetched(uri, "sam@a.example", "gardening"));
+            assert!(is_indexed(state, "gardening", actor.user_id));
+
+            FederationService::mirror(state, fetched(uri, "samuel@a.example", "cycling"));
+            assert!(is_indexed(state, "cycling", actor.user_id));
+            assert!(!is_indexed(state, "gardening", actor.user_id));
+            assert_eq!(state.find_username("sam@a.example"), None);
+            assert_eq!(state.find_username("samuel@a.example"), Some(actor.user_id));
+        });
+    }
+
+    #[test]
+    fn keys_must_be_served_from_the_actors_host() {
+        let actor = "https://a.example/users/1";
+        assert!(FederationService::same_host("https://A.example/users/1#main-key", actor));
+        assert!(FederationService::same_host("https://a.example/keys/1", actor));
+        assert!(!FederationService::same_host("https://b.example/users/1#main-key", actor));
+        assert!(!FederationService::same_host("https://a.example:8443/users/1#main-key", actor));
+        assert!(!FederationService::same_host("main-key", actor));
+    }
+
+    #[test]
+    fn actor_fetches_are_limited_per_host_and_in_total() {
+        let mut budget = FetchBudget::default();
+        let now = 5 * FETCH_INTERVAL_NS;
+        for _ in 0..MAX_HOST_FETCHES {
+            assert!(budget.take("a.example", now));
+        }
+        assert!(!budget.take("a.example", now));
+
+        let hosts = (0..).map(|n| format!("host{}.example", n));
+        let allowed = hosts.take(MAX_FETCHES as usize).filter(|host| budget.take(host, now)).count();
+        assert_eq!(allowed as u32, MAX_FETCHES - MAX_HOST_FETCHES);
+
+        // Both budgets refill in the next interval
+        assert!(budget.take("a.example", now + FETCH_INTERVAL_NS));
+        assert!(budget.take("b.example", now + FETCH_INTERVAL_NS));
+    }
+}
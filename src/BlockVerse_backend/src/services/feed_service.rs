@@ -0,0 +1,422 @@
+use crate::error::BlockVerseError;
+use crate::models::http::{HttpRequest, HttpResponse};
+use crate::models::page::Page;
+use crate::models::post::Post;
+use crate::models::user::User;
+use crate::services::http_service::HttpService;
+use crate::services::media_service::MediaService;
+use crate::services::post_service::PostService;
+use crate::services::tag_service::TagService;
+use crate::services::user_service::UserService;
+use crate::utils::dates::{rfc2822, rfc3339};
+use crate::utils::markup;
+
+/// Posts included in a feed.
+const FEED_ENTRIES: usize = 20;
+
+/// Longest excerpt used as an entry title.
+const TITLE_CHARS: usize = 80;
+
+/// Syndication formats, picked with `?format=`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Atom,
+    Rss,
+    Json,
+}
+
+impl FeedFormat {
+    pub const ALL: [FeedFormat; 3] = [Self::Atom, Self::Rss, Self::Json];
+
+    /// Atom unless `format` names another one.
+    fn parse(format: Option<&str>) -> Self {
+        match format {
+            Some("rss") => Self::Rss,
+            Some("json") => Self::Json,
+            _ => Self::Atom,
+        }
+    }
+
+    fn key(self) -> &'static str {
+        match self {
+            Self::Atom => "atom",
+            Self::Rss => "rss",
+            Self::Json => "json",
+        }
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Self::Atom => "application/atom+xml",
+            Self::Rss => "application/rss+xml",
+            Self::Json => "application/feed+json",
+        }
+    }
+}
+
+/// Atom, RSS 2.0 and JSON Feed documents of a user's posts or a hashtag,
+/// served at `/u/{username}/feed` and `/tags/{tag}/feed`.
+///
+/// Feeds are read as an anonymous viewer, so they hold exactly what the
+/// public pages show. Entry ids are `urn:blockverse:post:{id}` rather than
+/// URLs, so they stay the same whichever domain the feed was fetched from.
+pub struct FeedService;
+
+impl FeedService {
+    pub fn user_feed(request: &HttpRequest, username: &str) -> HttpResponse {
+        let Some(user) = UserService::get_user_by_username(username.to_string()) else {
+            return HttpResponse::not_found();
+        };
+        if user.is_private {
+            return HttpResponse::new(403, "text/plain; charset=utf-8", "This account is private");
+        }
+
+        let base = HttpService::base_url(request);
+        let posts = PostService::get_user_posts(user.id, None, FEED_ENTRIES);
+        let feed = Feed {
+            id: format!("urn:blockverse:user:{}", user.id),
+            title: format!("@{} on BlockVerse", user.username),
+            description: user.bio.clone(),
+            home_url: format!("{}/u/{}", base, user.username),
+            feed_url: Self::feed_url(&base, &format!("/u/{}/feed", user.username), request),
+            icon: HttpService::avatar_url(&user),
+            updated: user.updated_at,
+        };
+        Self::respond(request, &base, feed, posts)
+    }
+
+    pub fn hashtag_feed(request: &HttpRequest, tag: &str) -> HttpResponse {
+        let tag = tag.trim_start_matches('#').to_lowercase();
+        let base = HttpService::base_url(request);
+        let posts = TagService::get_posts_by_hashtag(tag.clone(), None, FEED_ENTRIES);
+        let feed = Feed {
+            id: format!("urn:blockverse:tag:{}", tag),
+            title: format!("#{} on BlockVerse", tag),
+            description: format!("Public posts tagged #{}", tag),
+            home_url: format!("{}/tags/{}", base, tag),
+            feed_url: Self::feed_url(&base, &format!("/tags/{}/feed", tag), request),
+            icon: None,
+            updated: 0,
+        };
+        Self::respond(request, &base, feed, posts)
+    }
+
+    /// The URL of a feed at `path` in `format`, for pages to link to.
+    pub fn url(base: &str, path: &str, format: FeedFormat) -> String {
+        match format {
+            FeedFormat::Atom => format!("{}{}", base, path),
+            _ => format!("{}{}?format={}", base, path, format.key()),
+        }
+    }
+
+    fn feed_url(base: &str, path: &str, request: &HttpRequest) -> String {
+        Self::url(base, path, FeedFormat::parse(request.query("format").as_deref()))
+    }
+
+    fn respond(
+        request: &HttpRequest,
+        base: &str,
+        mut feed: Feed,
+        posts: Result<Page<Post>, BlockVerseError>,
+    ) -> HttpResponse {
+        let entries: Vec<Entry> = posts
+            .map(|page| page.items)
+            .unwrap_or_default()
+            .iter()
+            .filter(|post| !post.is_deleted())
+            .filter_map(|post| Entry::of(base, post))
+            .collect();
+        if let Some(latest) = entries.iter().map(|entry| entry.updated).max() {
+            feed.updated = feed.updated.max(latest);
+        }
+
+        let format = FeedFormat::parse(request.query("format").as_deref());
+        let body = match format {
+            FeedFormat::Atom => feed.atom(&entries),
+            FeedFormat::Rss => feed.rss(&entries),
+            FeedFormat::Json => feed.json(&entries),
+        };
+        let content_type = match format {
+            FeedFormat::Json => format.media_type().to_string(),
+            _ => format!("{}; charset=utf-8", format.media_type()),
+        };
+        HttpResponse::new(200, &content_type, body)
+    }
+}
+
+struct Feed {
+    id: String,
+    title: String,
+    description: String,
+    home_url: String,
+    feed_url: String,
+    icon: Option<String>,
+    updated: u64,
+}
+
+impl Feed {
+    fn atom(&self, entries: &[Entry]) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?><feed xmlns="http://www.w3.org/2005/Atom">"#);
+        xml.push_str(&format!(
+            r#"<id>{}</id><title>{}</title><subtitle>{}</subtitle><updated>{}</updated><generator>BlockVerse</generator>"#,
+            markup::escape(&self.id),
+            markup::escape(&self.title),
+            markup::escape(&self.description),
+            rfc3339(self.updated),
+        ));
+        xml.push_str(&format!(
+            r#"<link rel="self" type="application/atom+xml" href="{}"/><link rel="alternate" type="text/html" href="{}"/>"#,
+            markup::escape(&self.feed_url),
+            markup::escape(&self.home_url),
+        ));
+        if let Some(icon) = &self.icon {
+            xml.push_str(&format!("<icon>{}</icon>", markup::escape(icon)));
+        }
+        for entry in entries {
+            xml.push_str(&format!(
+                r#"<entry><id>{}</id><title>{}</title><link rel="alternate" type="text/html" href="{}"/>"#,
+                markup::escape(&entry.id),
+                markup::escape(&entry.title),
+                markup::escape(&entry.url),
+            ));
+            xml.push_str(&format!(
+                "<published>{}</published><updated>{}</updated><author><name>{}</name><uri>{}</uri></author>",
+                rfc3339(entry.published),
+                rfc3339(entry.updated),
+                markup::escape(&entry.author_name),
+                markup::escape(&entry.author_url),
+            ));
+            xml.push_str(&format!(r#"<content type="html">{}</content></entry>"#, markup::escape(&entry.content_html)));
+        }
+        xml.push_str("</feed>");
+        xml
+    }
+
+    fn rss(&self, entries: &[Entry]) -> String {
+        let mut xml = String::from(concat!(
+            r#"<?xml version="1.0" encoding="utf-8"?>"#,
+            r#"<rss version="2.0" xmlns:atom="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/elements/1.1/">"#,
+            "<channel>",
+        ));
+        xml.push_str(&format!(
+            "<title>{}</title><link>{}</link><description>{}</description><lastBuildDate>{}</lastBuildDate>",
+            markup::escape(&self.title),
+            markup::escape(&self.home_url),
+            markup::escape(&self.description),
+            rfc2822(self.updated),
+        ));
+        xml.push_str(&format!(
+            r#"<atom:link rel="self" type="application/rss+xml" href="{}"/><generator>BlockVerse</generator>"#,
+            markup::escape(&self.feed_url),
+        ));
+        for entry in entries {
+            xml.push_str(&format!(
+                r#"<item><title>{}</title><link>{}</link><guid isPermaLink="false">{}</guid><pubDate>{}</pubDate>"#,
+                markup::escape(&entry.title),
+                markup::escape(&entry.url),
+                markup::escape(&entry.id),
+                rfc2822(entry.published),
+            ));
+            xml.push_str(&format!(
+                "<dc:creator>{}</dc:creator><description>{}</description></item>",
+                markup::escape(&entry.author_name),
+                markup::escape(&entry.content_html),
+            ));
+        }
+        xml.push_str("</channel></rss>");
+        xml
+    }
+
+    fn json(&self, entries: &[Entry]) -> String {
+        let items: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                serde_json::json!({
+                    "id": entry.id,
+                    "url": entry.url,
+                    "title": entry.title,
+                    "content_html": entry.content_html,
+                    "content_text": entry.content_text,
+                    "image": entry.image,
+                    "date_published": rfc3339(entry.published),
+                    "date_modified": rfc3339(entry.updated),
+                    "authors": [{ "name": entry.author_name, "url": entry.author_url }],
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "version": "https://jsonfeed.org/version/1.1",
+            "title": self.title,
+            "description": self.description,
+            "home_page_url": self.home_url,
+            "feed_url": self.feed_url,
+            "icon": self.icon,
+            "items": items,
+        })
+        .to_string()
+    }
+}
+
+/// One post as a feed entry. A share shows its comment followed by the
+/// shared post, credited to that post's author.
+struct Entry {
+    id: String,
+    url: String,
+    title: String,
+    content_html: String,
+    content_text: String,
+    image: Option<String>,
+    author_name: String,
+    author_url: String,
+    published: u64,
+    updated: u64,
+}
+
+impl Entry {
+    fn of(base: &str, post: &Post) -> Option<Self> {
+        let author = UserService::get_user(post.author)?;
+        let mut content_html = String::new();
+        let mut content_text = String::new();
+        let mut image = None;
+
+        let title = if post.is_shared {
+            let original = post
+                .original_post_id
+                .clone()
+                .and_then(PostService::get_post)
+                .filter(|original| !original.is_deleted());
+            let original_author = original.as_ref().and_then(|original| UserService::get_user(original.author));
+
+            if let Some(comment) = &post.share_comment {
+                content_html.push_str(&format!("<p>{}</p>", markup::escape(comment)));
+                content_text.push_str(comment);
+                content_text.push_str("\n\n");
+            }
+            match (&original, &original_author) {
+                (Some(original), Some(original_author)) => {
+                    let profile_url = format!("{}/u/{}", base, original_author.username);
+                    content_html.push_str(&format!(
+                        r#"<blockquote><p><a href="{}">@{}</a></p>"#,
+                        markup::escape(&profile_url),
+                        markup::escape(&original_author.username)
+                    ));
+                    content_html.push_str(&Self::body_html(base, original, &mut image));
+                    content_html.push_str("</blockquote>");
+                    content_text.push_str(&format!("@{}: {}", original_author.username, original.content));
+                    format!("Shared a post by @{}", original_author.username)
+                }
+                _ => {
+                    content_html.push_str("<p>This post is no longer available</p>");
+                    content_text.push_str("This post is no longer available");
+                    "Shared a post".to_string()
+                }
+            }
+        } else {
+            content_html.push_str(&Self::body_html(base, post, &mut image));
+            content_text.push_str(&post.content);
+            Self::title(post, &author)
+        };
+
+        Some(Self {
+            id: format!("urn:blockverse:post:{}", post.id),
+            url: format!("{}/post/{}", base, post.id),
+            title,
+            content_html,
+            content_text,
+            image,
+            author_name: format!("@{}", author.username),
+            author_url: format!("{}/u/{}", base, author.username),
+            published: post.created_at,
+            updated: post.edited_at.unwrap_or(post.created_at),
+        })
+    }
+
+    fn title(post: &Post, author: &User) -> String {
+        match post.content.lines().next().map(str::trim).filter(|line| !line.is_empty()) {
+            Some(line) => markup::truncate(line, TITLE_CHARS),
+            None => format!("Post by @{}", author.username),
+        }
+    }
+
+    /// A post's content and media, with absolute links. The first image
+    /// becomes the entry's `image`.
+    fn body_html(base: &str, post: &Post, image: &mut Option<String>) -> String {
+        let mut html = format!("<p>{}</p>", markup::escape(&post.content).replace('\n', "<br>"));
+        if let Some(asset) = post.media_asset_id.as_deref().and_then(MediaService::public_asset) {
+            let url = format!("{}{}", base, asset.path());
+            if asset.mime_type.starts_with("video/") {
+                html.push_str(&format!(r#"<video src="{}" controls></video>"#, markup::escape(&url)));
+            } else {
+                html.push_str(&format!(r#"<img src="{}" alt="">"#, markup::escape(&url)));
+                image.get_or_insert(url);
+            }
+        }
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::Visibility;
+    use crate::storage::state::STATE;
+    use crate::utils::testing::principal;
+
+    fn feed() -> Feed {
+        Feed {
+            id: "urn:blockverse:user:alice".to_string(),
+            title: "@alice on BlockVerse".to_string(),
+            description: "Rock & <roll>".to_string(),
+            home_url: "https://example.org/u/alice".to_string(),
+            feed_url: "https://example.org/u/alice/feed?format=rss".to_string(),
+            icon: None,
+            updated: 0,
+        }
+    }
+
+    #[test]
+    fn entries_are_escaped_in_every_format_and_keep_their_ids() {
+        let alice = principal(1);
+        STATE.with(|state| {
+            state.borrow_mut().insert_user(User::new(alice, "alice".to_string(), String::new(), String::new()))
+        });
+        let content = "<b>bold</b> & \"quoted\"\nsecond line";
+        let post = Post::new("post_1".to_string(), alice, content.to_string(), None, Visibility::Public);
+
+        let entry = Entry::of("https://example.org", &post).unwrap();
+        assert_eq!(entry.id, "urn:blockverse:post:post_1");
+        assert_eq!(entry.url, "https://example.org/post/post_1");
+        assert_eq!(entry.title, "<b>bold</b> & \"quoted\"");
+        assert_eq!(entry.content_html, "<p>&lt;b&gt;bold&lt;/b&gt; &amp; &quot;quoted&quot;<br>second line</p>");
+        let entries = [entry];
+
+        let atom = feed().atom(&entries);
+        assert!(atom.contains("<entry><id>urn:blockverse:post:post_1</id><title>&lt;b&gt;bold&lt;/b&gt; &amp; &quot;quoted&quot;</title>"));
+        assert!(atom.contains("<subtitle>Rock &amp; &lt;roll&gt;</subtitle>"));
+        assert!(atom.contains(r#"<content type="html">&lt;p&gt;&amp;lt;b&amp;gt;bold"#));
+        assert!(!atom.contains("<b>") && !atom.contains("<roll>"));
+
+        let rss = feed().rss(&entries);
+        assert!(rss.contains(r#"<guid isPermaLink="false">urn:blockverse:post:post_1</guid>"#));
+        assert!(rss.contains(r#"href="https://example.org/u/alice/feed?format=rss""#));
+        assert!(!rss.contains("<b>") && !rss.contains("<roll>"));
+
+        let json: serde_json::Value = serde_json::from_str(&feed().json(&entries)).unwrap();
+        assert_eq!(json["items"][0]["id"], "urn:blockverse:post:post_1");
+        assert_eq!(json["items"][0]["content_text"], content);
+        assert_eq!(json["items"][0]["authors"][0]["name"], "@alice");
+    }
+
+    #[test]
+    fn formats_are_picked_by_query_and_atom_is_the_default() {
+        assert!(FeedFormat::parse(None) == FeedFormat::Atom);
+        assert!(FeedFormat::parse(Some("xml")) == FeedFormat::Atom);
+        assert!(FeedFormat::parse(Some("rss")) == FeedFormat::Rss);
+        assert!(FeedFormat::parse(Some("json")) == FeedFormat::Json);
+        assert_eq!(FeedService::url("https://a.b", "/tags/rust/feed", FeedFormat::Atom), "https://a.b/tags/rust/feed");
+        assert_eq!(
+            FeedService::url("https://a.b", "/tags/rust/feed", FeedFormat::Json),
+            "https://a.b/tags/rust/feed?format=json"
+        );
+    }
+}
@@ -0,0 +1,535 @@
+use serde::Serialize;
+use crate::error::BlockVerseError;
+use crate::models::asset::{Asset, CHUNK_SIZE};
+use crate::models::http::{
+    HttpRequest, HttpResponse, StreamingCallback, StreamingCallbackHttpResponse, StreamingStrategy, StreamingToken,
+};
+use crate::models::post::{Post, Visibility};
+use crate::models::user::User;
+use crate::services::federation_service::FederationService;
+use crate::services::feed_service::{FeedFormat, FeedService};
+use crate::services::media_service::MediaService;
+use crate::services::post_service::PostService;
+use crate::services::user_service::UserService;
+use crate::storage::state::STATE;
+use crate::storage::storable::IdKey;
+use crate::utils::{certification, markup};
+
+/// Chunks sent per response. Query responses are capped at a few MiB.
+const CHUNKS_PER_RESPONSE: u32 = 8;
+
+/// Posts shown on a profile page.
+const PROFILE_POSTS: usize = 20;
+
+/// Longest post excerpt used as a page title or description.
+const EXCERPT_CHARS: usize = 160;
+
+const TEXT: &str = "text/plain; charset=utf-8";
+const HTML: &str = "text/html; charset=utf-8";
+const JSON: &str = "application/json";
+
+/// Serves the canister over the HTTP gateway:
+///
+/// - `/post/{id}` and `/u/{username}`: HTML pages with OpenGraph tags, so
+///   links unfurl and crawlers can index public content
+/// - `/u/{username}/avatar`: redirects to the user's avatar
+/// - `/u/{username}/feed` and `/tags/{tag}/feed`: see `FeedService`
+/// - `/media/{asset}`: uploaded media, with single-range requests
+/// - `/api/posts/{id}`, `/api/users/{username}` and
+///   `/api/users/{username}/posts`: the same data as JSON
+/// - `/.well-known/webfinger`, the inboxes, `/u/{username}/outbox`,
+///   `/u/{username}/followers` and `/u/{username}/following`, and posts and
+///   profiles asked for as `application/activity+json`: ActivityPub, see
+///   `FederationService`. Inbox requests are upgraded to update calls.
+///
+/// Requests are served as an anonymous viewer, so only what anyone may see
+/// is exposed. Full media responses are certified; pages, JSON and partial
+/// media responses change with every interaction and are not, so those
+/// need a gateway that accepts uncertified responses, such as the
+/// canister's `raw` domain.
+pub struct HttpService;
+
+impl HttpService {
+    pub fn http_request(request: HttpRequest) -> HttpResponse {
+        let path = request.path();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if request.method == "POST" && Self::is_inbox(&segments) {
+            return HttpResponse::upgrade();
+        }
+        if request.method != "GET" && request.method != "HEAD" {
+            return HttpResponse::new(405, TEXT, "Method not allowed").with_header("Allow", "GET, HEAD");
+        }
+
+        let wants_activity = FederationService::wants_activity(&request);
+        match segments.as_slice() {
+            [".well-known", "webfinger"] => FederationService::webfinger(&request),
+            ["post", post_id] if wants_activity => FederationService::note(post_id),
+            ["post", post_id] => Self::post_page(&request, post_id),
+            ["u", username] if wants_activity => FederationService::actor(username),
+            ["u", username] => Self::profile_page(&request, username),
+            ["u", username, "outbox"] => FederationService::outbox(&request, username),
+            ["u", username, collection @ ("followers" | "following")] => {
+                FederationService::collection(username, collection)
+            }
+            ["u", username, "avatar"] => Self::avatar(username),
+            ["u", username, "feed"] => FeedService::user_feed(&request, username),
+            ["tags", tag, "feed"] => FeedService::hashtag_feed(&request, tag),
+            ["media", asset_id] => Self::media(&request, asset_id),
+            ["api", "posts", post_id] => Self::json_post(post_id),
+            ["api", "users", username] => Self::json_user(username),
+            ["api", "users", username, "posts"] => Self::json_user_posts(&request, username),
+            _ => HttpResponse::not_found(),
+        }
+    }
+
+    /// Handles what `http_request` can't answer in a query: activities
+    /// posted to an inbox.
+    pub async fn http_request_update(request: HttpRequest) -> HttpResponse {
+        let path = request.path();
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        if request.method == "POST" && Self::is_inbox(&segments) {
+            FederationService::receive(request).await
+        } else {
+            HttpResponse::not_found()
+        }
+    }
+
+    /// Continues a media response too large to send at once.
+    pub fn http_request_streaming_callback(token: StreamingToken) -> StreamingCallbackHttpResponse {
+        let Some(asset) = MediaService::public_asset(&token.asset_id) else {
+            return StreamingCallbackHttpResponse { body: Vec::new().into(), token: None };
+        };
+        let (body, token) = Self::chunk_run(&asset, token.index);
+        StreamingCallbackHttpResponse { body: body.into(), token }
+    }
+
+    /// The shared inbox, or a user's.
+    fn is_inbox(segments: &[&str]) -> bool {
+        matches!(segments, ["inbox"] | ["u", _, "inbox"])
+    }
+
+    fn post_page(request: &HttpRequest, post_id: &str) -> HttpResponse {
+        let Some(post) = PostService::get_post(post_id.to_string()) else {
+            return Self::page_not_found();
+        };
+        if post.is_deleted() {
+            return HttpResponse::new(410, HTML, Self::message_page("This post was deleted"));
+        }
+        let Some(author) = UserService::get_user(post.author) else {
+            return Self::page_not_found();
+        };
+
+        let base = Self::base_url(request);
+        let original = post
+            .original_post_id
+            .clone()
+            .and_then(PostService::get_post)
+            .filter(|original| !original.is_deleted());
+        let shown = original.as_ref().unwrap_or(&post);
+        let media = shown.media_asset_id.as_deref().and_then(MediaService::public_asset);
+
+        let mut head = OpenGraph {
+            kind: "article",
+            title: format!("@{} on BlockVerse", author.username),
+            description: markup::truncate(&Self::summary(&post, original.as_ref()), EXCERPT_CHARS),
+            url: format!("{}/post/{}", base, post.id),
+            image: None,
+            video: None,
+            noindex: post.visibility != Visibility::Public,
+            feeds: Vec::new(),
+        };
+        match &media {
+            Some(asset) if asset.mime_type.starts_with("video/") => {
+                head.video = Some(format!("{}{}", base, asset.path()))
+            }
+            Some(asset) => head.image = Some(format!("{}{}", base, asset.path())),
+            None => head.image = Self::avatar_url(&author),
+        }
+
+        let mut body = format!(
+            r#"<article><header><a href="/u/{0}">@{0}</a></header>"#,
+            markup::escape(&author.username)
+        );
+        if post.is_shared {
+            if let Some(comment) = &post.share_comment {
+                body.push_str(&format!("<p>{}</p>", markup::escape(comment)));
+            }
+            body.push_str("<blockquote>");
+            match &original {
+                Some(original) => body.push_str(&Self::post_html(original)),
+                None => body.push_str("<p>This post is no longer available</p>"),
+            }
+            body.push_str("</blockquote>");
+        } else {
+            body.push_str(&Self::post_html(&post));
+        }
+        body.push_str("</article>");
+
+        HttpResponse::new(200, HTML, head.render(&body))
+    }
+
+    fn profile_page(request: &HttpRequest, username: &str) -> HttpResponse {
+        let Some(user) = UserService::get_user_by_username(username.to_string()) else {
+            return Self::page_not_found();
+        };
+
+        let base = Self::base_url(request);
+        let head = OpenGraph {
+            kind: "profile",
+            title: format!("@{} on BlockVerse", user.username),
+            description: markup::truncate(&user.bio, EXCERPT_CHARS),
+            url: format!("{}/u/{}", base, user.username),
+            image: Self::avatar_url(&user),
+            video: None,
+            noindex: false,
+            feeds: if user.is_private {
+                Vec::new()
+            } else {
+                let path = format!("/u/{}/feed", user.username);
+                FeedFormat::ALL.map(|format| (format, FeedService::url(&base, &path, format))).to_vec()
+            },
+        };
+
+        let mut body = format!(
+            "<header><h1>@{}</h1><p>{}</p><p>{} posts · {} followers · {} following</p></header>",
+            markup::escape(&user.username),
+            markup::escape(&user.bio),
+            user.posts_count,
+            user.followers_count,
+            user.following_count,
+        );
+        if user.is_private {
+            body.push_str("<p>This account is private</p>");
+        } else {
+            let posts = PostService::get_user_posts(user.id, None, PROFILE_POSTS)
+                .map(|page| page.items)
+                .unwrap_or_default();
+            for post in posts.iter().filter(|post| !post.is_deleted()) {
+                let original = post.original_post_id.clone().and_then(PostService::get_post);
+                body.push_str(&format!(
+                    r#"<article><a href="/post/{}">{}</a></article>"#,
+                    markup::escape(&post.id),
+                    markup::escape(&markup::truncate(&Self::summary(post, original.as_ref()), EXCERPT_CHARS)),
+                ));
+            }
+        }
+
+        HttpResponse::new(200, HTML, head.render(&body))
+    }
+
+    fn avatar(username: &str) -> HttpResponse {
+        match UserService::get_user_by_username(username.to_string()).and_then(|user| Self::avatar_url(&user)) {
+            Some(url) => HttpResponse::new(302, TEXT, "").with_header("Location", url),
+            None => HttpResponse::not_found(),
+        }
+    }
+
+    /// Serves an asset: a `Range` request gets just that range, anything
+    /// else the whole asset, streamed if it doesn't fit in one response.
+    fn media(request: &HttpRequest, asset_id: &str) -> HttpResponse {
+        let Some(asset) = MediaService::public_asset(asset_id) else {
+            return HttpResponse::not_found();
+        };
+
+        if let Some(range) = request.header("Range") {
+            return Self::media_range(&asset, range);
+        }
+
+        let (body, token) = Self::chunk_run(&asset, 0);
+        let mut response = Self::media_response(200, &asset, body);
+        response.streaming_strategy = token.map(|token| StreamingStrategy::Callback {
+            callback: StreamingCallback::new(ic_cdk::id(), "http_request_streaming_callback".to_string()),
+            token,
+        });
+        if let Some(header) = certification::header(&asset.path()) {
+            response.headers.push(header);
+        }
+        response
+    }
+
+    fn media_range(asset: &Asset, range: &str) -> HttpResponse {
+        let Some((start, end)) = Self::parse_range(range, asset.size) else {
+            return HttpResponse::new(416, TEXT, "Range not satisfiable")
+                .with_header("Content-Range", format!("bytes */{}", asset.size));
+        };
+        // Serve at most one response's worth; the client asks again for more
+        let end = end.min(start + u64::from(CHUNKS_PER_RESPONSE) * CHUNK_SIZE - 1);
+
+        let body = STATE.with(|state| {
+            let state = state.borrow();
+            let first = (start / CHUNK_SIZE) as u32;
+            let last = (end / CHUNK_SIZE) as u32;
+            let mut body = Vec::with_capacity((end - start + 1) as usize);
+            for index in first..=last {
+                let chunk = state.asset_chunks.get(&(IdKey(asset.id.clone()), index)).unwrap_or_default().0;
+                let offset = u64::from(index) * CHUNK_SIZE;
+                let from = start.saturating_sub(offset) as usize;
+                let to = ((end + 1 - offset) as usize).min(chunk.len());
+                body.extend_from_slice(&chunk[from.min(to)..to]);
+            }
+            body
+        });
+
+        Self::media_response(206, asset, body)
+            .with_header("Content-Range", format!("bytes {}-{}/{}", start, end, asset.size))
+    }
+
+    fn media_response(status_code: u16, asset: &Asset, body: Vec<u8>) -> HttpResponse {
+        HttpResponse::new(status_code, &asset.mime_type, body)
+            .with_header("Accept-Ranges", "bytes")
+            .with_header("Cache-Control", "public, max-age=31536000, immutable")
+            .with_header("ETag", format!("\"{}\"", asset.sha256))
+    }
+
+    /// Up to `CHUNKS_PER_RESPONSE` chunks of `asset` from `index`, and a
+    /// token for the rest if there is more.
+    fn chunk_run(asset: &Asset, index: u32) -> (Vec<u8>, Option<StreamingToken>) {
+        let end = asset.chunk_count.min(index.saturating_add(CHUNKS_PER_RESPONSE));
+        let body = STATE.with(|state| {
+            let state = state.borrow();
+            let mut body = Vec::new();
+            for index in index..end {
+                if let Some(chunk) = state.asset_chunks.get(&(IdKey(asset.id.clone()), index)) {
+                    body.extend_from_slice(&chunk.0);
+                }
+            }
+            body
+        });
+        let token = (end < asset.chunk_count).then(|| StreamingToken { asset_id: asset.id.clone(), index: end });
+        (body, token)
+    }
+
+    /// Parses a single `bytes=` range into inclusive offsets within `size`.
+    fn parse_range(header: &str, size: u64) -> Option<(u64, u64)> {
+        let (start, end) = header.trim().strip_prefix("bytes=")?.split_once('-')?;
+        let last = size.checked_sub(1)?;
+        let (start, end) = match (start.trim(), end.trim()) {
+            ("", suffix) => (size.saturating_sub(suffix.parse().ok().filter(|len| *len > 0)?), last),
+            (start, "") => (start.parse().ok()?, last),
+            (start, end) => (start.parse().ok()?, end.parse::<u64>().ok()?.min(last)),
+        };
+        (start <= end).then_some((start, end))
+    }
+
+    fn json_post(post_id: &str) -> HttpResponse {
+        match PostService::get_post(post_id.to_string()) {
+            Some(post) => Self::json(200, &post),
+            None => Self::json_error(404, "Post not found"),
+        }
+    }
+
+    fn json_user(username: &str) -> HttpResponse {
+        match UserService::get_user_by_username(username.to_string()) {
+            Some(user) => Self::json(200, &user),
+            None => Self::json_error(404, "User not found"),
+        }
+    }
+
+    /// `?cursor=` and `?limit=` page through the posts as in
+    /// `get_user_posts`.
+    fn json_user_posts(request: &HttpRequest, username: &str) -> HttpResponse {
+        let Some(user) = UserService::get_user_by_username(username.to_string()) else {
+            return Self::json_error(404, "User not found");
+        };
+        let limit = request.query("limit").and_then(|limit| limit.parse().ok()).unwrap_or(PROFILE_POSTS);
+        match PostService::get_user_posts(user.id, request.query("cursor"), limit) {
+            Ok(page) => Self::json(200, &page),
+            Err(BlockVerseError::Validation { reason, .. }) => Self::json_error(400, &reason),
+            Err(_) => Self::json_error(500, "Could not load posts"),
+        }
+    }
+
+    fn json(status_code: u16, value: &impl Serialize) -> HttpResponse {
+        match serde_json::to_vec(value) {
+            Ok(body) => HttpResponse::new(status_code, JSON, body),
+            Err(_) => Self::json_error(500, "Could not encode response"),
+        }
+    }
+
+    fn json_error(status_code: u16, message: &str) -> HttpResponse {
+        HttpResponse::new(status_code, JSON, serde_json::json!({ "error": message }).to_string())
+    }
+
+    fn page_not_found() -> HttpResponse {
+        HttpResponse::new(404, HTML, Self::message_page("Not found"))
+    }
+
+    fn message_page(message: &str) -> String {
+        format!(
+            "<!DOCTYPE html><html lang=\"en\"><head><meta charset=\"utf-8\"><title>{0} · BlockVerse</title></head>\
+             <body><p>{0}</p></body></html>",
+            markup::escape(message)
+        )
+    }
+
+    fn post_html(post: &Post) -> String {
+        let mut html = format!("<p>{}</p>", markup::escape(&post.content));
+        match post.media_asset_id.as_deref().and_then(MediaService::public_asset) {
+            Some(asset) if asset.mime_type.starts_with("video/") => {
+                html.push_str(&format!(r#"<video src="{}" controls></video>"#, markup::escape(&asset.path())))
+            }
+            Some(asset) => html.push_str(&format!(r#"<img src="{}" alt="">"#, markup::escape(&asset.path()))),
+            None => {}
+        }
+        html
+    }
+
+    /// The text a post is summarised by: its content, or for a share the
+    /// share comment or else the shared post's content.
+    fn summary(post: &Post, original: Option<&Post>) -> String {
+        if !post.is_shared {
+            return post.content.clone();
+        }
+        post.share_comment
+            .clone()
+            .or_else(|| original.map(|original| original.content.clone()))
+            .unwrap_or_default()
+    }
+
+    pub fn avatar_url(user: &User) -> Option<String> {
+        let url = user.avatar_url.trim();
+        (url.starts_with("https://") || url.starts_with("http://")).then(|| url.to_string())
+    }
+
+    /// The origin requests arrive at, for absolute links.
+    pub fn base_url(request: &HttpRequest) -> String {
+        match request.header("Host") {
+            Some(host) => format!("https://{}", host),
+            None => format!("https://{}.icp0.io", ic_cdk::id()),
+        }
+    }
+}
+
+/// Page metadata rendered as OpenGraph and Twitter card tags.
+struct OpenGraph {
+    kind: &'static str,
+    title: String,
+    description: String,
+    url: String,
+    image: Option<String>,
+    video: Option<String>,
+    /// Keeps search engines from indexing pages that aren't public
+    noindex: bool,
+    /// Feeds advertised to feed readers
+    feeds: Vec<(FeedFormat, String)>,
+}
+
+impl OpenGraph {
+    fn render(&self, body: &str) -> String {
+        let mut meta = vec![
+            ("og:site_name", "BlockVerse".to_string()),
+            ("og:type", self.kind.to_string()),
+            ("og:title", self.title.clone()),
+            ("og:description", self.description.clone()),
+            ("og:url", self.url.clone()),
+        ];
+        if let Some(image) = &self.image {
+            meta.push(("og:image", image.clone()));
+        }
+        if let Some(video) = &self.video {
+            meta.push(("og:video", video.clone()));
+        }
+
+        let mut head = format!(
+            "<meta charset=\"utf-8\"><meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+             <title>{}</title><meta name=\"description\" content=\"{}\"><link rel=\"canonical\" href=\"{}\">",
+            markup::escape(&self.title),
+            markup::escape(&self.description),
+            markup::escape(&self.url),
+        );
+        for (property, content) in meta {
+            head.push_str(&format!(
+                "<meta property=\"{}\" content=\"{}\">",
+                property,
+                markup::escape(&content)
+            ));
+        }
+        let card = if self.image.is_some() { "summary_large_image" } else { "summary" };
+        head.push_str(&format!("<meta name=\"twitter:card\" content=\"{}\">", card));
+        if self.noindex {
+            head.push_str("<meta name=\"robots\" content=\"noindex\">");
+        }
+        for (format, url) in &self.feeds {
+            head.push_str(&format!(
+                "<link rel=\"alternate\" type=\"{}\" href=\"{}\">",
+                format.media_type(),
+                markup::escape(url)
+            ));
+        }
+
+        format!("<!DOCTYPE html><html lang=\"en\"><head>{}</head><body>{}</body></html>", head, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use crate::storage::storable::ChunkData;
+
+    #[test]
+    fn pages_escape_what_users_write() {
+        let post = Post::new(
+            "post_1".to_string(),
+            Principal::anonymous(),
+            "<script>alert('hi')</script> & more".to_string(),
+            None,
+            Visibility::Public,
+        );
+        assert_eq!(
+            HttpService::post_html(&post),
+            "<p>&lt;script&gt;alert(&#39;hi&#39;)&lt;/script&gt; &amp; more</p>"
+        );
+        assert!(HttpService::message_page("<b>").contains("<p>&lt;b&gt;</p>"));
+
+        let page = OpenGraph {
+            kind: "article",
+            title: "@alice on BlockVerse".to_string(),
+            description: "\"quoted\" <em>text</em>".to_string(),
+            url: "https://example.org/post/post_1?a=1&b=2".to_string(),
+            image: None,
+            video: None,
+            noindex: true,
+            feeds: Vec::new(),
+        }
+        .render("");
+        assert!(page.contains(r#"<meta property="og:description" content="&quot;quoted&quot; &lt;em&gt;text&lt;/em&gt;">"#));
+        assert!(page.contains(r#"<link rel="canonical" href="https://example.org/post/post_1?a=1&amp;b=2">"#));
+        assert!(page.contains(r#"<meta name="robots" content="noindex">"#));
+        assert!(!page.contains("<em>"));
+    }
+
+    #[test]
+    fn ranges_are_served_across_chunk_boundaries() {
+        assert_eq!(HttpService::parse_range("bytes=0-99", 50), Some((0, 49)));
+        assert_eq!(HttpService::parse_range("bytes=10-", 50), Some((10, 49)));
+        assert_eq!(HttpService::parse_range("bytes=-20", 50), Some((30, 49)));
+        assert_eq!(HttpService::parse_range("bytes=60-", 50), None);
+        assert_eq!(HttpService::parse_range("bytes=0-0", 0), None);
+        assert_eq!(HttpService::parse_range("items=0-1", 50), None);
+
+        let asset = Asset {
+            id: "asset_1".to_string(),
+            owner: Principal::anonymous(),
+            mime_type: "image/png".to_string(),
+            size: CHUNK_SIZE + 2,
+            chunk_count: 2,
+            sha256: "00".to_string(),
+            created_at: 0,
+        };
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let key = IdKey(asset.id.clone());
+            state.asset_chunks.insert((key.clone(), 0), ChunkData(vec![0; CHUNK_SIZE as usize]));
+            state.asset_chunks.insert((key, 1), ChunkData(vec![1; 2]));
+        });
+
+        let range = format!("bytes={}-", CHUNK_SIZE - 2);
+        let response = HttpService::media_range(&asset, &range);
+        assert_eq!(response.status_code, 206);
+        assert_eq!(response.body.to_vec(), [0, 0, 1, 1]);
+        let content_range = format!("bytes {}-{}/{}", CHUNK_SIZE - 2, CHUNK_SIZE + 1, CHUNK_SIZE + 2);
+        assert!(response.headers.contains(&("Content-Range".to_string(), content_range)));
+        assert_eq!(HttpService::media_range(&asset, "bytes=-0").status_code, 416);
+    }
+}
@@ -0,0 +1,226 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use sha2::{Digest, Sha256};
+use crate::error::BlockVerseError;
+use crate::utils::clock;
+
+/// ICRC-1 account: an owner principal plus an optional 32-byte subaccount.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+impl Account {
+    pub fn new(owner: Principal) -> Self {
+        Self { owner, subaccount: None }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+struct TransferArg {
+    from_subaccount: Option<Vec<u8>>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: Account,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+/// Union of the ICRC-1 `TransferError` and ICRC-2 `TransferFromError`
+/// variants; Candid decodes either into it.
+#[derive(Debug, CandidType, Deserialize)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+/// Why a transfer didn't go through.
+#[derive(Debug)]
+pub enum TransferFailure {
+    /// The ledger answered with a `TransferError`: nothing moved.
+    Refused(BlockVerseError),
+    /// The call failed without an answer from the ledger, so the transfer
+    /// may still have gone through.
+    Unknown(BlockVerseError),
+    /// Resubmitting a transfer came too late for the ledger to tell whether
+    /// the original went through.
+    Expired(BlockVerseError),
+    /// The ledger already applied a transfer with the same memo and
+    /// creation time, at this block. Only a resubmission of that same
+    /// transfer may count it as done.
+    Duplicate(u64),
+}
+
+impl From<TransferFailure> for BlockVerseError {
+    fn from(failure: TransferFailure) -> Self {
+        match failure {
+            TransferFailure::Refused(error) | TransferFailure::Unknown(error) | TransferFailure::Expired(error) => error,
+            TransferFailure::Duplicate(block_index) => {
+                BlockVerseError::Ledger(format!("Duplicate of the transfer at block {}", block_index))
+            }
+        }
+    }
+}
+
+/// The ledger calls payments are made with. `Ledger` makes them against
+/// the ledger canister; tests substitute their own.
+pub trait LedgerApi {
+    async fn fee(&self) -> Result<u64, BlockVerseError>;
+
+    async fn balance_of(&self, account: Account) -> Result<u64, BlockVerseError>;
+
+    /// Sends `amount` from one of this canister's subaccounts, paying `fee`
+    /// on top. Submitting the same `memo` and `created_at_time` again is
+    /// deduplicated by the ledger, which answers with
+    /// `TransferFailure::Duplicate` and the original block. Returns the
+    /// ledger block index.
+    async fn transfer(
+        &self,
+        from_subaccount: Option<Vec<u8>>,
+        to: Account,
+        amount: u64,
+        fee: u64,
+        memo: Option<Vec<u8>>,
+        created_at_time: u64,
+    ) -> Result<u64, TransferFailure>;
+
+    /// Moves `amount` out of `from` under an allowance it gave this canister.
+    /// The ledger's default fee is charged to `from` on top. `memo` must be
+    /// unique to the transfer: the ledger refuses a second one with the same
+    /// memo in the same round as a duplicate.
+    async fn transfer_from(
+        &self,
+        from: Account,
+        to: Account,
+        amount: u64,
+        memo: Vec<u8>,
+    ) -> Result<u64, BlockVerseError>;
+}
+
+/// Client for the ICRC-1/ICRC-2 ledger tips are paid in.
+pub struct Ledger {
+    canister_id: Principal,
+}
+
+impl Ledger {
+    pub fn new(canister_id: Principal) -> Self {
+        Self { canister_id }
+    }
+
+    fn settle(result: Result<Nat, TransferError>, amount: u64) -> Result<u64, TransferFailure> {
+        match result {
+            Ok(block_index) => to_u64(&block_index).map_err(TransferFailure::Refused),
+            Err(TransferError::Duplicate { duplicate_of }) => {
+                Err(TransferFailure::Duplicate(to_u64(&duplicate_of).map_err(TransferFailure::Refused)?))
+            }
+            Err(TransferError::InsufficientFunds { balance }) => {
+                Err(TransferFailure::Refused(BlockVerseError::InsufficientBalance {
+                    balance: to_u64(&balance).unwrap_or(u64::MAX),
+                    required: amount,
+                }))
+            }
+            Err(TransferError::InsufficientAllowance { allowance }) => Err(TransferFailure::Refused(
+                BlockVerseError::Ledger(format!("Insufficient allowance: {}", allowance)),
+            )),
+            Err(error @ TransferError::TooOld) => {
+                Err(TransferFailure::Expired(BlockVerseError::Ledger(format!("{:?}", error))))
+            }
+            Err(error) => Err(TransferFailure::Refused(BlockVerseError::Ledger(format!("{:?}", error)))),
+        }
+    }
+}
+
+impl LedgerApi for Ledger {
+    async fn fee(&self) -> Result<u64, BlockVerseError> {
+        let (fee,): (Nat,) = ic_cdk::call(self.canister_id, "icrc1_fee", ())
+            .await
+            .map_err(|(_, message)| BlockVerseError::Ledger(message))?;
+        to_u64(&fee)
+    }
+
+    async fn balance_of(&self, account: Account) -> Result<u64, BlockVerseError> {
+        let (balance,): (Nat,) = ic_cdk::call(self.canister_id, "icrc1_balance_of", (account,))
+            .await
+            .map_err(|(_, message)| BlockVerseError::Ledger(message))?;
+        to_u64(&balance)
+    }
+
+    async fn transfer(
+        &self,
+        from_subaccount: Option<Vec<u8>>,
+        to: Account,
+        amount: u64,
+        fee: u64,
+        memo: Option<Vec<u8>>,
+        created_at_time: u64,
+    ) -> Result<u64, TransferFailure> {
+        let arg = TransferArg {
+            from_subaccount,
+            to,
+            amount: Nat::from(amount),
+            fee: Some(Nat::from(fee)),
+            memo,
+            created_at_time: Some(created_at_time),
+        };
+        let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(self.canister_id, "icrc1_transfer", (arg,))
+            .await
+            .map_err(|(_, message)| TransferFailure::Unknown(BlockVerseError::Ledger(message)))?;
+        Self::settle(result, amount)
+    }
+
+    async fn transfer_from(
+        &self,
+        from: Account,
+        to: Account,
+        amount: u64,
+        memo: Vec<u8>,
+    ) -> Result<u64, BlockVerseError> {
+        let args = TransferFromArgs {
+            spender_subaccount: None,
+            from,
+            to,
+            amount: Nat::from(amount),
+            fee: None,
+            memo: Some(memo),
+            created_at_time: Some(clock::now()),
+        };
+        let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(self.canister_id, "icrc2_transfer_from", (args,))
+            .await
+            .map_err(|(_, message)| BlockVerseError::Ledger(message))?;
+        Ok(Self::settle(result, amount)?)
+    }
+}
+
+/// The subaccount of this canister that `user_id` deposits into.
+pub fn deposit_subaccount(user_id: Principal) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"deposit");
+    hasher.update(user_id.as_slice());
+    hasher.finalize().to_vec()
+}
+
+fn to_u64(amount: &Nat) -> Result<u64, BlockVerseError> {
+    match amount.0.to_u64_digits().as_slice() {
+        [] => Ok(0),
+        [amount] => Ok(*amount),
+        _ => Err(BlockVerseError::Ledger(format!("Amount {} does not fit in 64 bits", amount))),
+    }
+}
@@ -0,0 +1,412 @@
+use candid::Principal;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::asset::{self, Asset, MediaUsage, Upload};
+use crate::models::page::Page;
+use crate::services::moderation_service::ModerationService;
+use crate::storage::pagination;
+use crate::storage::state::{self, State, STATE};
+use crate::storage::storable::{ChunkData, IdKey, PrincipalKey};
+use crate::utils::{certification, clock, crypto, validation};
+
+/// Expired uploads discarded per purge run.
+const EXPIRE_BATCH: usize = 20;
+
+/// Stores uploaded media in stable memory.
+///
+/// An upload is started with `begin_upload`, which reserves its size
+/// against the owner's quota, filled with `put_chunk` in any order, and
+/// turned into an `Asset` by `commit_upload`, which checks that the content
+/// matches the declared type and records its hash. The asset keeps the
+/// upload's id and chunks. Assets are served to their owner, and to anyone
+/// who can see a post showing them.
+pub struct MediaService;
+
+impl MediaService {
+    /// Starts an upload of `size` bytes of `mime_type` and returns its id.
+    pub fn begin_upload(mime_type: String, size: u64) -> Result<String, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        let max_size = validation::max_media_size(&mime_type)
+            .ok_or_else(|| BlockVerseError::validation("mime_type", "Unsupported media type"))?;
+        if size == 0 || size > max_size {
+            return Err(BlockVerseError::validation(
+                "size",
+                &format!("Must be 1-{} bytes for {}", max_size, mime_type),
+            ));
+        }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+
+            ModerationService::check_not_suspended(&state, caller)?;
+
+            if !state.has_user(caller) {
+                return Err(BlockVerseError::not_found(EntityKind::User, caller));
+            }
+
+            let used = Self::usage(&state, caller);
+            if used + size > asset::USER_MEDIA_QUOTA {
+                return Err(BlockVerseError::InvalidOperation("Media storage quota exceeded".to_string()));
+            }
+            state.media_usage.insert(PrincipalKey(caller), used + size);
+
+            let upload = Upload {
+                id: state.next_id("asset"),
+                owner: caller,
+                mime_type,
+                size,
+                chunk_count: size.div_ceil(asset::CHUNK_SIZE) as u32,
+                started_at: clock::now(),
+            };
+            state.uploads.insert(IdKey(upload.id.clone()), upload.clone());
+
+            Ok(upload.id)
+        })
+    }
+
+    /// Stores chunk `index` of an upload. Sending a chunk again replaces it.
+    pub fn put_chunk(upload_id: String, index: u32, data: Vec<u8>) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+
+            let upload = Self::owned_upload(&state, &upload_id, caller)?;
+            if index >= upload.chunk_count {
+                return Err(BlockVerseError::validation("index", "Past the end of the upload"));
+            }
+            if data.len() as u64 != upload.chunk_len(index) {
+                return Err(BlockVerseError::validation(
+                    "data",
+                    &format!("Chunk {} must be {} bytes", index, upload.chunk_len(index)),
+                ));
+            }
+
+            state.asset_chunks.insert((IdKey(upload_id), index), ChunkData(data));
+            Ok(())
+        })
+    }
+
+    /// Turns a fully uploaded upload into an asset. Content that doesn't
+    /// match the declared type is discarded along with the upload.
+    pub fn commit_upload(upload_id: String) -> Result<Asset, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+
+            let upload = Self::owned_upload(&state, &upload_id, caller)?;
+            let key = IdKey(upload_id.clone());
+            let received = state
+                .asset_chunks
+                .range((key.clone(), 0)..)
+                .take_while(|((id, _), _)| *id == key)
+                .count();
+            if received != upload.chunk_count as usize {
+                return Err(BlockVerseError::InvalidOperation(format!(
+                    "Received {} of {} chunks",
+                    received, upload.chunk_count
+                )));
+            }
+
+            let first = state.asset_chunks.get(&(key.clone(), 0)).unwrap_or_default();
+            if validation::sniff_media_type(&first.0) != Some(upload.mime_type.as_str()) {
+                Self::discard_upload(&mut state, &upload);
+                return Err(BlockVerseError::validation("mime_type", "Content does not match the media type"));
+            }
+
+            let sha256 = crypto::hash_chunks(
+                state
+                    .asset_chunks
+                    .range((key.clone(), 0)..)
+                    .take_while(|((id, _), _)| *id == key)
+                    .map(|(_, chunk)| chunk.0),
+            );
+
+            state.uploads.remove(&key);
+            let asset = Asset {
+                id: upload.id,
+                owner: upload.owner,
+                mime_type: upload.mime_type,
+                size: upload.size,
+                chunk_count: upload.chunk_count,
+                sha256,
+                created_at: clock::now(),
+            };
+            state.user_assets.insert((PrincipalKey(caller), key.clone()), ());
+            state.assets.insert(key, asset.clone());
+            Self::certify(&asset);
+
+            Ok(asset)
+        })
+    }
+
+    /// Abandons an upload and releases its quota.
+    pub fn abort_upload(upload_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let upload = Self::owned_upload(&state, &upload_id, caller)?;
+            Self::discard_upload(&mut state, &upload);
+            Ok(())
+        })
+    }
+
+    /// Deletes one of the caller's assets and releases its quota. Posts
+    /// that used it keep the id but no longer resolve it.
+    pub fn delete_asset(asset_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+
+            let asset = state
+                .assets
+                .get(&IdKey(asset_id.clone()))
+                .filter(|asset| asset.owner == caller)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Asset, &asset_id))?;
+
+            state.assets.remove(&IdKey(asset_id.clone()));
+            state.user_assets.remove(&(PrincipalKey(caller), IdKey(asset_id.clone())));
+            state::remove_members(&mut state.asset_posts, &IdKey(asset_id.clone()));
+            Self::remove_chunks(&mut state, &asset_id, asset.chunk_count);
+            Self::release(&mut state, caller, asset.size);
+            certification::uncertify(&asset.path());
+            Ok(())
+        })
+    }
+
+    pub fn get_asset(asset_id: String) -> Option<Asset> {
+        Self::find_asset(&asset_id, ic_cdk::caller())
+    }
+
+    /// `asset_id` if anyone may fetch it. This is what the HTTP gateway,
+    /// feeds and federation serve.
+    pub fn public_asset(asset_id: &str) -> Option<Asset> {
+        Self::find_asset(asset_id, Principal::anonymous())
+    }
+
+    /// `asset_id` if `viewer` may fetch it, see `can_view_asset`.
+    pub fn find_asset(asset_id: &str, viewer: Principal) -> Option<Asset> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            state.assets.get(&IdKey::from(asset_id)).filter(|asset| Self::can_view_asset(&state, asset, viewer))
+        })
+    }
+
+    /// Chunk `index` of an asset, each `asset::CHUNK_SIZE` bytes except
+    /// the last.
+    pub fn get_asset_chunk(asset_id: String, index: u32) -> Result<Vec<u8>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            let visible = state
+                .assets
+                .get(&IdKey(asset_id.clone()))
+                .is_some_and(|asset| Self::can_view_asset(&state, &asset, caller));
+            if !visible {
+                return Err(BlockVerseError::not_found(EntityKind::Asset, &asset_id));
+            }
+            state
+                .asset_chunks
+                .get(&(IdKey(asset_id), index))
+                .map(|chunk| chunk.0)
+                .ok_or_else(|| BlockVerseError::validation("index", "Past the end of the asset"))
+        })
+    }
+
+    /// The caller's assets.
+    pub fn get_my_assets(cursor: Option<String>, limit: usize) -> Result<Page<Asset>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.user_assets, PrincipalKey(caller), cursor, limit, |asset_id| {
+                state.assets.get(asset_id)
+            })
+        })
+    }
+
+    pub fn get_media_usage() -> MediaUsage {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            MediaUsage { used: Self::usage(&state, caller), quota: asset::USER_MEDIA_QUOTA }
+        })
+    }
+
+    /// Certifies every asset's HTTP response. The certification tree lives
+    /// on the heap, so this runs from both `init` and `post_upgrade`.
+    pub fn certify_assets() {
+        STATE.with(|state| {
+            let state = state.borrow();
+            for (_, asset) in state.assets.iter() {
+                Self::certify(&asset);
+            }
+        })
+    }
+
+    /// Whether `viewer` may fetch `asset`. Its owner always can, anyone
+    /// else only if they can see a live post showing it.
+    pub fn can_view_asset(state: &State, asset: &Asset, viewer: Principal) -> bool {
+        let key = IdKey(asset.id.clone());
+        asset.owner == viewer
+            || state
+                .asset_posts
+                .range((key.clone(), IdKey::default())..)
+                .take_while(|((asset_id, _), _)| *asset_id == key)
+                .filter_map(|((_, post_id), _)| state.get_live_post(&post_id.0))
+                .any(|post| state.can_view_post(&post, viewer))
+    }
+
+    /// Records that `post_id` shows `asset_id`, after `check_attachable`.
+    pub fn attach(state: &mut State, asset_id: &str, post_id: &str) {
+        state.asset_posts.insert((IdKey::from(asset_id), IdKey::from(post_id)), ());
+    }
+
+    /// Drops the links from `asset_ids` to `post_id` once it is deleted.
+    pub fn detach(state: &mut State, asset_ids: impl IntoIterator<Item = String>, post_id: &str) {
+        for asset_id in asset_ids {
+            state.asset_posts.remove(&(IdKey(asset_id), IdKey::from(post_id)));
+        }
+    }
+
+    /// Fails unless `asset_id` is one of `user_id`'s assets, so posts can
+    /// only attach media their author uploaded.
+    pub fn check_attachable(state: &State, asset_id: &str, user_id: Principal) -> Result<(), BlockVerseError> {
+        match state.assets.get(&IdKey::from(asset_id)) {
+            Some(asset) if asset.owner == user_id => Ok(()),
+            _ => Err(BlockVerseError::not_found(EntityKind::Asset, asset_id)),
+        }
+    }
+
+    /// Discards one batch of uploads older than `asset::UPLOAD_TTL_NS`.
+    /// Uploads are keyed by a time-ordered id, so the oldest come first.
+    pub fn expire_uploads(state: &mut State) -> u64 {
+        let cutoff = clock::now().saturating_sub(asset::UPLOAD_TTL_NS);
+        let expired: Vec<_> = state
+            .uploads
+            .iter()
+            .map(|(_, upload)| upload)
+            .take_while(|upload| upload.started_at <= cutoff)
+            .take(EXPIRE_BATCH)
+            .collect();
+
+        for upload in &expired {
+            Self::discard_upload(state, upload);
+        }
+        expired.len() as u64
+    }
+
+    fn certify(asset: &Asset) {
+        if let Ok(sha256) = hex::decode(&asset.sha256).unwrap_or_default().try_into() {
+            certification::certify(asset.path(), sha256);
+        }
+    }
+
+    fn owned_upload(state: &State, upload_id: &str, user_id: Principal) -> Result<Upload, BlockVerseError> {
+        state
+            .uploads
+            .get(&IdKey::from(upload_id))
+            .filter(|upload| upload.owner == user_id)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Upload, upload_id))
+    }
+
+    fn discard_upload(state: &mut State, upload: &Upload) {
+        state.uploads.remove(&IdKey(upload.id.clone()));
+        Self::remove_chunks(state, &upload.id, upload.chunk_count);
+        Self::release(state, upload.owner, upload.size);
+    }
+
+    fn remove_chunks(state: &mut State, id: &str, chunk_count: u32) {
+        for index in 0..chunk_count {
+            state.asset_chunks.remove(&(IdKey::from(id), index));
+        }
+    }
+
+    fn usage(state: &State, user_id: Principal) -> u64 {
+        state.media_usage.get(&PrincipalKey(user_id)).unwrap_or(0)
+    }
+
+    fn release(state: &mut State, user_id: Principal, size: u64) {
+        let used = Self::usage(state, user_id).saturating_sub(size);
+        state.media_usage.insert(PrincipalKey(user_id), used);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::{Post, Visibility};
+    use crate::models::user::User;
+    use crate::services::post_service::PostService;
+    use crate::utils::testing::principal;
+
+    fn asset(owner: Principal) -> Asset {
+        Asset {
+            id: "asset_1".to_string(),
+            owner,
+            mime_type: "image/png".to_string(),
+            size: 1,
+            chunk_count: 1,
+            sha256: String::new(),
+            created_at: 0,
+        }
+    }
+
+    fn show(state: &mut State, post_id: &str, author: Principal, visibility: Visibility) {
+        let post = Post::new(post_id.to_string(), author, String::new(), Some("asset_1".to_string()), visibility);
+        state.insert_post(post);
+        MediaService::attach(state, "asset_1", post_id);
+    }
+
+    #[test]
+    fn assets_follow_the_visibility_of_their_posts() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (alice, bob, stranger) = (principal(1), principal(2), Principal::anonymous());
+            state.insert_user(User::new(alice, "alice".to_string(), String::new(), String::new()));
+            state.user_following.insert((PrincipalKey(bob), PrincipalKey(alice)), ());
+            let asset = asset(alice);
+
+            // Unattached media is the owner's alone
+            assert!(MediaService::can_view_asset(state, &asset, alice));
+            assert!(!MediaService::can_view_asset(state, &asset, bob));
+
+            show(state, "post_1", alice, Visibility::FollowersOnly);
+            assert!(MediaService::can_view_asset(state, &asset, bob));
+            assert!(!MediaService::can_view_asset(state, &asset, stranger));
+
+            show(state, "post_2", alice, Visibility::Public);
+            assert!(MediaService::can_view_asset(state, &asset, stranger));
+
+            // Private accounts only show media to followers
+            state.update_user(alice, |user| user.is_private = true);
+            assert!(!MediaService::can_view_asset(state, &asset, stranger));
+            assert!(MediaService::can_view_asset(state, &asset, bob));
+        });
+    }
+
+    #[test]
+    fn deleted_posts_stop_serving_their_media() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let alice = principal(1);
+            let asset = asset(alice);
+            show(state, "post_1", alice, Visibility::Public);
+            assert!(MediaService::can_view_asset(state, &asset, Principal::anonymous()));
+
+            let post = state.get_post("post_1").unwrap();
+            PostService::delete(state, &post);
+            assert!(!MediaService::can_view_asset(state, &asset, Principal::anonymous()));
+            assert!(state.asset_posts.is_empty());
+        });
+    }
+}
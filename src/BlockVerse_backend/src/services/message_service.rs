@@ -0,0 +1,517 @@
+use candid::Principal;
+use std::ops::Bound;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::message::{self, Conversation, ConversationSummary, DmPolicy, Message, ReadReceipt};
+use crate::models::notification::NotificationKind;
+use crate::models::page::Page;
+use crate::services::moderation_service::ModerationService;
+use crate::services::notification_service::NotificationService;
+use crate::services::user_service::UserService;
+use crate::storage::pagination;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::{IdKey, PrincipalKey, RecencyKey, SequenceKey};
+use crate::utils::{clock, validation};
+
+/// Direct messages between users, one-to-one or in small groups.
+///
+/// Starting a conversation needs every recipient to accept messages from
+/// the caller: they must not have blocked the caller, and if their
+/// `DmPolicy` is `Following` they must follow the caller. Direct
+/// conversations re-check this on every message, so a later block or
+/// unfollow stops them; groups are only checked when they are created, and
+/// members who no longer want to hear from someone there leave the group.
+pub struct MessageService;
+
+impl MessageService {
+    /// Starts a conversation between the caller and `members`. With a
+    /// single other member this returns the existing direct conversation
+    /// if there is one.
+    pub fn start_conversation(members: Vec<Principal>) -> Result<Conversation, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::start(&mut state.borrow_mut(), caller, members))
+    }
+
+    fn start(state: &mut State, caller: Principal, members: Vec<Principal>) -> Result<Conversation, BlockVerseError> {
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        let mut others = Vec::new();
+        for member in members {
+            if member != caller && !others.contains(&member) {
+                others.push(member);
+            }
+        }
+        if others.is_empty() {
+            return Err(BlockVerseError::validation("members", "Must include someone besides yourself"));
+        }
+        if others.len() >= message::MAX_GROUP_MEMBERS {
+            return Err(BlockVerseError::validation("members", "Too many members"));
+        }
+
+        ModerationService::check_not_suspended(state, caller)?;
+
+        if !state.has_user(caller) {
+            return Err(BlockVerseError::not_found(EntityKind::User, caller));
+        }
+        for member in &others {
+            if !state.has_user(*member) {
+                return Err(BlockVerseError::not_found(EntityKind::User, member));
+            }
+            if state.is_remote(*member) {
+                return Err(BlockVerseError::InvalidOperation("Remote accounts can't be messaged".to_string()));
+            }
+            Self::check_can_message(state, caller, *member)?;
+        }
+
+        let is_group = others.len() > 1;
+        if !is_group {
+            let existing = state
+                .direct_conversations
+                .get(&Self::direct_key(caller, others[0]))
+                .and_then(|id| state.conversations.get(&id));
+            if let Some(conversation) = existing {
+                return Ok(conversation);
+            }
+        }
+
+        let now = clock::now();
+        let mut members = vec![caller];
+        members.extend(others);
+        let conversation = Conversation {
+            id: state.next_id("dm"),
+            members,
+            is_group,
+            created_by: caller,
+            created_at: now,
+            last_message_at: now,
+        };
+
+        if !is_group {
+            let key = Self::direct_key(conversation.members[0], conversation.members[1]);
+            state.direct_conversations.insert(key, IdKey(conversation.id.clone()));
+        }
+        let key = RecencyKey::of_conversation(&conversation);
+        for member in &conversation.members {
+            state.user_conversations.insert((PrincipalKey(*member), key.clone()), ());
+        }
+        state.conversations.insert(IdKey(conversation.id.clone()), conversation.clone());
+
+        Ok(conversation)
+    }
+
+    pub fn send_message(conversation_id: String, content: String) -> Result<Message, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::send(&mut state.borrow_mut(), caller, conversation_id, content))
+    }
+
+    fn send(
+        state: &mut State,
+        caller: Principal,
+        conversation_id: String,
+        content: String,
+    ) -> Result<Message, BlockVerseError> {
+        if !validation::is_valid_message(&content) {
+            return Err(BlockVerseError::validation(
+                "content",
+                &format!("Must be 1-{} characters", validation::MAX_MESSAGE_LENGTH),
+            ));
+        }
+
+        ModerationService::check_not_suspended(state, caller)?;
+
+        let mut conversation = Self::member_conversation(state, &conversation_id, caller)?;
+        if !conversation.is_group {
+            if let Some(recipient) = conversation.other_member(caller) {
+                Self::check_can_message(state, caller, recipient)?;
+            }
+        }
+
+        let message = Message {
+            id: state.next_sequence(),
+            conversation_id: conversation_id.clone(),
+            sender: caller,
+            content: validation::sanitize_content(&content),
+            created_at: clock::now(),
+        };
+        state
+            .messages
+            .insert((IdKey(conversation_id.clone()), SequenceKey(message.id)), message.clone());
+
+        // Move the conversation to the top of every member's list
+        let previous = RecencyKey::of_conversation(&conversation);
+        conversation.last_message_at = message.created_at;
+        let key = RecencyKey::of_conversation(&conversation);
+        for member in &conversation.members {
+            state.user_conversations.remove(&(PrincipalKey(*member), previous.clone()));
+            state.user_conversations.insert((PrincipalKey(*member), key.clone()), ());
+        }
+        state.conversations.insert(IdKey(conversation_id.clone()), conversation.clone());
+
+        Self::record_read(state, &conversation_id, caller, message.id);
+        for member in conversation.members.iter().copied().filter(|member| *member != caller) {
+            let unread = (PrincipalKey(member), IdKey(conversation_id.clone()));
+            let count = state.unread_message_counts.get(&unread).unwrap_or(0);
+            state.unread_message_counts.insert(unread, count + 1);
+
+            if !Self::is_muted(state, member, &conversation_id) {
+                NotificationService::notify(
+                    state,
+                    member,
+                    NotificationKind::Message,
+                    caller,
+                    Some(conversation_id.clone()),
+                    None,
+                );
+            }
+        }
+
+        Ok(message)
+    }
+
+    /// Messages in a conversation the caller belongs to, newest first.
+    pub fn get_messages(
+        conversation_id: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Message>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            Self::member_conversation(&state, &conversation_id, caller)?;
+
+            let conversation = IdKey(conversation_id);
+            let start = match pagination::decode_cursor::<SequenceKey>(cursor)? {
+                Some(after) => Bound::Excluded((conversation.clone(), after)),
+                None => Bound::Included((conversation.clone(), SequenceKey::default())),
+            };
+            let messages = state
+                .messages
+                .range((start, Bound::Unbounded))
+                .take_while(|((key, _), _)| *key == conversation)
+                .map(|((_, id), message)| (id, message));
+            Ok(pagination::collect_page(messages, pagination::page_size(limit), |_, message| Some(message)))
+        })
+    }
+
+    /// The caller's conversations, most recently active first.
+    pub fn get_conversations(
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<ConversationSummary>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.user_conversations, PrincipalKey(caller), cursor, limit, |entry| {
+                let conversation = state.conversations.get(&IdKey(entry.post_id.clone()))?;
+                let id = IdKey(conversation.id.clone());
+                Some(ConversationSummary {
+                    last_message: Self::last_message(&state, &conversation.id),
+                    unread_count: state.unread_message_counts.get(&(PrincipalKey(caller), id)).unwrap_or(0),
+                    muted: Self::is_muted(&state, caller, &conversation.id),
+                    conversation,
+                })
+            })
+        })
+    }
+
+    /// Marks everything in the conversation as read by the caller.
+    pub fn mark_conversation_read(conversation_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::mark_read(&mut state.borrow_mut(), caller, conversation_id))
+    }
+
+    fn mark_read(state: &mut State, caller: Principal, conversation_id: String) -> Result<(), BlockVerseError> {
+        Self::member_conversation(state, &conversation_id, caller)?;
+
+        if let Some(last) = Self::last_message(state, &conversation_id) {
+            Self::record_read(state, &conversation_id, caller, last.id);
+        }
+        state
+            .unread_message_counts
+            .remove(&(PrincipalKey(caller), IdKey(conversation_id.clone())));
+        NotificationService::mark_subject_read(state, caller, NotificationKind::Message, &conversation_id);
+        Ok(())
+    }
+
+    /// How far each member has read, for members who have read anything.
+    pub fn get_read_receipts(conversation_id: String) -> Result<Vec<ReadReceipt>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::read_receipts(&state.borrow(), caller, conversation_id))
+    }
+
+    fn read_receipts(
+        state: &State,
+        caller: Principal,
+        conversation_id: String,
+    ) -> Result<Vec<ReadReceipt>, BlockVerseError> {
+        Self::member_conversation(state, &conversation_id, caller)?;
+
+        let conversation = IdKey(conversation_id);
+        Ok(state
+            .read_receipts
+            .range((conversation.clone(), PrincipalKey::default())..)
+            .take_while(|((key, _), _)| *key == conversation)
+            .map(|(_, receipt)| receipt)
+            .collect())
+    }
+
+    /// Stops or resumes message notifications from one conversation.
+    pub fn set_conversation_muted(conversation_id: String, muted: bool) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::set_muted(&mut state.borrow_mut(), caller, conversation_id, muted))
+    }
+
+    fn set_muted(
+        state: &mut State,
+        caller: Principal,
+        conversation_id: String,
+        muted: bool,
+    ) -> Result<(), BlockVerseError> {
+        Self::member_conversation(state, &conversation_id, caller)?;
+
+        let key = (PrincipalKey(caller), IdKey(conversation_id));
+        if muted {
+            state.conversation_mutes.insert(key, ());
+        } else {
+            state.conversation_mutes.remove(&key);
+        }
+        Ok(())
+    }
+
+    /// Takes the caller out of a group conversation, along with its unread
+    /// count, receipt, mute and notifications.
+    pub fn leave_conversation(conversation_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::leave(&mut state.borrow_mut(), caller, &conversation_id))
+    }
+
+    fn leave(state: &mut State, caller: Principal, conversation_id: &str) -> Result<(), BlockVerseError> {
+        let mut conversation = Self::member_conversation(state, conversation_id, caller)?;
+        if !conversation.is_group {
+            return Err(BlockVerseError::InvalidOperation("Only group conversations can be left".to_string()));
+        }
+
+        state.user_conversations.remove(&(PrincipalKey(caller), RecencyKey::of_conversation(&conversation)));
+        conversation.members.retain(|member| *member != caller);
+        state.conversations.insert(IdKey::from(conversation_id), conversation);
+
+        let key = (PrincipalKey(caller), IdKey::from(conversation_id));
+        state.unread_message_counts.remove(&key);
+        state.conversation_mutes.remove(&key);
+        state.read_receipts.remove(&(IdKey::from(conversation_id), PrincipalKey(caller)));
+        NotificationService::mark_subject_read(state, caller, NotificationKind::Message, conversation_id);
+        Ok(())
+    }
+
+    pub fn set_dm_policy(policy: DmPolicy) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+
+            if !state.has_user(caller) {
+                return Err(BlockVerseError::not_found(EntityKind::User, caller));
+            }
+
+            match policy {
+                DmPolicy::Everyone => state.dm_policies.remove(&PrincipalKey(caller)),
+                DmPolicy::Following => state.dm_policies.insert(PrincipalKey(caller), policy),
+            };
+            Ok(())
+        })
+    }
+
+    pub fn get_dm_policy() -> DmPolicy {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            state.dm_policies.get(&PrincipalKey(caller)).unwrap_or_default()
+        })
+    }
+
+    /// Fails unless `recipient` accepts messages from `sender`.
+    fn check_can_message(state: &State, sender: Principal, recipient: Principal) -> Result<(), BlockVerseError> {
+        UserService::check_not_blocked(state, sender, recipient)?;
+        let policy = state.dm_policies.get(&PrincipalKey(recipient)).unwrap_or_default();
+        if policy == DmPolicy::Following && !state.is_following(recipient, sender) {
+            return Err(BlockVerseError::Unauthorized(
+                "This user only accepts messages from people they follow".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Loads a conversation, failing unless `user_id` is a member. Other
+    /// users get the same error as for a missing conversation.
+    fn member_conversation(
+        state: &State,
+        conversation_id: &str,
+        user_id: Principal,
+    ) -> Result<Conversation, BlockVerseError> {
+        state
+            .conversations
+            .get(&IdKey::from(conversation_id))
+            .filter(|conversation| conversation.has_member(user_id))
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Conversation, conversation_id))
+    }
+
+    fn last_message(state: &State, conversation_id: &str) -> Option<Message> {
+        let conversation = IdKey::from(conversation_id);
+        state
+            .messages
+            .range((conversation.clone(), SequenceKey::default())..)
+            .take_while(|((key, _), _)| *key == conversation)
+            .next()
+            .map(|(_, message)| message)
+    }
+
+    fn record_read(state: &mut State, conversation_id: &str, member: Principal, message_id: u64) {
+        let receipt = ReadReceipt { member, last_read: message_id, read_at: clock::now() };
+        state.read_receipts.insert((IdKey::from(conversation_id), PrincipalKey(member)), receipt);
+    }
+
+    fn is_muted(state: &State, user_id: Principal, conversation_id: &str) -> bool {
+        state
+            .conversation_mutes
+            .contains_key(&(PrincipalKey(user_id), IdKey::from(conversation_id)))
+    }
+
+    fn direct_key(a: Principal, b: Principal) -> (PrincipalKey, PrincipalKey) {
+        if a <= b {
+            (PrincipalKey(a), PrincipalKey(b))
+        } else {
+            (PrincipalKey(b), PrincipalKey(a))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::users;
+
+    fn unread(state: &State, user_id: Principal, conversation_id: &str) -> u64 {
+        state
+            .unread_message_counts
+            .get(&(PrincipalKey(user_id), IdKey::from(conversation_id)))
+            .unwrap_or(0)
+    }
+
+    fn notified(state: &State, user_id: Principal) -> u64 {
+        state.unread_notification_counts.get(&PrincipalKey(user_id)).unwrap_or(0)
+    }
+
+    #[test]
+    fn direct_conversations_are_reused_from_either_side() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol] = users(state, 3).try_into().unwrap();
+
+            let first = MessageService::start(state, alice, vec![bob]).unwrap();
+            let again = MessageService::start(state, bob, vec![alice, bob]).unwrap();
+            assert_eq!(again.id, first.id);
+            assert!(!first.is_group);
+
+            let group = MessageService::start(state, alice, vec![bob, carol]).unwrap();
+            assert_ne!(group.id, first.id);
+            assert!(group.is_group);
+        });
+    }
+
+    #[test]
+    fn following_only_policies_turn_away_strangers() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob] = users(state, 2).try_into().unwrap();
+            state.dm_policies.insert(PrincipalKey(bob), DmPolicy::Following);
+
+            let refused = MessageService::start(state, alice, vec![bob]);
+            assert!(matches!(refused, Err(BlockVerseError::Unauthorized(_))));
+            assert!(state.conversations.is_empty());
+
+            UserService::follow(state, bob, alice).unwrap();
+            MessageService::start(state, alice, vec![bob]).unwrap();
+        });
+    }
+
+    #[test]
+    fn blocking_stops_a_direct_conversation() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob] = users(state, 2).try_into().unwrap();
+            let conversation = MessageService::start(state, alice, vec![bob]).unwrap();
+            MessageService::send(state, alice, conversation.id.clone(), "hi".to_string()).unwrap();
+
+            state.user_blocks.insert((PrincipalKey(bob), PrincipalKey(alice)), ());
+            assert!(MessageService::send(state, alice, conversation.id.clone(), "hello?".to_string()).is_err());
+            assert!(MessageService::send(state, bob, conversation.id.clone(), "bye".to_string()).is_err());
+            assert!(MessageService::start(state, alice, vec![bob]).is_err());
+        });
+    }
+
+    #[test]
+    fn reading_clears_unread_counts_and_records_a_receipt() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol] = users(state, 3).try_into().unwrap();
+            let id = MessageService::start(state, alice, vec![bob, carol]).unwrap().id;
+            MessageService::send(state, alice, id.clone(), "one".to_string()).unwrap();
+            let last = MessageService::send(state, alice, id.clone(), "two".to_string()).unwrap();
+
+            assert_eq!((unread(state, alice, &id), unread(state, bob, &id)), (0, 2));
+            let receipts = MessageService::read_receipts(state, bob, id.clone()).unwrap();
+            assert_eq!(receipts.iter().map(|receipt| receipt.member).collect::<Vec<_>>(), vec![alice]);
+
+            MessageService::mark_read(state, bob, id.clone()).unwrap();
+            assert_eq!(unread(state, bob, &id), 0);
+            assert_eq!(unread(state, carol, &id), 2);
+            assert_eq!(notified(state, bob), 0);
+            let receipts = MessageService::read_receipts(state, carol, id.clone()).unwrap();
+            let bob_read = receipts.iter().find(|receipt| receipt.member == bob).map(|receipt| receipt.last_read);
+            assert_eq!(bob_read, Some(last.id));
+        });
+    }
+
+    #[test]
+    fn muted_conversations_count_unread_without_notifying() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob] = users(state, 2).try_into().unwrap();
+            let id = MessageService::start(state, alice, vec![bob]).unwrap().id;
+
+            MessageService::set_muted(state, bob, id.clone(), true).unwrap();
+            MessageService::send(state, alice, id.clone(), "hi".to_string()).unwrap();
+            assert_eq!(unread(state, bob, &id), 1);
+            assert_eq!(notified(state, bob), 0);
+
+            MessageService::set_muted(state, bob, id.clone(), false).unwrap();
+            MessageService::send(state, alice, id.clone(), "hello".to_string()).unwrap();
+            assert_eq!(notified(state, bob), 1);
+        });
+    }
+
+    #[test]
+    fn members_leave_groups_but_not_direct_conversations() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol] = users(state, 3).try_into().unwrap();
+            let direct = MessageService::start(state, alice, vec![bob]).unwrap().id;
+            let group = MessageService::start(state, alice, vec![bob, carol]).unwrap().id;
+            MessageService::send(state, alice, group.clone(), "hi".to_string()).unwrap();
+
+            assert!(MessageService::leave(state, bob, &direct).is_err());
+            MessageService::leave(state, bob, &group).unwrap();
+
+            assert_eq!(unread(state, bob, &group), 0);
+            assert!(!state.conversations.get(&IdKey::from(group.as_str())).unwrap().has_member(bob));
+            assert!(MessageService::send(state, bob, group.clone(), "back".to_string()).is_err());
+            MessageService::send(state, alice, group.clone(), "still here".to_string()).unwrap();
+            assert_eq!(unread(state, bob, &group), 0);
+            assert_eq!(unread(state, carol, &group), 2);
+        });
+    }
+}
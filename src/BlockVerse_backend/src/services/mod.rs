@@ -1,4 +1,17 @@
 pub mod user_service;
 pub mod post_service;
 pub mod comment_service;
-pub mod payment_service;
\ No newline at end of file
+pub mod payment_service;
+pub mod ledger;
+pub mod timeline_service;
+pub mod search_service;
+pub mod role_service;
+pub mod moderation_service;
+pub mod purge_service;
+pub mod notification_service;
+pub mod tag_service;
+pub mod message_service;
+pub mod media_service;
+pub mod http_service;
+pub mod feed_service;
+pub mod federation_service;
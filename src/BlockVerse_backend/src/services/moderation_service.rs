@@ -0,0 +1,475 @@
+use candid::Principal;
+use std::ops::Bound;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::moderation::{
+    Appeal, AppealStatus, Decision, ModerationAction, Report, ReportReason, ReportStatus, ReportTarget,
+};
+use crate::models::page::Page;
+use crate::services::comment_service::CommentService;
+use crate::services::post_service::PostService;
+use crate::storage::pagination;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::{IdKey, PrincipalKey, SequenceKey};
+use crate::utils::{clock, validation};
+
+/// Handles user reports, the moderators' review queue, the decision log
+/// and appeals against decisions.
+pub struct ModerationService;
+
+impl ModerationService {
+    pub fn report(target: ReportTarget, reason: ReportReason, details: String) -> Result<Report, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        if !validation::is_valid_note(&details) {
+            return Err(BlockVerseError::validation("details", "Too long"));
+        }
+
+        STATE.with(|state| Self::file_report(&mut state.borrow_mut(), caller, target, reason, details))
+    }
+
+    /// Open reports, oldest first.
+    pub fn get_report_queue(cursor: Option<String>, limit: usize) -> Result<Page<Report>, BlockVerseError> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let start = match pagination::decode_cursor::<IdKey>(cursor)? {
+                Some(after) => Bound::Excluded(after),
+                None => Bound::Unbounded,
+            };
+
+            let open = state.open_reports.range((start, Bound::Unbounded));
+            Ok(pagination::collect_page(open, pagination::page_size(limit), |report_id, _| {
+                state.reports.get(report_id)
+            }))
+        })
+    }
+
+    /// Applies `action` to what the report is about, logs the decision and
+    /// closes every open report against the same target.
+    pub fn resolve_report(
+        report_id: String,
+        action: ModerationAction,
+        note: String,
+    ) -> Result<Decision, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if !validation::is_valid_note(&note) {
+            return Err(BlockVerseError::validation("note", "Too long"));
+        }
+
+        STATE.with(|state| Self::resolve(&mut state.borrow_mut(), caller, report_id, action, note))
+    }
+
+    /// The whole moderation log, newest first.
+    pub fn get_decisions(cursor: Option<String>, limit: usize) -> Result<Page<Decision>, BlockVerseError> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let end = match pagination::decode_cursor::<SequenceKey>(cursor)? {
+                Some(after) => after.0,
+                None => state.decisions.len(),
+            };
+
+            let decisions = (0..end)
+                .rev()
+                .filter_map(|position| Some((SequenceKey(position), state.decisions.get(position)?)));
+            Ok(pagination::collect_page(decisions, pagination::page_size(limit), |_, decision| Some(decision)))
+        })
+    }
+
+    /// Decisions that applied to `user_id`, newest first.
+    pub fn get_user_decisions(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Decision>, BlockVerseError> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.subject_decisions, PrincipalKey(user_id), cursor, limit, |position| {
+                state.decisions.get(position.0)
+            })
+        })
+    }
+
+    /// Lets the subject of a decision contest it, once.
+    pub fn appeal_decision(decision_id: u64, statement: String) -> Result<Appeal, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if statement.trim().is_empty() {
+            return Err(BlockVerseError::validation("statement", "Appeal cannot be empty"));
+        }
+
+        if !validation::is_valid_note(&statement) {
+            return Err(BlockVerseError::validation("statement", "Too long"));
+        }
+
+        STATE.with(|state| Self::appeal(&mut state.borrow_mut(), caller, decision_id, statement))
+    }
+
+    /// Pending appeals, oldest decision first.
+    pub fn get_appeal_queue(cursor: Option<String>, limit: usize) -> Result<Page<Appeal>, BlockVerseError> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let start = match pagination::decode_cursor::<u64>(cursor)? {
+                Some(after) => Bound::Excluded(after),
+                None => Bound::Unbounded,
+            };
+
+            let pending = state.pending_appeals.range((start, Bound::Unbounded));
+            Ok(pagination::collect_page(pending, pagination::page_size(limit), |decision_id, _| {
+                state.appeals.get(decision_id)
+            }))
+        })
+    }
+
+    /// Closes an appeal. Overturning lifts a hide or a suspension; removals
+    /// and warnings can't be undone, so for those it only records the outcome.
+    pub fn resolve_appeal(decision_id: u64, overturn: bool, response: String) -> Result<Appeal, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if !validation::is_valid_note(&response) {
+            return Err(BlockVerseError::validation("response", "Too long"));
+        }
+
+        STATE.with(|state| Self::review_appeal(&mut state.borrow_mut(), caller, decision_id, overturn, response))
+    }
+
+    fn file_report(
+        state: &mut State,
+        caller: Principal,
+        target: ReportTarget,
+        reason: ReportReason,
+        details: String,
+    ) -> Result<Report, BlockVerseError> {
+        if !state.has_user(caller) {
+            return Err(BlockVerseError::not_found(EntityKind::User, caller));
+        }
+        Self::subject_of(state, &target)?;
+
+        let target_key = IdKey(target.key());
+        if state.reporter_targets.contains_key(&(PrincipalKey(caller), target_key.clone())) {
+            return Err(BlockVerseError::already_exists(EntityKind::Report, &target_key.0));
+        }
+
+        let report = Report {
+            id: state.next_id("report"),
+            reporter: caller,
+            target,
+            reason,
+            details,
+            status: ReportStatus::Open,
+            created_at: clock::now(),
+            decision_id: None,
+        };
+
+        let report_id = IdKey(report.id.clone());
+        state.reports.insert(report_id.clone(), report.clone());
+        state.open_reports.insert(report_id.clone(), ());
+        state.target_reports.insert((target_key.clone(), report_id), ());
+        state.reporter_targets.insert((PrincipalKey(caller), target_key), ());
+
+        Ok(report)
+    }
+
+    fn resolve(
+        state: &mut State,
+        caller: Principal,
+        report_id: String,
+        action: ModerationAction,
+        note: String,
+    ) -> Result<Decision, BlockVerseError> {
+        let report = state
+            .reports
+            .get(&IdKey(report_id.clone()))
+            .filter(|report| report.status == ReportStatus::Open)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Report, &report_id))?;
+
+        let subject = Self::subject_of(state, &report.target)?;
+        Self::apply(state, &report.target, subject, &action)?;
+        let decision = Self::log(state, caller, report.target.clone(), subject, action, note);
+
+        let target_key = IdKey(report.target.key());
+        let reports: Vec<_> = state
+            .target_reports
+            .range((target_key.clone(), IdKey::default())..)
+            .take_while(|((key, _), _)| *key == target_key)
+            .map(|((_, report_id), _)| report_id)
+            .collect();
+        for report_id in reports {
+            if state.open_reports.remove(&report_id).is_none() {
+                continue;
+            }
+            if let Some(mut report) = state.reports.get(&report_id) {
+                state.reporter_targets.remove(&(PrincipalKey(report.reporter), target_key.clone()));
+                report.status = ReportStatus::Resolved;
+                report.decision_id = Some(decision.id);
+                state.reports.insert(report_id, report);
+            }
+        }
+
+        Ok(decision)
+    }
+
+    fn appeal(
+        state: &mut State,
+        caller: Principal,
+        decision_id: u64,
+        statement: String,
+    ) -> Result<Appeal, BlockVerseError> {
+        let decision = state
+            .decisions
+            .get(decision_id)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Decision, decision_id))?;
+
+        if decision.subject != caller {
+            return Err(BlockVerseError::Unauthorized("Only the affected user can appeal".to_string()));
+        }
+        if decision.action == ModerationAction::Dismiss {
+            return Err(BlockVerseError::InvalidOperation("Dismissals cannot be appealed".to_string()));
+        }
+        if state.appeals.contains_key(&decision_id) {
+            return Err(BlockVerseError::already_exists(EntityKind::Appeal, decision_id));
+        }
+
+        let appeal = Appeal {
+            decision_id,
+            appellant: caller,
+            statement,
+            status: AppealStatus::Pending,
+            reviewer: None,
+            response: None,
+            created_at: clock::now(),
+            resolved_at: None,
+        };
+
+        state.appeals.insert(decision_id, appeal.clone());
+        state.pending_appeals.insert(decision_id, ());
+        Ok(appeal)
+    }
+
+    fn review_appeal(
+        state: &mut State,
+        caller: Principal,
+        decision_id: u64,
+        overturn: bool,
+        response: String,
+    ) -> Result<Appeal, BlockVerseError> {
+        let mut appeal = state
+            .appeals
+            .get(&decision_id)
+            .filter(|appeal| appeal.status == AppealStatus::Pending)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Appeal, decision_id))?;
+        let decision = state
+            .decisions
+            .get(decision_id)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Decision, decision_id))?;
+
+        if overturn {
+            match (&decision.action, &decision.target) {
+                (ModerationAction::Hide, ReportTarget::Post(id) | ReportTarget::Comment(id)) => {
+                    state.hidden_content.remove(&IdKey(id.clone()));
+                }
+                (ModerationAction::Suspend { .. }, _) => {
+                    state.suspensions.remove(&PrincipalKey(decision.subject));
+                }
+                _ => {}
+            }
+        }
+
+        appeal.status = if overturn { AppealStatus::Overturned } else { AppealStatus::Upheld };
+        appeal.reviewer = Some(caller);
+        appeal.response = Some(response);
+        appeal.resolved_at = Some(clock::now());
+
+        state.appeals.insert(decision_id, appeal.clone());
+        state.pending_appeals.remove(&decision_id);
+        Ok(appeal)
+    }
+
+    /// Fails if `user_id` is currently suspended. Call before letting a user
+    /// post, comment or interact with others.
+    pub fn check_not_suspended(state: &State, user_id: Principal) -> Result<(), BlockVerseError> {
+        match state.suspensions.get(&PrincipalKey(user_id)) {
+            Some(until) if until > clock::now() => Err(BlockVerseError::Suspended { until }),
+            _ => Ok(()),
+        }
+    }
+
+    /// The user a target belongs to; fails if the target doesn't exist.
+    /// Tombstones still count, so reports on deleted content can be closed.
+    fn subject_of(state: &State, target: &ReportTarget) -> Result<Principal, BlockVerseError> {
+        match target {
+            ReportTarget::Post(post_id) => state
+                .get_post(post_id)
+                .map(|post| post.author)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, post_id)),
+            ReportTarget::Comment(comment_id) => state
+                .get_comment(comment_id)
+                .map(|comment| comment.author)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Comment, comment_id)),
+            ReportTarget::User(user_id) => state
+                .has_user(*user_id)
+                .then_some(*user_id)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::User, user_id)),
+        }
+    }
+
+    fn apply(
+        state: &mut State,
+        target: &ReportTarget,
+        subject: Principal,
+        action: &ModerationAction,
+    ) -> Result<(), BlockVerseError> {
+        match (action, target) {
+            (ModerationAction::Hide, ReportTarget::Post(id) | ReportTarget::Comment(id)) => {
+                state.hidden_content.insert(IdKey(id.clone()), ());
+            }
+            (ModerationAction::Remove, ReportTarget::Post(post_id)) => {
+                if let Some(post) = state.get_live_post(post_id) {
+                    PostService::delete(state, &post);
+                }
+            }
+            (ModerationAction::Remove, ReportTarget::Comment(comment_id)) => {
+                if let Some(comment) = state.get_live_comment(comment_id) {
+                    CommentService::delete(state, &comment);
+                }
+            }
+            (ModerationAction::Hide | ModerationAction::Remove, ReportTarget::User(_)) => {
+                return Err(BlockVerseError::validation("action", "Users can only be warned or suspended"));
+            }
+            (ModerationAction::Suspend { duration_ns }, _) => {
+                if *duration_ns == 0 {
+                    return Err(BlockVerseError::validation("duration_ns", "Must be greater than 0"));
+                }
+                let until = clock::now().saturating_add(*duration_ns);
+                let current = state.suspensions.get(&PrincipalKey(subject)).unwrap_or(0);
+                state.suspensions.insert(PrincipalKey(subject), until.max(current));
+            }
+            (ModerationAction::Warn | ModerationAction::Dismiss, _) => {}
+        }
+        Ok(())
+    }
+
+    fn log(
+        state: &mut State,
+        moderator: Principal,
+        target: ReportTarget,
+        subject: Principal,
+        action: ModerationAction,
+        note: String,
+    ) -> Decision {
+        let decision = Decision {
+            id: state.decisions.len(),
+            moderator,
+            target,
+            subject,
+            action,
+            note,
+            created_at: clock::now(),
+        };
+
+        state.decisions.push(&decision).expect("Failed to record decision");
+        if decision.action != ModerationAction::Dismiss {
+            state.subject_decisions.insert((PrincipalKey(subject), SequenceKey(decision.id)), ());
+        }
+        decision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::{Post, Visibility};
+    use crate::models::user::User;
+    use crate::utils::testing::principal;
+
+    /// An author with a post and two users to report it.
+    fn setup(state: &mut State) -> (Principal, Principal, Principal) {
+        let (author, first, second) = (principal(1), principal(2), principal(3));
+        for (user_id, username) in [(author, "author"), (first, "first"), (second, "second")] {
+            state.insert_user(User::new(user_id, username.to_string(), String::new(), String::new()));
+        }
+        state.insert_post(Post::new("post_1".to_string(), author, "hello".to_string(), None, Visibility::Public));
+        (author, first, second)
+    }
+
+    fn report(state: &mut State, reporter: Principal) -> Result<Report, BlockVerseError> {
+        let target = ReportTarget::Post("post_1".to_string());
+        ModerationService::file_report(state, reporter, target, ReportReason::Spam, String::new())
+    }
+
+    #[test]
+    fn resolving_a_report_closes_every_report_on_the_target() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (author, first, second) = setup(state);
+            let moderator = principal(9);
+            let report_id = report(state, first).unwrap().id;
+            assert!(matches!(report(state, first), Err(BlockVerseError::AlreadyExists { .. })));
+            report(state, second).unwrap();
+
+            let decision =
+                ModerationService::resolve(state, moderator, report_id, ModerationAction::Hide, String::new()).unwrap();
+
+            assert_eq!((decision.subject, decision.moderator), (author, moderator));
+            assert!(state.hidden_content.contains_key(&IdKey::from("post_1")));
+            assert!(state.open_reports.is_empty());
+            assert!(state.reports.iter().all(|(_, report)| report.decision_id == Some(decision.id)));
+            // Reporters can report the target again once it was acted on
+            report(state, first).unwrap();
+        });
+    }
+
+    #[test]
+    fn subjects_can_appeal_once_and_overturning_lifts_a_suspension() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (author, first, _) = setup(state);
+            let moderator = principal(9);
+            let report_id = report(state, first).unwrap().id;
+            let suspend = ModerationAction::Suspend { duration_ns: 60_000_000_000 };
+            let decision = ModerationService::resolve(state, moderator, report_id, suspend, String::new()).unwrap();
+            assert!(matches!(
+                ModerationService::check_not_suspended(state, author),
+                Err(BlockVerseError::Suspended { .. })
+            ));
+
+            let statement = || "It was a joke".to_string();
+            assert!(matches!(
+                ModerationService::appeal(state, first, decision.id, statement()),
+                Err(BlockVerseError::Unauthorized(_))
+            ));
+            ModerationService::appeal(state, author, decision.id, statement()).unwrap();
+            assert!(matches!(
+                ModerationService::appeal(state, author, decision.id, statement()),
+                Err(BlockVerseError::AlreadyExists { .. })
+            ));
+
+            let appeal = ModerationService::review_appeal(state, moderator, decision.id, true, String::new()).unwrap();
+            assert_eq!(appeal.status, AppealStatus::Overturned);
+            assert!(ModerationService::check_not_suspended(state, author).is_ok());
+            assert!(state.pending_appeals.is_empty());
+        });
+    }
+
+    #[test]
+    fn dismissals_are_logged_but_not_held_against_the_subject() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (author, first, _) = setup(state);
+            let report_id = report(state, first).unwrap().id;
+            let decision =
+                ModerationService::resolve(state, principal(9), report_id, ModerationAction::Dismiss, String::new())
+                    .unwrap();
+
+            assert_eq!(state.decisions.len(), 1);
+            assert!(state.subject_decisions.is_empty());
+            assert!(matches!(
+                ModerationService::appeal(state, author, decision.id, "Why?".to_string()),
+                Err(BlockVerseError::InvalidOperation(_))
+            ));
+        });
+    }
+}
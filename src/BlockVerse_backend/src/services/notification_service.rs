@@ -0,0 +1,358 @@
+use candid::Principal;
+use std::ops::Bound;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::notification::{Notification, NotificationKind};
+use crate::models::page::Page;
+use crate::storage::pagination;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::{IdKey, PrincipalKey, SequenceKey};
+use crate::utils::clock;
+
+/// Actors kept on a grouped notification; the rest only add to its count.
+const MAX_GROUP_ACTORS: usize = 3;
+
+/// Per-user notification inbox.
+///
+/// While a notification is unread, further events of the same kind about
+/// the same subject are folded into it instead of adding new entries, and
+/// count once per actor. Once it has been read, the next event starts a
+/// new group.
+pub struct NotificationService;
+
+impl NotificationService {
+    /// Tells `recipient` that `actor` did something, unless it was their
+    /// own doing or they muted `kind`. Remote accounts aren't notified.
+    pub fn notify(
+        state: &mut State,
+        recipient: Principal,
+        kind: NotificationKind,
+        actor: Principal,
+        subject: Option<String>,
+        amount: Option<u64>,
+    ) {
+        if recipient == actor
+            || !state.has_user(recipient)
+            || state.is_remote(recipient)
+            || Self::is_muted(state, recipient, kind)
+        {
+            return;
+        }
+
+        let now = clock::now();
+        let group = (PrincipalKey(recipient), Self::group_key(kind, subject.as_deref()));
+        let new_actor = state.unread_notification_actors.insert((group.clone(), PrincipalKey(actor)), ()).is_none();
+        let grouped = state
+            .unread_notification_groups
+            .get(&group)
+            .and_then(|id| state.notifications.remove(&(PrincipalKey(recipient), SequenceKey(id))));
+
+        let notification = match grouped {
+            Some(mut notification) => {
+                notification.actors.retain(|existing| *existing != actor);
+                notification.actors.insert(0, actor);
+                notification.actors.truncate(MAX_GROUP_ACTORS);
+                if new_actor {
+                    notification.count += 1;
+                }
+                notification.amount = notification.amount.zip(amount).map(|(total, amount)| total + amount);
+                notification.id = state.next_sequence();
+                notification.updated_at = now;
+                notification
+            }
+            None => {
+                let count = state.unread_notification_counts.get(&PrincipalKey(recipient)).unwrap_or(0);
+                state.unread_notification_counts.insert(PrincipalKey(recipient), count + 1);
+                Notification {
+                    id: state.next_sequence(),
+                    kind,
+                    subject,
+                    actors: vec![actor],
+                    count: 1,
+                    amount,
+                    read: false,
+                    created_at: now,
+                    updated_at: now,
+                }
+            }
+        };
+
+        state.unread_notification_groups.insert(group, notification.id);
+        state.notifications.insert((PrincipalKey(recipient), SequenceKey(notification.id)), notification);
+    }
+
+    /// Takes `actor` back out of `recipient`'s unread notification of `kind`
+    /// about `subject`, e.g. on unlike. A notification left without actors
+    /// is removed; read ones are left as they were.
+    pub fn retract(state: &mut State, recipient: Principal, kind: NotificationKind, actor: Principal, subject: &str) {
+        let group = (PrincipalKey(recipient), Self::group_key(kind, Some(subject)));
+        if state.unread_notification_actors.remove(&(group.clone(), PrincipalKey(actor))).is_none() {
+            return;
+        }
+        let Some(id) = state.unread_notification_groups.get(&group) else {
+            return;
+        };
+        let key = (PrincipalKey(recipient), SequenceKey(id));
+        let Some(mut notification) = state.notifications.get(&key) else {
+            return;
+        };
+
+        notification.count = notification.count.saturating_sub(1);
+        if notification.count > 0 {
+            notification.actors.retain(|existing| *existing != actor);
+            let others: Vec<_> = Self::group_actors(state, &group)
+                .into_iter()
+                .map(|(_, other)| other.0)
+                .filter(|other| !notification.actors.contains(other))
+                .take(MAX_GROUP_ACTORS.saturating_sub(notification.actors.len()))
+                .collect();
+            notification.actors.extend(others);
+            state.notifications.insert(key, notification);
+            return;
+        }
+
+        state.notifications.remove(&key);
+        state.unread_notification_groups.remove(&group);
+        let count = state.unread_notification_counts.get(&PrincipalKey(recipient)).unwrap_or(0);
+        state.unread_notification_counts.insert(PrincipalKey(recipient), count.saturating_sub(1));
+    }
+
+    /// The caller's inbox, most recently updated first.
+    pub fn get_notifications(cursor: Option<String>, limit: usize) -> Result<Page<Notification>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            let owner = PrincipalKey(caller);
+            let start = match pagination::decode_cursor::<SequenceKey>(cursor)? {
+                Some(after) => Bound::Excluded((owner, after)),
+                None => Bound::Included((owner, SequenceKey::default())),
+            };
+            let notifications = state
+                .notifications
+                .range((start, Bound::Unbounded))
+                .take_while(|((recipient, _), _)| *recipient == owner)
+                .map(|((_, id), notification)| (id, notification));
+            Ok(pagination::collect_page(notifications, pagination::page_size(limit), |_, notification| {
+                Some(notification)
+            }))
+        })
+    }
+
+    pub fn get_unread_count() -> u64 {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            state.unread_notification_counts.get(&PrincipalKey(caller)).unwrap_or(0)
+        })
+    }
+
+    /// Marks the given notifications read. Ids that no longer exist, for
+    /// example because more events were grouped in since, are skipped.
+    pub fn mark_read(ids: Vec<u64>) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            for id in ids {
+                Self::mark_one_read(&mut state, caller, id);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn mark_all_read() -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let owner = PrincipalKey(caller);
+            let unread: Vec<_> = state
+                .unread_notification_groups
+                .range((owner, IdKey::default())..)
+                .take_while(|((recipient, _), _)| *recipient == owner)
+                .map(|(_, id)| id)
+                .collect();
+            for id in unread {
+                Self::mark_one_read(&mut state, caller, id);
+            }
+            Ok(())
+        })
+    }
+
+    /// Marks `recipient`'s unread notification of `kind` about `subject`
+    /// read, if there is one.
+    pub fn mark_subject_read(state: &mut State, recipient: Principal, kind: NotificationKind, subject: &str) {
+        let group = (PrincipalKey(recipient), Self::group_key(kind, Some(subject)));
+        if let Some(id) = state.unread_notification_groups.get(&group) {
+            Self::mark_one_read(state, recipient, id);
+        }
+    }
+
+    /// Stops or resumes notifications of `kind` for the caller.
+    pub fn set_muted(kind: NotificationKind, muted: bool) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+
+            if !state.has_user(caller) {
+                return Err(BlockVerseError::not_found(EntityKind::User, caller));
+            }
+
+            let key = (PrincipalKey(caller), IdKey::from(kind.key()));
+            if muted {
+                state.notification_mutes.insert(key, ());
+            } else {
+                state.notification_mutes.remove(&key);
+            }
+            Ok(())
+        })
+    }
+
+    pub fn get_muted() -> Vec<NotificationKind> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            NotificationKind::ALL
+                .into_iter()
+                .filter(|kind| Self::is_muted(&state, caller, *kind))
+                .collect()
+        })
+    }
+
+    fn is_muted(state: &State, user_id: Principal, kind: NotificationKind) -> bool {
+        state.notification_mutes.contains_key(&(PrincipalKey(user_id), IdKey::from(kind.key())))
+    }
+
+    pub fn group_key(kind: NotificationKind, subject: Option<&str>) -> IdKey {
+        IdKey(format!("{}:{}", kind.key(), subject.unwrap_or_default()))
+    }
+
+    fn group_actors(state: &State, group: &(PrincipalKey, IdKey)) -> Vec<((PrincipalKey, IdKey), PrincipalKey)> {
+        state
+            .unread_notification_actors
+            .range((group.clone(), PrincipalKey::default())..)
+            .take_while(|((grouped, _), _)| grouped == group)
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    fn mark_one_read(state: &mut State, recipient: Principal, id: u64) {
+        let key = (PrincipalKey(recipient), SequenceKey(id));
+        let Some(mut notification) = state.notifications.get(&key).filter(|notification| !notification.read) else {
+            return;
+        };
+
+        notification.read = true;
+        let group = (PrincipalKey(recipient), Self::group_key(notification.kind, notification.subject.as_deref()));
+        state.unread_notification_groups.remove(&group);
+        for key in Self::group_actors(state, &group) {
+            state.unread_notification_actors.remove(&key);
+        }
+        state.notifications.insert(key, notification);
+
+        let count = state.unread_notification_counts.get(&PrincipalKey(recipient)).unwrap_or(0);
+        state.unread_notification_counts.insert(PrincipalKey(recipient), count.saturating_sub(1));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::User;
+    use crate::utils::testing::principal;
+
+    fn inbox(state: &State, recipient: Principal) -> Vec<Notification> {
+        state
+            .notifications
+            .iter()
+            .filter(|((owner, _), _)| owner.0 == recipient)
+            .map(|(_, notification)| notification)
+            .collect()
+    }
+
+    fn setup(state: &mut State) -> [Principal; 4] {
+        let users = [principal(1), principal(2), principal(3), principal(4)];
+        for (n, user_id) in users.iter().enumerate() {
+            state.insert_user(User::new(*user_id, format!("user{}", n), String::new(), String::new()));
+        }
+        users
+    }
+
+    #[test]
+    fn groups_count_each_actor_once() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol, _] = setup(state);
+            let like = |state: &mut State, actor| {
+                NotificationService::notify(state, alice, NotificationKind::Like, actor, Some("post_1".to_string()), None)
+            };
+            like(state, bob);
+            like(state, carol);
+            like(state, bob);
+
+            let [grouped] = inbox(state, alice).try_into().unwrap();
+            assert_eq!(grouped.count, 2);
+            assert_eq!(grouped.actors, [bob, carol]);
+            assert_eq!(state.unread_notification_counts.get(&PrincipalKey(alice)), Some(1));
+
+            // Once read, the same actor starts a new group
+            NotificationService::mark_subject_read(state, alice, NotificationKind::Like, "post_1");
+            like(state, bob);
+            let unread: Vec<_> = inbox(state, alice).into_iter().filter(|notification| !notification.read).collect();
+            assert_eq!(unread.len(), 1);
+            assert_eq!(unread[0].count, 1);
+        });
+    }
+
+    #[test]
+    fn retracting_the_last_actor_removes_the_notification() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol, dave] = setup(state);
+            for actor in [bob, carol, dave] {
+                NotificationService::notify(state, alice, NotificationKind::Like, actor, Some("post_1".to_string()), None);
+            }
+
+            NotificationService::retract(state, alice, NotificationKind::Like, dave, "post_1");
+            NotificationService::retract(state, alice, NotificationKind::Like, dave, "post_1");
+            let [grouped] = inbox(state, alice).try_into().unwrap();
+            assert_eq!(grouped.count, 2);
+            assert!(!grouped.actors.contains(&dave));
+
+            NotificationService::retract(state, alice, NotificationKind::Like, bob, "post_1");
+            NotificationService::retract(state, alice, NotificationKind::Like, carol, "post_1");
+            assert!(inbox(state, alice).is_empty());
+            assert_eq!(state.unread_notification_counts.get(&PrincipalKey(alice)), Some(0));
+            assert!(state.unread_notification_actors.is_empty());
+        });
+    }
+
+    #[test]
+    fn own_actions_and_muted_kinds_are_not_delivered() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, _, _] = setup(state);
+            state.notification_mutes.insert((PrincipalKey(alice), IdKey::from(NotificationKind::Follow.key())), ());
+
+            NotificationService::notify(state, alice, NotificationKind::Like, alice, Some("post_1".to_string()), None);
+            NotificationService::notify(state, alice, NotificationKind::Follow, bob, None, None);
+            assert!(inbox(state, alice).is_empty());
+
+            NotificationService::notify(state, alice, NotificationKind::Tip, bob, None, Some(5));
+            NotificationService::notify(state, alice, NotificationKind::Tip, bob, None, Some(7));
+            let [tip] = inbox(state, alice).try_into().unwrap();
+            assert_eq!((tip.count, tip.amount), (1, Some(12)));
+        });
+    }
+}
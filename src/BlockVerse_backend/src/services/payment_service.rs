@@ -1,96 +1,954 @@
 use candid::Principal;
-use crate::storage::state::STATE;
+use std::ops::Bound;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::notification::NotificationKind;
+use crate::models::page::Page;
+use crate::models::role::Role;
+use crate::models::transaction::{PendingWithdrawal, Transaction, TransactionTotals, TransactionType};
+use crate::services::ledger::{self, Account, Ledger, LedgerApi, TransferFailure};
+use crate::services::moderation_service::ModerationService;
+use crate::services::notification_service::NotificationService;
+use crate::services::role_service::RoleService;
+use crate::services::user_service::UserService;
+use crate::storage::pagination;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::{IdKey, PrincipalKey, SequenceKey};
+use crate::utils::clock;
 
+/// Prefix of the ledger memo of a tip pulled with `icrc2_transfer_from`,
+/// followed by a sequence number unique to the tip.
+const TIP_MEMO: &[u8] = b"blockverse:tip:";
+
+/// Prefix of the ledger memo of a deposit sweep, followed by a sequence
+/// number unique to the claim.
+const DEPOSIT_MEMO: &[u8] = b"blockverse:deposit:";
+
+/// Prefix of the ledger memo of a withdrawal, followed by its id.
+const WITHDRAWAL_MEMO: &[u8] = b"blockverse:withdraw:";
+
+/// Moves ledger tokens in and out of the canister.
+///
+/// `User.balance` is the part of this canister's main ledger account that
+/// belongs to a user. It is funded by claiming deposits sent to the user's
+/// deposit subaccount and by tips received, and drained by tips sent and
+/// withdrawals.
 pub struct PaymentService;
 
 impl PaymentService {
-    pub fn tip_user(user_id: Principal, amount: u64) -> Result<(), String> {
+    /// Tips `user_id` out of the caller's balance when it covers `amount`,
+    /// and otherwise pulls `amount` from the caller's wallet, which must
+    /// have approved this canister as an ICRC-2 spender.
+    pub async fn tip_user(user_id: Principal, amount: u64) -> Result<(), BlockVerseError> {
+        Self::tip(ic_cdk::caller(), user_id, amount, None).await
+    }
+
+    /// Tips the author of `post_id` like `tip_user`, crediting the tip to
+    /// the post.
+    pub async fn tip_post(post_id: String, amount: u64) -> Result<(), BlockVerseError> {
         let caller = ic_cdk::caller();
+        let author = STATE.with(|state| {
+            let state = state.borrow();
+            state
+                .get_live_post(&post_id)
+                .filter(|post| state.can_view_post(post, caller))
+                .map(|post| post.author)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))
+        })?;
+
+        Self::tip(caller, author, amount, Some(post_id)).await
+    }
 
+    async fn tip(
+        caller: Principal,
+        user_id: Principal,
+        amount: u64,
+        post_id: Option<String>,
+    ) -> Result<(), BlockVerseError> {
+        let ledger = STATE.with(|state| Self::ledger(&state.borrow()))?;
+        Self::tip_with(&ledger, ic_cdk::id(), caller, user_id, amount, post_id).await
+    }
+
+    async fn tip_with(
+        ledger: &impl LedgerApi,
+        canister_id: Principal,
+        caller: Principal,
+        user_id: Principal,
+        amount: u64,
+        post_id: Option<String>,
+    ) -> Result<(), BlockVerseError> {
         if caller == Principal::anonymous() {
-            return Err("Anonymous users cannot send tips".to_string());
+            return Err(BlockVerseError::AnonymousCaller);
         }
 
         if amount == 0 {
-            return Err("Tip amount must be greater than 0".to_string());
+            return Err(BlockVerseError::validation("amount", "Must be greater than 0"));
         }
 
         if caller == user_id {
-            return Err("Cannot tip yourself".to_string());
+            return Err(BlockVerseError::InvalidOperation("Cannot tip yourself".to_string()));
         }
 
-        STATE.with(|state| {
+        let memo = STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
+
+            ModerationService::check_not_suspended(&state, caller)?;
+
             // Check if both users exist
-            if !state.users.contains_key(&caller) || !state.users.contains_key(&user_id) {
-                return Err("One or both users not found".to_string());
+            for user_id in [caller, user_id] {
+                if !state.has_user(user_id) {
+                    return Err(BlockVerseError::not_found(EntityKind::User, user_id));
+                }
             }
 
-            // Check if sender has enough balance
-            let sender_balance = state.users.get(&caller).unwrap().balance;
-            if sender_balance < amount {
-                return Err("Insufficient balance".to_string());
-            }
+            UserService::check_not_blocked(&state, caller, user_id)?;
+
+            // Pay from the deposited balance if it covers the tip
+            let paid = state
+                .update_user(caller, |sender| {
+                    let covered = sender.balance >= amount;
+                    if covered {
+                        sender.balance -= amount;
+                    }
+                    covered
+                })
+                .unwrap_or(false);
 
-            // Transfer the tip
-            if let Some(sender) = state.users.get_mut(&caller) {
-                sender.balance -= amount;
+            // Otherwise the tip is pulled under a memo of its own, so the
+            // ledger can't take it for another tip in the same round
+            Ok((!paid).then(|| [TIP_MEMO, &state.next_sequence().to_be_bytes()].concat()))
+        })?;
+
+        let block_index = match memo {
+            Some(memo) => {
+                Some(ledger.transfer_from(Account::new(caller), Account::new(canister_id), amount, memo).await?)
             }
-            
-            if let Some(recipient) = state.users.get_mut(&user_id) {
-                recipient.balance += amount;
+            None => None,
+        };
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.update_user(user_id, |recipient| recipient.balance += amount);
+            Self::record(&mut state, caller, user_id, amount, TransactionType::Tip, block_index, post_id.clone());
+            NotificationService::notify(&mut state, user_id, NotificationKind::Tip, caller, post_id, Some(amount));
+        });
+
+        Ok(())
+    }
+
+    /// The account the caller sends tokens to before `claim_deposit`.
+    pub fn get_deposit_account() -> Account {
+        Account {
+            owner: ic_cdk::id(),
+            subaccount: Some(ledger::deposit_subaccount(ic_cdk::caller())),
+        }
+    }
+
+    /// Sweeps whatever sits in the caller's deposit subaccount into the
+    /// canister's main account and credits it, less the ledger fee, to the
+    /// caller's balance. Returns the amount credited.
+    pub async fn claim_deposit() -> Result<u64, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        let ledger = STATE.with(|state| Self::ledger(&state.borrow()))?;
+        Self::claim_deposit_with(&ledger, ic_cdk::id(), caller).await
+    }
+
+    async fn claim_deposit_with(
+        ledger: &impl LedgerApi,
+        canister_id: Principal,
+        caller: Principal,
+    ) -> Result<u64, BlockVerseError> {
+        if STATE.with(|state| !state.borrow().has_user(caller)) {
+            return Err(BlockVerseError::not_found(EntityKind::User, caller));
+        }
+
+        let deposit = Account {
+            owner: canister_id,
+            subaccount: Some(ledger::deposit_subaccount(caller)),
+        };
+        let fee = ledger.fee().await?;
+        let balance = ledger.balance_of(deposit.clone()).await?;
+        if balance <= fee {
+            return Err(BlockVerseError::InvalidOperation("Nothing to claim".to_string()));
+        }
+
+        // A memo of its own keeps a concurrent claim of the same deposit from
+        // being answered with this sweep's block and credited again
+        let amount = balance - fee;
+        let memo = STATE.with(|state| [DEPOSIT_MEMO, &state.borrow_mut().next_sequence().to_be_bytes()].concat());
+        let block_index = ledger
+            .transfer(deposit.subaccount, Account::new(canister_id), amount, fee, Some(memo), clock::now())
+            .await?;
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.update_user(caller, |user| user.balance += amount);
+            Self::record(&mut state, caller, caller, amount, TransactionType::Deposit, Some(block_index), None);
+        });
+
+        Ok(amount)
+    }
+
+    /// Sends `amount` of the caller's balance to `to`. The ledger fee comes
+    /// out of `amount`. Returns the ledger block index.
+    ///
+    /// The balance is refunded only when the ledger refuses the transfer.
+    /// When the call fails without an answer the amount stays debited and
+    /// the withdrawal is kept pending for `retry_withdrawal`.
+    pub async fn withdraw(to: Account, amount: u64) -> Result<u64, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == Principal::anonymous() {
+            return Err(BlockVerseError::AnonymousCaller);
+        }
+
+        let ledger = STATE.with(|state| Self::ledger(&state.borrow()))?;
+        Self::withdraw_with(&ledger, caller, to, amount).await
+    }
+
+    async fn withdraw_with(
+        ledger: &impl LedgerApi,
+        caller: Principal,
+        to: Account,
+        amount: u64,
+    ) -> Result<u64, BlockVerseError> {
+        let fee = ledger.fee().await?;
+        if amount <= fee {
+            return Err(BlockVerseError::validation("amount", "Must be greater than the ledger fee"));
+        }
+
+        // Debit before the call so concurrent withdrawals can't overdraw
+        let withdrawal = STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let balance = state
+                .get_user(caller)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::User, caller))?
+                .balance;
+            if balance < amount {
+                return Err(BlockVerseError::InsufficientBalance { balance, required: amount });
             }
+            state.update_user(caller, |user| user.balance -= amount);
 
-            // Record the transaction
-            let transaction = Transaction {
-                id: format!("tip_{}_{}", caller.to_text(), ic_cdk::api::time()),
-                from: caller,
-                to: user_id,
+            let id = state.next_sequence();
+            let withdrawal = PendingWithdrawal {
+                id,
+                user_id: caller,
+                to_owner: to.owner,
+                to_subaccount: to.subaccount,
                 amount,
-                transaction_type: TransactionType::Tip,
-                timestamp: ic_cdk::api::time(),
+                fee,
+                memo: [WITHDRAWAL_MEMO, &id.to_be_bytes()].concat(),
+                created_at_time: clock::now(),
             };
+            state.pending_withdrawals.insert((PrincipalKey(caller), SequenceKey(id)), withdrawal.clone());
+            Ok(withdrawal)
+        })?;
 
-            state.transactions.push(transaction);
+        Self::submit_withdrawal(ledger, withdrawal).await
+    }
+
+    /// Resubmits one of the caller's pending withdrawals. The ledger
+    /// answers with the original block if the first attempt went through.
+    pub async fn retry_withdrawal(id: u64) -> Result<u64, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        let (ledger, withdrawal) = STATE.with(|state| {
+            let state = state.borrow();
+            let withdrawal = state
+                .pending_withdrawals
+                .get(&(PrincipalKey(caller), SequenceKey(id)))
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Withdrawal, id))?;
+            Ok::<_, BlockVerseError>((Self::ledger(&state)?, withdrawal))
+        })?;
+
+        Self::submit_withdrawal(&ledger, withdrawal).await
+    }
 
+    /// The caller's withdrawals still waiting for a ledger answer, oldest
+    /// first.
+    pub fn get_pending_withdrawals(
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<PendingWithdrawal>, BlockVerseError> {
+        let caller = PrincipalKey(ic_cdk::caller());
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            let start = match pagination::decode_cursor::<SequenceKey>(cursor)? {
+                Some(after) => Bound::Excluded((caller, after)),
+                None => Bound::Included((caller, SequenceKey::default())),
+            };
+            let entries = state
+                .pending_withdrawals
+                .range((start, Bound::Unbounded))
+                .take_while(|((user_id, _), _)| *user_id == caller)
+                .map(|((_, id), withdrawal)| (id, withdrawal));
+            Ok(pagination::collect_page(entries, pagination::page_size(limit), |_, withdrawal| Some(withdrawal)))
+        })
+    }
+
+    /// Settles a pending withdrawal the ledger can no longer deduplicate,
+    /// after an admin looked it up on the ledger: recorded at `block_index`
+    /// if it went through, refunded otherwise.
+    pub fn settle_withdrawal(user_id: Principal, id: u64, block_index: Option<u64>) -> Result<(), BlockVerseError> {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            let withdrawal = state
+                .pending_withdrawals
+                .get(&(PrincipalKey(user_id), SequenceKey(id)))
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Withdrawal, id))?;
+            Self::settle_withdrawal_outcome(&mut state, &withdrawal, block_index);
             Ok(())
         })
     }
 
+    /// Sends a pending withdrawal to the ledger and settles it by the
+    /// answer. Without one it stays pending. A duplicate can only be an
+    /// earlier submission of this same withdrawal, so it counts as done.
+    async fn submit_withdrawal(ledger: &impl LedgerApi, withdrawal: PendingWithdrawal) -> Result<u64, BlockVerseError> {
+        let to = Account { owner: withdrawal.to_owner, subaccount: withdrawal.to_subaccount.clone() };
+        let result = ledger
+            .transfer(
+                None,
+                to,
+                withdrawal.amount - withdrawal.fee,
+                withdrawal.fee,
+                Some(withdrawal.memo.clone()),
+                withdrawal.created_at_time,
+            )
+            .await;
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            match result {
+                Ok(block_index) | Err(TransferFailure::Duplicate(block_index)) => {
+                    Self::settle_withdrawal_outcome(&mut state, &withdrawal, Some(block_index));
+                    Ok(block_index)
+                }
+                Err(TransferFailure::Refused(error)) => {
+                    Self::settle_withdrawal_outcome(&mut state, &withdrawal, None);
+                    Err(error)
+                }
+                Err(failure) => Err(failure.into()),
+            }
+        })
+    }
+
+    /// Records a withdrawal that went through at `block_index`, or refunds
+    /// one that didn't. Only the first outcome for a withdrawal counts, so
+    /// concurrent retries settle it once.
+    fn settle_withdrawal_outcome(state: &mut State, withdrawal: &PendingWithdrawal, block_index: Option<u64>) {
+        let key = (PrincipalKey(withdrawal.user_id), SequenceKey(withdrawal.id));
+        if state.pending_withdrawals.remove(&key).is_none() {
+            return;
+        }
+
+        match block_index {
+            Some(block_index) => Self::record(
+                state,
+                withdrawal.user_id,
+                withdrawal.to_owner,
+                withdrawal.amount,
+                TransactionType::Withdrawal,
+                Some(block_index),
+                None,
+            ),
+            None => {
+                state.update_user(withdrawal.user_id, |user| user.balance += withdrawal.amount);
+            }
+        }
+    }
+
     pub fn get_user_balance(user_id: Principal) -> u64 {
         STATE.with(|state| {
             let state = state.borrow();
-            state.users.get(&user_id).map(|user| user.balance).unwrap_or(0)
+            state.get_user(user_id).map(|user| user.balance).unwrap_or(0)
+        })
+    }
+
+    /// Everything `user_id` sent or received, newest first. See `history`
+    /// for who may read it.
+    pub fn get_transactions(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Transaction>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::history(&state.borrow(), caller, user_id, cursor, limit, |_| true))
+    }
+
+    pub fn get_sent_transactions(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Transaction>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| {
+            Self::history(&state.borrow(), caller, user_id, cursor, limit, |transaction| transaction.is_sent_by(user_id))
+        })
+    }
+
+    pub fn get_received_transactions(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Transaction>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| {
+            Self::history(&state.borrow(), caller, user_id, cursor, limit, |transaction| {
+                transaction.is_received_by(user_id)
+            })
+        })
+    }
+
+    /// Tips sent for `post_id`, newest first, if the caller can see it.
+    pub fn get_post_tips(
+        post_id: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Transaction>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::post_tips(&state.borrow(), caller, post_id, cursor, limit))
+    }
+
+    fn post_tips(
+        state: &State,
+        caller: Principal,
+        post_id: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Transaction>, BlockVerseError> {
+        Self::check_can_view_post(state, caller, &post_id)?;
+        pagination::page_members(&state.post_tips, IdKey(post_id), cursor, limit, |position| {
+            state.transactions.get(position.0)
+        })
+    }
+
+    /// The sum of the tips sent for `post_id`, if the caller can see it.
+    pub fn get_post_tip_total(post_id: String) -> Result<u64, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::post_tip_total(&state.borrow(), caller, post_id))
+    }
+
+    fn post_tip_total(state: &State, caller: Principal, post_id: String) -> Result<u64, BlockVerseError> {
+        Self::check_can_view_post(state, caller, &post_id)?;
+        Ok(state.post_tip_totals.get(&IdKey(post_id)).unwrap_or(0))
+    }
+
+    fn check_can_view_post(state: &State, caller: Principal, post_id: &str) -> Result<(), BlockVerseError> {
+        if !state.get_post(post_id).is_some_and(|post| state.can_view_post(&post, caller)) {
+            return Err(BlockVerseError::not_found(EntityKind::Post, post_id));
+        }
+        Ok(())
+    }
+
+    pub fn get_transaction_totals(user_id: Principal) -> TransactionTotals {
+        STATE.with(|state| {
+            let state = state.borrow();
+            state.transaction_totals.get(&PrincipalKey(user_id)).unwrap_or_default()
         })
     }
 
-    pub fn add_balance(user_id: Principal, amount: u64) -> Result<(), String> {
+    pub fn set_ledger_canister(ledger: Principal) {
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            if let Some(user) = state.users.get_mut(&user_id) {
-                user.balance += amount;
-                Ok(())
-            } else {
-                Err("User not found".to_string())
-            }
+            state.set_ledger(ledger);
         })
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct Transaction {
-    pub id: String,
-    pub from: Principal,
-    pub to: Principal,
-    pub amount: u64,
-    pub transaction_type: TransactionType,
-    pub timestamp: u64,
+    fn ledger(state: &State) -> Result<Ledger, BlockVerseError> {
+        state
+            .ledger()
+            .map(Ledger::new)
+            .ok_or_else(|| BlockVerseError::InvalidOperation("Ledger canister is not configured".to_string()))
+    }
+
+    /// Pages `user_id`'s transactions matching `select`. Only `user_id` and
+    /// support staff may read them, and staff don't see tips for posts they
+    /// can't see.
+    fn history(
+        state: &State,
+        caller: Principal,
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+        mut select: impl FnMut(&Transaction) -> bool,
+    ) -> Result<Page<Transaction>, BlockVerseError> {
+        if caller != user_id && !RoleService::has_role(state, caller, Role::Support) {
+            return Err(BlockVerseError::Unauthorized("Only the user and support staff can read transactions".to_string()));
+        }
+        pagination::page_members(&state.user_transactions, PrincipalKey(user_id), cursor, limit, |position| {
+            state
+                .transactions
+                .get(position.0)
+                .filter(|transaction| select(transaction) && Self::shows_to(state, caller, transaction))
+        })
+    }
+
+    /// Whether `caller` may see `transaction`: its parties always may, anyone
+    /// else only if it isn't a tip for a post they can't see.
+    fn shows_to(state: &State, caller: Principal, transaction: &Transaction) -> bool {
+        caller == transaction.from
+            || caller == transaction.to
+            || transaction.post_id.as_deref().is_none_or(|post_id| Self::check_can_view_post(state, caller, post_id).is_ok())
+    }
+
+    fn record(
+        state: &mut State,
+        from: Principal,
+        to: Principal,
+        amount: u64,
+        transaction_type: TransactionType,
+        block_index: Option<u64>,
+        post_id: Option<String>,
+    ) {
+        let transaction = Transaction {
+            id: state.next_id("tx"),
+            from,
+            to,
+            amount,
+            transaction_type,
+            timestamp: clock::now(),
+            block_index,
+            post_id,
+        };
+
+        let position = state.transactions.len();
+        state.transactions.push(&transaction).expect("Failed to record transaction");
+        Self::index(state, position, &transaction);
+    }
+
+    /// Adds the transaction at `position` to the history indexes and totals.
+    pub fn index(state: &mut State, position: u64, transaction: &Transaction) {
+        let key = SequenceKey(position);
+        let amount = transaction.amount;
+
+        state.user_transactions.insert((PrincipalKey(transaction.from), key), ());
+        if transaction.is_received_by(transaction.to) {
+            state.user_transactions.insert((PrincipalKey(transaction.to), key), ());
+        }
+
+        let mut add = |user_id: Principal, update: fn(&mut TransactionTotals, u64)| {
+            let mut totals = state.transaction_totals.get(&PrincipalKey(user_id)).unwrap_or_default();
+            update(&mut totals, amount);
+            state.transaction_totals.insert(PrincipalKey(user_id), totals);
+        };
+        match transaction.transaction_type {
+            TransactionType::Tip => {
+                add(transaction.from, |totals, amount| totals.tips_sent += amount);
+                add(transaction.to, |totals, amount| totals.tips_received += amount);
+            }
+            TransactionType::Reward => add(transaction.to, |totals, amount| totals.rewards_received += amount),
+            TransactionType::Deposit => add(transaction.to, |totals, amount| totals.deposited += amount),
+            TransactionType::Withdrawal => add(transaction.from, |totals, amount| totals.withdrawn += amount),
+        }
+
+        if let Some(post_id) = &transaction.post_id {
+            state.post_tips.insert((IdKey(post_id.clone()), key), ());
+            let total = state.post_tip_totals.get(&IdKey(post_id.clone())).unwrap_or(0);
+            state.post_tip_totals.insert(IdKey(post_id.clone()), total + amount);
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
-pub enum TransactionType {
-    Tip,
-    Reward,
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+    use crate::models::post::{Post, Visibility};
+    use crate::models::user::User;
+    use crate::utils::testing::principal;
+
+    const FEE: u64 = 10;
+
+    fn canister() -> Principal {
+        principal(9)
+    }
+
+    /// Drives a future to completion. The test ledger answers every call
+    /// immediately, so it never has to wait.
+    fn run<T>(future: impl Future<Output = T>) -> T {
+        match pin!(future).as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("The test ledger never suspends"),
+        }
+    }
+
+    /// How the test ledger fails its next `transfer`.
+    #[derive(Clone, Copy)]
+    enum Failure {
+        /// Answers with a `TransferError`
+        Refuse,
+        /// The call is lost before the ledger sees it
+        LoseCall,
+        /// The ledger applies the transfer but the answer is lost
+        LoseAnswer,
+    }
+
+    type AccountKey = (Principal, Option<Vec<u8>>);
+
+    /// What the ledger dedups a transfer on. Every call in these tests lands
+    /// in the same round, so `created_at_time` would be the same for all of
+    /// them and is left out.
+    type DedupKey = (AccountKey, AccountKey, u64, Option<Vec<u8>>);
+
+    #[derive(Default)]
+    struct TestLedger {
+        balances: RefCell<HashMap<AccountKey, u64>>,
+        /// Block of every applied transfer
+        applied: RefCell<HashMap<DedupKey, u64>>,
+        blocks: Cell<u64>,
+        failure: Cell<Option<Failure>>,
+        /// What `balance_of` answers instead of the balance, like a call
+        /// that read it before another one moved the funds
+        reported_balance: Cell<Option<u64>>,
+    }
+
+    impl TestLedger {
+        fn balance(&self, owner: Principal, subaccount: Option<Vec<u8>>) -> u64 {
+            self.balances.borrow().get(&(owner, subaccount)).copied().unwrap_or(0)
+        }
+
+        fn mint(&self, owner: Principal, subaccount: Option<Vec<u8>>, amount: u64) {
+            *self.balances.borrow_mut().entry((owner, subaccount)).or_default() += amount;
+        }
+
+        /// Applies a transfer unless the same one was applied before.
+        fn submit(
+            &self,
+            from: AccountKey,
+            to: Account,
+            amount: u64,
+            memo: Option<Vec<u8>>,
+        ) -> Result<u64, TransferFailure> {
+            let dedup = (from.clone(), (to.owner, to.subaccount.clone()), amount, memo);
+            if let Some(block_index) = self.applied.borrow().get(&dedup).copied() {
+                return Err(TransferFailure::Duplicate(block_index));
+            }
+
+            let balance = self.balance(from.0, from.1.clone());
+            if balance < amount + FEE {
+                return Err(TransferFailure::Refused(BlockVerseError::InsufficientBalance { balance, required: amount }));
+            }
+            self.balances.borrow_mut().insert(from, balance - amount - FEE);
+            self.mint(to.owner, to.subaccount, amount);
+            self.blocks.set(self.blocks.get() + 1);
+            self.applied.borrow_mut().insert(dedup, self.blocks.get());
+            Ok(self.blocks.get())
+        }
+    }
+
+    impl LedgerApi for TestLedger {
+        async fn fee(&self) -> Result<u64, BlockVerseError> {
+            Ok(FEE)
+        }
+
+        async fn balance_of(&self, account: Account) -> Result<u64, BlockVerseError> {
+            Ok(self.reported_balance.take().unwrap_or_else(|| self.balance(account.owner, account.subaccount)))
+        }
+
+        async fn transfer(
+            &self,
+            from_subaccount: Option<Vec<u8>>,
+            to: Account,
+            amount: u64,
+            fee: u64,
+            memo: Option<Vec<u8>>,
+            _created_at_time: u64,
+        ) -> Result<u64, TransferFailure> {
+            assert_eq!(fee, FEE);
+            let lost = || TransferFailure::Unknown(BlockVerseError::Ledger("Call timed out".to_string()));
+            let from = (canister(), from_subaccount);
+            match self.failure.take() {
+                Some(Failure::Refuse) => {
+                    Err(TransferFailure::Refused(BlockVerseError::Ledger("TemporarilyUnavailable".to_string())))
+                }
+                Some(Failure::LoseCall) => Err(lost()),
+                Some(Failure::LoseAnswer) => self.submit(from, to, amount, memo).and(Err(lost())),
+                None => self.submit(from, to, amount, memo),
+            }
+        }
+
+        async fn transfer_from(
+            &self,
+            from: Account,
+            to: Account,
+            amount: u64,
+            memo: Vec<u8>,
+        ) -> Result<u64, BlockVerseError> {
+            Ok(self.submit((from.owner, from.subaccount), to, amount, Some(memo))?)
+        }
+    }
+
+    /// Alice and Bob, with `balance` credited to Alice.
+    fn users(state: &mut State, balance: u64) -> (Principal, Principal) {
+        let (alice, bob) = (principal(1), principal(2));
+        let mut user = User::new(alice, "alice".to_string(), String::new(), String::new());
+        user.balance = balance;
+        state.insert_user(user);
+        state.insert_user(User::new(bob, "bob".to_string(), String::new(), String::new()));
+        (alice, bob)
+    }
+
+    fn balance(user_id: Principal) -> u64 {
+        STATE.with(|state| state.borrow().get_user(user_id).unwrap().balance)
+    }
+
+    fn pending(user_id: Principal) -> Vec<PendingWithdrawal> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            state
+                .pending_withdrawals
+                .iter()
+                .filter(|((owner, _), _)| owner.0 == user_id)
+                .map(|(_, withdrawal)| withdrawal)
+                .collect()
+        })
+    }
+
+    fn withdrawals_recorded() -> usize {
+        STATE.with(|state| {
+            state
+                .borrow()
+                .transactions
+                .iter()
+                .filter(|transaction| transaction.transaction_type == TransactionType::Withdrawal)
+                .count()
+        })
+    }
+
+    #[test]
+    fn tips_come_out_of_the_balance_first() {
+        let ledger = TestLedger::default();
+        let (alice, bob) = STATE.with(|state| users(&mut state.borrow_mut(), 100));
+
+        run(PaymentService::tip_with(&ledger, canister(), alice, bob, 30, None)).unwrap();
+
+        assert_eq!((balance(alice), balance(bob)), (70, 30));
+        assert_eq!(ledger.blocks.get(), 0);
+        let tip = STATE.with(|state| state.borrow().transactions.get(0).unwrap());
+        assert_eq!((tip.from, tip.to, tip.amount, tip.block_index), (alice, bob, 30, None));
+    }
+
+    #[test]
+    fn tips_beyond_the_balance_are_pulled_from_the_wallet() {
+        let ledger = TestLedger::default();
+        let (alice, bob) = STATE.with(|state| users(&mut state.borrow_mut(), 10));
+        ledger.mint(alice, None, 1_000);
+
+        run(PaymentService::tip_with(&ledger, canister(), alice, bob, 30, Some("post_1".to_string()))).unwrap();
+
+        assert_eq!((balance(alice), balance(bob)), (10, 30));
+        assert_eq!(ledger.balance(alice, None), 1_000 - 30 - FEE);
+        assert_eq!(ledger.balance(canister(), None), 30);
+        assert_eq!(STATE.with(|state| state.borrow().post_tip_totals.get(&IdKey::from("post_1"))), Some(30));
+
+        // Nothing is credited when the wallet can't cover it
+        let result = run(PaymentService::tip_with(&ledger, canister(), alice, bob, 1_000, None));
+        assert!(matches!(result, Err(BlockVerseError::InsufficientBalance { .. })));
+        assert_eq!(balance(bob), 30);
+    }
+
+    #[test]
+    fn claim_deposit_credits_the_deposit_less_the_fee() {
+        let ledger = TestLedger::default();
+        let (alice, _) = STATE.with(|state| users(&mut state.borrow_mut(), 0));
+        ledger.mint(canister(), Some(ledger::deposit_subaccount(alice)), 500);
+
+        assert_eq!(run(PaymentService::claim_deposit_with(&ledger, canister(), alice)), Ok(490));
+        assert_eq!(balance(alice), 490);
+        assert_eq!(ledger.balance(canister(), None), 490);
+
+        assert!(matches!(
+            run(PaymentService::claim_deposit_with(&ledger, canister(), alice)),
+            Err(BlockVerseError::InvalidOperation(_))
+        ));
+        assert_eq!(balance(alice), 490);
+    }
+
+    #[test]
+    fn refused_withdrawals_are_refunded() {
+        let ledger = TestLedger::default();
+        let (alice, _) = STATE.with(|state| users(&mut state.borrow_mut(), 100));
+        ledger.mint(canister(), None, 100);
+        ledger.failure.set(Some(Failure::Refuse));
+
+        let result = run(PaymentService::withdraw_with(&ledger, alice, Account::new(alice), 100));
+        assert!(matches!(result, Err(BlockVerseError::Ledger(_))));
+        assert_eq!(balance(alice), 100);
+        assert!(pending(alice).is_empty());
+
+        assert!(run(PaymentService::withdraw_with(&ledger, alice, Account::new(alice), 100)).is_ok());
+        assert_eq!(balance(alice), 0);
+        assert_eq!(ledger.balance(alice, None), 100 - FEE);
+        assert_eq!(withdrawals_recorded(), 1);
+    }
+
+    #[test]
+    fn unanswered_withdrawals_stay_debited_until_resubmitted() {
+        let ledger = TestLedger::default();
+        let (alice, _) = STATE.with(|state| users(&mut state.borrow_mut(), 100));
+        ledger.mint(canister(), None, 100);
+        ledger.failure.set(Some(Failure::LoseAnswer));
+
+        let result = run(PaymentService::withdraw_with(&ledger, alice, Account::new(alice), 100));
+        assert!(matches!(result, Err(BlockVerseError::Ledger(_))));
+        assert_eq!(balance(alice), 0);
+        assert_eq!(ledger.balance(alice, None), 100 - FEE);
+        let [withdrawal] = pending(alice).try_into().unwrap();
+
+        // The ledger recognises the resubmission and answers with the
+        // original block, so nothing is paid twice
+        let block_index = run(PaymentService::submit_withdrawal(&ledger, withdrawal.clone())).unwrap();
+        assert_eq!(block_index, 1);
+        assert_eq!(ledger.balance(alice, None), 100 - FEE);
+        assert_eq!(balance(alice), 0);
+        assert!(pending(alice).is_empty());
+        assert_eq!(withdrawals_recorded(), 1);
+
+        // Settling again changes nothing
+        run(PaymentService::submit_withdrawal(&ledger, withdrawal)).unwrap();
+        assert_eq!((balance(alice), withdrawals_recorded()), (0, 1));
+    }
+
+    #[test]
+    fn lost_withdrawal_calls_go_through_when_resubmitted() {
+        let ledger = TestLedger::default();
+        let (alice, _) = STATE.with(|state| users(&mut state.borrow_mut(), 100));
+        ledger.mint(canister(), None, 100);
+        ledger.failure.set(Some(Failure::LoseCall));
+
+        assert!(run(PaymentService::withdraw_with(&ledger, alice, Account::new(alice), 100)).is_err());
+        assert_eq!((balance(alice), ledger.balance(alice, None)), (0, 0));
+
+        let [withdrawal] = pending(alice).try_into().unwrap();
+        run(PaymentService::submit_withdrawal(&ledger, withdrawal)).unwrap();
+        assert_eq!(ledger.balance(alice, None), 100 - FEE);
+        assert!(pending(alice).is_empty());
+        assert_eq!(withdrawals_recorded(), 1);
+    }
+
+    #[test]
+    fn post_tips_are_only_listed_to_those_who_can_see_the_post() {
+        let ledger = TestLedger::default();
+        let (alice, bob) = STATE.with(|state| users(&mut state.borrow_mut(), 100));
+        let post = Post::new("post_1".to_string(), bob, "hello".to_string(), None, Visibility::FollowersOnly);
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            state.insert_post(post);
+            state.user_following.insert((PrincipalKey(alice), PrincipalKey(bob)), ());
+        });
+
+        run(PaymentService::tip_with(&ledger, canister(), alice, bob, 30, Some("post_1".to_string()))).unwrap();
+
+        STATE.with(|state| {
+            let state = &state.borrow();
+            let tips = PaymentService::post_tips(state, alice, "post_1".to_string(), None, 10).unwrap();
+            assert_eq!(tips.items.len(), 1);
+            assert!(matches!(
+                PaymentService::post_tips(state, principal(3), "post_1".to_string(), None, 10),
+                Err(BlockVerseError::NotFound { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn history_lists_both_sides_newest_first_and_keeps_totals() {
+        let ledger = TestLedger::default();
+        let (alice, bob) = STATE.with(|state| users(&mut state.borrow_mut(), 100));
+        STATE.with(|state| state.borrow_mut().update_user(bob, |user| user.balance = 100));
+        for (from, to, amount) in [(alice, bob, 10), (bob, alice, 5), (alice, bob, 20)] {
+            run(PaymentService::tip_with(&ledger, canister(), from, to, amount, None)).unwrap();
+        }
+
+        let amounts = |user_id: Principal, cursor: Option<String>, limit: usize, select: fn(&Transaction) -> bool| {
+            STATE.with(|state| {
+                let page = PaymentService::history(&state.borrow(), user_id, user_id, cursor, limit, select).unwrap();
+                (page.items.iter().map(|transaction| transaction.amount).collect::<Vec<_>>(), page.next_cursor)
+            })
+        };
+        assert_eq!(amounts(alice, None, 10, |_| true).0, [20, 5, 10]);
+        assert_eq!(amounts(alice, None, 10, |transaction| transaction.is_sent_by(principal(1))).0, [20, 10]);
+        assert_eq!(amounts(alice, None, 10, |transaction| transaction.is_received_by(principal(1))).0, [5]);
+
+        let (first, cursor) = amounts(bob, None, 2, |_| true);
+        assert_eq!((first, amounts(bob, cursor, 2, |_| true).0), (vec![20, 5], vec![10]));
+
+        let totals = PaymentService::get_transaction_totals(alice);
+        assert_eq!((totals.tips_sent, totals.tips_received), (30, 5));
+    }
+
+    #[test]
+    fn identical_tips_in_one_round_are_each_pulled_from_the_wallet() {
+        let ledger = TestLedger::default();
+        let (alice, bob) = STATE.with(|state| users(&mut state.borrow_mut(), 0));
+        let carol = principal(3);
+        STATE.with(|state| {
+            state.borrow_mut().insert_user(User::new(carol, "carol".to_string(), String::new(), String::new()))
+        });
+        ledger.mint(alice, None, 1_000);
+
+        for recipient in [bob, bob, carol] {
+            run(PaymentService::tip_with(&ledger, canister(), alice, recipient, 30, None)).unwrap();
+        }
+
+        assert_eq!((balance(bob), balance(carol)), (60, 30));
+        assert_eq!(ledger.balance(alice, None), 1_000 - 3 * (30 + FEE));
+        assert_eq!(ledger.balance(canister(), None), 90);
+    }
+
+    #[test]
+    fn concurrent_claims_credit_a_deposit_once() {
+        let ledger = TestLedger::default();
+        let (alice, _) = STATE.with(|state| users(&mut state.borrow_mut(), 0));
+        ledger.mint(canister(), Some(ledger::deposit_subaccount(alice)), 500);
+
+        assert_eq!(run(PaymentService::claim_deposit_with(&ledger, canister(), alice)), Ok(490));
+
+        // A second claim that read the balance before the first one swept it
+        ledger.reported_balance.set(Some(500));
+        let result = run(PaymentService::claim_deposit_with(&ledger, canister(), alice));
+        assert!(matches!(result, Err(BlockVerseError::InsufficientBalance { .. })));
+        assert_eq!(balance(alice), 490);
+        assert_eq!(ledger.balance(canister(), None), 490);
+    }
+
+    #[test]
+    fn history_and_tip_totals_are_only_shown_to_those_allowed_to_see_them() {
+        let ledger = TestLedger::default();
+        let (alice, bob) = STATE.with(|state| users(&mut state.borrow_mut(), 100));
+        let (carol, support) = (principal(3), principal(4));
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let post = Post::new("post_1".to_string(), bob, "hello".to_string(), None, Visibility::FollowersOnly);
+            state.insert_post(post);
+            state.user_following.insert((PrincipalKey(alice), PrincipalKey(bob)), ());
+            state.roles.insert(PrincipalKey(support), Role::Support);
+        });
+        run(PaymentService::tip_with(&ledger, canister(), alice, bob, 30, Some("post_1".to_string()))).unwrap();
+        run(PaymentService::tip_with(&ledger, canister(), alice, bob, 5, None)).unwrap();
+
+        STATE.with(|state| {
+            let state = &state.borrow();
+            let history = |caller| PaymentService::history(state, caller, alice, None, 10, |_| true);
+            assert_eq!(history(alice).unwrap().items.len(), 2);
+            assert!(matches!(history(carol), Err(BlockVerseError::Unauthorized(_))));
+            let seen_by_support: Vec<_> = history(support).unwrap().items.iter().map(|tip| tip.amount).collect();
+            assert_eq!(seen_by_support, [5]);
+
+            assert_eq!(PaymentService::post_tip_total(state, alice, "post_1".to_string()), Ok(30));
+            assert!(matches!(
+                PaymentService::post_tip_total(state, carol, "post_1".to_string()),
+                Err(BlockVerseError::NotFound { .. })
+            ));
+        });
+    }
+}
@@ -1,226 +1,546 @@
 use candid::Principal;
-use crate::models::post::Post;
-use crate::storage::state::STATE;
+use std::ops::Bound;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::{page::Page, post::{Post, PostRevision, Visibility}, role::Role};
+use crate::models::notification::NotificationKind;
+use crate::services::comment_service::CommentService;
+use crate::services::federation_service::FederationService;
+use crate::services::media_service::MediaService;
+use crate::services::moderation_service::ModerationService;
+use crate::services::notification_service::NotificationService;
+use crate::services::role_service::RoleService;
+use crate::storage::pagination;
+use crate::storage::state::{self, State, STATE};
+use crate::services::search_service::SearchService;
+use crate::services::tag_service::TagService;
+use crate::services::timeline_service::TimelineService;
+use crate::services::user_service::UserService;
+use crate::storage::storable::{IdKey, PrincipalKey, RecencyKey, SequenceKey};
+use crate::utils::{clock, validation};
 
 pub struct PostService;
 
 impl PostService {
-    pub fn create_post(content: String, media_url: Option<String>) -> Result<Post, String> {
+    /// Publishes a post, `Public` unless `visibility` says otherwise.
+    /// `media_asset_id` must be one of the caller's uploaded assets.
+    pub fn create_post(
+        content: String,
+        media_asset_id: Option<String>,
+        visibility: Option<Visibility>,
+    ) -> Result<Post, BlockVerseError> {
         let caller = ic_cdk::caller();
 
         if caller == Principal::anonymous() {
-            return Err("Anonymous users cannot create posts".to_string());
+            return Err(BlockVerseError::AnonymousCaller);
         }
 
-        if content.trim().is_empty() && media_url.is_none() {
-            return Err("Post cannot be empty".to_string());
-        }
+        let content = Self::validate(&content, &media_asset_id)?;
 
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
+
+            ModerationService::check_not_suspended(&state, caller)?;
+
             // Check if user exists
-            if !state.users.contains_key(&caller) {
-                return Err("User not found".to_string());
+            if !state.has_user(caller) {
+                return Err(BlockVerseError::not_found(EntityKind::User, caller));
+            }
+            if let Some(asset_id) = &media_asset_id {
+                MediaService::check_attachable(&state, asset_id, caller)?;
             }
 
-            let post = Post::new(caller, content, media_url);
-            let post_id = post.id.clone();
-            
-            state.posts.insert(post_id.clone(), post.clone());
-            
-            // Add to user's posts
-            let user_posts = state.user_posts.entry(caller).or_insert_with(Vec::new);
-            user_posts.push(post_id);
+            let visibility = visibility.unwrap_or_default();
+            let mut post = Post::new(state.next_id("post"), caller, content, media_asset_id, visibility);
+            TagService::parse_post(&state, &mut post);
 
-            // Update user's post count
-            if let Some(user) = state.users.get_mut(&caller) {
-                user.posts_count += 1;
+            state.insert_post(post.clone());
+            if let Some(asset_id) = &post.media_asset_id {
+                MediaService::attach(&mut state, asset_id, &post.id);
             }
 
+            // Add to user's posts and followers' timelines
+            state.user_posts.insert((PrincipalKey(caller), RecencyKey::of(&post)), ());
+            Self::index_recent(&mut state, &post);
+            TimelineService::publish(&mut state, &post);
+            FederationService::publish(&mut state, &post);
+            SearchService::index_post(&mut state, &post);
+            TagService::index_post(&mut state, &post);
+            TagService::notify_mentions(&mut state, caller, &post.id, &post.mentions, &[]);
+
+            // Update user's post count
+            state.update_user(caller, |user| user.posts_count += 1);
+
             Ok(post)
         })
     }
 
-    pub fn get_post(post_id: String) -> Option<Post> {
+    /// Replaces the content of one of the caller's posts, keeping the old
+    /// version as a revision. Only allowed within the edit window.
+    pub fn update_post(
+        post_id: String,
+        content: String,
+        media_asset_id: Option<String>,
+    ) -> Result<Post, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        let content = Self::validate(&content, &media_asset_id)?;
+
+        STATE.with(|state| Self::edit(&mut state.borrow_mut(), caller, post_id, content, media_asset_id))
+    }
+
+    fn edit(
+        state: &mut State,
+        caller: Principal,
+        post_id: String,
+        content: String,
+        media_asset_id: Option<String>,
+    ) -> Result<Post, BlockVerseError> {
+        ModerationService::check_not_suspended(state, caller)?;
+
+        let mut post = state
+            .get_live_post(&post_id)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))?;
+        if post.author != caller {
+            return Err(BlockVerseError::Unauthorized("Only the author can edit this post".to_string()));
+        }
+        if post.is_shared {
+            return Err(BlockVerseError::InvalidOperation("Shares cannot be edited".to_string()));
+        }
+        if let Some(asset_id) = &media_asset_id {
+            MediaService::check_attachable(state, asset_id, caller)?;
+        }
+
+        let now = clock::now();
+        if now.saturating_sub(post.created_at) > state.edit_window() {
+            return Err(BlockVerseError::InvalidOperation("The edit window for this post has closed".to_string()));
+        }
+
+        let key = IdKey(post_id.clone());
+        let number = state
+            .post_revisions
+            .range((key.clone(), SequenceKey::default())..)
+            .next()
+            .filter(|((revised, _), _)| *revised == key)
+            .map_or(0, |((_, latest), _)| latest.0 + 1);
+
+        SearchService::unindex_post(state, &post);
+        TagService::unindex_post(state, &post);
+        let previous_mentions = post.mentions.clone();
+
+        let revision = post.edit(content, media_asset_id, number, now);
+        TagService::parse_post(state, &mut post);
+        state.post_revisions.insert((key, SequenceKey(number)), revision);
+        state.insert_post(post.clone());
+        if let Some(asset_id) = &post.media_asset_id {
+            MediaService::attach(state, asset_id, &post.id);
+        }
+
+        SearchService::index_post(state, &post);
+        TagService::index_post(state, &post);
+        TagService::notify_mentions(state, caller, &post.id, &post.mentions, &previous_mentions);
+        FederationService::update(state, &post);
+
+        Ok(post)
+    }
+
+    /// Earlier versions of an edited post, newest first.
+    pub fn get_post_revisions(
+        post_id: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<PostRevision>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
         STATE.with(|state| {
             let state = state.borrow();
-            state.posts.get(&post_id).cloned()
+
+            let post = state
+                .get_post(&post_id)
+                .filter(|post| state.can_view_post(post, caller))
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))?;
+
+            let key = IdKey(post.id);
+            let start = match pagination::decode_cursor::<SequenceKey>(cursor)? {
+                Some(after) => Bound::Excluded((key.clone(), after)),
+                None => Bound::Included((key.clone(), SequenceKey::default())),
+            };
+            let revisions = state
+                .post_revisions
+                .range((start, Bound::Unbounded))
+                .take_while(|((revised, _), _)| *revised == key)
+                .map(|((_, number), revision)| (number, revision));
+            Ok(pagination::collect_page(revisions, pagination::page_size(limit), |_, revision| Some(revision)))
+        })
+    }
+
+    pub fn get_edit_window() -> u64 {
+        STATE.with(|state| state.borrow().edit_window())
+    }
+
+    pub fn set_edit_window(window_ns: u64) {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.set_edit_window(window_ns);
         })
     }
 
-    pub fn get_user_posts(user_id: Principal) -> Vec<Post> {
+    pub fn get_post(post_id: String) -> Option<Post> {
+        let caller = ic_cdk::caller();
+
         STATE.with(|state| {
             let state = state.borrow();
-            
-            if let Some(post_ids) = state.user_posts.get(&user_id) {
-                post_ids.iter()
-                    .filter_map(|id| state.posts.get(id))
-                    .cloned()
-                    .collect()
-            } else {
-                Vec::new()
-            }
+            state.get_post(&post_id).filter(|post| state.can_view_post(post, caller))
         })
     }
 
-    pub fn get_feed(user_id: Principal, limit: usize, offset: usize) -> Vec<Post> {
+    pub fn get_user_posts(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
         STATE.with(|state| {
             let state = state.borrow();
-            
-            // Get posts from followed users and own posts
-            let mut feed_posts = Vec::new();
-            
-            // Add own posts
-            if let Some(post_ids) = state.user_posts.get(&user_id) {
-                for post_id in post_ids {
-                    if let Some(post) = state.posts.get(post_id) {
-                        feed_posts.push(post.clone());
-                    }
-                }
-            }
+            pagination::page_members(&state.user_posts, PrincipalKey(user_id), cursor, limit, |entry| {
+                state.get_post(&entry.post_id).filter(|post| state.can_view_post(post, caller))
+            })
+        })
+    }
 
-            // Add posts from followed users
-            if let Some(following) = state.user_following.get(&user_id) {
-                for followed_user in following {
-                    if let Some(post_ids) = state.user_posts.get(followed_user) {
-                        for post_id in post_ids {
-                            if let Some(post) = state.posts.get(post_id) {
-                                feed_posts.push(post.clone());
-                            }
-                        }
-                    }
-                }
-            }
+    /// Reads the caller's home feed. `user_id` must be the caller: a feed
+    /// holds posts only its owner may see.
+    pub fn get_feed(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        let caller = ic_cdk::caller();
 
-            // Sort by creation time (newest first)
-            feed_posts.sort_by(|a, b| b.created_at.cmp(&a.created_at));
-            
-            // Apply pagination
-            feed_posts.into_iter()
-                .skip(offset)
-                .take(limit)
-                .collect()
+        STATE.with(|state| {
+            let state = state.borrow();
+            Self::read_feed(&state, caller, user_id, cursor, limit)
         })
     }
 
-    pub fn like_post(post_id: String) -> Result<(), String> {
+    fn read_feed(
+        state: &State,
+        caller: Principal,
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        if caller != user_id {
+            return Err(BlockVerseError::Unauthorized("Can only read your own feed".to_string()));
+        }
+        TimelineService::read(state, user_id, cursor, limit)
+    }
+
+    pub fn like_post(post_id: String) -> Result<(), BlockVerseError> {
         let caller = ic_cdk::caller();
 
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            match state.posts.get_mut(&post_id) {
-                Some(post) => {
-                    if post.add_like(caller) {
-                        Ok(())
-                    } else {
-                        Err("Already liked this post".to_string())
-                    }
-                }
-                None => Err("Post not found".to_string())
+
+            ModerationService::check_not_suspended(&state, caller)?;
+
+            let post = state
+                .get_live_post(&post_id)
+                .filter(|post| state.can_view_post(post, caller))
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))?;
+            UserService::check_not_blocked(&state, caller, post.author)?;
+
+            let key = (IdKey(post_id.clone()), PrincipalKey(caller));
+            if state.post_likes.insert(key, ()).is_some() {
+                return Err(BlockVerseError::already_exists(EntityKind::Like, &post_id));
             }
+
+            state.update_post(&post_id, |post| post.likes_count += 1);
+            NotificationService::notify(&mut state, post.author, NotificationKind::Like, caller, Some(post_id), None);
+            Ok(())
         })
     }
 
-    pub fn unlike_post(post_id: String) -> Result<(), String> {
+    pub fn unlike_post(post_id: String) -> Result<(), BlockVerseError> {
         let caller = ic_cdk::caller();
 
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            match state.posts.get_mut(&post_id) {
-                Some(post) => {
-                    if post.remove_like(caller) {
-                        Ok(())
-                    } else {
-                        Err("Haven't liked this post".to_string())
-                    }
-                }
-                None => Err("Post not found".to_string())
+
+            if !state.has_post(&post_id) {
+                return Err(BlockVerseError::not_found(EntityKind::Post, &post_id));
+            }
+
+            let key = (IdKey(post_id.clone()), PrincipalKey(caller));
+            if state.post_likes.remove(&key).is_none() {
+                return Err(BlockVerseError::not_found(EntityKind::Like, &post_id));
+            }
+
+            state.update_post(&post_id, |post| post.likes_count = post.likes_count.saturating_sub(1));
+            if let Some(post) = state.get_post(&post_id) {
+                NotificationService::retract(&mut state, post.author, NotificationKind::Like, caller, &post_id);
             }
+            Ok(())
         })
     }
 
-    pub fn share_post(post_id: String, comment: Option<String>) -> Result<Post, String> {
+    /// Shares a post the caller can see. The share keeps the original's
+    /// visibility and is only shown to those who can see the original, so
+    /// sharing never widens a post's audience.
+    pub fn share_post(post_id: String, comment: Option<String>) -> Result<Post, BlockVerseError> {
         let caller = ic_cdk::caller();
 
+        if comment.as_ref().is_some_and(|comment| comment.len() > validation::MAX_CONTENT_LENGTH) {
+            return Err(BlockVerseError::validation("comment", "Too long"));
+        }
+
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            // Check if original post exists
-            if !state.posts.contains_key(&post_id) {
-                return Err("Original post not found".to_string());
-            }
 
-            let share_post = Post::new_share(caller, post_id.clone(), comment);
-            let share_id = share_post.id.clone();
-            
-            state.posts.insert(share_id.clone(), share_post.clone());
-            
-            // Add to user's posts
-            let user_posts = state.user_posts.entry(caller).or_insert_with(Vec::new);
-            user_posts.push(share_id);
+            ModerationService::check_not_suspended(&state, caller)?;
+
+            // Check if original post exists and the caller can see it
+            let original_post = state
+                .get_live_post(&post_id)
+                .filter(|original_post| state.can_view_post(original_post, caller))
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))?;
+            let original_author = original_post.author;
+            UserService::check_not_blocked(&state, caller, original_author)?;
+
+            let share_post = Post::new_share(state.next_id("post"), caller, &original_post, comment);
+
+            state.insert_post(share_post.clone());
+            state.post_shares.insert((IdKey(post_id.clone()), IdKey(share_post.id.clone())), ());
+
+            // Add to user's posts and followers' timelines
+            state.user_posts.insert((PrincipalKey(caller), RecencyKey::of(&share_post)), ());
+            Self::index_recent(&mut state, &share_post);
+            TimelineService::publish(&mut state, &share_post);
+            FederationService::publish(&mut state, &share_post);
+            SearchService::index_post(&mut state, &share_post);
 
             // Update original post's share count
-            if let Some(original_post) = state.posts.get_mut(&post_id) {
-                original_post.shares_count += 1;
-            }
+            state.update_post(&post_id, |original_post| original_post.shares_count += 1);
+            NotificationService::notify(&mut state, original_author, NotificationKind::Share, caller, Some(post_id), None);
 
             // Update user's post count
-            if let Some(user) = state.users.get_mut(&caller) {
-                user.posts_count += 1;
-            }
+            state.update_user(caller, |user| user.posts_count += 1);
 
             Ok(share_post)
         })
     }
 
-    pub fn search_posts(query: String) -> Vec<Post> {
-        let query = query.to_lowercase();
-        
+    pub fn search_posts(
+        query: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| SearchService::search_posts(&state.borrow(), caller, &query, cursor, limit))
+    }
+
+    /// Listed posts published after `timestamp`, newest first.
+    pub fn get_posts_since(
+        timestamp: u64,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        // Newer posts sort first, so this ends the scan at `timestamp`
+        let end = Bound::Excluded(RecencyKey { created_at: timestamp, post_id: String::new() });
+
         STATE.with(|state| {
             let state = state.borrow();
-            state.posts
-                .values()
-                .filter(|post| {
-                    post.content.to_lowercase().contains(&query)
-                })
-                .cloned()
-                .collect()
+            pagination::page_range(&state.recent_posts, end, cursor, limit, |entry, _| {
+                state.get_live_post(&entry.post_id).filter(|post| state.can_view_post(post, caller))
+            })
         })
     }
 
-    pub fn get_posts_since(timestamp: u64) -> Vec<Post> {
+    /// Adds `post` to `State.recent_posts` if it is listed.
+    pub fn index_recent(state: &mut State, post: &Post) {
+        if post.is_listed() {
+            state.recent_posts.insert(RecencyKey::of(post), ());
+        }
+    }
+
+    /// Deletes one of the caller's posts. Moderators can delete anyone's.
+    pub fn delete_post(post_id: String) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
         STATE.with(|state| {
-            let state = state.borrow();
-            state.posts
-                .values()
-                .filter(|post| post.created_at > timestamp)
-                .cloned()
-                .collect()
+            let mut state = state.borrow_mut();
+
+            let post = state
+                .get_live_post(&post_id)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))?;
+            if post.author != caller && !RoleService::has_role(&state, caller, Role::Moderator) {
+                return Err(BlockVerseError::Unauthorized("Only the author can delete this post".to_string()));
+            }
+
+            Self::delete(&mut state, &post);
+            Ok(())
         })
     }
 
-    pub fn remove_post(post_id: String) -> Result<(), String> {
+    pub fn remove_post(post_id: String) -> Result<(), BlockVerseError> {
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            if let Some(post) = state.posts.remove(&post_id) {
-                // Remove from user's posts
-                if let Some(user_posts) = state.user_posts.get_mut(&post.author) {
-                    user_posts.retain(|id| id != &post_id);
-                }
-
-                // Update user's post count
-                if let Some(user) = state.users.get_mut(&post.author) {
-                    user.posts_count = user.posts_count.saturating_sub(1);
-                }
-
-                Ok(())
-            } else {
-                Err("Post not found".to_string())
-            }
+
+            let post = state
+                .get_live_post(&post_id)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::Post, &post_id))?;
+            Self::delete(&mut state, &post);
+            Ok(())
         })
     }
-}
\ No newline at end of file
+
+    /// Turns `post` into a tombstone and drops it from every listing. Its
+    /// comments, likes and shares stay until `PurgeService` hard-deletes it.
+    pub fn delete(state: &mut State, post: &Post) {
+        let now = clock::now();
+        state.update_post(&post.id, |post| post.tombstone(now));
+        state.tombstones.insert((now, IdKey(post.id.clone())), ());
+
+        // Earlier versions would keep the deleted content around
+        let key = IdKey(post.id.clone());
+        let revisions: Vec<_> = state
+            .post_revisions
+            .range((key.clone(), SequenceKey::default())..)
+            .take_while(|((revised, _), _)| *revised == key)
+            .collect();
+        let mut media = post.media_asset_id.clone().into_iter().collect::<Vec<_>>();
+        for (revision_key, revision) in revisions {
+            state.post_revisions.remove(&revision_key);
+            media.extend(revision.media_asset_id);
+        }
+        MediaService::detach(state, media, &post.id);
+
+        // Remove from user's posts, timelines and search
+        state.user_posts.remove(&(PrincipalKey(post.author), RecencyKey::of(post)));
+        state.recent_posts.remove(&RecencyKey::of(post));
+        TimelineService::retract(state, post);
+        FederationService::retract(state, post);
+        SearchService::unindex_post(state, post);
+        TagService::unindex_post(state, post);
+
+        // A deleted share no longer counts towards the original
+        if let Some(original_post_id) = &post.original_post_id {
+            state.post_shares.remove(&(IdKey(original_post_id.clone()), IdKey(post.id.clone())));
+            state.update_post(original_post_id, |original_post| {
+                original_post.shares_count = original_post.shares_count.saturating_sub(1);
+            });
+        }
+
+        // Update user's post count
+        state.update_user(post.author, |user| {
+            user.posts_count = user.posts_count.saturating_sub(1);
+        });
+    }
+
+    /// Hard-deletes a post tombstone with its likes and comments. Shares
+    /// of it are kept but lose their `original_post_id`.
+    pub fn purge(state: &mut State, post: &Post) {
+        let post_id = IdKey(post.id.clone());
+        state.posts.remove(&post_id);
+        state.hidden_content.remove(&post_id);
+        state::remove_members(&mut state.post_likes, &post_id);
+
+        for key in state::remove_members(&mut state.post_comments, &post_id) {
+            let comment_id = &key.comment_id;
+            if let Some(deleted_at) = state.get_comment(comment_id).and_then(|comment| comment.deleted_at) {
+                state.tombstones.remove(&(deleted_at, IdKey(comment_id.clone())));
+            }
+            CommentService::purge_record(state, comment_id);
+        }
+
+        for share_id in state::remove_members(&mut state.post_shares, &post_id) {
+            state.update_post(&share_id.0, |share| share.original_post_id = None);
+        }
+    }
+
+    /// Checks a post body and returns the sanitised content.
+    fn validate(content: &str, media_asset_id: &Option<String>) -> Result<String, BlockVerseError> {
+        let content = validation::sanitize_content(content);
+
+        if content.is_empty() && media_asset_id.is_none() {
+            return Err(BlockVerseError::validation("content", "Post cannot be empty"));
+        }
+
+        if content.len() > validation::MAX_CONTENT_LENGTH {
+            return Err(BlockVerseError::validation("content", "Too long"));
+        }
+
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::user::User;
+    use crate::utils::testing::principal;
+
+    fn publish(state: &mut State, id: &str, author: Principal, visibility: Visibility) {
+        let post = Post::new(id.to_string(), author, "hello".to_string(), None, visibility);
+        state.insert_post(post.clone());
+        state.user_posts.insert((PrincipalKey(author), RecencyKey::of(&post)), ());
+        TimelineService::publish(state, &post);
+    }
+
+    fn ids(page: Page<Post>) -> Vec<String> {
+        page.items.into_iter().map(|post| post.id).collect()
+    }
+
+    #[test]
+    fn feeds_are_only_readable_by_their_owner() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (alice, bob, mallory) = (principal(1), principal(2), principal(3));
+            for (user_id, username) in [(alice, "alice"), (bob, "bob"), (mallory, "mallory")] {
+                state.insert_user(User::new(user_id, username.to_string(), String::new(), String::new()));
+            }
+            UserService::follow(state, bob, alice).unwrap();
+            publish(state, "post_1", alice, Visibility::FollowersOnly);
+
+            let own = PostService::read_feed(state, bob, bob, None, 10).unwrap();
+            assert_eq!(ids(own), ["post_1"]);
+
+            assert!(matches!(
+                PostService::read_feed(state, mallory, bob, None, 10),
+                Err(BlockVerseError::Unauthorized(_))
+            ));
+            assert!(PostService::read_feed(state, mallory, mallory, None, 10).unwrap().items.is_empty());
+        });
+    }
+
+    #[test]
+    fn edits_keep_every_revision_within_the_window() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (alice, bob) = (principal(1), principal(2));
+            state.insert_user(User::new(alice, "alice".to_string(), String::new(), String::new()));
+            publish(state, "post_1", alice, Visibility::Public);
+            let post = state.get_post("post_1").unwrap();
+            SearchService::index_post(state, &post);
+            let edit = |state: &mut State, caller, content: &str| {
+                PostService::edit(state, caller, "post_1".to_string(), content.to_string(), None)
+            };
+
+            edit(state, alice, "first edit").unwrap();
+            let post = edit(state, alice, "second edit").unwrap();
+            assert_eq!(post.content, "second edit");
+            assert!(post.edited_at.is_some());
+            let revisions: Vec<_> =
+                state.post_revisions.iter().map(|((_, number), revision)| (number.0, revision.content)).collect();
+            assert_eq!(revisions, [(1, "first edit".to_string()), (0, "hello".to_string())]);
+            assert!(state.post_terms.contains_key(&(IdKey::from("second"), RecencyKey::of(&post))));
+            assert!(!state.post_terms.contains_key(&(IdKey::from("hello"), RecencyKey::of(&post))));
+
+            assert!(matches!(edit(state, bob, "mine now"), Err(BlockVerseError::Unauthorized(_))));
+            state.set_edit_window(0);
+            state.update_post("post_1", |post| post.created_at -= 1);
+            assert!(matches!(edit(state, alice, "too late"), Err(BlockVerseError::InvalidOperation(_))));
+            assert_eq!(state.post_revisions.len(), 2);
+        });
+    }
+}
@@ -0,0 +1,129 @@
+use crate::services::comment_service::CommentService;
+use crate::services::media_service::MediaService;
+use crate::services::post_service::PostService;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::IdKey;
+use crate::utils::clock;
+
+/// How long deleted posts and comments stay around as tombstones.
+const RETENTION_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+
+#[cfg(target_arch = "wasm32")]
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Tombstones hard-deleted per run, so a backlog can't exhaust the
+/// instruction limit.
+const PURGE_BATCH: usize = 100;
+
+/// Hard-deletes tombstones once they are older than the retention window.
+pub struct PurgeService;
+
+impl PurgeService {
+    /// Starts the periodic purge. Timers don't survive upgrades, so this
+    /// runs from both `init` and `post_upgrade`. `ic-cdk-timers` only links
+    /// on the canister target, so native builds go without.
+    pub fn schedule() {
+        #[cfg(target_arch = "wasm32")]
+        ic_cdk_timers::set_timer_interval(PURGE_INTERVAL, || {
+            Self::run();
+        });
+    }
+
+    /// Purges one batch of expired tombstones and returns how many it
+    /// removed. Abandoned media uploads are discarded along the way.
+    pub fn run() -> u64 {
+        STATE.with(|state| Self::run_at(&mut state.borrow_mut(), clock::now()))
+    }
+
+    fn run_at(state: &mut State, now: u64) -> u64 {
+        let cutoff = now.saturating_sub(RETENTION_NS);
+
+        let due: Vec<_> = state
+            .tombstones
+            .iter()
+            .take_while(|((deleted_at, _), _)| *deleted_at <= cutoff)
+            .take(PURGE_BATCH)
+            .map(|(key, _)| key)
+            .collect();
+
+        let purged = due.len() as u64;
+        for key in due {
+            state.tombstones.remove(&key);
+            Self::purge(state, &key.1 .0, now);
+        }
+        MediaService::expire_uploads(state);
+        purged
+    }
+
+    fn purge(state: &mut State, id: &str, now: u64) {
+        if let Some(post) = state.get_post(id).filter(|post| post.is_deleted()) {
+            PostService::purge(state, &post);
+        } else if let Some(comment) = state.get_comment(id).filter(|comment| comment.is_deleted()) {
+            // Replies keep their parent's tombstone around; try again later
+            if CommentService::has_replies(state, &comment) {
+                state.tombstones.insert((now, IdKey::from(id)), ());
+            } else {
+                CommentService::purge(state, &comment);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::comment::Comment;
+    use crate::models::post::{Post, Visibility};
+    use crate::utils::testing::principal;
+
+    fn tombstone(state: &mut State, id: &str, deleted_at: u64) {
+        let author = principal(1);
+        let mut post = Post::new(id.to_string(), author, String::new(), None, Visibility::Public);
+        post.tombstone(deleted_at);
+        state.insert_post(post);
+        state.tombstones.insert((deleted_at, IdKey::from(id)), ());
+    }
+
+    #[test]
+    fn expired_tombstones_are_purged_in_batches() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let now = RETENTION_NS + 1_000;
+            let expired = PURGE_BATCH + PURGE_BATCH / 2;
+            for n in 0..expired {
+                tombstone(state, &format!("post_{:04}", n), n as u64);
+            }
+            tombstone(state, "post_recent", 2_000);
+
+            assert_eq!(PurgeService::run_at(state, now), PURGE_BATCH as u64);
+            assert_eq!(PurgeService::run_at(state, now), (expired - PURGE_BATCH) as u64);
+            assert_eq!(PurgeService::run_at(state, now), 0);
+
+            let left: Vec<_> = state.posts.iter().map(|(id, _)| id.0).collect();
+            assert_eq!(left, ["post_recent"]);
+            assert_eq!(state.tombstones.len(), 1);
+        });
+    }
+
+    #[test]
+    fn comments_with_replies_wait_for_their_replies() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let author = principal(1);
+            state.insert_post(Post::new("post_1".to_string(), author, String::new(), None, Visibility::Public));
+            let mut parent = Comment::new("comment_1".to_string(), "post_1".to_string(), author, String::new());
+            let reply = Comment::new_reply("comment_2".to_string(), &parent, author, "reply".to_string());
+            parent.tombstone(0);
+            for comment in [&parent, &reply] {
+                state.insert_comment(comment.clone());
+                CommentService::place(state, comment);
+            }
+            state.tombstones.insert((0, IdKey::from("comment_1")), ());
+
+            let now = RETENTION_NS + 1;
+            assert_eq!(PurgeService::run_at(state, now), 1);
+            assert!(state.get_comment("comment_1").is_some());
+            assert!(state.tombstones.contains_key(&(now, IdKey::from("comment_1"))));
+        });
+    }
+}
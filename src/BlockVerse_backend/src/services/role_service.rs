@@ -0,0 +1,178 @@
+use candid::Principal;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::{page::Page, role::{Role, RoleChange}};
+use crate::storage::pagination;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::{PrincipalKey, SequenceKey};
+use crate::utils::clock;
+
+pub struct RoleService;
+
+impl RoleService {
+    /// Guard for staff-only endpoints: fails unless the caller holds
+    /// `required` or a role that includes it. Returns the caller.
+    pub fn require(required: Role) -> Result<Principal, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            if !Self::has_role(&state, caller, required) {
+                return Err(BlockVerseError::Unauthorized(format!("Requires the {:?} role", required)));
+            }
+            Ok(caller)
+        })
+    }
+
+    /// Whether `user_id` holds `required` or a role that includes it.
+    pub fn has_role(state: &State, user_id: Principal, required: Role) -> bool {
+        state.get_role(user_id).is_some_and(|role| role.includes(required))
+    }
+
+    pub fn grant_role(user_id: Principal, role: Role) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::grant(&mut state.borrow_mut(), caller, user_id, role))
+    }
+
+    pub fn revoke_role(user_id: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| Self::revoke(&mut state.borrow_mut(), caller, user_id))
+    }
+
+    fn grant(state: &mut State, caller: Principal, user_id: Principal, role: Role) -> Result<(), BlockVerseError> {
+        let previous = state.get_role(user_id);
+        Self::check_manages(state, caller, role)?;
+        if let Some(previous) = previous {
+            Self::check_manages(state, caller, previous)?;
+            if previous == Role::Owner && role != Role::Owner {
+                Self::check_not_last_owner(state)?;
+            }
+        }
+
+        state.roles.insert(PrincipalKey(user_id), role);
+        Self::log(state, caller, user_id, previous, Some(role));
+        Ok(())
+    }
+
+    fn revoke(state: &mut State, caller: Principal, user_id: Principal) -> Result<(), BlockVerseError> {
+        let previous = state
+            .get_role(user_id)
+            .ok_or_else(|| BlockVerseError::not_found(EntityKind::Role, user_id))?;
+        Self::check_manages(state, caller, previous)?;
+        if previous == Role::Owner {
+            Self::check_not_last_owner(state)?;
+        }
+
+        state.roles.remove(&PrincipalKey(user_id));
+        Self::log(state, caller, user_id, Some(previous), None);
+        Ok(())
+    }
+
+    pub fn get_role(user_id: Principal) -> Option<Role> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            state.get_role(user_id)
+        })
+    }
+
+    /// The role audit trail, newest first.
+    pub fn get_role_changes(cursor: Option<String>, limit: usize) -> Result<Page<RoleChange>, BlockVerseError> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            let end = match pagination::decode_cursor::<SequenceKey>(cursor)? {
+                Some(after) => after.0,
+                None => state.role_changes.len(),
+            };
+
+            let changes = (0..end)
+                .rev()
+                .filter_map(|position| Some((SequenceKey(position), state.role_changes.get(position)?)));
+            Ok(pagination::collect_page(changes, pagination::page_size(limit), |_, change| Some(change)))
+        })
+    }
+
+    /// Makes `owner` an owner without any checks. Only for `init` and
+    /// migrations, when there is nobody to authorise it.
+    pub fn bootstrap_owner(state: &mut State, owner: Principal) {
+        let previous = state.get_role(owner);
+        state.roles.insert(PrincipalKey(owner), Role::Owner);
+        Self::log(state, owner, owner, previous, Some(Role::Owner));
+    }
+
+    fn check_manages(state: &State, caller: Principal, role: Role) -> Result<(), BlockVerseError> {
+        match state.get_role(caller) {
+            Some(own) if own.manages(role) => Ok(()),
+            _ => Err(BlockVerseError::Unauthorized(format!("Cannot manage the {:?} role", role))),
+        }
+    }
+
+    fn check_not_last_owner(state: &State) -> Result<(), BlockVerseError> {
+        let owners = state.roles.iter().filter(|(_, role)| *role == Role::Owner).take(2).count();
+        if owners < 2 {
+            return Err(BlockVerseError::InvalidOperation("Cannot remove the last owner".to_string()));
+        }
+        Ok(())
+    }
+
+    fn log(state: &mut State, actor: Principal, target: Principal, previous: Option<Role>, role: Option<Role>) {
+        let change = RoleChange { actor, target, previous, role, timestamp: clock::now() };
+        state.role_changes.push(&change).expect("Failed to record role change");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::principal;
+
+    fn unauthorized(result: Result<(), BlockVerseError>) -> bool {
+        matches!(result, Err(BlockVerseError::Unauthorized(_)))
+    }
+
+    #[test]
+    fn staff_only_manage_roles_below_their_own() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (owner, admin, moderator, user) = (principal(1), principal(2), principal(3), principal(4));
+            RoleService::bootstrap_owner(state, owner);
+            RoleService::grant(state, owner, admin, Role::Admin).unwrap();
+            RoleService::grant(state, admin, moderator, Role::Moderator).unwrap();
+
+            assert!(unauthorized(RoleService::grant(state, admin, user, Role::Admin)));
+            assert!(unauthorized(RoleService::grant(state, moderator, user, Role::Moderator)));
+            assert!(unauthorized(RoleService::grant(state, user, user, Role::Support)));
+            assert!(unauthorized(RoleService::revoke(state, admin, owner)));
+            // Nor can an admin promote someone past them by replacing a role
+            assert!(unauthorized(RoleService::grant(state, admin, moderator, Role::Owner)));
+
+            assert!(RoleService::has_role(state, admin, Role::Moderator));
+            assert!(!RoleService::has_role(state, moderator, Role::Admin));
+            assert!(!RoleService::has_role(state, user, Role::Support));
+
+            RoleService::revoke(state, admin, moderator).unwrap();
+            assert_eq!(state.get_role(moderator), None);
+            let last = state.role_changes.get(state.role_changes.len() - 1).unwrap();
+            assert_eq!(
+                (last.actor, last.target, last.previous, last.role),
+                (admin, moderator, Some(Role::Moderator), None)
+            );
+        });
+    }
+
+    #[test]
+    fn the_last_owner_cannot_be_removed() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (owner, other) = (principal(1), principal(2));
+            RoleService::bootstrap_owner(state, owner);
+
+            let last_owner = |result| matches!(result, Err(BlockVerseError::InvalidOperation(_)));
+            assert!(last_owner(RoleService::revoke(state, owner, owner)));
+            assert!(last_owner(RoleService::grant(state, owner, owner, Role::Admin)));
+
+            RoleService::grant(state, owner, other, Role::Owner).unwrap();
+            RoleService::revoke(state, other, owner).unwrap();
+            assert!(last_owner(RoleService::revoke(state, other, other)));
+            assert_eq!(state.get_role(other), Some(Role::Owner));
+        });
+    }
+}
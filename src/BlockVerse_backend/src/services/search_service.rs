@@ -0,0 +1,364 @@
+use candid::Principal;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use crate::error::BlockVerseError;
+use crate::models::{page::Page, post::Post, user::User};
+use crate::storage::memory::Memory;
+use crate::storage::pagination::{self, CursorKey};
+use crate::storage::state::State;
+use crate::storage::storable::{IdKey, PrincipalKey, RecencyKey, MAX_ID_LEN};
+use crate::utils::{clock, tokenizer};
+
+/// Indexed terms a query term is expanded to as a prefix, in term order.
+const MAX_TERMS_PER_PREFIX: usize = 16;
+
+/// Postings read per indexed term. Post postings are read newest first, so
+/// past this it's the oldest posts with a common term that are left out.
+const MAX_POSTINGS_PER_TERM: usize = 500;
+
+const TERM_WEIGHT: f64 = 10.0;
+const RECENCY_WEIGHT: f64 = 5.0;
+/// Age at which a post's recency boost has halved.
+const RECENCY_HALF_LIFE_NS: f64 = 24.0 * 60.0 * 60.0 * 1e9;
+
+/// `(term, document) -> term frequency`
+type TermIndex<D> = StableBTreeMap<(IdKey, D), u32, Memory>;
+
+/// Maintains the inverted indexes behind `search_posts` and `search_users`
+/// and answers ranked queries against them. Every query term must match
+/// (as a prefix of an indexed term) for a document to be returned.
+pub struct SearchService;
+
+impl SearchService {
+    pub fn index_post(state: &mut State, post: &Post) {
+        add_terms(&mut state.post_terms, RecencyKey::of(post), &Self::post_text(post));
+    }
+
+    pub fn unindex_post(state: &mut State, post: &Post) {
+        remove_terms(&mut state.post_terms, RecencyKey::of(post), &Self::post_text(post));
+    }
+
+    pub fn index_user(state: &mut State, user: &User) {
+        add_terms(&mut state.user_terms, PrincipalKey(user.id), &Self::user_text(user));
+    }
+
+    pub fn unindex_user(state: &mut State, user: &User) {
+        remove_terms(&mut state.user_terms, PrincipalKey(user.id), &Self::user_text(user));
+    }
+
+    /// Indexes every stored post and user. Used when backfilling.
+    pub fn rebuild(state: &mut State) {
+        for (_, post) in state.posts.iter() {
+            add_terms(&mut state.post_terms, RecencyKey::of(&post), &Self::post_text(&post));
+        }
+        for (id, user) in state.users.iter() {
+            add_terms(&mut state.user_terms, id, &Self::user_text(&user));
+        }
+    }
+
+    /// Ranks matching posts by term frequency and recency. Likes only order
+    /// posts that are equally relevant within a page, which recency makes
+    /// rare, so they hardly affect the ranking. Only public posts the caller
+    /// can see, by users they haven't muted, are included.
+    pub fn search_posts(
+        state: &State,
+        caller: Principal,
+        query: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        let after = pagination::decode_cursor::<RankCursor>(cursor)?;
+        let as_of = after.as_ref().map_or_else(clock::now, |after| after.as_of);
+
+        let ranked = match_all(&state.post_terms, &tokenizer::query_terms(query))
+            .into_iter()
+            .filter_map(|(entry, frequency)| {
+                let post = state
+                    .get_post(&entry.post_id)
+                    .filter(|post| post.is_listed() && !state.has_muted(caller, post.author))
+                    .filter(|post| state.can_view_post(post, caller))?;
+                let age = as_of.saturating_sub(post.created_at) as f64;
+                let relevance =
+                    TERM_WEIGHT * frequency as f64 + RECENCY_WEIGHT * 0.5f64.powf(age / RECENCY_HALF_LIFE_NS);
+                Some((RankCursor::new(as_of, relevance, entry.post_id.into_bytes()), post.likes_count, post))
+            })
+            .collect();
+
+        Ok(rank_page(ranked, after, limit))
+    }
+
+    /// Ranks matching users by term frequency, then follower count, leaving
+    /// out users the caller muted.
+    pub fn search_users(
+        state: &State,
+        caller: Principal,
+        query: &str,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<User>, BlockVerseError> {
+        let after = pagination::decode_cursor::<RankCursor>(cursor)?;
+        let as_of = after.as_ref().map_or_else(clock::now, |after| after.as_of);
+
+        let ranked = match_all(&state.user_terms, &tokenizer::query_terms(query))
+            .into_iter()
+            .filter_map(|(id, frequency)| {
+                let user = state.get_user(id.0).filter(|user| !state.has_muted(caller, user.id))?;
+                let relevance = TERM_WEIGHT * frequency as f64;
+                Some((RankCursor::new(as_of, relevance, id.0.as_slice().to_vec()), user.followers_count, user))
+            })
+            .collect();
+
+        Ok(rank_page(ranked, after, limit))
+    }
+
+    fn post_text(post: &Post) -> String {
+        match &post.share_comment {
+            Some(comment) => format!("{} {}", post.content, comment),
+            None => post.content.clone(),
+        }
+    }
+
+    fn user_text(user: &User) -> String {
+        format!("@{} {}", user.username, user.bio)
+    }
+}
+
+fn term_counts(text: &str) -> BTreeMap<String, u32> {
+    let mut counts = BTreeMap::new();
+    for term in tokenizer::index_terms(text) {
+        *counts.entry(term).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn add_terms<D: BoundedStorable + Ord + Clone + Default>(index: &mut TermIndex<D>, doc: D, text: &str) {
+    for (term, frequency) in term_counts(text) {
+        index.insert((IdKey(term), doc.clone()), frequency);
+    }
+}
+
+fn remove_terms<D: BoundedStorable + Ord + Clone + Default>(index: &mut TermIndex<D>, doc: D, text: &str) {
+    for term in term_counts(text).into_keys() {
+        index.remove(&(IdKey(term), doc.clone()));
+    }
+}
+
+/// Documents that have an indexed term starting with each of `terms`,
+/// with the summed frequency of all the terms they matched. Each query term
+/// reads at most `MAX_POSTINGS_PER_TERM` postings of each of the first
+/// `MAX_TERMS_PER_PREFIX` terms it is a prefix of.
+fn match_all<D>(index: &TermIndex<D>, terms: &[String]) -> BTreeMap<D, u32>
+where
+    D: BoundedStorable + Ord + Clone + Default,
+{
+    let mut matches: Option<BTreeMap<D, u32>> = None;
+
+    for term in terms {
+        let mut term_matches = BTreeMap::new();
+        let mut from = term.clone();
+        for _ in 0..MAX_TERMS_PER_PREFIX {
+            let Some(((IdKey(indexed), _), _)) = index.range((IdKey(from), D::default())..).next() else {
+                break;
+            };
+            if !indexed.starts_with(term.as_str()) {
+                break;
+            }
+            let postings = index
+                .range((IdKey(indexed.clone()), D::default())..)
+                .take_while(|((other, _), _)| other.0 == indexed)
+                .take(MAX_POSTINGS_PER_TERM);
+            for ((_, doc), frequency) in postings {
+                *term_matches.entry(doc).or_insert(0) += frequency;
+            }
+            // The smallest term after `indexed`
+            from = format!("{}\0", indexed);
+        }
+
+        matches = Some(match matches {
+            None => term_matches,
+            Some(previous) => previous
+                .into_iter()
+                .filter_map(|(doc, frequency)| term_matches.get(&doc).map(|more| (doc, frequency + more)))
+                .collect(),
+        });
+    }
+
+    matches.unwrap_or_default()
+}
+
+/// Sorts `(position, popularity, item)` matches by relevance and returns
+/// the page after `after`. Popularity only orders equally relevant matches
+/// within a page: it changes between requests, so it never decides which
+/// page a match lands on.
+fn rank_page<T>(mut ranked: Vec<(RankCursor, u64, T)>, after: Option<RankCursor>, limit: usize) -> Page<T> {
+    ranked.sort_by(|(a, _, _), (b, _, _)| a.rank().cmp(&b.rank()));
+
+    let entries = ranked
+        .into_iter()
+        .skip_while(|(position, _, _)| after.as_ref().is_some_and(|after| position.rank() <= after.rank()))
+        .map(|(position, popularity, item)| (position, (popularity, item)));
+    let page = pagination::collect_page(entries, pagination::page_size(limit), |position, (popularity, item)| {
+        Some((position.relevance, popularity, item))
+    });
+
+    let mut items = page.items;
+    items.sort_by_key(|(relevance, popularity, _)| (Reverse(*relevance), Reverse(*popularity)));
+    Page { items: items.into_iter().map(|(_, _, item)| item).collect(), next_cursor: page.next_cursor }
+}
+
+/// Position in a ranked result list. Relevance is computed as of the time
+/// the first page was requested, so later pages rank consistently with it.
+struct RankCursor {
+    as_of: u64,
+    relevance: u64,
+    id: Vec<u8>,
+}
+
+impl RankCursor {
+    fn new(as_of: u64, relevance: f64, id: Vec<u8>) -> Self {
+        // Fixed point keeps the ordering total and the cursor compact.
+        Self { as_of, relevance: (relevance * 1_000.0) as u64, id }
+    }
+
+    fn rank(&self) -> (Reverse<u64>, &[u8]) {
+        (Reverse(self.relevance), &self.id)
+    }
+}
+
+impl CursorKey for RankCursor {
+    fn to_cursor_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 + self.id.len());
+        bytes.extend_from_slice(&self.as_of.to_be_bytes());
+        bytes.extend_from_slice(&self.relevance.to_be_bytes());
+        bytes.extend_from_slice(&self.id);
+        bytes
+    }
+
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 16 || bytes.len() > 16 + MAX_ID_LEN as usize {
+            return None;
+        }
+        let (as_of, rest) = bytes.split_at(8);
+        let (relevance, id) = rest.split_at(8);
+        Some(Self {
+            as_of: u64::from_be_bytes(as_of.try_into().ok()?),
+            relevance: u64::from_be_bytes(relevance.try_into().ok()?),
+            id: id.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::Visibility;
+    use crate::storage::state::STATE;
+    use crate::utils::testing::principal;
+
+    fn publish(state: &mut State, id: &str, content: &str, created_at: u64) {
+        let mut post = Post::new(id.to_string(), principal(1), content.to_string(), None, Visibility::Public);
+        post.created_at = created_at;
+        SearchService::index_post(state, &post);
+        state.insert_post(post);
+    }
+
+    fn ids(page: &Page<Post>) -> Vec<&str> {
+        page.items.iter().map(|post| post.id.as_str()).collect()
+    }
+
+    #[test]
+    fn every_term_must_match_and_frequency_ranks_first() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let now = clock::now();
+            publish(state, "post_1", "rust canisters", now);
+            publish(state, "post_2", "rust rust canister", now);
+            publish(state, "post_3", "rust only", now);
+
+            let page = SearchService::search_posts(state, principal(2), "rust can", None, 10).unwrap();
+            assert_eq!(ids(&page), ["post_2", "post_1"]);
+            assert_eq!(page.next_cursor, None);
+        });
+    }
+
+    #[test]
+    fn likes_after_the_first_page_dont_move_posts_across_the_cursor() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let now = clock::now();
+            for id in ["post_1", "post_2", "post_3", "post_4"] {
+                publish(state, id, "rust", now);
+            }
+
+            let first = SearchService::search_posts(state, principal(2), "rust", None, 2).unwrap();
+            let seen = ids(&first).join(",");
+            // The last post gets popular between requests
+            let mut popular = state.get_post("post_4").unwrap();
+            popular.likes_count = 1_000;
+            state.insert_post(popular);
+
+            let second = SearchService::search_posts(state, principal(2), "rust", first.next_cursor.clone(), 2).unwrap();
+            let mut all: Vec<_> = seen.split(',').chain(ids(&second)).collect();
+            all.sort();
+            assert_eq!(all, ["post_1", "post_2", "post_3", "post_4"]);
+            assert_eq!(ids(&second)[0], "post_4");
+        });
+    }
+
+    #[test]
+    fn users_match_on_handle_or_bio_and_muted_users_are_left_out() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (alice, bob, carol) = (principal(1), principal(2), principal(3));
+            let mut popular = User::new(alice, "gardener".to_string(), String::new(), String::new());
+            popular.followers_count = 50;
+            for user in [
+                User::new(bob, "bob".to_string(), "Gardening and bikes".to_string(), String::new()),
+                popular,
+                User::new(carol, "carol".to_string(), "gardens".to_string(), String::new()),
+            ] {
+                SearchService::index_user(state, &user);
+                state.insert_user(user);
+            }
+            state.user_mutes.insert((PrincipalKey(bob), PrincipalKey(carol)), ());
+
+            let page = SearchService::search_users(state, bob, "garden", None, 10).unwrap();
+            let found: Vec<_> = page.items.iter().map(|user| user.id).collect();
+            assert_eq!(found, [alice, bob]);
+        });
+    }
+
+    #[test]
+    fn posts_the_caller_cant_see_are_left_out() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let now = clock::now();
+            publish(state, "post_1", "rust", now);
+            let mut private =
+                Post::new("post_2".to_string(), principal(1), "rust".to_string(), None, Visibility::FollowersOnly);
+            private.created_at = now;
+            SearchService::index_post(state, &private);
+            state.insert_post(private);
+
+            let page = SearchService::search_posts(state, principal(2), "rust", None, 10).unwrap();
+            assert_eq!(ids(&page), ["post_1"]);
+        });
+    }
+
+    #[test]
+    fn common_terms_keep_their_newest_posts_and_longer_terms_stay_reachable() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let now = clock::now();
+            let (count, hour) = (MAX_POSTINGS_PER_TERM as u64 + 1, 60 * 60 * 1_000_000_000);
+            for n in 0..count {
+                publish(state, &format!("post_{:04}", n), "rust", now - (count - n) * hour);
+            }
+            publish(state, "post_9999", "rustacean", now);
+
+            let page = SearchService::search_posts(state, principal(2), "rust", None, 2).unwrap();
+            assert_eq!(ids(&page), ["post_9999", &format!("post_{:04}", count - 1)]);
+        });
+    }
+}
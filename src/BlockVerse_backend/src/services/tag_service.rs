@@ -0,0 +1,366 @@
+use candid::Principal;
+use std::collections::BTreeMap;
+use crate::error::BlockVerseError;
+use crate::models::notification::NotificationKind;
+use crate::models::tag::{Mention, TrendingHashtag};
+use crate::models::{comment::Comment, page::Page, post::Post};
+use crate::services::notification_service::NotificationService;
+use crate::storage::pagination;
+use crate::storage::state::{State, STATE};
+use crate::storage::storable::{IdKey, PrincipalKey, RecencyKey};
+use crate::utils::{clock, tokenizer};
+
+/// Mentions and hashtags kept per post or comment; later ones are ignored.
+const MAX_TAGS: usize = 10;
+
+/// Longest window `get_trending_hashtags` looks back over.
+const MAX_TRENDING_WINDOW_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+
+/// Width of the buckets hashtag use is counted in, see `hashtag_counts`.
+const TRENDING_BUCKET_NS: u64 = 60 * 60 * 1_000_000_000;
+
+const TRENDING_LIMIT: usize = 10;
+
+/// Parses `@username` mentions and `#tag`s out of posts and comments and
+/// maintains the indexes behind hashtag and mention lookups.
+pub struct TagService;
+
+impl TagService {
+    /// Fills in `post.mentions` and `post.hashtags` from its content.
+    /// Mentions of usernames that don't exist are dropped.
+    pub fn parse_post(state: &State, post: &mut Post) {
+        post.mentions = Self::resolve_mentions(state, &post.content);
+        post.hashtags = Self::hashtags(&post.content);
+    }
+
+    pub fn parse_comment(state: &State, comment: &mut Comment) {
+        comment.mentions = Self::resolve_mentions(state, &comment.content);
+        comment.hashtags = Self::hashtags(&comment.content);
+    }
+
+    /// Only listed posts go into the hashtag indexes, so other posts don't
+    /// count towards trending tags either.
+    pub fn index_post(state: &mut State, post: &Post) {
+        let key = RecencyKey::of(post);
+        let hashtags = if post.is_listed() { post.hashtags.as_slice() } else { &[] };
+        for tag in hashtags {
+            if state.hashtag_posts.insert((IdKey(tag.clone()), key.clone()), ()).is_none() {
+                Self::count_hashtag(state, tag, post.created_at, 1);
+            }
+        }
+        for user_id in &post.mentions {
+            state.user_mentions.insert((PrincipalKey(*user_id), key.clone()), ());
+        }
+    }
+
+    pub fn unindex_post(state: &mut State, post: &Post) {
+        let key = RecencyKey::of(post);
+        for tag in &post.hashtags {
+            if state.hashtag_posts.remove(&(IdKey(tag.clone()), key.clone())).is_some() {
+                Self::count_hashtag(state, tag, post.created_at, -1);
+            }
+        }
+        for user_id in &post.mentions {
+            state.user_mentions.remove(&(PrincipalKey(*user_id), key.clone()));
+        }
+    }
+
+    /// Comments are only indexed by mention; hashtag lookups cover posts.
+    pub fn index_comment(state: &mut State, comment: &Comment) {
+        let key = RecencyKey::of_comment(comment);
+        for user_id in &comment.mentions {
+            state.user_mentions.insert((PrincipalKey(*user_id), key.clone()), ());
+        }
+    }
+
+    pub fn unindex_comment(state: &mut State, comment: &Comment) {
+        let key = RecencyKey::of_comment(comment);
+        for user_id in &comment.mentions {
+            state.user_mentions.remove(&(PrincipalKey(*user_id), key.clone()));
+        }
+    }
+
+    /// Recounts `hashtag_counts` from the hashtag index. Used when backfilling.
+    pub fn recount_hashtags(state: &mut State) {
+        let mut counts = BTreeMap::new();
+        for ((tag, post), _) in state.hashtag_posts.iter() {
+            *counts.entry((post.created_at / TRENDING_BUCKET_NS, tag)).or_insert(0) += 1;
+        }
+        for (key, count) in counts {
+            state.hashtag_counts.insert(key, count);
+        }
+    }
+
+    /// Notifies users mentioned in `mentions` but not in `previous`, so an
+    /// edit only notifies the users it adds.
+    pub fn notify_mentions(
+        state: &mut State,
+        author: Principal,
+        subject: &str,
+        mentions: &[Principal],
+        previous: &[Principal],
+    ) {
+        for user_id in mentions.iter().filter(|user_id| !previous.contains(user_id)) {
+            NotificationService::notify(
+                state,
+                *user_id,
+                NotificationKind::Mention,
+                author,
+                Some(subject.to_string()),
+                None,
+            );
+        }
+    }
+
+    /// Parses and indexes every live post and comment. Used when backfilling.
+    pub fn rebuild(state: &mut State) {
+        let posts: Vec<_> = state.posts.iter().map(|(_, post)| post).filter(|post| !post.is_deleted()).collect();
+        for mut post in posts {
+            Self::parse_post(state, &mut post);
+            Self::index_post(state, &post);
+            state.insert_post(post);
+        }
+
+        let comments: Vec<_> = state
+            .comments
+            .iter()
+            .map(|(_, comment)| comment)
+            .filter(|comment| !comment.is_deleted())
+            .collect();
+        for mut comment in comments {
+            Self::parse_comment(state, &mut comment);
+            Self::index_comment(state, &comment);
+            state.insert_comment(comment);
+        }
+    }
+
+    /// Posts tagged `#tag`, newest first, leaving out users the caller muted.
+    pub fn get_posts_by_hashtag(
+        tag: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        let tag = tag.trim_start_matches('#').to_lowercase();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.hashtag_posts, IdKey(tag), cursor, limit, |entry| {
+                state
+                    .get_post(&entry.post_id)
+                    .filter(|post| post.is_listed() && !state.has_muted(caller, post.author))
+                    .filter(|post| state.can_view_post(post, caller))
+            })
+        })
+    }
+
+    /// Posts and comments mentioning `user_id`, newest first.
+    pub fn get_mentions(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Mention>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.user_mentions, PrincipalKey(user_id), cursor, limit, |entry| {
+                let id = &entry.post_id;
+                match state.get_post(id) {
+                    Some(post) => state.can_view_post(&post, caller).then_some(Mention::Post(post)),
+                    None => state
+                        .get_comment(id)
+                        .filter(|comment| !state.is_hidden_from(&comment.id, comment.author, caller))
+                        .filter(|comment| {
+                            state.get_post(&comment.post_id).is_some_and(|post| state.can_view_post(&post, caller))
+                        })
+                        .map(Mention::Comment),
+                }
+            })
+        })
+    }
+
+    /// The most used hashtags on posts from the last `window_ns`
+    /// nanoseconds (at most a week), most used first. The window is rounded
+    /// out to the start of the hour it begins in.
+    pub fn get_trending_hashtags(window_ns: u64) -> Vec<TrendingHashtag> {
+        STATE.with(|state| Self::trending(&state.borrow(), clock::now(), window_ns))
+    }
+
+    fn trending(state: &State, now: u64, window_ns: u64) -> Vec<TrendingHashtag> {
+        let cutoff = now.saturating_sub(window_ns.min(MAX_TRENDING_WINDOW_NS));
+        let first_bucket = (cutoff / TRENDING_BUCKET_NS, IdKey(String::new()));
+
+        let mut counts = BTreeMap::new();
+        for ((_, tag), count) in state.hashtag_counts.range(first_bucket..) {
+            *counts.entry(tag.0).or_insert(0u64) += count;
+        }
+
+        let mut trending: Vec<_> = counts
+            .into_iter()
+            .map(|(tag, posts_count)| TrendingHashtag { tag, posts_count })
+            .collect();
+        trending.sort_by(|a, b| b.posts_count.cmp(&a.posts_count).then_with(|| a.tag.cmp(&b.tag)));
+        trending.truncate(TRENDING_LIMIT);
+        trending
+    }
+
+    /// Adds `delta` to the count for `tag` in the bucket `created_at` falls
+    /// in, dropping counts that reach zero.
+    fn count_hashtag(state: &mut State, tag: &str, created_at: u64, delta: i64) {
+        let key = (created_at / TRENDING_BUCKET_NS, IdKey(tag.to_string()));
+        let count = state.hashtag_counts.get(&key).unwrap_or(0).saturating_add_signed(delta);
+        if count == 0 {
+            state.hashtag_counts.remove(&key);
+        } else {
+            state.hashtag_counts.insert(key, count);
+        }
+    }
+
+    fn resolve_mentions(state: &State, content: &str) -> Vec<Principal> {
+        let mut mentions = Vec::new();
+        for username in tokenizer::mentions(content) {
+            if let Some(user_id) = state.find_username(&username) {
+                if !mentions.contains(&user_id) {
+                    mentions.push(user_id);
+                }
+            }
+            if mentions.len() == MAX_TAGS {
+                break;
+            }
+        }
+        mentions
+    }
+
+    fn hashtags(content: &str) -> Vec<String> {
+        let mut hashtags = tokenizer::hashtags(content);
+        hashtags.truncate(MAX_TAGS);
+        hashtags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::Visibility;
+    use crate::models::user::User;
+    use crate::utils::testing::principal;
+
+    const HOUR: u64 = TRENDING_BUCKET_NS;
+
+    fn tagged(id: &str, content: &str, created_at: u64, visibility: Visibility) -> Post {
+        let mut post = Post::new(id.to_string(), principal(1), content.to_string(), None, visibility);
+        post.created_at = created_at;
+        post.hashtags = TagService::hashtags(content);
+        post
+    }
+
+    fn user(state: &mut State, n: u8, username: &str) -> Principal {
+        let user_id = principal(n);
+        state.insert_user(User::new(user_id, username.to_string(), String::new(), String::new()));
+        user_id
+    }
+
+    fn counts(trending: Vec<TrendingHashtag>) -> Vec<(String, u64)> {
+        trending.into_iter().map(|tag| (tag.tag, tag.posts_count)).collect()
+    }
+
+    #[test]
+    fn trending_sums_the_buckets_in_the_window() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let now = 30 * HOUR;
+            for post in [
+                tagged("post_1", "#rust #ic", now - 2 * HOUR, Visibility::Public),
+                tagged("post_2", "#rust", now - HOUR, Visibility::Public),
+                tagged("post_3", "#rust", now - 20 * HOUR, Visibility::Public),
+                tagged("post_4", "#ic", now, Visibility::FollowersOnly),
+            ] {
+                TagService::index_post(state, &post);
+            }
+            // Indexing twice doesn't count twice
+            TagService::index_post(state, &tagged("post_2", "#rust", now - HOUR, Visibility::Public));
+
+            let recent = TagService::trending(state, now, 3 * HOUR);
+            assert_eq!(counts(recent), [("rust".to_string(), 2), ("ic".to_string(), 1)]);
+            let day = TagService::trending(state, now, 24 * HOUR);
+            assert_eq!(counts(day), [("rust".to_string(), 3), ("ic".to_string(), 1)]);
+        });
+    }
+
+    #[test]
+    fn unindexed_posts_stop_counting() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let post = tagged("post_1", "#rust", HOUR, Visibility::Public);
+            TagService::index_post(state, &post);
+            TagService::index_post(state, &tagged("post_2", "#rust", HOUR, Visibility::Public));
+
+            TagService::unindex_post(state, &post);
+            TagService::unindex_post(state, &post);
+            assert_eq!(counts(TagService::trending(state, HOUR, HOUR)), [("rust".to_string(), 1)]);
+
+            TagService::unindex_post(state, &tagged("post_2", "#rust", HOUR, Visibility::Public));
+            assert!(TagService::trending(state, HOUR, HOUR).is_empty());
+            assert!(state.hashtag_counts.is_empty());
+        });
+    }
+
+    #[test]
+    fn mentions_resolve_to_existing_users_and_tags_are_capped() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let bob = user(state, 2, "Bob");
+            let tags: Vec<_> = (0..MAX_TAGS + 2).map(|n| format!("#tag{}", n)).collect();
+            let content = format!("@bob @BOB @nobody #Rust {}", tags.join(" "));
+            let mut post = tagged("post_1", &content, 0, Visibility::Public);
+
+            TagService::parse_post(state, &mut post);
+            assert_eq!(post.mentions, [bob]);
+            assert_eq!(post.hashtags.len(), MAX_TAGS);
+            assert_eq!(post.hashtags[0], "rust");
+        });
+    }
+
+    #[test]
+    fn only_listed_posts_are_indexed_by_hashtag_but_mentions_always_are() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let bob = user(state, 2, "bob");
+            let mut public = tagged("post_1", "@bob #rust", 10, Visibility::Public);
+            let mut private = tagged("post_2", "@bob #rust", 20, Visibility::FollowersOnly);
+            for post in [&mut public, &mut private] {
+                TagService::parse_post(state, post);
+                TagService::index_post(state, post);
+            }
+            let mut comment =
+                Comment::new("comment_1".to_string(), "post_1".to_string(), bob, "thanks @bob".to_string());
+            TagService::parse_comment(state, &mut comment);
+            TagService::index_comment(state, &comment);
+
+            let tagged_posts: Vec<_> = state.hashtag_posts.iter().map(|((_, entry), _)| entry.post_id).collect();
+            assert_eq!(tagged_posts, ["post_1"]);
+            let mentioned: Vec<_> = state.user_mentions.iter().map(|((_, entry), _)| entry.post_id).collect();
+            assert_eq!(mentioned.len(), 3);
+
+            TagService::unindex_post(state, &public);
+            TagService::unindex_comment(state, &comment);
+            assert!(state.hashtag_posts.is_empty());
+            let mentioned: Vec<_> = state.user_mentions.iter().map(|((_, entry), _)| entry.post_id).collect();
+            assert_eq!(mentioned, ["post_2"]);
+        });
+    }
+
+    #[test]
+    fn edits_only_notify_newly_mentioned_users() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let (alice, bob, carol) = (user(state, 1, "alice"), user(state, 2, "bob"), user(state, 3, "carol"));
+
+            TagService::notify_mentions(state, alice, "post_1", &[bob, carol], &[bob]);
+
+            let notified: Vec<_> = state.notifications.iter().map(|((recipient, _), _)| recipient.0).collect();
+            assert_eq!(notified, [carol]);
+        });
+    }
+}
@@ -0,0 +1,271 @@
+use candid::Principal;
+use std::iter::Peekable;
+use std::ops::Bound;
+use ic_stable_structures::StableBTreeMap;
+use crate::error::BlockVerseError;
+use crate::models::{page::Page, post::Post};
+use crate::storage::memory::Memory;
+use crate::storage::pagination;
+use crate::storage::state::State;
+use crate::storage::storable::{PrincipalKey, RecencyKey};
+
+/// Authors with more followers than this stop being fanned out on write.
+pub const FANOUT_FOLLOWER_LIMIT: u64 = 5_000;
+
+/// How many of an author's latest posts are copied into (or dropped from)
+/// a timeline on follow (or unfollow). Older ones are filtered at read.
+const FOLLOW_BACKFILL: usize = 100;
+
+type PostIndex = StableBTreeMap<(PrincipalKey, RecencyKey), (), Memory>;
+type KeyStream<'a> = Peekable<Box<dyn Iterator<Item = RecencyKey> + 'a>>;
+
+/// Maintains the per-user home timelines. All functions run inside the
+/// caller's `STATE` borrow.
+pub struct TimelineService;
+
+impl TimelineService {
+    /// Delivers a new post to its author's timeline and, unless the author
+    /// is read on pull, to every follower's.
+    pub fn publish(state: &mut State, post: &Post) {
+        let entry = RecencyKey::of(post);
+        state.timelines.insert((PrincipalKey(post.author), entry.clone()), ());
+
+        if state.pull_authors.contains_key(&PrincipalKey(post.author)) {
+            return;
+        }
+        for follower in state.followers(post.author) {
+            state.timelines.insert((PrincipalKey(follower), entry.clone()), ());
+        }
+    }
+
+    /// Removes a post from every timeline it was delivered to.
+    pub fn retract(state: &mut State, post: &Post) {
+        let entry = RecencyKey::of(post);
+        state.timelines.remove(&(PrincipalKey(post.author), entry.clone()));
+
+        // A pull author's followers only hold entries from before the
+        // switch; reads skip those once the post is a tombstone.
+        if state.pull_authors.contains_key(&PrincipalKey(post.author)) {
+            return;
+        }
+        for follower in state.followers(post.author) {
+            state.timelines.remove(&(PrincipalKey(follower), entry.clone()));
+        }
+    }
+
+    /// Call after `follower` started following `followed`.
+    pub fn on_follow(state: &mut State, follower: Principal, followed: Principal) {
+        let followed_key = PrincipalKey(followed);
+
+        if !state.pull_authors.contains_key(&followed_key) {
+            let followers_count = state.get_user(followed).map_or(0, |user| user.followers_count);
+            if followers_count <= FANOUT_FOLLOWER_LIMIT {
+                for entry in Self::latest(&state.user_posts, followed, FOLLOW_BACKFILL) {
+                    state.timelines.insert((PrincipalKey(follower), entry), ());
+                }
+                return;
+            }
+
+            // Too popular to keep fanning out: switch the author to pull
+            // for everyone who already follows them. This is sticky, so
+            // posts that were never fanned out stay reachable.
+            state.pull_authors.insert(followed_key, ());
+            for existing in state.followers(followed) {
+                state.followed_pull_authors.insert((PrincipalKey(existing), followed_key), ());
+            }
+        }
+
+        state.followed_pull_authors.insert((PrincipalKey(follower), followed_key), ());
+    }
+
+    /// Call after `follower` stopped following `followed`.
+    pub fn on_unfollow(state: &mut State, follower: Principal, followed: Principal) {
+        state.followed_pull_authors.remove(&(PrincipalKey(follower), PrincipalKey(followed)));
+
+        for entry in Self::latest(&state.user_posts, followed, FOLLOW_BACKFILL) {
+            state.timelines.remove(&(PrincipalKey(follower), entry));
+        }
+    }
+
+    /// Seeds `user_id`'s timeline with their own latest posts and those of
+    /// everyone they follow. Used when backfilling existing accounts.
+    pub fn rebuild(state: &mut State, user_id: Principal) {
+        for entry in Self::latest(&state.user_posts, user_id, FOLLOW_BACKFILL) {
+            state.timelines.insert((PrincipalKey(user_id), entry), ());
+        }
+        for followed in state.following(user_id) {
+            Self::on_follow(state, user_id, followed);
+        }
+    }
+
+    /// Reads a page of `user_id`'s home timeline: the fanned-out entries
+    /// merged with the latest posts of every pull author they follow.
+    /// Authors `user_id` muted are skipped.
+    pub fn read(
+        state: &State,
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Post>, BlockVerseError> {
+        let after = pagination::decode_cursor::<RecencyKey>(cursor)?;
+
+        let mut streams = vec![Self::stream(&state.timelines, user_id, after.as_ref())];
+        for author in state.followed_pull_authors(user_id) {
+            streams.push(Self::stream(&state.user_posts, author, after.as_ref()));
+        }
+
+        let entries = MergeNewest { streams, last: None }.map(|entry| (entry, ()));
+        Ok(pagination::collect_page(entries, pagination::page_size(limit), |entry, _| {
+            let post = state.get_live_post(&entry.post_id)?;
+            // Entries left behind by an unfollow are dropped here.
+            let visible = (post.author == user_id || state.is_following(user_id, post.author))
+                && !state.has_muted(user_id, post.author)
+                && state.can_view_post(&post, user_id);
+            visible.then_some(post)
+        }))
+    }
+
+    fn latest(index: &PostIndex, owner: Principal, count: usize) -> Vec<RecencyKey> {
+        Self::stream(index, owner, None).take(count).collect()
+    }
+
+    fn stream<'a>(index: &'a PostIndex, owner: Principal, after: Option<&RecencyKey>) -> KeyStream<'a> {
+        let owner = PrincipalKey(owner);
+        let start = match after {
+            Some(after) => Bound::Excluded((owner, after.clone())),
+            None => Bound::Included((owner, RecencyKey::default())),
+        };
+
+        let entries = index
+            .range((start, Bound::Unbounded))
+            .take_while(move |((key_owner, _), _)| *key_owner == owner)
+            .map(|((_, entry), _)| entry);
+        (Box::new(entries) as Box<dyn Iterator<Item = RecencyKey>>).peekable()
+    }
+}
+
+/// Merges streams that are each sorted newest first, dropping duplicates
+/// (a post can be both fanned out and pulled).
+struct MergeNewest<'a> {
+    streams: Vec<KeyStream<'a>>,
+    last: Option<RecencyKey>,
+}
+
+impl Iterator for MergeNewest<'_> {
+    type Item = RecencyKey;
+
+    fn next(&mut self) -> Option<RecencyKey> {
+        loop {
+            let newest = self.streams
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, stream)| stream.peek().map(|entry| (i, entry)))
+                .min_by(|(_, a), (_, b)| a.cmp(b))
+                .map(|(i, _)| i)?;
+
+            let entry = self.streams[newest].next()?;
+            if self.last.as_ref() != Some(&entry) {
+                self.last = Some(entry.clone());
+                return Some(entry);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::Visibility;
+    use crate::services::post_service::PostService;
+    use crate::services::user_service::UserService;
+    use crate::storage::state::STATE;
+    use crate::utils::testing::users;
+
+    fn publish(state: &mut State, id: &str, author: Principal, created_at: u64) {
+        let mut post = Post::new(id.to_string(), author, "hello".to_string(), None, Visibility::Public);
+        post.created_at = created_at;
+        state.insert_post(post.clone());
+        state.user_posts.insert((PrincipalKey(author), RecencyKey::of(&post)), ());
+        TimelineService::publish(state, &post);
+    }
+
+    fn delivered(state: &State, user_id: Principal, created_at: u64, post_id: &str) -> bool {
+        state.timelines.contains_key(&(PrincipalKey(user_id), RecencyKey { created_at, post_id: post_id.to_string() }))
+    }
+
+    fn read_all(state: &State, user_id: Principal, limit: usize) -> Vec<String> {
+        let mut ids = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = TimelineService::read(state, user_id, cursor, limit).unwrap();
+            ids.extend(page.items.into_iter().map(|post| post.id));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => return ids,
+            }
+        }
+    }
+
+    #[test]
+    fn posts_are_fanned_out_to_followers() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol] = users(state, 3).try_into().unwrap();
+            UserService::follow(state, bob, alice).unwrap();
+            publish(state, "post_1", alice, 10);
+            publish(state, "post_2", carol, 20);
+
+            assert!(delivered(state, bob, 10, "post_1"));
+            assert_eq!(read_all(state, bob, 10), ["post_1"]);
+            assert_eq!(read_all(state, alice, 10), ["post_1"]);
+
+            // Following backfills, unfollowing takes the posts back out
+            UserService::follow(state, bob, carol).unwrap();
+            assert_eq!(read_all(state, bob, 10), ["post_2", "post_1"]);
+            UserService::unfollow(state, bob, alice);
+            assert_eq!(read_all(state, bob, 10), ["post_2"]);
+        });
+    }
+
+    #[test]
+    fn popular_authors_are_pulled_and_merged_in_order() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [star, alice, bob] = users(state, 3).try_into().unwrap();
+            UserService::follow(state, bob, alice).unwrap();
+            UserService::follow(state, alice, star).unwrap();
+            state.update_user(star, |user| user.followers_count = FANOUT_FOLLOWER_LIMIT);
+            UserService::follow(state, bob, star).unwrap();
+            assert!(state.pull_authors.contains_key(&PrincipalKey(star)));
+
+            for (n, author) in [star, alice, star, alice, star].into_iter().enumerate() {
+                publish(state, &format!("post_{}", n), author, n as u64 * 10);
+            }
+            // Nothing new is fanned out for the star
+            assert!(!delivered(state, bob, 40, "post_4"));
+
+            let expected = ["post_4", "post_3", "post_2", "post_1", "post_0"];
+            assert_eq!(read_all(state, bob, 2), expected);
+            assert_eq!(read_all(state, alice, 10), expected);
+        });
+    }
+
+    #[test]
+    fn deleted_posts_drop_out_of_entries_left_from_before_a_switch_to_pull() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [star, bob] = users(state, 2).try_into().unwrap();
+            UserService::follow(state, bob, star).unwrap();
+            publish(state, "post_1", star, 10);
+            publish(state, "post_2", star, 20);
+            state.update_user(star, |user| user.followers_count = FANOUT_FOLLOWER_LIMIT + 1);
+            TimelineService::on_follow(state, bob, star);
+            assert!(state.pull_authors.contains_key(&PrincipalKey(star)));
+
+            let post = state.get_post("post_1").unwrap();
+            PostService::delete(state, &post);
+            assert!(delivered(state, bob, 10, "post_1"));
+            assert_eq!(read_all(state, bob, 10), ["post_2"]);
+        });
+    }
+}
@@ -1,38 +1,51 @@
 use candid::Principal;
-use crate::models::user::User;
-use crate::storage::state::STATE;
-use crate::utils::validation;
+use crate::error::{BlockVerseError, EntityKind};
+use crate::models::{page::Page, user::User};
+use crate::models::notification::NotificationKind;
+use crate::services::federation_service::FederationService;
+use crate::services::search_service::SearchService;
+use crate::services::timeline_service::TimelineService;
+use crate::services::moderation_service::ModerationService;
+use crate::services::notification_service::NotificationService;
+use crate::storage::pagination;
+use crate::storage::state::{self, State, STATE};
+use crate::storage::storable::PrincipalKey;
+use crate::utils::{clock, validation};
 
 pub struct UserService;
 
 impl UserService {
-    pub fn create_user(username: String, bio: String, avatar_url: String) -> Result<User, String> {
+    pub fn create_user(username: String, bio: String, avatar_url: String) -> Result<User, BlockVerseError> {
         let caller = ic_cdk::caller();
-        
+
         if caller == Principal::anonymous() {
-            return Err("Anonymous users cannot create profiles".to_string());
+            return Err(BlockVerseError::AnonymousCaller);
         }
 
         if !validation::is_valid_username(&username) {
-            return Err("Invalid username format".to_string());
+            return Err(BlockVerseError::validation(
+                "username",
+                "Must be 1-20 letters, digits or underscores",
+            ));
         }
 
+        Self::validate_profile(&bio, &avatar_url)?;
+
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            if state.users.contains_key(&caller) {
-                return Err("User already exists".to_string());
+
+            if state.has_user(caller) {
+                return Err(BlockVerseError::already_exists(EntityKind::User, caller));
             }
 
             // Check if username is already taken
-            for user in state.users.values() {
-                if user.username.to_lowercase() == username.to_lowercase() {
-                    return Err("Username already taken".to_string());
-                }
+            if state.find_username(&username).is_some() {
+                return Err(BlockVerseError::already_exists(EntityKind::Username, &username));
             }
 
             let user = User::new(caller, username, bio, avatar_url);
-            state.users.insert(caller, user.clone());
+            state.insert_user(user.clone());
+            SearchService::index_user(&mut state, &user);
             Ok(user)
         })
     }
@@ -40,122 +53,486 @@ impl UserService {
     pub fn get_user(user_id: Principal) -> Option<User> {
         STATE.with(|state| {
             let state = state.borrow();
-            state.users.get(&user_id).cloned()
+            state.get_user(user_id)
         })
     }
 
-    pub fn update_user(bio: String, avatar_url: String) -> Result<User, String> {
+    /// Looks a user up by username, ignoring case.
+    pub fn get_user_by_username(username: String) -> Option<User> {
+        STATE.with(|state| {
+            let state = state.borrow();
+            state.find_username(&username).and_then(|user_id| state.get_user(user_id))
+        })
+    }
+
+    pub fn update_user(bio: String, avatar_url: String) -> Result<User, BlockVerseError> {
         let caller = ic_cdk::caller();
 
+        Self::validate_profile(&bio, &avatar_url)?;
+
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            match state.users.get_mut(&caller) {
-                Some(user) => {
-                    user.update(bio, avatar_url);
-                    Ok(user.clone())
-                }
-                None => Err("User not found".to_string())
-            }
+
+            let previous = state
+                .get_user(caller)
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::User, caller))?;
+
+            let mut user = previous.clone();
+            user.update(bio, avatar_url);
+            state.insert_user(user.clone());
+
+            SearchService::unindex_user(&mut state, &previous);
+            SearchService::index_user(&mut state, &user);
+            Ok(user)
         })
     }
 
-    pub fn follow_user(user_to_follow: Principal) -> Result<(), String> {
+    /// Follows `user_to_follow`. Private accounts get a follow request
+    /// instead, which takes effect once they approve it.
+    pub fn follow_user(user_to_follow: Principal) -> Result<(), BlockVerseError> {
         let caller = ic_cdk::caller();
 
         if caller == user_to_follow {
-            return Err("Cannot follow yourself".to_string());
+            return Err(BlockVerseError::InvalidOperation("Cannot follow yourself".to_string()));
         }
 
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            // Check if both users exist
-            if !state.users.contains_key(&caller) || !state.users.contains_key(&user_to_follow) {
-                return Err("One or both users not found".to_string());
+            if state.is_remote(user_to_follow) {
+                return Err(BlockVerseError::InvalidOperation("Remote accounts can't be followed".to_string()));
             }
+            Self::follow(&mut state, caller, user_to_follow).map(|_| ())
+        })
+    }
 
-            // Add to following list
-            let following = state.user_following.entry(caller).or_insert_with(Vec::new);
-            if !following.contains(&user_to_follow) {
-                following.push(user_to_follow);
-                
-                // Add to followers list
-                let followers = state.user_followers.entry(user_to_follow).or_insert_with(Vec::new);
-                followers.push(caller);
-
-                // Update counts
-                if let Some(user) = state.users.get_mut(&caller) {
-                    user.following_count += 1;
-                }
-                if let Some(user) = state.users.get_mut(&user_to_follow) {
-                    user.followers_count += 1;
-                }
+    /// Makes `follower` follow `followed`, or request to if `followed` is
+    /// private. Returns whether `follower` now follows them.
+    pub fn follow(state: &mut State, follower: Principal, followed: Principal) -> Result<bool, BlockVerseError> {
+        ModerationService::check_not_suspended(state, follower)?;
+
+        // Check if both users exist
+        for user_id in [follower, followed] {
+            if !state.has_user(user_id) {
+                return Err(BlockVerseError::not_found(EntityKind::User, user_id));
             }
+        }
 
+        Self::check_not_blocked(state, follower, followed)?;
+
+        if state.is_following(follower, followed) || state.has_requested_follow(follower, followed) {
+            return Ok(state.is_following(follower, followed));
+        }
+
+        let is_private = state.get_user(followed).is_some_and(|user| user.is_private);
+        if is_private {
+            let (requester, user_id) = (PrincipalKey(follower), PrincipalKey(followed));
+            state.follow_requests.insert((user_id, requester), ());
+            state.sent_follow_requests.insert((requester, user_id), ());
+            NotificationService::notify(state, followed, NotificationKind::FollowRequest, follower, None, None);
+        } else {
+            Self::add_follow(state, follower, followed);
+            NotificationService::notify(state, followed, NotificationKind::Follow, follower, None, None);
+        }
+
+        Ok(!is_private)
+    }
+
+    pub fn unfollow_user(user_to_unfollow: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            Self::remove_follow(&mut state, caller, user_to_unfollow);
             Ok(())
         })
     }
 
-    pub fn unfollow_user(user_to_unfollow: Principal) -> Result<(), String> {
+    /// Ends `follower`'s follow of `followed`, or withdraws their request.
+    pub fn unfollow(state: &mut State, follower: Principal, followed: Principal) {
+        Self::remove_follow(state, follower, followed);
+        Self::remove_follow_request(state, follower, followed);
+    }
+
+    /// Makes the caller's account private or public. Going public approves
+    /// every pending follow request.
+    pub fn set_account_private(is_private: bool) -> Result<User, BlockVerseError> {
         let caller = ic_cdk::caller();
 
         STATE.with(|state| {
             let mut state = state.borrow_mut();
-            
-            // Remove from following list
-            if let Some(following) = state.user_following.get_mut(&caller) {
-                if let Some(pos) = following.iter().position(|&x| x == user_to_unfollow) {
-                    following.remove(pos);
-                    
-                    // Remove from followers list
-                    if let Some(followers) = state.user_followers.get_mut(&user_to_unfollow) {
-                        if let Some(pos) = followers.iter().position(|&x| x == caller) {
-                            followers.remove(pos);
-                        }
-                    }
-
-                    // Update counts
-                    if let Some(user) = state.users.get_mut(&caller) {
-                        user.following_count = user.following_count.saturating_sub(1);
-                    }
-                    if let Some(user) = state.users.get_mut(&user_to_unfollow) {
-                        user.followers_count = user.followers_count.saturating_sub(1);
-                    }
+
+            state
+                .update_user(caller, |user| {
+                    user.is_private = is_private;
+                    user.updated_at = clock::now();
+                })
+                .ok_or_else(|| BlockVerseError::not_found(EntityKind::User, caller))?;
+
+            if !is_private {
+                for requester in state::remove_members(&mut state.follow_requests, &PrincipalKey(caller)) {
+                    state.sent_follow_requests.remove(&(requester, PrincipalKey(caller)));
+                    Self::add_follow(&mut state, requester.0, caller);
+                    FederationService::accept_follow(&mut state, requester.0, caller);
                 }
             }
 
+            state.get_user(caller).ok_or_else(|| BlockVerseError::not_found(EntityKind::User, caller))
+        })
+    }
+
+    /// Users waiting for the caller to approve their follow request.
+    pub fn get_follow_requests(cursor: Option<String>, limit: usize) -> Result<Page<Principal>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.follow_requests, PrincipalKey(caller), cursor, limit, |requester| {
+                Some(requester.0)
+            })
+        })
+    }
+
+    /// Private accounts the caller has asked to follow.
+    pub fn get_sent_follow_requests(
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Principal>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.sent_follow_requests, PrincipalKey(caller), cursor, limit, |user_id| {
+                Some(user_id.0)
+            })
+        })
+    }
+
+    pub fn approve_follow_request(requester: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| Self::approve(&mut state.borrow_mut(), caller, requester))
+    }
+
+    pub fn reject_follow_request(requester: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| Self::reject(&mut state.borrow_mut(), caller, requester))
+    }
+
+    /// Withdraws the caller's pending request to follow `user_id`.
+    pub fn cancel_follow_request(user_id: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            if !Self::remove_follow_request(&mut state, caller, user_id) {
+                return Err(BlockVerseError::not_found(EntityKind::FollowRequest, user_id));
+            }
             Ok(())
         })
     }
 
-    pub fn get_user_followers(user_id: Principal) -> Vec<Principal> {
+    pub fn get_user_followers(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Principal>, BlockVerseError> {
         STATE.with(|state| {
             let state = state.borrow();
-            state.user_followers.get(&user_id).cloned().unwrap_or_default()
+            pagination::page_members(&state.user_followers, PrincipalKey(user_id), cursor, limit, |follower| {
+                Some(follower.0)
+            })
         })
     }
 
-    pub fn get_user_following(user_id: Principal) -> Vec<Principal> {
+    pub fn get_user_following(
+        user_id: Principal,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<Principal>, BlockVerseError> {
         STATE.with(|state| {
             let state = state.borrow();
-            state.user_following.get(&user_id).cloned().unwrap_or_default()
+            pagination::page_members(&state.user_following, PrincipalKey(user_id), cursor, limit, |followed| {
+                Some(followed.0)
+            })
         })
     }
 
-    pub fn search_users(query: String) -> Vec<User> {
-        let query = query.to_lowercase();
-        
+    pub fn is_following(follower: Principal, followed: Principal) -> bool {
         STATE.with(|state| {
             let state = state.borrow();
-            state.users
-                .values()
-                .filter(|user| {
-                    user.username.to_lowercase().contains(&query) ||
-                    user.bio.to_lowercase().contains(&query)
-                })
-                .cloned()
-                .collect()
+            state.is_following(follower, followed)
         })
     }
-}
\ No newline at end of file
+
+    /// Blocks `user_to_block` from interacting with the caller: following,
+    /// commenting, liking, sharing, tipping and messaging are refused in
+    /// both directions. Existing follows between the two are removed.
+    pub fn block_user(user_to_block: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == user_to_block {
+            return Err(BlockVerseError::InvalidOperation("Cannot block yourself".to_string()));
+        }
+
+        STATE.with(|state| Self::block(&mut state.borrow_mut(), caller, user_to_block))
+    }
+
+    pub fn unblock_user(user_to_unblock: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.user_blocks.remove(&(PrincipalKey(caller), PrincipalKey(user_to_unblock)));
+            Ok(())
+        })
+    }
+
+    /// Users the caller has blocked.
+    pub fn get_blocked_users(cursor: Option<String>, limit: usize) -> Result<Page<Principal>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.user_blocks, PrincipalKey(caller), cursor, limit, |blocked| {
+                Some(blocked.0)
+            })
+        })
+    }
+
+    /// Hides `user_to_mute`'s posts from the caller's feed and search
+    /// results. Unlike a block, the muted user can't tell.
+    pub fn mute_user(user_to_mute: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        if caller == user_to_mute {
+            return Err(BlockVerseError::InvalidOperation("Cannot mute yourself".to_string()));
+        }
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+
+            for user_id in [caller, user_to_mute] {
+                if !state.has_user(user_id) {
+                    return Err(BlockVerseError::not_found(EntityKind::User, user_id));
+                }
+            }
+
+            state.user_mutes.insert((PrincipalKey(caller), PrincipalKey(user_to_mute)), ());
+            Ok(())
+        })
+    }
+
+    pub fn unmute_user(user_to_unmute: Principal) -> Result<(), BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.user_mutes.remove(&(PrincipalKey(caller), PrincipalKey(user_to_unmute)));
+            Ok(())
+        })
+    }
+
+    /// Users the caller has muted.
+    pub fn get_muted_users(cursor: Option<String>, limit: usize) -> Result<Page<Principal>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+
+        STATE.with(|state| {
+            let state = state.borrow();
+            pagination::page_members(&state.user_mutes, PrincipalKey(caller), cursor, limit, |muted| {
+                Some(muted.0)
+            })
+        })
+    }
+
+    /// Fails if either user has blocked the other.
+    pub fn check_not_blocked(state: &State, user_id: Principal, other: Principal) -> Result<(), BlockVerseError> {
+        if state.has_blocked(user_id, other) {
+            return Err(BlockVerseError::InvalidOperation("You have blocked this user".to_string()));
+        }
+        if state.has_blocked(other, user_id) {
+            return Err(BlockVerseError::Unauthorized("This user has blocked you".to_string()));
+        }
+        Ok(())
+    }
+
+    pub fn search_users(
+        query: String,
+        cursor: Option<String>,
+        limit: usize,
+    ) -> Result<Page<User>, BlockVerseError> {
+        let caller = ic_cdk::caller();
+        STATE.with(|state| SearchService::search_users(&state.borrow(), caller, &query, cursor, limit))
+    }
+
+    fn approve(state: &mut State, caller: Principal, requester: Principal) -> Result<(), BlockVerseError> {
+        if !Self::remove_follow_request(state, requester, caller) {
+            return Err(BlockVerseError::not_found(EntityKind::FollowRequest, requester));
+        }
+        Self::add_follow(state, requester, caller);
+        FederationService::accept_follow(state, requester, caller);
+        Ok(())
+    }
+
+    fn reject(state: &mut State, caller: Principal, requester: Principal) -> Result<(), BlockVerseError> {
+        if !Self::remove_follow_request(state, requester, caller) {
+            return Err(BlockVerseError::not_found(EntityKind::FollowRequest, requester));
+        }
+        FederationService::reject_follow(state, requester, caller);
+        Ok(())
+    }
+
+    fn block(state: &mut State, caller: Principal, user_to_block: Principal) -> Result<(), BlockVerseError> {
+        for user_id in [caller, user_to_block] {
+            if !state.has_user(user_id) {
+                return Err(BlockVerseError::not_found(EntityKind::User, user_id));
+            }
+        }
+
+        state.user_blocks.insert((PrincipalKey(caller), PrincipalKey(user_to_block)), ());
+        FederationService::reject_follow(state, user_to_block, caller);
+        Self::remove_follow(state, caller, user_to_block);
+        Self::remove_follow(state, user_to_block, caller);
+        Self::remove_follow_request(state, caller, user_to_block);
+        Self::remove_follow_request(state, user_to_block, caller);
+        Ok(())
+    }
+
+    fn add_follow(state: &mut State, follower: Principal, followed: Principal) {
+        // Add to following list
+        let (follower_key, followed_key) = (PrincipalKey(follower), PrincipalKey(followed));
+        state.user_following.insert((follower_key, followed_key), ());
+
+        // Add to followers list
+        state.user_followers.insert((followed_key, follower_key), ());
+
+        // Update counts
+        state.update_user(follower, |user| user.following_count += 1);
+        state.update_user(followed, |user| user.followers_count += 1);
+
+        TimelineService::on_follow(state, follower, followed);
+    }
+
+    fn remove_follow(state: &mut State, follower: Principal, followed: Principal) {
+        // Remove from following list
+        let (follower_key, followed_key) = (PrincipalKey(follower), PrincipalKey(followed));
+        if state.user_following.remove(&(follower_key, followed_key)).is_none() {
+            return;
+        }
+
+        // Remove from followers list
+        state.user_followers.remove(&(followed_key, follower_key));
+
+        // Update counts
+        state.update_user(follower, |user| {
+            user.following_count = user.following_count.saturating_sub(1);
+        });
+        state.update_user(followed, |user| {
+            user.followers_count = user.followers_count.saturating_sub(1);
+        });
+
+        TimelineService::on_unfollow(state, follower, followed);
+    }
+
+    /// Removes a pending request; returns whether there was one.
+    fn remove_follow_request(state: &mut State, requester: Principal, user_id: Principal) -> bool {
+        let (requester, user_id) = (PrincipalKey(requester), PrincipalKey(user_id));
+        state.sent_follow_requests.remove(&(requester, user_id));
+        state.follow_requests.remove(&(user_id, requester)).is_some()
+    }
+
+    fn validate_profile(bio: &str, avatar_url: &str) -> Result<(), BlockVerseError> {
+        if !validation::is_valid_bio(bio) {
+            return Err(BlockVerseError::validation("bio", "Too long"));
+        }
+        if !validation::is_valid_url(avatar_url) {
+            return Err(BlockVerseError::validation("avatar_url", "Too long"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::post::{Post, Visibility};
+    use crate::utils::testing::users;
+
+    #[test]
+    fn blocking_ends_follows_both_ways_and_stops_new_ones() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob] = users(state, 2).try_into().unwrap();
+            UserService::follow(state, alice, bob).unwrap();
+            UserService::follow(state, bob, alice).unwrap();
+
+            UserService::block(state, alice, bob).unwrap();
+
+            assert!(!state.is_following(alice, bob) && !state.is_following(bob, alice));
+            assert_eq!(state.get_user(alice).unwrap().followers_count, 0);
+            assert!(matches!(UserService::follow(state, bob, alice), Err(BlockVerseError::Unauthorized(_))));
+            assert!(matches!(UserService::follow(state, alice, bob), Err(BlockVerseError::InvalidOperation(_))));
+        });
+    }
+
+    #[test]
+    fn blocking_withdraws_pending_follow_requests() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob] = users(state, 2).try_into().unwrap();
+            state.update_user(alice, |user| user.is_private = true);
+            assert!(!UserService::follow(state, bob, alice).unwrap());
+            assert!(state.has_requested_follow(bob, alice));
+
+            UserService::block(state, alice, bob).unwrap();
+            assert!(!state.has_requested_follow(bob, alice));
+            assert!(state.sent_follow_requests.is_empty());
+        });
+    }
+
+    #[test]
+    fn muted_authors_drop_out_of_the_home_feed() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob] = users(state, 2).try_into().unwrap();
+            UserService::follow(state, alice, bob).unwrap();
+            let post = Post::new("post_1".to_string(), bob, "hello".to_string(), None, Visibility::Public);
+            state.insert_post(post.clone());
+            TimelineService::publish(state, &post);
+            assert_eq!(TimelineService::read(state, alice, None, 10).unwrap().items.len(), 1);
+
+            state.user_mutes.insert((PrincipalKey(alice), PrincipalKey(bob)), ());
+            assert!(TimelineService::read(state, alice, None, 10).unwrap().items.is_empty());
+            // Muting doesn't unfollow
+            assert!(state.is_following(alice, bob));
+        });
+    }
+
+    #[test]
+    fn private_accounts_approve_followers_before_they_see_posts() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol] = users(state, 3).try_into().unwrap();
+            state.update_user(alice, |user| user.is_private = true);
+            let post = Post::new("post_1".to_string(), alice, "hello".to_string(), None, Visibility::Public);
+            state.insert_post(post.clone());
+
+            assert!(!UserService::follow(state, bob, alice).unwrap());
+            assert!(!UserService::follow(state, carol, alice).unwrap());
+            assert!(!state.is_following(bob, alice));
+            assert!(!state.can_view_post(&post, bob));
+            let requested = state.notifications.iter().next().map(|(_, notification)| notification.kind);
+            assert_eq!(requested, Some(NotificationKind::FollowRequest));
+
+            UserService::approve(state, alice, bob).unwrap();
+            UserService::reject(state, alice, carol).unwrap();
+            assert!(state.is_following(bob, alice) && state.can_view_post(&post, bob));
+            assert!(!state.is_following(carol, alice) && !state.can_view_post(&post, carol));
+            assert!(state.follow_requests.is_empty());
+            assert!(matches!(
+                UserService::approve(state, alice, carol),
+                Err(BlockVerseError::NotFound { kind: EntityKind::FollowRequest, .. })
+            ));
+        });
+    }
+}
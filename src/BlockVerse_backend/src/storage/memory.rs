@@ -0,0 +1,101 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Each stable structure owns one virtual memory. These ids are part of the
+// stable layout: never reuse or renumber them, only append new ones.
+/// Held the single admin principal up to schema version 4.
+pub const ADMIN_V4_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const USERS_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const POSTS_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const COMMENTS_MEMORY_ID: MemoryId = MemoryId::new(3);
+/// Held `user_posts` keyed by post id up to schema version 1.
+pub const USER_POSTS_V1_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const USER_FOLLOWERS_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const USER_FOLLOWING_MEMORY_ID: MemoryId = MemoryId::new(6);
+/// Held `post_comments` keyed by joined comment ids up to schema version 8.
+pub const POST_COMMENTS_V8_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const POST_LIKES_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const COMMENT_LIKES_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const TRANSACTIONS_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const SCHEMA_VERSION_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const ID_SEQUENCE_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const USER_POSTS_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const TIMELINES_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub const PULL_AUTHORS_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub const FOLLOWED_PULL_AUTHORS_MEMORY_ID: MemoryId = MemoryId::new(16);
+/// Held `post_terms` keyed by post id up to schema version 12.
+pub const POST_TERMS_V12_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub const USER_TERMS_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub const LEDGER_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub const USER_TRANSACTIONS_MEMORY_ID: MemoryId = MemoryId::new(20);
+pub const POST_TIPS_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub const TRANSACTION_TOTALS_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub const POST_TIP_TOTALS_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub const ROLES_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub const ROLE_CHANGES_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub const REPORTS_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub const OPEN_REPORTS_MEMORY_ID: MemoryId = MemoryId::new(27);
+pub const TARGET_REPORTS_MEMORY_ID: MemoryId = MemoryId::new(28);
+pub const REPORTER_TARGETS_MEMORY_ID: MemoryId = MemoryId::new(29);
+pub const DECISIONS_MEMORY_ID: MemoryId = MemoryId::new(30);
+pub const SUBJECT_DECISIONS_MEMORY_ID: MemoryId = MemoryId::new(31);
+pub const APPEALS_MEMORY_ID: MemoryId = MemoryId::new(32);
+pub const PENDING_APPEALS_MEMORY_ID: MemoryId = MemoryId::new(33);
+pub const HIDDEN_CONTENT_MEMORY_ID: MemoryId = MemoryId::new(34);
+pub const SUSPENSIONS_MEMORY_ID: MemoryId = MemoryId::new(35);
+pub const TOMBSTONES_MEMORY_ID: MemoryId = MemoryId::new(36);
+pub const POST_SHARES_MEMORY_ID: MemoryId = MemoryId::new(37);
+pub const POST_REVISIONS_MEMORY_ID: MemoryId = MemoryId::new(38);
+pub const EDIT_WINDOW_MEMORY_ID: MemoryId = MemoryId::new(39);
+pub const NOTIFICATIONS_MEMORY_ID: MemoryId = MemoryId::new(40);
+pub const UNREAD_NOTIFICATION_GROUPS_MEMORY_ID: MemoryId = MemoryId::new(41);
+pub const UNREAD_NOTIFICATION_COUNTS_MEMORY_ID: MemoryId = MemoryId::new(42);
+pub const NOTIFICATION_MUTES_MEMORY_ID: MemoryId = MemoryId::new(43);
+pub const USERNAMES_MEMORY_ID: MemoryId = MemoryId::new(44);
+pub const HASHTAG_POSTS_MEMORY_ID: MemoryId = MemoryId::new(45);
+/// Held `(post, tag)` for every tagged post up to schema version 10.
+pub const RECENT_HASHTAGS_V10_MEMORY_ID: MemoryId = MemoryId::new(46);
+pub const USER_MENTIONS_MEMORY_ID: MemoryId = MemoryId::new(47);
+pub const CONVERSATIONS_MEMORY_ID: MemoryId = MemoryId::new(48);
+pub const USER_CONVERSATIONS_MEMORY_ID: MemoryId = MemoryId::new(49);
+pub const DIRECT_CONVERSATIONS_MEMORY_ID: MemoryId = MemoryId::new(50);
+pub const MESSAGES_MEMORY_ID: MemoryId = MemoryId::new(51);
+pub const READ_RECEIPTS_MEMORY_ID: MemoryId = MemoryId::new(52);
+pub const UNREAD_MESSAGE_COUNTS_MEMORY_ID: MemoryId = MemoryId::new(53);
+pub const CONVERSATION_MUTES_MEMORY_ID: MemoryId = MemoryId::new(54);
+pub const DM_POLICIES_MEMORY_ID: MemoryId = MemoryId::new(55);
+pub const USER_BLOCKS_MEMORY_ID: MemoryId = MemoryId::new(56);
+pub const USER_MUTES_MEMORY_ID: MemoryId = MemoryId::new(57);
+pub const FOLLOW_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(58);
+pub const SENT_FOLLOW_REQUESTS_MEMORY_ID: MemoryId = MemoryId::new(59);
+pub const ASSETS_MEMORY_ID: MemoryId = MemoryId::new(60);
+pub const USER_ASSETS_MEMORY_ID: MemoryId = MemoryId::new(61);
+pub const UPLOADS_MEMORY_ID: MemoryId = MemoryId::new(62);
+pub const ASSET_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(63);
+pub const MEDIA_USAGE_MEMORY_ID: MemoryId = MemoryId::new(64);
+pub const REMOTE_ACTORS_MEMORY_ID: MemoryId = MemoryId::new(65);
+pub const REMOTE_FOLLOWS_MEMORY_ID: MemoryId = MemoryId::new(66);
+pub const REMOTE_OBJECTS_MEMORY_ID: MemoryId = MemoryId::new(67);
+pub const DELIVERIES_MEMORY_ID: MemoryId = MemoryId::new(68);
+pub const FEDERATION_KEY_MEMORY_ID: MemoryId = MemoryId::new(69);
+pub const FEDERATION_DOMAIN_MEMORY_ID: MemoryId = MemoryId::new(70);
+pub const RECENT_POSTS_MEMORY_ID: MemoryId = MemoryId::new(71);
+pub const POST_COMMENTS_MEMORY_ID: MemoryId = MemoryId::new(72);
+pub const COMMENT_THREADS_MEMORY_ID: MemoryId = MemoryId::new(73);
+pub const PENDING_WITHDRAWALS_MEMORY_ID: MemoryId = MemoryId::new(74);
+pub const ASSET_POSTS_MEMORY_ID: MemoryId = MemoryId::new(75);
+pub const HASHTAG_COUNTS_MEMORY_ID: MemoryId = MemoryId::new(76);
+pub const UNREAD_NOTIFICATION_ACTORS_MEMORY_ID: MemoryId = MemoryId::new(77);
+pub const POST_TERMS_MEMORY_ID: MemoryId = MemoryId::new(78);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.borrow().get(id))
+}
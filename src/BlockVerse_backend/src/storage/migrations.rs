@@ -0,0 +1,840 @@
+use candid::Principal;
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use crate::services::comment_service::CommentService;
+use crate::services::media_service::MediaService;
+use crate::services::notification_service::NotificationService;
+use crate::services::payment_service::PaymentService;
+use crate::services::post_service::PostService;
+use crate::services::role_service::RoleService;
+use crate::services::search_service::SearchService;
+use crate::services::tag_service::TagService;
+use crate::services::timeline_service::TimelineService;
+use super::memory::{
+    get_memory, Memory, ADMIN_V4_MEMORY_ID, POST_COMMENTS_V8_MEMORY_ID, POST_TERMS_V12_MEMORY_ID,
+    RECENT_HASHTAGS_V10_MEMORY_ID, USER_POSTS_V1_MEMORY_ID,
+};
+use super::state::{State, STATE};
+use super::storable::{IdKey, PrincipalKey, RecencyKey};
+
+/// Version of the stable layout written by this build.
+///
+/// Version 0 is the heap snapshot written by `stable_save` before state
+/// moved to stable structures; version 1 is the first stable layout. Bump
+/// this and add a step to `migrate` whenever the layout changes in a way
+/// the per-record envelopes in `storable` can't absorb (new indexes that
+/// need backfilling, re-keyed maps, ...).
+///
+/// Version 2 re-keys `user_posts` newest first and adds home timelines;
+/// version 3 adds the search indexes; version 4 the transaction history
+/// indexes and totals; version 5 replaces the single admin with roles;
+/// version 6 adds the share index and tombstones; version 7 the username
+/// index and the mention and hashtag indexes; version 8 the global
+/// recency index; version 9 re-keys comment threads by sequence number;
+/// version 10 adds the index of posts showing each asset; version 11
+/// replaces the recent hashtag index with hourly hashtag counts; version
+/// 12 tracks the actors grouped into each unread notification; version 13
+/// re-keys the post search index newest first.
+pub const CURRENT_SCHEMA_VERSION: u32 = 13;
+
+/// Brings stable memory up to `CURRENT_SCHEMA_VERSION`. Must run before
+/// anything else touches `STATE`, since a legacy snapshot is overwritten
+/// as soon as the memory manager initialises.
+pub fn run() {
+    let legacy = v0::take_snapshot();
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+
+        if let Some(snapshot) = legacy {
+            v0::import(&mut state, snapshot);
+            state.set_schema_version(1);
+        }
+
+        let mut version = state.schema_version();
+        if version > CURRENT_SCHEMA_VERSION {
+            ic_cdk::trap(&format!(
+                "Stable memory has schema version {}, this build only supports up to {}",
+                version, CURRENT_SCHEMA_VERSION
+            ));
+        }
+
+        while version < CURRENT_SCHEMA_VERSION {
+            migrate(&mut state, version);
+            version += 1;
+            state.set_schema_version(version);
+        }
+    });
+}
+
+/// Upgrades the layout from `version` to `version + 1`.
+fn migrate(state: &mut State, version: u32) {
+    match version {
+        1 => v1_to_v2(state),
+        2 => SearchService::rebuild(state),
+        3 => v3_to_v4(state),
+        4 => v4_to_v5(state),
+        5 => v5_to_v6(state),
+        6 => v6_to_v7(state),
+        7 => v7_to_v8(state),
+        8 => v8_to_v9(state),
+        9 => v9_to_v10(state),
+        10 => v10_to_v11(state),
+        11 => v11_to_v12(state),
+        12 => v12_to_v13(state),
+        _ => ic_cdk::trap(&format!("No migration from schema version {}", version)),
+    }
+}
+
+fn v3_to_v4(state: &mut State) {
+    for position in 0..state.transactions.len() {
+        if let Some(transaction) = state.transactions.get(position) {
+            PaymentService::index(state, position, &transaction);
+        }
+    }
+}
+
+/// The admin principal as stored up to version 4.
+fn admin_v4() -> StableCell<PrincipalKey, Memory> {
+    StableCell::init(get_memory(ADMIN_V4_MEMORY_ID), PrincipalKey(Principal::anonymous()))
+        .expect("Failed to initialize legacy admin")
+}
+
+fn v4_to_v5(state: &mut State) {
+    let admin = admin_v4().get().0;
+    if admin != Principal::anonymous() {
+        RoleService::bootstrap_owner(state, admin);
+    }
+}
+
+/// Indexes shares and cleans up after the hard deletes of earlier
+/// versions, which left comments, likes and shares of removed posts behind.
+fn v5_to_v6(state: &mut State) {
+    let posts: Vec<_> = state.posts.iter().map(|(_, post)| post).collect();
+    for post in &posts {
+        state.update_post(&post.id, |post| post.shares_count = 0);
+    }
+    for share in &posts {
+        let Some(original_post_id) = &share.original_post_id else { continue };
+        if state.update_post(original_post_id, |original_post| original_post.shares_count += 1).is_some() {
+            state.post_shares.insert((IdKey(original_post_id.clone()), IdKey(share.id.clone())), ());
+        } else {
+            state.update_post(&share.id, |share| share.original_post_id = None);
+        }
+    }
+
+    let orphans: Vec<_> = state
+        .comments
+        .iter()
+        .filter(|(_, comment)| !state.posts.contains_key(&IdKey(comment.post_id.clone())))
+        .map(|(_, comment)| comment)
+        .collect();
+    for comment in &orphans {
+        CommentService::purge(state, comment);
+    }
+
+    let likes: Vec<_> = state
+        .post_likes
+        .iter()
+        .filter(|((post_id, _), _)| !state.posts.contains_key(post_id))
+        .map(|(key, _)| key)
+        .collect();
+    for key in &likes {
+        state.post_likes.remove(key);
+    }
+}
+
+fn v6_to_v7(state: &mut State) {
+    let usernames: Vec<_> = state
+        .users
+        .iter()
+        .map(|(user_id, user)| (IdKey(user.username.to_lowercase()), user_id))
+        .collect();
+    for (username, user_id) in usernames {
+        state.usernames.insert(username, user_id);
+    }
+    TagService::rebuild(state);
+}
+
+fn v7_to_v8(state: &mut State) {
+    let posts: Vec<_> = state.posts.iter().map(|(_, post)| post).filter(|post| !post.is_deleted()).collect();
+    for post in &posts {
+        PostService::index_recent(state, post);
+    }
+}
+
+/// `post_comments` as laid out up to version 8: `(post id, the ids of the
+/// comment and its ancestors joined by "/")`.
+type PostCommentsV8 = StableBTreeMap<(IdKey, IdKey), (), Memory>;
+
+fn post_comments_v8() -> PostCommentsV8 {
+    StableBTreeMap::init(get_memory(POST_COMMENTS_V8_MEMORY_ID))
+}
+
+/// Places every comment again, parents before their replies and siblings
+/// oldest first, which is the order the joined ids sorted them in.
+fn v8_to_v9(state: &mut State) {
+    post_comments_v8().clear();
+
+    let mut comments: Vec<_> = state.comments.iter().map(|(_, comment)| comment).collect();
+    comments.sort_by(|a, b| (a.depth, a.created_at, &a.id).cmp(&(b.depth, b.created_at, &b.id)));
+    for comment in &comments {
+        CommentService::place(state, comment);
+    }
+}
+
+/// Links every asset to the live posts showing it, now or in a revision.
+fn v9_to_v10(state: &mut State) {
+    let mut links: Vec<_> = state
+        .posts
+        .iter()
+        .filter(|(_, post)| !post.is_deleted())
+        .filter_map(|(_, post)| Some((post.media_asset_id?, post.id)))
+        .collect();
+    links.extend(
+        state
+            .post_revisions
+            .iter()
+            .filter_map(|(_, revision)| Some((revision.media_asset_id?, revision.post_id))),
+    );
+    for (asset_id, post_id) in links {
+        MediaService::attach(state, &asset_id, &post_id);
+    }
+}
+
+/// `(post, tag)` for every tagged post, as kept up to version 10.
+type RecentHashtagsV10 = StableBTreeMap<(RecencyKey, IdKey), (), Memory>;
+
+fn recent_hashtags_v10() -> RecentHashtagsV10 {
+    StableBTreeMap::init(get_memory(RECENT_HASHTAGS_V10_MEMORY_ID))
+}
+
+fn v10_to_v11(state: &mut State) {
+    recent_hashtags_v10().clear();
+    TagService::recount_hashtags(state);
+}
+
+/// Records the actors shown on unread notifications. Actors grouped in
+/// beyond those shown weren't kept, so they can't be retracted.
+fn v11_to_v12(state: &mut State) {
+    let unread: Vec<_> = state
+        .notifications
+        .iter()
+        .filter(|(_, notification)| !notification.read)
+        .map(|((recipient, _), notification)| {
+            (NotificationService::group_key(notification.kind, notification.subject.as_deref()), recipient, notification)
+        })
+        .collect();
+    for (group, recipient, notification) in unread {
+        for actor in notification.actors {
+            state.unread_notification_actors.insert(((recipient, group.clone()), PrincipalKey(actor)), ());
+        }
+    }
+}
+
+/// `post_terms` as laid out up to version 12: `(term, post id)`.
+type PostTermsV12 = StableBTreeMap<(IdKey, IdKey), u32, Memory>;
+
+fn post_terms_v12() -> PostTermsV12 {
+    StableBTreeMap::init(get_memory(POST_TERMS_V12_MEMORY_ID))
+}
+
+fn v12_to_v13(state: &mut State) {
+    let legacy = post_terms_v12();
+    for ((term, post_id), frequency) in legacy.iter() {
+        if let Some(post) = state.get_post(&post_id.0) {
+            state.post_terms.insert((term, RecencyKey::of(&post)), frequency);
+        }
+    }
+    legacy.clear();
+}
+
+/// `user_posts` as laid out up to version 1: `(author, post id)`.
+type UserPostsV1 = StableBTreeMap<(PrincipalKey, IdKey), (), Memory>;
+
+fn user_posts_v1() -> UserPostsV1 {
+    StableBTreeMap::init(get_memory(USER_POSTS_V1_MEMORY_ID))
+}
+
+fn v1_to_v2(state: &mut State) {
+    let legacy = user_posts_v1();
+    for ((author, post_id), _) in legacy.iter() {
+        if let Some(post) = state.get_post(&post_id.0) {
+            state.user_posts.insert((author, RecencyKey::of(&post)), ());
+        }
+    }
+    legacy.clear();
+
+    let user_ids: Vec<_> = state.users.iter().map(|(user_id, _)| user_id.0).collect();
+    for user_id in user_ids {
+        TimelineService::rebuild(state, user_id);
+    }
+}
+
+/// The pre-stable-structures layout: a single Candid snapshot of the heap
+/// maps at the start of stable memory. Types here are frozen copies of the
+/// models as they were then and must not change.
+mod v0 {
+    use candid::{CandidType, Deserialize, Principal};
+    use std::collections::{HashMap, HashSet};
+    use crate::models;
+    use crate::storage::state::State;
+    use crate::storage::storable::{IdKey, PrincipalKey};
+    use super::{admin_v4, post_comments_v8, user_posts_v1};
+
+    const CANDID_MAGIC: &[u8] = b"DIDL";
+
+    #[derive(CandidType, Deserialize)]
+    pub struct User {
+        pub id: Principal,
+        pub username: String,
+        pub bio: String,
+        pub avatar_url: String,
+        pub followers_count: u64,
+        pub following_count: u64,
+        pub posts_count: u64,
+        pub balance: u64,
+        pub created_at: u64,
+        pub updated_at: u64,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    pub struct Post {
+        pub id: String,
+        pub author: Principal,
+        pub content: String,
+        pub media_url: Option<String>,
+        pub likes: HashSet<Principal>,
+        pub likes_count: u64,
+        pub comments_count: u64,
+        pub shares_count: u64,
+        pub is_shared: bool,
+        pub original_post_id: Option<String>,
+        pub share_comment: Option<String>,
+        pub created_at: u64,
+        pub updated_at: u64,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    pub struct Comment {
+        pub id: String,
+        pub post_id: String,
+        pub author: Principal,
+        pub content: String,
+        pub likes: HashSet<Principal>,
+        pub likes_count: u64,
+        pub created_at: u64,
+    }
+
+    /// What the old `pre_upgrade` saved. `user_following`, `post_comments`
+    /// and `transactions` were never written; the first two are rebuilt
+    /// from their inverse relations on import, transactions are lost.
+    pub type Snapshot = (
+        HashMap<Principal, User>,
+        HashMap<String, Post>,
+        HashMap<String, Comment>,
+        HashMap<Principal, Vec<String>>,
+        HashMap<Principal, Vec<Principal>>,
+        Principal,
+    );
+
+    pub fn take_snapshot() -> Option<Snapshot> {
+        if ic_cdk::api::stable::stable_size() == 0 {
+            return None;
+        }
+
+        let mut magic = [0u8; 4];
+        ic_cdk::api::stable::stable_read(0, &mut magic);
+        if magic != CANDID_MAGIC {
+            return None;
+        }
+
+        Some(ic_cdk::storage::stable_restore().expect("Failed to restore legacy state snapshot"))
+    }
+
+    pub fn import(state: &mut State, snapshot: Snapshot) {
+        let (users, posts, comments, user_posts, user_followers, admin) = snapshot;
+
+        for user in users.into_values() {
+            state.insert_user(models::user::User {
+                id: user.id,
+                username: user.username,
+                bio: user.bio,
+                avatar_url: user.avatar_url,
+                followers_count: user.followers_count,
+                following_count: user.following_count,
+                posts_count: user.posts_count,
+                balance: user.balance,
+                is_private: false,
+                created_at: user.created_at,
+                updated_at: user.updated_at,
+            });
+        }
+
+        for post in posts.into_values() {
+            for liker in &post.likes {
+                state.post_likes.insert((IdKey(post.id.clone()), PrincipalKey(*liker)), ());
+            }
+            state.insert_post(models::post::Post {
+                id: post.id,
+                author: post.author,
+                content: post.content,
+                media_url: post.media_url,
+                media_asset_id: None,
+                likes_count: post.likes_count,
+                comments_count: post.comments_count,
+                shares_count: post.shares_count,
+                is_shared: post.is_shared,
+                original_post_id: post.original_post_id,
+                share_comment: post.share_comment,
+                mentions: Vec::new(),
+                hashtags: Vec::new(),
+                visibility: models::post::Visibility::Public,
+                created_at: post.created_at,
+                updated_at: post.updated_at,
+                edited_at: None,
+                deleted_at: None,
+            });
+        }
+
+        for comment in comments.into_values() {
+            for liker in &comment.likes {
+                state.comment_likes.insert((IdKey(comment.id.clone()), PrincipalKey(*liker)), ());
+            }
+            post_comments_v8().insert((IdKey(comment.post_id.clone()), IdKey(comment.id.clone())), ());
+            state.insert_comment(models::comment::Comment {
+                id: comment.id,
+                post_id: comment.post_id,
+                author: comment.author,
+                content: comment.content,
+                mentions: Vec::new(),
+                hashtags: Vec::new(),
+                likes_count: comment.likes_count,
+                created_at: comment.created_at,
+                parent_comment_id: None,
+                depth: 0,
+                replies_count: 0,
+                edited_at: None,
+                deleted_at: None,
+            });
+        }
+
+        let mut legacy_user_posts = user_posts_v1();
+        for (author, post_ids) in user_posts {
+            for post_id in post_ids {
+                legacy_user_posts.insert((PrincipalKey(author), IdKey(post_id)), ());
+            }
+        }
+
+        for (followed, followers) in user_followers {
+            for follower in followers {
+                let (followed, follower) = (PrincipalKey(followed), PrincipalKey(follower));
+                state.user_followers.insert((followed, follower), ());
+                state.user_following.insert((follower, followed), ());
+            }
+        }
+
+        admin_v4().set(PrincipalKey(admin)).expect("Failed to store legacy admin");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, HashSet};
+    use crate::models::comment::Comment;
+    use crate::models::notification::NotificationKind;
+    use crate::models::post::{Post, Visibility};
+    use crate::models::role::Role;
+    use crate::models::transaction::{Transaction, TransactionType};
+    use crate::models::user::User;
+    use crate::storage::storable::SequenceKey;
+    use crate::utils::testing::principal;
+
+    fn user(n: u8, username: &str) -> User {
+        User::new(principal(n), username.to_string(), String::new(), String::new())
+    }
+
+    fn post(id: &str, author: Principal, content: &str, created_at: u64) -> Post {
+        let mut post = Post::new(id.to_string(), author, content.to_string(), None, Visibility::Public);
+        post.created_at = created_at;
+        post
+    }
+
+    /// Runs `f` against this test thread's own, empty stable memory.
+    fn with_state(f: impl FnOnce(&mut State)) {
+        STATE.with(|state| f(&mut state.borrow_mut()));
+    }
+
+    #[test]
+    fn v0_snapshot_imports_into_v1_layout() {
+        let (alice, bob) = (principal(1), principal(2));
+        let v0_user = |id, username: &str| v0::User {
+            id,
+            username: username.to_string(),
+            bio: String::new(),
+            avatar_url: String::new(),
+            followers_count: 0,
+            following_count: 0,
+            posts_count: 0,
+            balance: 100,
+            created_at: 1,
+            updated_at: 1,
+        };
+        let snapshot: v0::Snapshot = (
+            HashMap::from([(alice, v0_user(alice, "Alice")), (bob, v0_user(bob, "bob"))]),
+            HashMap::from([(
+                "post_1".to_string(),
+                v0::Post {
+                    id: "post_1".to_string(),
+                    author: alice,
+                    content: "hello".to_string(),
+                    media_url: None,
+                    likes: HashSet::from([bob]),
+                    likes_count: 1,
+                    comments_count: 1,
+                    shares_count: 0,
+                    is_shared: false,
+                    original_post_id: None,
+                    share_comment: None,
+                    created_at: 5,
+                    updated_at: 5,
+                },
+            )]),
+            HashMap::from([(
+                "comment_1".to_string(),
+                v0::Comment {
+                    id: "comment_1".to_string(),
+                    post_id: "post_1".to_string(),
+                    author: bob,
+                    content: "hi".to_string(),
+                    likes: HashSet::from([alice]),
+                    likes_count: 1,
+                    created_at: 6,
+                },
+            )]),
+            HashMap::from([(alice, vec!["post_1".to_string()])]),
+            HashMap::from([(alice, vec![bob])]),
+            alice,
+        );
+
+        // What the old `pre_upgrade` wrote with `stable_save`
+        let bytes = candid::encode_args(snapshot).unwrap();
+        assert!(bytes.starts_with(b"DIDL"));
+        let snapshot: v0::Snapshot = candid::decode_args(&bytes).unwrap();
+
+        with_state(|state| {
+            v0::import(state, snapshot);
+
+            let imported = state.get_user(alice).unwrap();
+            assert_eq!(imported.balance, 100);
+            assert!(!imported.is_private);
+            assert_eq!(state.find_username("alice"), Some(alice));
+
+            let imported = state.get_post("post_1").unwrap();
+            assert_eq!(imported.content, "hello");
+            assert_eq!(imported.visibility, Visibility::Public);
+            assert!(state.post_likes.contains_key(&(IdKey::from("post_1"), PrincipalKey(bob))));
+
+            assert_eq!(state.get_comment("comment_1").unwrap().post_id, "post_1");
+            assert!(state.comment_likes.contains_key(&(IdKey::from("comment_1"), PrincipalKey(alice))));
+            assert!(post_comments_v8().contains_key(&(IdKey::from("post_1"), IdKey::from("comment_1"))));
+
+            assert!(state.user_followers.contains_key(&(PrincipalKey(alice), PrincipalKey(bob))));
+            assert!(state.user_following.contains_key(&(PrincipalKey(bob), PrincipalKey(alice))));
+            assert!(user_posts_v1().contains_key(&(PrincipalKey(alice), IdKey::from("post_1"))));
+            assert_eq!(admin_v4().get().0, alice);
+        });
+    }
+
+    #[test]
+    fn v1_to_v2_rekeys_user_posts_newest_first() {
+        let (alice, bob) = (principal(1), principal(2));
+        with_state(|state| {
+            state.insert_user(user(1, "alice"));
+            state.insert_user(user(2, "bob"));
+            state.user_followers.insert((PrincipalKey(alice), PrincipalKey(bob)), ());
+            state.user_following.insert((PrincipalKey(bob), PrincipalKey(alice)), ());
+            let mut legacy = user_posts_v1();
+            for (id, created_at) in [("post_a", 10), ("post_b", 20)] {
+                state.insert_post(post(id, alice, "hello", created_at));
+                legacy.insert((PrincipalKey(alice), IdKey::from(id)), ());
+            }
+
+            migrate(state, 1);
+
+            let posts: Vec<_> = state.user_posts.iter().map(|((_, entry), _)| entry.post_id).collect();
+            assert_eq!(posts, ["post_b", "post_a"]);
+            assert!(user_posts_v1().is_empty());
+            let timeline: Vec<_> = state
+                .timelines
+                .iter()
+                .filter(|((owner, _), _)| owner.0 == bob)
+                .map(|((_, entry), _)| entry.post_id)
+                .collect();
+            assert_eq!(timeline, ["post_b", "post_a"]);
+        });
+    }
+
+    #[test]
+    fn v2_to_v3_indexes_search_terms() {
+        with_state(|state| {
+            state.insert_user(user(1, "alice"));
+            state.insert_post(post("post_1", principal(1), "sunny weather", 10));
+
+            migrate(state, 2);
+
+            assert!(state.post_terms.iter().any(|((_, entry), _)| entry.post_id == "post_1"));
+            assert!(state.user_terms.iter().any(|((_, id), _)| id.0 == principal(1)));
+        });
+    }
+
+    #[test]
+    fn v3_to_v4_indexes_transactions() {
+        let (alice, bob) = (principal(1), principal(2));
+        let transaction = |id: &str, from, to, amount, transaction_type, post_id: Option<&str>| Transaction {
+            id: id.to_string(),
+            from,
+            to,
+            amount,
+            transaction_type,
+            timestamp: 1,
+            block_index: None,
+            post_id: post_id.map(str::to_string),
+        };
+        with_state(|state| {
+            for recorded in [
+                transaction("tx_0", alice, bob, 30, TransactionType::Tip, Some("post_1")),
+                transaction("tx_1", alice, alice, 100, TransactionType::Deposit, None),
+                transaction("tx_2", bob, bob, 10, TransactionType::Withdrawal, None),
+            ] {
+                state.transactions.push(&recorded).unwrap();
+            }
+
+            migrate(state, 3);
+
+            let of = |user_id| -> Vec<u64> {
+                state
+                    .user_transactions
+                    .iter()
+                    .filter(|((owner, _), _)| owner.0 == user_id)
+                    .map(|((_, position), _)| position.0)
+                    .collect()
+            };
+            // Newest first
+            assert_eq!(of(alice), [1, 0]);
+            assert_eq!(of(bob), [2, 0]);
+
+            let totals = state.transaction_totals.get(&PrincipalKey(alice)).unwrap();
+            assert_eq!((totals.tips_sent, totals.deposited), (30, 100));
+            let totals = state.transaction_totals.get(&PrincipalKey(bob)).unwrap();
+            assert_eq!((totals.tips_received, totals.withdrawn), (30, 10));
+
+            assert!(state.post_tips.contains_key(&(IdKey::from("post_1"), SequenceKey(0))));
+            assert_eq!(state.post_tip_totals.get(&IdKey::from("post_1")), Some(30));
+        });
+    }
+
+    #[test]
+    fn v4_to_v5_makes_the_admin_owner() {
+        with_state(|state| {
+            admin_v4().set(PrincipalKey(principal(1))).unwrap();
+
+            migrate(state, 4);
+
+            assert_eq!(state.get_role(principal(1)), Some(Role::Owner));
+            assert_eq!(state.roles.len(), 1);
+        });
+    }
+
+    #[test]
+    fn v4_to_v5_without_admin_grants_nothing() {
+        with_state(|state| {
+            migrate(state, 4);
+
+            assert!(state.roles.is_empty());
+        });
+    }
+
+    #[test]
+    fn v5_to_v6_indexes_shares_and_drops_orphans() {
+        let alice = principal(1);
+        with_state(|state| {
+            state.insert_post(post("post_1", alice, "hello", 10));
+            let mut share = post("share_1", alice, "", 20);
+            share.original_post_id = Some("post_1".to_string());
+            state.insert_post(share);
+            let mut dangling = post("share_2", alice, "", 30);
+            dangling.original_post_id = Some("post_gone".to_string());
+            state.insert_post(dangling);
+
+            let orphan = Comment::new("comment_1".to_string(), "post_gone".to_string(), alice, "hi".to_string());
+            post_comments_v8().insert((IdKey::from("post_gone"), IdKey::from("comment_1")), ());
+            state.insert_comment(orphan);
+            state.post_likes.insert((IdKey::from("post_gone"), PrincipalKey(alice)), ());
+
+            migrate(state, 5);
+
+            assert_eq!(state.get_post("post_1").unwrap().shares_count, 1);
+            assert!(state.post_shares.contains_key(&(IdKey::from("post_1"), IdKey::from("share_1"))));
+            assert_eq!(state.get_post("share_2").unwrap().original_post_id, None);
+            assert!(state.get_comment("comment_1").is_none());
+            assert!(state.post_likes.is_empty());
+        });
+    }
+
+    #[test]
+    fn v6_to_v7_indexes_usernames_and_tags() {
+        let (alice, bob) = (principal(1), principal(2));
+        with_state(|state| {
+            state.users.insert(PrincipalKey(alice), user(1, "Alice"));
+            state.users.insert(PrincipalKey(bob), user(2, "bob"));
+            state.insert_post(post("post_1", alice, "hi @bob #Rust", 10));
+
+            migrate(state, 6);
+
+            assert_eq!(state.find_username("ALICE"), Some(alice));
+            assert_eq!(state.find_username("bob"), Some(bob));
+            let tagged = state.get_post("post_1").unwrap();
+            assert_eq!(tagged.mentions, [bob]);
+            assert_eq!(tagged.hashtags, ["rust"]);
+            assert!(state.hashtag_posts.iter().any(|((tag, entry), _)| tag.0 == "rust" && entry.post_id == "post_1"));
+        });
+    }
+
+    #[test]
+    fn v7_to_v8_indexes_live_listed_posts_newest_first() {
+        let alice = principal(1);
+        with_state(|state| {
+            state.insert_post(post("post_old", alice, "hello", 10));
+            state.insert_post(post("post_new", alice, "hello", 20));
+            let mut unlisted = post("post_unlisted", alice, "hello", 30);
+            unlisted.visibility = Visibility::Unlisted;
+            state.insert_post(unlisted);
+            let mut deleted = post("post_deleted", alice, "hello", 40);
+            deleted.tombstone(50);
+            state.insert_post(deleted);
+
+            migrate(state, 7);
+
+            let posts: Vec<_> = state.recent_posts.iter().map(|(entry, _)| entry.post_id).collect();
+            assert_eq!(posts, ["post_new", "post_old"]);
+        });
+    }
+
+    #[test]
+    fn v8_to_v9_rekeys_threads_by_sequence() {
+        let alice = principal(1);
+        // Legacy ids this long made joined paths overflow `MAX_ID_LEN`
+        let (root_id, reply_id) = ("a".repeat(200), "b".repeat(200));
+        with_state(|state| {
+            state.insert_post(post("post_1", alice, "hello", 10));
+            let mut root = Comment::new(root_id.clone(), "post_1".to_string(), alice, "first".to_string());
+            root.created_at = 10;
+            let mut reply = Comment::new_reply(reply_id.clone(), &root, alice, "reply".to_string());
+            reply.created_at = 30;
+            let mut nested = Comment::new_reply("comment_0".to_string(), &reply, alice, "nested".to_string());
+            nested.created_at = 40;
+            let mut later = Comment::new("comment_9".to_string(), "post_1".to_string(), alice, "second".to_string());
+            later.created_at = 20;
+            for comment in [&root, &reply, &nested, &later] {
+                state.insert_comment(comment.clone());
+            }
+            post_comments_v8().insert((IdKey::from("post_1"), IdKey(root_id.clone())), ());
+
+            migrate(state, 8);
+
+            assert!(post_comments_v8().is_empty());
+            let thread: Vec<_> = state.post_comments.iter().map(|((_, key), _)| key.comment_id).collect();
+            assert_eq!(thread, [root_id.as_str(), &reply_id, "comment_0", "comment_9"]);
+            let nested_key = state.comment_threads.get(&IdKey::from("comment_0")).unwrap();
+            assert_eq!(nested_key.path.len(), 3);
+            assert!(CommentService::has_replies(state, &root));
+            assert!(!CommentService::has_replies(state, &later));
+        });
+    }
+
+    #[test]
+    fn v9_to_v10_links_assets_to_their_posts() {
+        let alice = principal(1);
+        with_state(|state| {
+            let mut shown = post("post_1", alice, "hello", 10);
+            shown.media_asset_id = Some("asset_1".to_string());
+            let mut edited = post("post_2", alice, "hello", 20);
+            edited.media_asset_id = Some("asset_2".to_string());
+            let revision = edited.edit("edited".to_string(), None, 0, 30);
+            state.post_revisions.insert((IdKey::from("post_2"), SequenceKey(0)), revision);
+            let mut deleted = post("post_3", alice, "", 40);
+            deleted.media_asset_id = Some("asset_3".to_string());
+            deleted.deleted_at = Some(50);
+            for post in [shown, edited, deleted] {
+                state.insert_post(post);
+            }
+
+            migrate(state, 9);
+
+            let links: Vec<_> = state.asset_posts.iter().map(|((asset, post), _)| (asset.0, post.0)).collect();
+            assert_eq!(
+                links,
+                [("asset_1".to_string(), "post_1".to_string()), ("asset_2".to_string(), "post_2".to_string())]
+            );
+        });
+    }
+
+    #[test]
+    fn v10_to_v11_counts_hashtags_by_hour() {
+        let hour = 60 * 60 * 1_000_000_000;
+        with_state(|state| {
+            let mut legacy = recent_hashtags_v10();
+            for (id, created_at) in [("post_1", 10), ("post_2", 20), ("post_3", hour + 10)] {
+                let key = RecencyKey { created_at, post_id: id.to_string() };
+                state.hashtag_posts.insert((IdKey::from("rust"), key.clone()), ());
+                legacy.insert((key, IdKey::from("rust")), ());
+            }
+
+            migrate(state, 10);
+
+            assert!(recent_hashtags_v10().is_empty());
+            let counts: Vec<_> =
+                state.hashtag_counts.iter().map(|((bucket, tag), count)| (bucket, tag.0, count)).collect();
+            assert_eq!(counts, [(0, "rust".to_string(), 2), (1, "rust".to_string(), 1)]);
+        });
+    }
+
+    #[test]
+    fn v11_to_v12_tracks_the_actors_of_unread_notifications() {
+        let (alice, bob, carol) = (principal(1), principal(2), principal(3));
+        with_state(|state| {
+            state.users.insert(PrincipalKey(alice), user(1, "alice"));
+            let kind = NotificationKind::Like;
+            NotificationService::notify(state, alice, kind, bob, Some("post_1".to_string()), None);
+            NotificationService::notify(state, alice, kind, carol, Some("post_2".to_string()), None);
+            NotificationService::mark_subject_read(state, alice, kind, "post_2");
+            state.unread_notification_actors.remove(&((PrincipalKey(alice), IdKey::from("like:post_1")), PrincipalKey(bob)));
+
+            migrate(state, 11);
+
+            let actors: Vec<_> = state.unread_notification_actors.iter().map(|((group, _), _)| group.1 .0).collect();
+            assert_eq!(actors, ["like:post_1"]);
+            NotificationService::retract(state, alice, kind, bob, "post_1");
+            assert!(state.notifications.iter().all(|(_, notification)| notification.read));
+        });
+    }
+
+    #[test]
+    fn v12_to_v13_rekeys_post_terms_newest_first() {
+        with_state(|state| {
+            let mut legacy = post_terms_v12();
+            for (id, created_at) in [("post_a", 10), ("post_b", 20)] {
+                state.insert_post(post(id, principal(1), "hello", created_at));
+                legacy.insert((IdKey::from("hello"), IdKey::from(id)), 1);
+            }
+            legacy.insert((IdKey::from("hello"), IdKey::from("post_gone")), 1);
+
+            migrate(state, 12);
+
+            assert!(post_terms_v12().is_empty());
+            let posts: Vec<_> = state.post_terms.iter().map(|((_, entry), _)| entry.post_id).collect();
+            assert_eq!(posts, ["post_b", "post_a"]);
+        });
+    }
+}
@@ -1 +1,5 @@
-pub mod state;
\ No newline at end of file
+pub mod memory;
+pub mod migrations;
+pub mod pagination;
+pub mod state;
+pub mod storable;
\ No newline at end of file
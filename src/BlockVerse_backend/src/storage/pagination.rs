@@ -0,0 +1,220 @@
+use candid::Principal;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap};
+use std::ops::Bound;
+use crate::error::BlockVerseError;
+use crate::models::page::Page;
+use super::memory::Memory;
+use super::storable::{IdKey, PrincipalKey, RecencyKey, SequenceKey, ThreadKey, MAX_ID_LEN};
+
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Entries a page may look at per item it can hold, so a list that mostly
+/// holds entries the caller can't see still pages in bounded work.
+pub const SCAN_FACTOR: usize = 10;
+
+/// A key component that can be handed out as a page cursor.
+pub trait CursorKey: Sized {
+    fn to_cursor_bytes(&self) -> Vec<u8>;
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self>;
+}
+
+impl CursorKey for IdKey {
+    fn to_cursor_bytes(&self) -> Vec<u8> {
+        self.0.as_bytes().to_vec()
+    }
+
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() > MAX_ID_LEN as usize {
+            return None;
+        }
+        String::from_utf8(bytes.to_vec()).ok().map(IdKey)
+    }
+}
+
+impl CursorKey for PrincipalKey {
+    fn to_cursor_bytes(&self) -> Vec<u8> {
+        self.0.as_slice().to_vec()
+    }
+
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self> {
+        Principal::try_from_slice(bytes).ok().map(PrincipalKey)
+    }
+}
+
+impl CursorKey for u64 {
+    fn to_cursor_bytes(&self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(u64::from_be_bytes(bytes.try_into().ok()?))
+    }
+}
+
+impl CursorKey for SequenceKey {
+    fn to_cursor_bytes(&self) -> Vec<u8> {
+        self.0.to_be_bytes().to_vec()
+    }
+
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self(u64::from_be_bytes(bytes.try_into().ok()?)))
+    }
+}
+
+impl CursorKey for RecencyKey {
+    fn to_cursor_bytes(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::decode(bytes)
+    }
+}
+
+impl CursorKey for ThreadKey {
+    fn to_cursor_bytes(&self) -> Vec<u8> {
+        self.encode()
+    }
+
+    fn from_cursor_bytes(bytes: &[u8]) -> Option<Self> {
+        Self::decode(bytes)
+    }
+}
+
+pub fn encode_cursor<K: CursorKey>(key: &K) -> String {
+    hex::encode(key.to_cursor_bytes())
+}
+
+pub fn decode_cursor<K: CursorKey>(cursor: Option<String>) -> Result<Option<K>, BlockVerseError> {
+    let Some(cursor) = cursor else {
+        return Ok(None);
+    };
+    hex::decode(&cursor)
+        .ok()
+        .and_then(|bytes| K::from_cursor_bytes(&bytes))
+        .map(Some)
+        .ok_or_else(|| BlockVerseError::validation("cursor", "Malformed cursor"))
+}
+
+/// Clamps a requested page size, treating 0 as "use the default".
+pub fn page_size(limit: usize) -> usize {
+    if limit == 0 {
+        DEFAULT_PAGE_SIZE
+    } else {
+        limit.min(MAX_PAGE_SIZE)
+    }
+}
+
+/// Pages through the members of `owner` in an index keyed by
+/// `(owner, member)`, in key order. `load` turns a member into an item;
+/// members it rejects are skipped but still advance the cursor.
+pub fn page_members<O, M, T>(
+    index: &StableBTreeMap<(O, M), (), Memory>,
+    owner: O,
+    cursor: Option<String>,
+    limit: usize,
+    mut load: impl FnMut(&M) -> Option<T>,
+) -> Result<Page<T>, BlockVerseError>
+where
+    O: BoundedStorable + Ord + Clone + Default,
+    M: BoundedStorable + Ord + Clone + Default + CursorKey,
+{
+    let start = match decode_cursor::<M>(cursor)? {
+        Some(after) => Bound::Excluded((owner.clone(), after)),
+        None => Bound::Included((owner.clone(), M::default())),
+    };
+
+    let members = index
+        .range((start, Bound::Unbounded))
+        .take_while(|((key_owner, _), _)| *key_owner == owner)
+        .map(|((_, member), _)| (member, ()));
+
+    Ok(collect_page(members, page_size(limit), |member, _| load(member)))
+}
+
+/// Pages through a map in key order up to `end`, keeping the entries
+/// `select` maps to an item.
+pub fn page_range<K, V, T>(
+    map: &StableBTreeMap<K, V, Memory>,
+    end: Bound<K>,
+    cursor: Option<String>,
+    limit: usize,
+    select: impl FnMut(&K, V) -> Option<T>,
+) -> Result<Page<T>, BlockVerseError>
+where
+    K: BoundedStorable + Ord + Clone + CursorKey,
+    V: BoundedStorable,
+{
+    let start = match decode_cursor::<K>(cursor)? {
+        Some(after) => Bound::Excluded(after),
+        None => Bound::Unbounded,
+    };
+
+    let entries = map.range((start, end));
+
+    Ok(collect_page(entries, page_size(limit), select))
+}
+
+/// Fills a page from `entries` in order, keeping the values `select` maps
+/// to an item. At most `limit * SCAN_FACTOR` entries are looked at; a page
+/// that runs out of them ends early, short or even empty, with a cursor to
+/// carry on from.
+pub fn collect_page<K: CursorKey, V, T>(
+    entries: impl Iterator<Item = (K, V)>,
+    limit: usize,
+    mut select: impl FnMut(&K, V) -> Option<T>,
+) -> Page<T> {
+    let mut page = Page::empty();
+    let mut last_key = None;
+    let mut budget = limit.saturating_mul(SCAN_FACTOR);
+
+    for (key, value) in entries {
+        if page.items.len() == limit || budget == 0 {
+            page.next_cursor = last_key.as_ref().map(encode_cursor);
+            break;
+        }
+        budget -= 1;
+        if let Some(item) = select(&key, value) {
+            page.items.push(item);
+        }
+        last_key = Some(key);
+    }
+
+    page
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(count: u64) -> impl Iterator<Item = (u64, ())> {
+        (0..count).map(|key| (key, ()))
+    }
+
+    #[test]
+    fn pages_end_at_the_limit_with_a_cursor() {
+        let page = collect_page(entries(5), 2, |key, _| Some(*key));
+        assert_eq!(page.items, [0, 1]);
+        assert_eq!(decode_cursor::<u64>(page.next_cursor).unwrap(), Some(1));
+
+        let page = collect_page(entries(2), 2, |key, _| Some(*key));
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn filtered_entries_count_against_the_scan_budget() {
+        let limit = 2;
+        let visible = |key: &u64, _| (*key >= 100).then_some(*key);
+
+        let page = collect_page(entries(200), limit, visible);
+        assert!(page.items.is_empty());
+        let after = decode_cursor::<u64>(page.next_cursor).unwrap();
+        assert_eq!(after, Some((limit * SCAN_FACTOR) as u64 - 1));
+
+        // Everything scanned and filtered out ends the list
+        let page = collect_page(entries(limit as u64 * SCAN_FACTOR as u64), limit, visible);
+        assert!(page.items.is_empty());
+        assert_eq!(page.next_cursor, None);
+    }
+}
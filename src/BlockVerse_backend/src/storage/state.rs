@@ -1,22 +1,563 @@
 use candid::Principal;
+use ic_stable_structures::{BoundedStorable, StableBTreeMap, StableCell, StableVec};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use crate::models::{user::User, post::Post, comment::Comment};
-use crate::services::payment_service::Transaction;
+use crate::models::{user::User, post::{self, Post, PostRevision, Visibility}, comment::Comment};
+use crate::models::asset::{Asset, Upload};
+use crate::models::federation::{Delivery, RemoteActor};
+use crate::models::message::{Conversation, DmPolicy, Message, ReadReceipt};
+use crate::models::moderation::{Appeal, Decision, Report};
+use crate::models::notification::Notification;
+use crate::models::role::{Role, RoleChange};
+use crate::models::transaction::{PendingWithdrawal, Transaction, TransactionTotals};
+use crate::utils::{clock, crypto};
+use super::memory::{self, get_memory, Memory};
+use super::migrations::CURRENT_SCHEMA_VERSION;
+use super::storable::{ChunkData, IdKey, PrincipalKey, RecencyKey, SequenceKey, ThreadKey};
 
 thread_local! {
-    pub static STATE: RefCell<State> = RefCell::new(State::default());
+    pub static STATE: RefCell<State> = RefCell::new(State::init());
 }
 
-#[derive(Default)]
+/// Low bits of an id sequence number reserved for the per-millisecond counter.
+const ID_COUNTER_BITS: u32 = 20;
+
+type PrincipalSet = StableBTreeMap<(PrincipalKey, PrincipalKey), (), Memory>;
+
+/// Canister state. Everything lives in stable memory, so upgrades don't
+/// need to serialise anything.
+///
+/// One-to-many relations are stored as sets of `(owner, member)` keys
+/// rather than `Vec` values, so they stay bounded and can be range scanned.
 pub struct State {
-    pub users: HashMap<Principal, User>,
-    pub posts: HashMap<String, Post>,
-    pub comments: HashMap<String, Comment>,
-    pub user_posts: HashMap<Principal, Vec<String>>,
-    pub user_followers: HashMap<Principal, Vec<Principal>>,
-    pub user_following: HashMap<Principal, Vec<Principal>>,
-    pub post_comments: HashMap<String, Vec<String>>,
-    pub transactions: Vec<Transaction>,
-    pub admin: Principal,
-}
\ No newline at end of file
+    pub users: StableBTreeMap<PrincipalKey, User, Memory>,
+    /// Lowercased username -> user
+    pub usernames: StableBTreeMap<IdKey, PrincipalKey, Memory>,
+    pub posts: StableBTreeMap<IdKey, Post, Memory>,
+    pub comments: StableBTreeMap<IdKey, Comment, Memory>,
+    /// `(author, post)`, newest first
+    pub user_posts: StableBTreeMap<(PrincipalKey, RecencyKey), (), Memory>,
+    /// Every live listed post, newest first
+    pub recent_posts: StableBTreeMap<RecencyKey, (), Memory>,
+    /// `(reader, post)`: each user's home timeline, newest first. Filled on
+    /// write for every author except those in `pull_authors`.
+    pub timelines: StableBTreeMap<(PrincipalKey, RecencyKey), (), Memory>,
+    /// Authors with too many followers to fan out to; readers merge their
+    /// posts into the timeline at read time instead.
+    pub pull_authors: StableBTreeMap<PrincipalKey, (), Memory>,
+    /// `(follower, pull author)`
+    pub followed_pull_authors: PrincipalSet,
+    /// `(followed, follower)`
+    pub user_followers: PrincipalSet,
+    /// `(follower, followed)`
+    pub user_following: PrincipalSet,
+    /// `(private user, requester)` for every pending follow request
+    pub follow_requests: PrincipalSet,
+    /// `(requester, private user)`, the reverse of `follow_requests`
+    pub sent_follow_requests: PrincipalSet,
+    /// `(post_id, thread key)`: each post's comments in thread order
+    pub post_comments: StableBTreeMap<(IdKey, ThreadKey), (), Memory>,
+    /// Each comment's key in `post_comments`
+    pub comment_threads: StableBTreeMap<IdKey, ThreadKey, Memory>,
+    /// `(post, revision number)`, newest first
+    pub post_revisions: StableBTreeMap<(IdKey, SequenceKey), PostRevision, Memory>,
+    /// `(original post, share)`
+    pub post_shares: StableBTreeMap<(IdKey, IdKey), (), Memory>,
+    /// `(post_id, liker)`
+    pub post_likes: StableBTreeMap<(IdKey, PrincipalKey), (), Memory>,
+    /// `(comment_id, liker)`
+    pub comment_likes: StableBTreeMap<(IdKey, PrincipalKey), (), Memory>,
+    /// `(tag, post)`, newest first
+    pub hashtag_posts: StableBTreeMap<(IdKey, RecencyKey), (), Memory>,
+    /// `(hour, tag) -> listed posts from that hour with the tag`; see
+    /// `TagService::get_trending_hashtags`
+    pub hashtag_counts: StableBTreeMap<(u64, IdKey), u64, Memory>,
+    /// `(mentioned user, post or comment)`, newest first
+    pub user_mentions: StableBTreeMap<(PrincipalKey, RecencyKey), (), Memory>,
+    /// `(term, post) -> term frequency`, newest first; see `SearchService`
+    pub post_terms: StableBTreeMap<(IdKey, RecencyKey), u32, Memory>,
+    /// `(term, user) -> term frequency`
+    pub user_terms: StableBTreeMap<(IdKey, PrincipalKey), u32, Memory>,
+    pub transactions: StableVec<Transaction, Memory>,
+    /// `(user, transaction)`, newest first. A transaction is listed under
+    /// both parties when both are users.
+    pub user_transactions: StableBTreeMap<(PrincipalKey, SequenceKey), (), Memory>,
+    /// `(post, tip)`, newest first
+    pub post_tips: StableBTreeMap<(IdKey, SequenceKey), (), Memory>,
+    pub transaction_totals: StableBTreeMap<PrincipalKey, TransactionTotals, Memory>,
+    pub post_tip_totals: StableBTreeMap<IdKey, u64, Memory>,
+    /// `(user, withdrawal id)` for withdrawals whose outcome is unknown
+    pub pending_withdrawals: StableBTreeMap<(PrincipalKey, SequenceKey), PendingWithdrawal, Memory>,
+    pub roles: StableBTreeMap<PrincipalKey, Role, Memory>,
+    /// Audit trail of every grant and revoke, oldest first
+    pub role_changes: StableVec<RoleChange, Memory>,
+    pub reports: StableBTreeMap<IdKey, Report, Memory>,
+    /// Ids of unresolved reports, oldest first
+    pub open_reports: StableBTreeMap<IdKey, (), Memory>,
+    /// `(target key, report)`, see `ReportTarget::key`
+    pub target_reports: StableBTreeMap<(IdKey, IdKey), (), Memory>,
+    /// `(reporter, target key)` for every open report
+    pub reporter_targets: StableBTreeMap<(PrincipalKey, IdKey), (), Memory>,
+    /// The moderation log. A decision's id is its position.
+    pub decisions: StableVec<Decision, Memory>,
+    /// `(subject, decision)`, newest first
+    pub subject_decisions: StableBTreeMap<(PrincipalKey, SequenceKey), (), Memory>,
+    /// Appeals keyed by the decision they contest
+    pub appeals: StableBTreeMap<u64, Appeal, Memory>,
+    pub pending_appeals: StableBTreeMap<u64, (), Memory>,
+    /// Posts and comments a moderator hid
+    pub hidden_content: StableBTreeMap<IdKey, (), Memory>,
+    /// Suspended users and when their suspension ends
+    pub suspensions: StableBTreeMap<PrincipalKey, u64, Memory>,
+    /// `(recipient, notification)`, most recently updated first
+    pub notifications: StableBTreeMap<(PrincipalKey, SequenceKey), Notification, Memory>,
+    /// `(recipient, group key) -> notification` for every unread
+    /// notification, see `NotificationService`
+    pub unread_notification_groups: StableBTreeMap<(PrincipalKey, IdKey), u64, Memory>,
+    /// `((recipient, group key), actor)` for every actor grouped into an
+    /// unread notification
+    pub unread_notification_actors: StableBTreeMap<((PrincipalKey, IdKey), PrincipalKey), (), Memory>,
+    pub unread_notification_counts: StableBTreeMap<PrincipalKey, u64, Memory>,
+    /// `(user, notification kind)` for every kind the user muted
+    pub notification_mutes: StableBTreeMap<(PrincipalKey, IdKey), (), Memory>,
+    /// `(deleted_at, post or comment)` for every tombstone awaiting purge,
+    /// oldest first
+    pub tombstones: StableBTreeMap<(u64, IdKey), (), Memory>,
+    pub conversations: StableBTreeMap<IdKey, Conversation, Memory>,
+    /// `(member, conversation)`, most recently active first
+    pub user_conversations: StableBTreeMap<(PrincipalKey, RecencyKey), (), Memory>,
+    /// `(member, member) -> conversation` for direct conversations, with
+    /// the lower principal first
+    pub direct_conversations: StableBTreeMap<(PrincipalKey, PrincipalKey), IdKey, Memory>,
+    /// `(conversation, message)`, newest first
+    pub messages: StableBTreeMap<(IdKey, SequenceKey), Message, Memory>,
+    /// `(conversation, member)` for every member who has read anything
+    pub read_receipts: StableBTreeMap<(IdKey, PrincipalKey), ReadReceipt, Memory>,
+    /// `(member, conversation) -> messages received since last reading it`
+    pub unread_message_counts: StableBTreeMap<(PrincipalKey, IdKey), u64, Memory>,
+    /// `(member, conversation)` for every conversation the member muted
+    pub conversation_mutes: StableBTreeMap<(PrincipalKey, IdKey), (), Memory>,
+    /// Users who changed their `DmPolicy` from the default
+    pub dm_policies: StableBTreeMap<PrincipalKey, DmPolicy, Memory>,
+    /// `(blocker, blocked)`
+    pub user_blocks: PrincipalSet,
+    /// `(muter, muted)`
+    pub user_mutes: PrincipalSet,
+    pub assets: StableBTreeMap<IdKey, Asset, Memory>,
+    /// `(owner, asset)`
+    pub user_assets: StableBTreeMap<(PrincipalKey, IdKey), (), Memory>,
+    /// `(asset, post)` for every live post showing the asset, now or in an
+    /// earlier version. Assets are only served through these.
+    pub asset_posts: StableBTreeMap<(IdKey, IdKey), (), Memory>,
+    /// Uploads in progress, oldest first
+    pub uploads: StableBTreeMap<IdKey, Upload, Memory>,
+    /// `(asset or upload, chunk index)`. An upload's chunks become its
+    /// asset's when it is committed.
+    pub asset_chunks: StableBTreeMap<(IdKey, u32), ChunkData, Memory>,
+    /// Bytes each user has stored or reserved for uploads in progress
+    pub media_usage: StableBTreeMap<PrincipalKey, u64, Memory>,
+    /// Accounts on other servers, keyed by the user standing in for them
+    pub remote_actors: StableBTreeMap<PrincipalKey, RemoteActor, Memory>,
+    /// `(local user, remote follower) -> Follow activity id`, which the
+    /// answer to the follow has to refer to
+    pub remote_follows: StableBTreeMap<(PrincipalKey, PrincipalKey), IdKey, Memory>,
+    /// Hash of a remote object's id -> the comment it was received as
+    pub remote_objects: StableBTreeMap<IdKey, IdKey, Memory>,
+    /// `(due at, sequence)` for every activity awaiting delivery, soonest
+    /// first
+    pub deliveries: StableBTreeMap<(u64, u64), Delivery, Memory>,
+    ledger: StableCell<PrincipalKey, Memory>,
+    edit_window: StableCell<u64, Memory>,
+    schema_version: StableCell<u32, Memory>,
+    id_sequence: StableCell<u64, Memory>,
+    federation_key: StableCell<String, Memory>,
+    federation_domain: StableCell<String, Memory>,
+}
+
+impl State {
+    fn init() -> Self {
+        Self {
+            users: StableBTreeMap::init(get_memory(memory::USERS_MEMORY_ID)),
+            usernames: StableBTreeMap::init(get_memory(memory::USERNAMES_MEMORY_ID)),
+            posts: StableBTreeMap::init(get_memory(memory::POSTS_MEMORY_ID)),
+            comments: StableBTreeMap::init(get_memory(memory::COMMENTS_MEMORY_ID)),
+            user_posts: StableBTreeMap::init(get_memory(memory::USER_POSTS_MEMORY_ID)),
+            recent_posts: StableBTreeMap::init(get_memory(memory::RECENT_POSTS_MEMORY_ID)),
+            timelines: StableBTreeMap::init(get_memory(memory::TIMELINES_MEMORY_ID)),
+            pull_authors: StableBTreeMap::init(get_memory(memory::PULL_AUTHORS_MEMORY_ID)),
+            followed_pull_authors: StableBTreeMap::init(get_memory(memory::FOLLOWED_PULL_AUTHORS_MEMORY_ID)),
+            user_followers: StableBTreeMap::init(get_memory(memory::USER_FOLLOWERS_MEMORY_ID)),
+            user_following: StableBTreeMap::init(get_memory(memory::USER_FOLLOWING_MEMORY_ID)),
+            follow_requests: StableBTreeMap::init(get_memory(memory::FOLLOW_REQUESTS_MEMORY_ID)),
+            sent_follow_requests: StableBTreeMap::init(get_memory(memory::SENT_FOLLOW_REQUESTS_MEMORY_ID)),
+            post_comments: StableBTreeMap::init(get_memory(memory::POST_COMMENTS_MEMORY_ID)),
+            comment_threads: StableBTreeMap::init(get_memory(memory::COMMENT_THREADS_MEMORY_ID)),
+            post_revisions: StableBTreeMap::init(get_memory(memory::POST_REVISIONS_MEMORY_ID)),
+            post_shares: StableBTreeMap::init(get_memory(memory::POST_SHARES_MEMORY_ID)),
+            post_likes: StableBTreeMap::init(get_memory(memory::POST_LIKES_MEMORY_ID)),
+            comment_likes: StableBTreeMap::init(get_memory(memory::COMMENT_LIKES_MEMORY_ID)),
+            hashtag_posts: StableBTreeMap::init(get_memory(memory::HASHTAG_POSTS_MEMORY_ID)),
+            hashtag_counts: StableBTreeMap::init(get_memory(memory::HASHTAG_COUNTS_MEMORY_ID)),
+            user_mentions: StableBTreeMap::init(get_memory(memory::USER_MENTIONS_MEMORY_ID)),
+            post_terms: StableBTreeMap::init(get_memory(memory::POST_TERMS_MEMORY_ID)),
+            user_terms: StableBTreeMap::init(get_memory(memory::USER_TERMS_MEMORY_ID)),
+            transactions: StableVec::init(get_memory(memory::TRANSACTIONS_MEMORY_ID))
+                .expect("Failed to initialize transactions"),
+            user_transactions: StableBTreeMap::init(get_memory(memory::USER_TRANSACTIONS_MEMORY_ID)),
+            post_tips: StableBTreeMap::init(get_memory(memory::POST_TIPS_MEMORY_ID)),
+            transaction_totals: StableBTreeMap::init(get_memory(memory::TRANSACTION_TOTALS_MEMORY_ID)),
+            post_tip_totals: StableBTreeMap::init(get_memory(memory::POST_TIP_TOTALS_MEMORY_ID)),
+            pending_withdrawals: StableBTreeMap::init(get_memory(memory::PENDING_WITHDRAWALS_MEMORY_ID)),
+            roles: StableBTreeMap::init(get_memory(memory::ROLES_MEMORY_ID)),
+            role_changes: StableVec::init(get_memory(memory::ROLE_CHANGES_MEMORY_ID))
+                .expect("Failed to initialize role changes"),
+            reports: StableBTreeMap::init(get_memory(memory::REPORTS_MEMORY_ID)),
+            open_reports: StableBTreeMap::init(get_memory(memory::OPEN_REPORTS_MEMORY_ID)),
+            target_reports: StableBTreeMap::init(get_memory(memory::TARGET_REPORTS_MEMORY_ID)),
+            reporter_targets: StableBTreeMap::init(get_memory(memory::REPORTER_TARGETS_MEMORY_ID)),
+            decisions: StableVec::init(get_memory(memory::DECISIONS_MEMORY_ID))
+                .expect("Failed to initialize decisions"),
+            subject_decisions: StableBTreeMap::init(get_memory(memory::SUBJECT_DECISIONS_MEMORY_ID)),
+            appeals: StableBTreeMap::init(get_memory(memory::APPEALS_MEMORY_ID)),
+            pending_appeals: StableBTreeMap::init(get_memory(memory::PENDING_APPEALS_MEMORY_ID)),
+            hidden_content: StableBTreeMap::init(get_memory(memory::HIDDEN_CONTENT_MEMORY_ID)),
+            suspensions: StableBTreeMap::init(get_memory(memory::SUSPENSIONS_MEMORY_ID)),
+            notifications: StableBTreeMap::init(get_memory(memory::NOTIFICATIONS_MEMORY_ID)),
+            unread_notification_groups: StableBTreeMap::init(get_memory(memory::UNREAD_NOTIFICATION_GROUPS_MEMORY_ID)),
+            unread_notification_actors: StableBTreeMap::init(get_memory(memory::UNREAD_NOTIFICATION_ACTORS_MEMORY_ID)),
+            unread_notification_counts: StableBTreeMap::init(get_memory(memory::UNREAD_NOTIFICATION_COUNTS_MEMORY_ID)),
+            notification_mutes: StableBTreeMap::init(get_memory(memory::NOTIFICATION_MUTES_MEMORY_ID)),
+            tombstones: StableBTreeMap::init(get_memory(memory::TOMBSTONES_MEMORY_ID)),
+            conversations: StableBTreeMap::init(get_memory(memory::CONVERSATIONS_MEMORY_ID)),
+            user_conversations: StableBTreeMap::init(get_memory(memory::USER_CONVERSATIONS_MEMORY_ID)),
+            direct_conversations: StableBTreeMap::init(get_memory(memory::DIRECT_CONVERSATIONS_MEMORY_ID)),
+            messages: StableBTreeMap::init(get_memory(memory::MESSAGES_MEMORY_ID)),
+            read_receipts: StableBTreeMap::init(get_memory(memory::READ_RECEIPTS_MEMORY_ID)),
+            unread_message_counts: StableBTreeMap::init(get_memory(memory::UNREAD_MESSAGE_COUNTS_MEMORY_ID)),
+            conversation_mutes: StableBTreeMap::init(get_memory(memory::CONVERSATION_MUTES_MEMORY_ID)),
+            dm_policies: StableBTreeMap::init(get_memory(memory::DM_POLICIES_MEMORY_ID)),
+            user_blocks: StableBTreeMap::init(get_memory(memory::USER_BLOCKS_MEMORY_ID)),
+            user_mutes: StableBTreeMap::init(get_memory(memory::USER_MUTES_MEMORY_ID)),
+            assets: StableBTreeMap::init(get_memory(memory::ASSETS_MEMORY_ID)),
+            user_assets: StableBTreeMap::init(get_memory(memory::USER_ASSETS_MEMORY_ID)),
+            asset_posts: StableBTreeMap::init(get_memory(memory::ASSET_POSTS_MEMORY_ID)),
+            uploads: StableBTreeMap::init(get_memory(memory::UPLOADS_MEMORY_ID)),
+            asset_chunks: StableBTreeMap::init(get_memory(memory::ASSET_CHUNKS_MEMORY_ID)),
+            media_usage: StableBTreeMap::init(get_memory(memory::MEDIA_USAGE_MEMORY_ID)),
+            remote_actors: StableBTreeMap::init(get_memory(memory::REMOTE_ACTORS_MEMORY_ID)),
+            remote_follows: StableBTreeMap::init(get_memory(memory::REMOTE_FOLLOWS_MEMORY_ID)),
+            remote_objects: StableBTreeMap::init(get_memory(memory::REMOTE_OBJECTS_MEMORY_ID)),
+            deliveries: StableBTreeMap::init(get_memory(memory::DELIVERIES_MEMORY_ID)),
+            ledger: StableCell::init(get_memory(memory::LEDGER_MEMORY_ID), PrincipalKey(Principal::anonymous()))
+                .expect("Failed to initialize ledger"),
+            edit_window: StableCell::init(get_memory(memory::EDIT_WINDOW_MEMORY_ID), post::DEFAULT_EDIT_WINDOW_NS)
+                .expect("Failed to initialize edit window"),
+            schema_version: StableCell::init(get_memory(memory::SCHEMA_VERSION_MEMORY_ID), CURRENT_SCHEMA_VERSION)
+                .expect("Failed to initialize schema version"),
+            id_sequence: StableCell::init(get_memory(memory::ID_SEQUENCE_MEMORY_ID), 0)
+                .expect("Failed to initialize id sequence"),
+            federation_key: StableCell::init(get_memory(memory::FEDERATION_KEY_MEMORY_ID), String::new())
+                .expect("Failed to initialize federation key"),
+            federation_domain: StableCell::init(get_memory(memory::FEDERATION_DOMAIN_MEMORY_ID), String::new())
+                .expect("Failed to initialize federation domain"),
+        }
+    }
+
+    pub fn get_role(&self, user_id: Principal) -> Option<Role> {
+        self.roles.get(&PrincipalKey(user_id))
+    }
+
+    /// The ICRC ledger tips are paid in, if one has been configured.
+    pub fn ledger(&self) -> Option<Principal> {
+        let ledger = self.ledger.get().0;
+        (ledger != Principal::anonymous()).then_some(ledger)
+    }
+
+    pub fn set_ledger(&mut self, ledger: Principal) {
+        self.ledger.set(PrincipalKey(ledger)).expect("Failed to store ledger");
+    }
+
+    /// How long after publishing authors can edit a post.
+    pub fn edit_window(&self) -> u64 {
+        *self.edit_window.get()
+    }
+
+    pub fn set_edit_window(&mut self, window_ns: u64) {
+        self.edit_window.set(window_ns).expect("Failed to store edit window");
+    }
+
+    pub fn schema_version(&self) -> u32 {
+        *self.schema_version.get()
+    }
+
+    pub fn set_schema_version(&mut self, version: u32) {
+        self.schema_version.set(version).expect("Failed to store schema version");
+    }
+
+    /// The PKCS#8 PEM key federated activities are signed with, once it
+    /// has been generated.
+    pub fn federation_key(&self) -> Option<String> {
+        let key = self.federation_key.get();
+        (!key.is_empty()).then(|| key.clone())
+    }
+
+    pub fn set_federation_key(&mut self, pem: String) {
+        self.federation_key.set(pem).expect("Failed to store federation key");
+    }
+
+    /// The domain federated actors live under, if an admin configured one.
+    pub fn federation_domain(&self) -> Option<String> {
+        let domain = self.federation_domain.get();
+        (!domain.is_empty()).then(|| domain.clone())
+    }
+
+    pub fn set_federation_domain(&mut self, domain: String) {
+        self.federation_domain.set(domain).expect("Failed to store federation domain");
+    }
+
+    /// Allocates a unique sequence number. It carries the current time in
+    /// milliseconds in its high bits and a counter in the low ones, so
+    /// numbers allocated in the same round never collide and still sort in
+    /// allocation order.
+    pub fn next_sequence(&mut self) -> u64 {
+        let candidate = (clock::now() / 1_000_000) << ID_COUNTER_BITS;
+        let sequence = candidate.max(*self.id_sequence.get() + 1);
+        self.id_sequence.set(sequence).expect("Failed to store id sequence");
+        sequence
+    }
+
+    /// Allocates a unique id for a new record, see `next_sequence`.
+    pub fn next_id(&mut self, prefix: &str) -> String {
+        let sequence = self.next_sequence();
+        crypto::generate_id(prefix, sequence)
+    }
+
+    pub fn get_user(&self, user_id: Principal) -> Option<User> {
+        self.users.get(&PrincipalKey(user_id))
+    }
+
+    pub fn has_user(&self, user_id: Principal) -> bool {
+        self.users.contains_key(&PrincipalKey(user_id))
+    }
+
+    /// Whether `user_id` stands in for an account on another server.
+    pub fn is_remote(&self, user_id: Principal) -> bool {
+        self.remote_actors.contains_key(&PrincipalKey(user_id))
+    }
+
+    pub fn insert_user(&mut self, user: User) {
+        self.usernames.insert(IdKey(user.username.to_lowercase()), PrincipalKey(user.id));
+        self.users.insert(PrincipalKey(user.id), user);
+    }
+
+    /// Looks a user up by username, ignoring case.
+    pub fn find_username(&self, username: &str) -> Option<Principal> {
+        self.usernames.get(&IdKey(username.to_lowercase())).map(|user_id| user_id.0)
+    }
+
+    /// Applies `f` to the stored user and writes it back.
+    pub fn update_user<R>(&mut self, user_id: Principal, f: impl FnOnce(&mut User) -> R) -> Option<R> {
+        let mut user = self.get_user(user_id)?;
+        let result = f(&mut user);
+        self.insert_user(user);
+        Some(result)
+    }
+
+    pub fn get_post(&self, post_id: &str) -> Option<Post> {
+        self.posts.get(&IdKey::from(post_id))
+    }
+
+    /// The post unless it has been deleted. Use this wherever a tombstone
+    /// can't be interacted with.
+    pub fn get_live_post(&self, post_id: &str) -> Option<Post> {
+        self.get_post(post_id).filter(|post| !post.is_deleted())
+    }
+
+    /// Whether `post_id` exists and hasn't been deleted.
+    pub fn has_post(&self, post_id: &str) -> bool {
+        self.get_live_post(post_id).is_some()
+    }
+
+    pub fn insert_post(&mut self, post: Post) {
+        self.posts.insert(IdKey(post.id.clone()), post);
+    }
+
+    /// Applies `f` to the stored post and writes it back.
+    pub fn update_post<R>(&mut self, post_id: &str, f: impl FnOnce(&mut Post) -> R) -> Option<R> {
+        let mut post = self.get_post(post_id)?;
+        let result = f(&mut post);
+        self.insert_post(post);
+        Some(result)
+    }
+
+    pub fn get_comment(&self, comment_id: &str) -> Option<Comment> {
+        self.comments.get(&IdKey::from(comment_id))
+    }
+
+    /// The comment unless it has been deleted.
+    pub fn get_live_comment(&self, comment_id: &str) -> Option<Comment> {
+        self.get_comment(comment_id).filter(|comment| !comment.is_deleted())
+    }
+
+    pub fn insert_comment(&mut self, comment: Comment) {
+        self.comments.insert(IdKey(comment.id.clone()), comment);
+    }
+
+    /// Applies `f` to the stored comment and writes it back.
+    pub fn update_comment<R>(&mut self, comment_id: &str, f: impl FnOnce(&mut Comment) -> R) -> Option<R> {
+        let mut comment = self.get_comment(comment_id)?;
+        let result = f(&mut comment);
+        self.insert_comment(comment);
+        Some(result)
+    }
+
+    /// Whether a moderator hid `content_id`, a post or comment by `author`,
+    /// from `viewer`. Authors still see their own hidden content.
+    pub fn is_hidden_from(&self, content_id: &str, author: Principal, viewer: Principal) -> bool {
+        author != viewer && self.hidden_content.contains_key(&IdKey(content_id.to_string()))
+    }
+
+    /// Whether `viewer` may see posts by `author`: private accounts only
+    /// show them to their followers.
+    pub fn can_view_posts_of(&self, author: Principal, viewer: Principal) -> bool {
+        author == viewer
+            || self.is_following(viewer, author)
+            || !self.get_user(author).is_some_and(|user| user.is_private)
+    }
+
+    /// Whether `viewer` may see `post`. Every read path goes through this:
+    /// it combines moderation, private accounts and the post's own
+    /// visibility. A share is only visible to those who can see what it
+    /// shares.
+    pub fn can_view_post(&self, post: &Post, viewer: Principal) -> bool {
+        if self.is_hidden_from(&post.id, post.author, viewer) || !self.can_view_posts_of(post.author, viewer) {
+            return false;
+        }
+
+        let in_audience = post.author == viewer
+            || match post.visibility {
+                Visibility::Public | Visibility::Unlisted => true,
+                Visibility::FollowersOnly => self.is_following(viewer, post.author),
+                Visibility::MentionedOnly => post.mentions.contains(&viewer),
+            };
+
+        in_audience
+            && post
+                .original_post_id
+                .as_ref()
+                .and_then(|original_id| self.get_post(original_id))
+                .is_none_or(|original| self.can_view_post(&original, viewer))
+    }
+
+    pub fn has_requested_follow(&self, requester: Principal, user_id: Principal) -> bool {
+        self.follow_requests.contains_key(&(PrincipalKey(user_id), PrincipalKey(requester)))
+    }
+
+    pub fn followers(&self, user_id: Principal) -> Vec<Principal> {
+        Self::members(&self.user_followers, user_id)
+    }
+
+    pub fn following(&self, user_id: Principal) -> Vec<Principal> {
+        Self::members(&self.user_following, user_id)
+    }
+
+    pub fn followed_pull_authors(&self, user_id: Principal) -> Vec<Principal> {
+        Self::members(&self.followed_pull_authors, user_id)
+    }
+
+    pub fn is_following(&self, follower: Principal, followed: Principal) -> bool {
+        self.user_following.contains_key(&(PrincipalKey(follower), PrincipalKey(followed)))
+    }
+
+    pub fn has_blocked(&self, blocker: Principal, blocked: Principal) -> bool {
+        self.user_blocks.contains_key(&(PrincipalKey(blocker), PrincipalKey(blocked)))
+    }
+
+    pub fn has_muted(&self, muter: Principal, muted: Principal) -> bool {
+        self.user_mutes.contains_key(&(PrincipalKey(muter), PrincipalKey(muted)))
+    }
+
+    fn members(set: &PrincipalSet, owner: Principal) -> Vec<Principal> {
+        let owner = PrincipalKey(owner);
+        set.range((owner, PrincipalKey::default())..)
+            .take_while(|((key, _), _)| *key == owner)
+            .map(|((_, member), _)| member.0)
+            .collect()
+    }
+}
+
+/// Removes every `(owner, member)` key from a relation set and returns the
+/// members.
+pub fn remove_members<O, M>(set: &mut StableBTreeMap<(O, M), (), Memory>, owner: &O) -> Vec<M>
+where
+    O: BoundedStorable + Ord + Clone + Default,
+    M: BoundedStorable + Ord + Clone + Default,
+{
+    let keys: Vec<_> = set
+        .range((owner.clone(), M::default())..)
+        .take_while(|((key, _), _)| key == owner)
+        .map(|(key, _)| key)
+        .collect();
+    for key in &keys {
+        set.remove(key);
+    }
+    keys.into_iter().map(|(_, member)| member).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::principal;
+
+    #[test]
+    fn ids_are_unique_and_sort_in_allocation_order() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let ids: Vec<_> = (0..100).map(|_| state.next_id("post")).collect();
+            let mut sorted = ids.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted, ids);
+            assert!(ids[0].starts_with("post_") && ids[0].len() == "post_".len() + 16);
+        });
+    }
+
+    #[test]
+    fn sequences_keep_increasing_when_the_clock_is_behind() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let ahead = (clock::now() / 1_000_000 + 60_000) << ID_COUNTER_BITS;
+            state.id_sequence.set(ahead).unwrap();
+
+            assert_eq!(state.next_sequence(), ahead + 1);
+            assert_eq!(state.next_sequence(), ahead + 2);
+        });
+    }
+
+    #[test]
+    fn post_visibility_follows_audience_moderation_and_shares() {
+        STATE.with(|state| {
+            let state = &mut state.borrow_mut();
+            let [alice, bob, carol] = [1, 2, 3].map(principal);
+            state.user_following.insert((PrincipalKey(bob), PrincipalKey(alice)), ());
+            let post = |id: &str, visibility| Post::new(id.to_string(), alice, String::new(), None, visibility);
+
+            let mut mentioned = post("post_1", Visibility::MentionedOnly);
+            mentioned.mentions.push(carol);
+            let visible_to = |state: &State, post: &Post| [alice, bob, carol].map(|viewer| state.can_view_post(post, viewer));
+            assert_eq!(visible_to(state, &post("post_2", Visibility::Public)), [true, true, true]);
+            assert_eq!(visible_to(state, &post("post_3", Visibility::Unlisted)), [true, true, true]);
+            assert_eq!(visible_to(state, &post("post_4", Visibility::FollowersOnly)), [true, true, false]);
+            assert_eq!(visible_to(state, &mentioned), [true, false, true]);
+
+            let hidden = post("post_5", Visibility::Public);
+            state.hidden_content.insert(IdKey(hidden.id.clone()), ());
+            assert_eq!(visible_to(state, &hidden), [true, false, false]);
+
+            let original = post("post_6", Visibility::FollowersOnly);
+            state.insert_post(original.clone());
+            let mut share = Post::new_share("post_7".to_string(), bob, &original, None);
+            share.visibility = Visibility::Public;
+            assert_eq!(visible_to(state, &share), [true, true, false]);
+        });
+    }
+}
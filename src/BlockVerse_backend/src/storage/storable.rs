@@ -0,0 +1,1025 @@
+use candid::{CandidType, Decode, Deserialize, Encode, Principal};
+use ic_stable_structures::{BoundedStorable, Storable};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use crate::models::{comment::{Comment, MAX_COMMENT_DEPTH}, post::{Post, PostRevision, Visibility}, user::User};
+use crate::models::asset::{Asset, Upload, CHUNK_SIZE};
+use crate::models::federation::{Delivery, RemoteActor};
+use crate::models::message::{Conversation, DmPolicy, Message, ReadReceipt};
+use crate::models::moderation::{Appeal, Decision, Report};
+use crate::models::notification::Notification;
+use crate::models::role::{Role, RoleChange};
+use crate::models::transaction::{PendingWithdrawal, Transaction, TransactionTotals};
+
+/// Maximum length in bytes of any record id used as a key.
+pub const MAX_ID_LEN: u32 = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PrincipalKey(pub Principal);
+
+/// The empty principal sorts before every other one, which makes the
+/// default usable as the lower bound of prefix scans.
+impl Default for PrincipalKey {
+    fn default() -> Self {
+        Self(Principal::management_canister())
+    }
+}
+
+impl From<Principal> for PrincipalKey {
+    fn from(principal: Principal) -> Self {
+        Self(principal)
+    }
+}
+
+impl Storable for PrincipalKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_slice())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(Principal::from_slice(&bytes))
+    }
+}
+
+impl BoundedStorable for PrincipalKey {
+    const MAX_SIZE: u32 = 29;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct IdKey(pub String);
+
+impl From<String> for IdKey {
+    fn from(id: String) -> Self {
+        Self(id)
+    }
+}
+
+impl From<&str> for IdKey {
+    fn from(id: &str) -> Self {
+        Self(id.to_string())
+    }
+}
+
+impl Storable for IdKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(self.0.as_bytes())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(String::from_utf8(bytes.into_owned()).expect("Invalid UTF-8 in stored id"))
+    }
+}
+
+impl BoundedStorable for IdKey {
+    const MAX_SIZE: u32 = MAX_ID_LEN;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Position in an append-only log such as `State.transactions`, ordered
+/// newest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SequenceKey(pub u64);
+
+impl Ord for SequenceKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.0.cmp(&self.0)
+    }
+}
+
+impl PartialOrd for SequenceKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The last position sorts first.
+impl Default for SequenceKey {
+    fn default() -> Self {
+        Self(u64::MAX)
+    }
+}
+
+impl Storable for SequenceKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.0.to_be_bytes().to_vec())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(u64::from_be_bytes(bytes.as_ref().try_into().expect("Invalid sequence key")))
+    }
+}
+
+impl BoundedStorable for SequenceKey {
+    const MAX_SIZE: u32 = 8;
+    const IS_FIXED_SIZE: bool = true;
+}
+
+/// A post's position in a per-user list: newest first, ties broken by id.
+/// Lists that mix in comments, like `State.user_mentions`, key those by
+/// the comment's creation time and id; `State.user_conversations` keys
+/// conversations by their last message time and id.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RecencyKey {
+    pub created_at: u64,
+    pub post_id: String,
+}
+
+impl RecencyKey {
+    pub fn of(post: &Post) -> Self {
+        Self { created_at: post.created_at, post_id: post.id.clone() }
+    }
+
+    pub fn of_comment(comment: &Comment) -> Self {
+        Self { created_at: comment.created_at, post_id: comment.id.clone() }
+    }
+
+    pub fn of_conversation(conversation: &Conversation) -> Self {
+        Self { created_at: conversation.last_message_at, post_id: conversation.id.clone() }
+    }
+
+    /// Encodes the creation time inverted so byte order matches `Ord`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = (u64::MAX - self.created_at).to_be_bytes().to_vec();
+        bytes.extend_from_slice(self.post_id.as_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 8 || bytes.len() > 8 + MAX_ID_LEN as usize {
+            return None;
+        }
+        let (time, id) = bytes.split_at(8);
+        Some(Self {
+            created_at: u64::MAX - u64::from_be_bytes(time.try_into().ok()?),
+            post_id: String::from_utf8(id.to_vec()).ok()?,
+        })
+    }
+}
+
+impl Ord for RecencyKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.created_at.cmp(&self.created_at).then_with(|| self.post_id.cmp(&other.post_id))
+    }
+}
+
+impl PartialOrd for RecencyKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The smallest key, usable as the lower bound of prefix scans.
+impl Default for RecencyKey {
+    fn default() -> Self {
+        Self { created_at: u64::MAX, post_id: String::new() }
+    }
+}
+
+impl Storable for RecencyKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.encode())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self::decode(&bytes).expect("Invalid stored recency key")
+    }
+}
+
+impl BoundedStorable for RecencyKey {
+    const MAX_SIZE: u32 = 8 + MAX_ID_LEN;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Most sequence numbers in a `ThreadKey`: one per level of nesting.
+const MAX_THREAD_LEVELS: usize = MAX_COMMENT_DEPTH as usize + 1;
+
+/// A comment's place in its post's thread: the sequence numbers of its
+/// ancestors and its own, top-level comment first. Sorting by path puts
+/// every comment right before its replies, and siblings in the order they
+/// were placed. The comment id only rides along.
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ThreadKey {
+    pub path: Vec<u64>,
+    pub comment_id: String,
+}
+
+impl ThreadKey {
+    /// Whether `other` sits somewhere below this comment in the thread.
+    pub fn is_ancestor_of(&self, other: &ThreadKey) -> bool {
+        other.path.len() > self.path.len() && other.path.starts_with(&self.path)
+    }
+
+    /// The number of levels, then each sequence number in 8 bytes, then the id.
+    pub fn encode(&self) -> Vec<u8> {
+        assert!(self.path.len() <= MAX_THREAD_LEVELS, "Thread key nests too deep");
+        let mut bytes = vec![self.path.len() as u8];
+        for sequence in &self.path {
+            bytes.extend_from_slice(&sequence.to_be_bytes());
+        }
+        bytes.extend_from_slice(self.comment_id.as_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        let (&levels, rest) = bytes.split_first()?;
+        let levels = levels as usize;
+        if levels > MAX_THREAD_LEVELS || rest.len() < levels * 8 || rest.len() > levels * 8 + MAX_ID_LEN as usize {
+            return None;
+        }
+        let (path, id) = rest.split_at(levels * 8);
+        Some(Self {
+            path: path.chunks_exact(8).map(|sequence| u64::from_be_bytes(sequence.try_into().unwrap())).collect(),
+            comment_id: String::from_utf8(id.to_vec()).ok()?,
+        })
+    }
+}
+
+impl Storable for ThreadKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(self.encode())
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self::decode(&bytes).expect("Invalid stored thread key")
+    }
+}
+
+impl BoundedStorable for ThreadKey {
+    const MAX_SIZE: u32 = 1 + 8 * MAX_THREAD_LEVELS as u32 + MAX_ID_LEN;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+/// Raw bytes of one upload chunk.
+#[derive(Clone, Debug, Default)]
+pub struct ChunkData(pub Vec<u8>);
+
+impl Storable for ChunkData {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Borrowed(&self.0)
+    }
+
+    fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+        Self(bytes.into_owned())
+    }
+}
+
+impl BoundedStorable for ChunkData {
+    const MAX_SIZE: u32 = CHUNK_SIZE as u32;
+    const IS_FIXED_SIZE: bool = false;
+}
+
+// Records are stored Candid-encoded inside a versioned envelope, so a
+// record type can change shape without breaking values already on disk:
+// freeze the old struct, add a variant for the new one and convert in
+// `into_current`. The bounds leave headroom over what `utils::validation`
+// lets through.
+macro_rules! impl_versioned_storable {
+    ($ty:ty, $stored:ident :: $latest:ident, $max_size:expr) => {
+        impl Storable for $ty {
+            fn to_bytes(&self) -> Cow<'_, [u8]> {
+                let stored = $stored::$latest(self.clone());
+                Cow::Owned(Encode!(&stored).expect(concat!("Failed to encode ", stringify!($ty))))
+            }
+
+            fn from_bytes(bytes: Cow<'_, [u8]>) -> Self {
+                Decode!(bytes.as_ref(), $stored)
+                    .expect(concat!("Failed to decode ", stringify!($ty)))
+                    .into_current()
+            }
+        }
+
+        impl BoundedStorable for $ty {
+            const MAX_SIZE: u32 = $max_size;
+            const IS_FIXED_SIZE: bool = false;
+        }
+    };
+}
+
+/// `User` before private accounts.
+#[derive(CandidType, Deserialize)]
+struct UserV1 {
+    id: Principal,
+    username: String,
+    bio: String,
+    avatar_url: String,
+    followers_count: u64,
+    following_count: u64,
+    posts_count: u64,
+    balance: u64,
+    created_at: u64,
+    updated_at: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredUser {
+    V1(UserV1),
+    V2(User),
+}
+
+impl StoredUser {
+    fn into_current(self) -> User {
+        match self {
+            Self::V1(user) => User {
+                id: user.id,
+                username: user.username,
+                bio: user.bio,
+                avatar_url: user.avatar_url,
+                followers_count: user.followers_count,
+                following_count: user.following_count,
+                posts_count: user.posts_count,
+                balance: user.balance,
+                is_private: false,
+                created_at: user.created_at,
+                updated_at: user.updated_at,
+            },
+            Self::V2(user) => user,
+        }
+    }
+}
+
+/// `Post` before mentions and hashtags.
+#[derive(CandidType, Deserialize)]
+struct PostV1 {
+    id: String,
+    author: Principal,
+    content: String,
+    media_url: Option<String>,
+    likes_count: u64,
+    comments_count: u64,
+    shares_count: u64,
+    is_shared: bool,
+    original_post_id: Option<String>,
+    share_comment: Option<String>,
+    created_at: u64,
+    updated_at: u64,
+    edited_at: Option<u64>,
+    deleted_at: Option<u64>,
+}
+
+/// `Post` before per-post visibility.
+#[derive(CandidType, Deserialize)]
+struct PostV2 {
+    id: String,
+    author: Principal,
+    content: String,
+    media_url: Option<String>,
+    likes_count: u64,
+    comments_count: u64,
+    shares_count: u64,
+    is_shared: bool,
+    original_post_id: Option<String>,
+    share_comment: Option<String>,
+    mentions: Vec<Principal>,
+    hashtags: Vec<String>,
+    created_at: u64,
+    updated_at: u64,
+    edited_at: Option<u64>,
+    deleted_at: Option<u64>,
+}
+
+/// `Post` before uploaded media.
+#[derive(CandidType, Deserialize)]
+struct PostV3 {
+    id: String,
+    author: Principal,
+    content: String,
+    media_url: Option<String>,
+    likes_count: u64,
+    comments_count: u64,
+    shares_count: u64,
+    is_shared: bool,
+    original_post_id: Option<String>,
+    share_comment: Option<String>,
+    mentions: Vec<Principal>,
+    hashtags: Vec<String>,
+    visibility: Visibility,
+    created_at: u64,
+    updated_at: u64,
+    edited_at: Option<u64>,
+    deleted_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredPost {
+    V1(PostV1),
+    V2(PostV2),
+    V3(PostV3),
+    V4(Post),
+}
+
+impl StoredPost {
+    fn into_current(self) -> Post {
+        match self {
+            Self::V1(post) => Post {
+                id: post.id,
+                author: post.author,
+                content: post.content,
+                media_url: post.media_url,
+                media_asset_id: None,
+                likes_count: post.likes_count,
+                comments_count: post.comments_count,
+                shares_count: post.shares_count,
+                is_shared: post.is_shared,
+                original_post_id: post.original_post_id,
+                share_comment: post.share_comment,
+                mentions: Vec::new(),
+                hashtags: Vec::new(),
+                visibility: Visibility::Public,
+                created_at: post.created_at,
+                updated_at: post.updated_at,
+                edited_at: post.edited_at,
+                deleted_at: post.deleted_at,
+            },
+            Self::V2(post) => Post {
+                id: post.id,
+                author: post.author,
+                content: post.content,
+                media_url: post.media_url,
+                media_asset_id: None,
+                likes_count: post.likes_count,
+                comments_count: post.comments_count,
+                shares_count: post.shares_count,
+                is_shared: post.is_shared,
+                original_post_id: post.original_post_id,
+                share_comment: post.share_comment,
+                mentions: post.mentions,
+                hashtags: post.hashtags,
+                visibility: Visibility::Public,
+                created_at: post.created_at,
+                updated_at: post.updated_at,
+                edited_at: post.edited_at,
+                deleted_at: post.deleted_at,
+            },
+            Self::V3(post) => Post {
+                id: post.id,
+                author: post.author,
+                content: post.content,
+                media_url: post.media_url,
+                media_asset_id: None,
+                likes_count: post.likes_count,
+                comments_count: post.comments_count,
+                shares_count: post.shares_count,
+                is_shared: post.is_shared,
+                original_post_id: post.original_post_id,
+                share_comment: post.share_comment,
+                mentions: post.mentions,
+                hashtags: post.hashtags,
+                visibility: post.visibility,
+                created_at: post.created_at,
+                updated_at: post.updated_at,
+                edited_at: post.edited_at,
+                deleted_at: post.deleted_at,
+            },
+            Self::V4(post) => post,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredPostRevision {
+    V1(PostRevision),
+}
+
+impl StoredPostRevision {
+    fn into_current(self) -> PostRevision {
+        match self {
+            Self::V1(revision) => revision,
+        }
+    }
+}
+
+/// `Comment` before replies.
+#[derive(CandidType, Deserialize)]
+struct CommentV1 {
+    id: String,
+    post_id: String,
+    author: Principal,
+    content: String,
+    likes_count: u64,
+    created_at: u64,
+    deleted_at: Option<u64>,
+}
+
+/// `Comment` before mentions and hashtags.
+#[derive(CandidType, Deserialize)]
+struct CommentV2 {
+    id: String,
+    post_id: String,
+    author: Principal,
+    content: String,
+    likes_count: u64,
+    created_at: u64,
+    parent_comment_id: Option<String>,
+    depth: u32,
+    replies_count: u64,
+    edited_at: Option<u64>,
+    deleted_at: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredComment {
+    V1(CommentV1),
+    V2(CommentV2),
+    V3(Comment),
+}
+
+impl StoredComment {
+    fn into_current(self) -> Comment {
+        match self {
+            Self::V1(comment) => Comment {
+                id: comment.id,
+                post_id: comment.post_id,
+                author: comment.author,
+                content: comment.content,
+                mentions: Vec::new(),
+                hashtags: Vec::new(),
+                likes_count: comment.likes_count,
+                created_at: comment.created_at,
+                parent_comment_id: None,
+                depth: 0,
+                replies_count: 0,
+                edited_at: None,
+                deleted_at: comment.deleted_at,
+            },
+            Self::V2(comment) => Comment {
+                id: comment.id,
+                post_id: comment.post_id,
+                author: comment.author,
+                content: comment.content,
+                mentions: Vec::new(),
+                hashtags: Vec::new(),
+                likes_count: comment.likes_count,
+                created_at: comment.created_at,
+                parent_comment_id: comment.parent_comment_id,
+                depth: comment.depth,
+                replies_count: comment.replies_count,
+                edited_at: comment.edited_at,
+                deleted_at: comment.deleted_at,
+            },
+            Self::V3(comment) => comment,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredTransaction {
+    V1(Transaction),
+}
+
+impl StoredTransaction {
+    fn into_current(self) -> Transaction {
+        match self {
+            Self::V1(transaction) => transaction,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredPendingWithdrawal {
+    V1(PendingWithdrawal),
+}
+
+impl StoredPendingWithdrawal {
+    fn into_current(self) -> PendingWithdrawal {
+        match self {
+            Self::V1(withdrawal) => withdrawal,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredTransactionTotals {
+    V1(TransactionTotals),
+}
+
+impl StoredTransactionTotals {
+    fn into_current(self) -> TransactionTotals {
+        match self {
+            Self::V1(totals) => totals,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredRole {
+    V1(Role),
+}
+
+impl StoredRole {
+    fn into_current(self) -> Role {
+        match self {
+            Self::V1(role) => role,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredRoleChange {
+    V1(RoleChange),
+}
+
+impl StoredRoleChange {
+    fn into_current(self) -> RoleChange {
+        match self {
+            Self::V1(change) => change,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredReport {
+    V1(Report),
+}
+
+impl StoredReport {
+    fn into_current(self) -> Report {
+        match self {
+            Self::V1(report) => report,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredDecision {
+    V1(Decision),
+}
+
+impl StoredDecision {
+    fn into_current(self) -> Decision {
+        match self {
+            Self::V1(decision) => decision,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredAppeal {
+    V1(Appeal),
+}
+
+impl StoredAppeal {
+    fn into_current(self) -> Appeal {
+        match self {
+            Self::V1(appeal) => appeal,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredNotification {
+    V1(Notification),
+}
+
+impl StoredNotification {
+    fn into_current(self) -> Notification {
+        match self {
+            Self::V1(notification) => notification,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredConversation {
+    V1(Conversation),
+}
+
+impl StoredConversation {
+    fn into_current(self) -> Conversation {
+        match self {
+            Self::V1(conversation) => conversation,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredMessage {
+    V1(Message),
+}
+
+impl StoredMessage {
+    fn into_current(self) -> Message {
+        match self {
+            Self::V1(message) => message,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredReadReceipt {
+    V1(ReadReceipt),
+}
+
+impl StoredReadReceipt {
+    fn into_current(self) -> ReadReceipt {
+        match self {
+            Self::V1(receipt) => receipt,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredDmPolicy {
+    V1(DmPolicy),
+}
+
+impl StoredDmPolicy {
+    fn into_current(self) -> DmPolicy {
+        match self {
+            Self::V1(policy) => policy,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredAsset {
+    V1(Asset),
+}
+
+impl StoredAsset {
+    fn into_current(self) -> Asset {
+        match self {
+            Self::V1(asset) => asset,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredUpload {
+    V1(Upload),
+}
+
+impl StoredUpload {
+    fn into_current(self) -> Upload {
+        match self {
+            Self::V1(upload) => upload,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredRemoteActor {
+    V1(RemoteActor),
+}
+
+impl StoredRemoteActor {
+    fn into_current(self) -> RemoteActor {
+        match self {
+            Self::V1(actor) => actor,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+enum StoredDelivery {
+    V1(Delivery),
+}
+
+impl StoredDelivery {
+    fn into_current(self) -> Delivery {
+        match self {
+            Self::V1(delivery) => delivery,
+        }
+    }
+}
+
+impl_versioned_storable!(User, StoredUser::V2, 2048);
+impl_versioned_storable!(Post, StoredPost::V4, 4096);
+impl_versioned_storable!(PostRevision, StoredPostRevision::V1, 2048);
+impl_versioned_storable!(Comment, StoredComment::V3, 2048);
+impl_versioned_storable!(Transaction, StoredTransaction::V1, 1024);
+impl_versioned_storable!(TransactionTotals, StoredTransactionTotals::V1, 256);
+impl_versioned_storable!(PendingWithdrawal, StoredPendingWithdrawal::V1, 512);
+impl_versioned_storable!(Role, StoredRole::V1, 64);
+impl_versioned_storable!(RoleChange, StoredRoleChange::V1, 256);
+impl_versioned_storable!(Report, StoredReport::V1, 2048);
+impl_versioned_storable!(Decision, StoredDecision::V1, 2048);
+impl_versioned_storable!(Appeal, StoredAppeal::V1, 2048);
+impl_versioned_storable!(Notification, StoredNotification::V1, 1024);
+impl_versioned_storable!(Conversation, StoredConversation::V1, 1024);
+impl_versioned_storable!(Message, StoredMessage::V1, 2048);
+impl_versioned_storable!(ReadReceipt, StoredReadReceipt::V1, 128);
+impl_versioned_storable!(DmPolicy, StoredDmPolicy::V1, 64);
+impl_versioned_storable!(Asset, StoredAsset::V1, 1024);
+impl_versioned_storable!(Upload, StoredUpload::V1, 1024);
+impl_versioned_storable!(RemoteActor, StoredRemoteActor::V1, 8192);
+impl_versioned_storable!(Delivery, StoredDelivery::V1, 20 * 1024);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::testing::principal;
+
+    fn author() -> Principal {
+        principal(1)
+    }
+
+    /// Decodes `stored` the way a stable map reads a value back.
+    fn load<T: Storable>(stored: impl CandidType) -> T {
+        T::from_bytes(Cow::Owned(Encode!(&stored).unwrap()))
+    }
+
+    #[test]
+    fn user_v1_loads_as_public_account() {
+        let user: User = load(StoredUser::V1(UserV1 {
+            id: author(),
+            username: "alice".to_string(),
+            bio: "bio".to_string(),
+            avatar_url: "https://example.com/a.png".to_string(),
+            followers_count: 3,
+            following_count: 2,
+            posts_count: 1,
+            balance: 500,
+            created_at: 10,
+            updated_at: 20,
+        }));
+
+        assert_eq!(user.id, author());
+        assert_eq!(user.username, "alice");
+        assert_eq!((user.followers_count, user.following_count, user.posts_count), (3, 2, 1));
+        assert_eq!(user.balance, 500);
+        assert!(!user.is_private);
+        assert_eq!((user.created_at, user.updated_at), (10, 20));
+    }
+
+    #[test]
+    fn user_v2_round_trips() {
+        let mut user = User::new(author(), "alice".to_string(), String::new(), String::new());
+        user.is_private = true;
+
+        let loaded: User = load(StoredUser::V2(user.clone()));
+        assert!(loaded.is_private);
+        assert_eq!(User::from_bytes(user.to_bytes()).username, "alice");
+    }
+
+    #[test]
+    fn post_v1_loads_without_mentions_or_visibility() {
+        let post: Post = load(StoredPost::V1(PostV1 {
+            id: "post_1".to_string(),
+            author: author(),
+            content: "hello #world".to_string(),
+            media_url: Some("https://example.com/p.png".to_string()),
+            likes_count: 4,
+            comments_count: 5,
+            shares_count: 6,
+            is_shared: false,
+            original_post_id: None,
+            share_comment: None,
+            created_at: 10,
+            updated_at: 20,
+            edited_at: Some(15),
+            deleted_at: None,
+        }));
+
+        assert_eq!(post.id, "post_1");
+        assert_eq!(post.media_url.as_deref(), Some("https://example.com/p.png"));
+        assert_eq!(post.media_asset_id, None);
+        assert_eq!((post.likes_count, post.comments_count, post.shares_count), (4, 5, 6));
+        assert!(post.mentions.is_empty() && post.hashtags.is_empty());
+        assert_eq!(post.visibility, Visibility::Public);
+        assert_eq!(post.edited_at, Some(15));
+    }
+
+    #[test]
+    fn post_v2_keeps_mentions_and_hashtags() {
+        let post: Post = load(StoredPost::V2(PostV2 {
+            id: "post_2".to_string(),
+            author: author(),
+            content: "hi @bob #rust".to_string(),
+            media_url: None,
+            likes_count: 0,
+            comments_count: 0,
+            shares_count: 0,
+            is_shared: true,
+            original_post_id: Some("post_1".to_string()),
+            share_comment: Some("look".to_string()),
+            mentions: vec![author()],
+            hashtags: vec!["rust".to_string()],
+            created_at: 10,
+            updated_at: 10,
+            edited_at: None,
+            deleted_at: Some(30),
+        }));
+
+        assert_eq!(post.mentions, vec![author()]);
+        assert_eq!(post.hashtags, vec!["rust".to_string()]);
+        assert_eq!(post.original_post_id.as_deref(), Some("post_1"));
+        assert_eq!(post.visibility, Visibility::Public);
+        assert_eq!(post.deleted_at, Some(30));
+    }
+
+    #[test]
+    fn post_v3_keeps_visibility_without_uploaded_media() {
+        let post: Post = load(StoredPost::V3(PostV3 {
+            id: "post_3".to_string(),
+            author: author(),
+            content: "friends only".to_string(),
+            media_url: Some("https://example.com/p.png".to_string()),
+            likes_count: 1,
+            comments_count: 0,
+            shares_count: 0,
+            is_shared: false,
+            original_post_id: None,
+            share_comment: None,
+            mentions: Vec::new(),
+            hashtags: Vec::new(),
+            visibility: Visibility::FollowersOnly,
+            created_at: 10,
+            updated_at: 10,
+            edited_at: None,
+            deleted_at: None,
+        }));
+
+        assert_eq!(post.visibility, Visibility::FollowersOnly);
+        assert_eq!(post.media_url.as_deref(), Some("https://example.com/p.png"));
+        assert_eq!(post.media_asset_id, None);
+    }
+
+    #[test]
+    fn post_v4_round_trips() {
+        let post = Post::new(
+            "post_4".to_string(),
+            author(),
+            "hi".to_string(),
+            Some("asset_1".to_string()),
+            Visibility::Unlisted,
+        );
+
+        let loaded: Post = load(StoredPost::V4(post.clone()));
+        assert_eq!(loaded.media_asset_id.as_deref(), Some("asset_1"));
+        assert_eq!(Post::from_bytes(post.to_bytes()).visibility, Visibility::Unlisted);
+    }
+
+    #[test]
+    fn comment_v1_loads_as_top_level() {
+        let comment: Comment = load(StoredComment::V1(CommentV1 {
+            id: "comment_1".to_string(),
+            post_id: "post_1".to_string(),
+            author: author(),
+            content: "nice".to_string(),
+            likes_count: 2,
+            created_at: 10,
+            deleted_at: None,
+        }));
+
+        assert_eq!(comment.post_id, "post_1");
+        assert_eq!(comment.likes_count, 2);
+        assert_eq!(comment.parent_comment_id, None);
+        assert_eq!((comment.depth, comment.replies_count), (0, 0));
+        assert_eq!(comment.edited_at, None);
+    }
+
+    #[test]
+    fn comment_v2_keeps_thread_position() {
+        let comment: Comment = load(StoredComment::V2(CommentV2 {
+            id: "comment_2".to_string(),
+            post_id: "post_1".to_string(),
+            author: author(),
+            content: "agreed".to_string(),
+            likes_count: 0,
+            created_at: 10,
+            parent_comment_id: Some("comment_1".to_string()),
+            depth: 1,
+            replies_count: 3,
+            edited_at: Some(12),
+            deleted_at: None,
+        }));
+
+        assert_eq!(comment.parent_comment_id.as_deref(), Some("comment_1"));
+        assert_eq!((comment.depth, comment.replies_count), (1, 3));
+        assert_eq!(comment.edited_at, Some(12));
+        assert!(comment.mentions.is_empty() && comment.hashtags.is_empty());
+    }
+
+    #[test]
+    fn comment_v3_round_trips() {
+        let comment = Comment::new("comment_3".to_string(), "post_1".to_string(), author(), "hi".to_string());
+
+        let loaded: Comment = load(StoredComment::V3(comment.clone()));
+        assert_eq!(loaded.id, "comment_3");
+        assert_eq!(Comment::from_bytes(comment.to_bytes()).content, "hi");
+    }
+
+    #[test]
+    fn thread_keys_sort_replies_under_their_parent() {
+        let key = |path: &[u64], id: &str| ThreadKey { path: path.to_vec(), comment_id: id.to_string() };
+        let (root, reply, sibling) = (key(&[5], "z"), key(&[5, 9], "a"), key(&[7], "b"));
+
+        assert!(root < reply && reply < sibling);
+        assert!(root.is_ancestor_of(&reply));
+        assert!(!root.is_ancestor_of(&sibling) && !root.is_ancestor_of(&root));
+    }
+
+    #[test]
+    fn thread_keys_stay_within_bounds() {
+        let deepest = ThreadKey {
+            path: vec![u64::MAX; MAX_THREAD_LEVELS],
+            comment_id: "c".repeat(MAX_ID_LEN as usize),
+        };
+
+        let bytes = deepest.to_bytes();
+        assert_eq!(bytes.len() as u32, ThreadKey::MAX_SIZE);
+        assert_eq!(ThreadKey::from_bytes(bytes), deepest);
+        assert_eq!(ThreadKey::decode(&[MAX_THREAD_LEVELS as u8 + 1]), None);
+    }
+}
@@ -0,0 +1,58 @@
+use ic_certification::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
+use serde::Serialize;
+use std::cell::RefCell;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crate::models::http::HeaderField;
+
+/// The subtree the HTTP gateway looks up certified paths in.
+const ASSETS_LABEL: &[u8] = b"http_assets";
+
+thread_local! {
+    /// SHA-256 of each certified response body, by path. Kept on the heap
+    /// and rebuilt after upgrades, see `MediaService::certify_assets`.
+    static TREE: RefCell<RbTree<String, Hash>> = const { RefCell::new(RbTree::new()) };
+}
+
+/// Certifies that `path` is served with a body hashing to `sha256`. Only
+/// callable from updates, `init` and `post_upgrade`.
+pub fn certify(path: String, sha256: Hash) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.insert(path, sha256);
+        publish(&tree);
+    });
+}
+
+pub fn uncertify(path: &str) {
+    TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        tree.delete(path.as_bytes());
+        publish(&tree);
+    });
+}
+
+/// The `IC-Certificate` header proving the body certified for `path`, or
+/// `None` if it isn't certified or this isn't a query.
+pub fn header(path: &str) -> Option<HeaderField> {
+    let certificate = ic_cdk::api::data_certificate()?;
+    TREE.with(|tree| {
+        let tree = tree.borrow();
+        tree.get(path.as_bytes())?;
+
+        let witness = labeled(ASSETS_LABEL, tree.witness(path.as_bytes()));
+        let mut serializer = serde_cbor::Serializer::new(Vec::new());
+        serializer.self_describe().ok()?;
+        witness.serialize(&mut serializer).ok()?;
+
+        let value = format!(
+            "certificate=:{}:, tree=:{}:",
+            STANDARD.encode(certificate),
+            STANDARD.encode(serializer.into_inner())
+        );
+        Some(("IC-Certificate".to_string(), value))
+    })
+}
+
+fn publish(tree: &RbTree<String, Hash>) {
+    ic_cdk::api::set_certified_data(&labeled_hash(ASSETS_LABEL, &tree.root_hash()));
+}
@@ -1,13 +1,17 @@
 use sha2::{Digest, Sha256};
 
-pub fn hash_string(input: &str) -> String {
+/// Hex SHA-256 of the concatenation of `chunks`.
+pub fn hash_chunks<T: AsRef<[u8]>>(chunks: impl IntoIterator<Item = T>) -> String {
     let mut hasher = Sha256::new();
-    hasher.update(input.as_bytes());
+    for chunk in chunks {
+        hasher.update(chunk.as_ref());
+    }
     hex::encode(hasher.finalize())
 }
 
-pub fn generate_id(prefix: &str, data: &str) -> String {
-    let timestamp = ic_cdk::api::time();
-    let combined = format!("{}_{}_{}", prefix, data, timestamp);
-    hash_string(&combined)
+/// Formats a sequence number from `State::next_sequence` as a record id.
+/// The fixed-width hex keeps ids of the same prefix sorting in allocation
+/// order.
+pub fn generate_id(prefix: &str, sequence: u64) -> String {
+    format!("{}_{:016x}", prefix, sequence)
 }
\ No newline at end of file
@@ -0,0 +1,32 @@
+use time::format_description::well_known::{Rfc2822, Rfc3339};
+use time::macros::format_description;
+use time::OffsetDateTime;
+
+/// A canister timestamp as an RFC 3339 date, as used by Atom, JSON Feed
+/// and ActivityPub.
+pub fn rfc3339(ns: u64) -> String {
+    timestamp(ns).format(&Rfc3339).unwrap_or_default()
+}
+
+/// A canister timestamp as an RFC 2822 date, as used by RSS.
+pub fn rfc2822(ns: u64) -> String {
+    timestamp(ns).format(&Rfc2822).unwrap_or_default()
+}
+
+/// A canister timestamp as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub fn http_date(ns: u64) -> String {
+    let format = format_description!(
+        "[weekday repr:short], [day] [month repr:short] [year] [hour]:[minute]:[second] GMT"
+    );
+    timestamp(ns).format(&format).unwrap_or_default()
+}
+
+/// Parses an HTTP date into a canister timestamp.
+pub fn parse_http_date(date: &str) -> Option<u64> {
+    let parsed = OffsetDateTime::parse(date.trim(), &Rfc2822).ok()?;
+    u64::try_from(parsed.unix_timestamp_nanos()).ok()
+}
+
+fn timestamp(ns: u64) -> OffsetDateTime {
+    OffsetDateTime::from_unix_timestamp_nanos(i128::from(ns)).unwrap_or(OffsetDateTime::UNIX_EPOCH)
+}
@@ -1,2 +1,10 @@
+pub mod certification;
+pub mod clock;
 pub mod crypto;
-pub mod validation;
\ No newline at end of file
+pub mod dates;
+pub mod markup;
+pub mod signature;
+#[cfg(test)]
+pub mod testing;
+pub mod tokenizer;
+pub mod validation;
@@ -0,0 +1,114 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rand_chacha::rand_core::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use rsa::pkcs1::DecodeRsaPublicKey;
+use rsa::pkcs1v15::{Signature as RsaSignature, SigningKey, VerifyingKey};
+use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding};
+use rsa::signature::{SignatureEncoding, Signer, Verifier};
+use rsa::{RsaPrivateKey, RsaPublicKey};
+use sha2::{Digest, Sha256};
+
+/// Size of the instance key. Fediverse servers expect RSA keys of at least
+/// this size.
+const KEY_BITS: usize = 2048;
+
+/// A parsed `Signature` header, as defined by the HTTP Signatures draft
+/// that ActivityPub servers use.
+pub struct Signature {
+    pub key_id: String,
+    /// Lowercased names of the signed headers, in signing order
+    pub headers: Vec<String>,
+    pub signature: Vec<u8>,
+}
+
+impl Signature {
+    pub fn parse(header: &str) -> Option<Self> {
+        let (mut key_id, mut headers, mut signature) = (None, None, None);
+        for param in header.split(',') {
+            let (name, value) = param.trim().split_once('=')?;
+            let value = value.trim().trim_matches('"');
+            match name.trim() {
+                "keyId" => key_id = Some(value.to_string()),
+                "headers" => headers = Some(value.split_whitespace().map(str::to_lowercase).collect()),
+                "signature" => signature = BASE64.decode(value).ok(),
+                "algorithm" if !matches!(value, "rsa-sha256" | "hs2019") => return None,
+                _ => {}
+            }
+        }
+        Some(Self {
+            key_id: key_id?,
+            // Without a list only the date is signed
+            headers: headers.unwrap_or_else(|| vec!["date".to_string()]),
+            signature: signature?,
+        })
+    }
+
+    pub fn covers(&self, header: &str) -> bool {
+        self.headers.iter().any(|signed| signed == header)
+    }
+}
+
+/// The string a request's signature is computed over: one `name: value`
+/// line per signed header. `value_of` returns a header's value, or for
+/// `(request-target)` the lowercased method and the path.
+pub fn signing_string<'a>(headers: &[String], value_of: impl Fn(&str) -> Option<&'a str>) -> Option<String> {
+    let lines: Option<Vec<String>> = headers
+        .iter()
+        .map(|name| value_of(name).map(|value| format!("{}: {}", name, value.trim())))
+        .collect();
+    Some(lines?.join("\n"))
+}
+
+/// A `Signature` header value signing `headers` with `private_key_pem`.
+pub fn sign(private_key_pem: &str, key_id: &str, headers: &[&str], signing_string: &str) -> Option<String> {
+    let key = RsaPrivateKey::from_pkcs8_pem(private_key_pem).ok()?;
+    let signature = SigningKey::<Sha256>::new(key).sign(signing_string.as_bytes());
+    Some(format!(
+        r#"keyId="{}",algorithm="rsa-sha256",headers="{}",signature="{}""#,
+        key_id,
+        headers.join(" "),
+        BASE64.encode(signature.to_vec())
+    ))
+}
+
+/// Whether `signature` is `public_key_pem`'s signature of `signing_string`.
+pub fn verify(public_key_pem: &str, signing_string: &str, signature: &[u8]) -> bool {
+    let Some(key) = parse_public_key(public_key_pem) else {
+        return false;
+    };
+    let Ok(signature) = RsaSignature::try_from(signature) else {
+        return false;
+    };
+    VerifyingKey::<Sha256>::new(key).verify(signing_string.as_bytes(), &signature).is_ok()
+}
+
+/// Whether `pem` is an RSA public key, in either of the encodings servers
+/// publish.
+pub fn is_public_key(pem: &str) -> bool {
+    parse_public_key(pem).is_some()
+}
+
+/// A `Digest` header value for `body`.
+pub fn digest(body: &[u8]) -> String {
+    format!("SHA-256={}", BASE64.encode(Sha256::digest(body)))
+}
+
+/// Generates an RSA key from a random `seed` and returns it as PKCS#8 PEM.
+pub fn generate_key(seed: [u8; 32]) -> Option<String> {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    let key = RsaPrivateKey::new(&mut rng, KEY_BITS).ok()?;
+    key.to_pkcs8_pem(LineEnding::LF).ok().map(|pem| pem.to_string())
+}
+
+/// The public half of a PKCS#8 PEM private key, as SPKI PEM.
+pub fn public_key_pem(private_key_pem: &str) -> Option<String> {
+    let key = RsaPrivateKey::from_pkcs8_pem(private_key_pem).ok()?;
+    RsaPublicKey::from(&key).to_public_key_pem(LineEnding::LF).ok()
+}
+
+fn parse_public_key(pem: &str) -> Option<RsaPublicKey> {
+    RsaPublicKey::from_public_key_pem(pem.trim())
+        .or_else(|_| RsaPublicKey::from_pkcs1_pem(pem.trim()))
+        .ok()
+}
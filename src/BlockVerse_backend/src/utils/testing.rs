@@ -0,0 +1,21 @@
+//! Fixtures shared by the unit tests.
+
+use candid::Principal;
+use crate::models::user::User;
+use crate::storage::state::State;
+
+/// A distinct, non-anonymous principal for each `n`.
+pub fn principal(n: u8) -> Principal {
+    Principal::from_slice(&[n; 29])
+}
+
+/// Stores users `user1` to `user{count}`, with ids `principal(1)` on.
+pub fn users(state: &mut State, count: u8) -> Vec<Principal> {
+    (1..=count)
+        .map(|n| {
+            let user_id = principal(n);
+            state.insert_user(User::new(user_id, format!("user{}", n), String::new(), String::new()));
+            user_id
+        })
+        .collect()
+}
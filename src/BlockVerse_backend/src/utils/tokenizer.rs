@@ -0,0 +1,73 @@
+/// Longest term kept in the search index; longer words are truncated.
+pub const MAX_TERM_LEN: usize = 32;
+
+/// Splits text into the lowercase terms it is indexed under. Words are runs
+/// of letters, digits and underscores; a word written as `#tag` or `@name`
+/// is indexed both plain and with its marker, so hashtags and mentions can
+/// be searched for exactly.
+pub fn index_terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    split_words(text, |marker, word| {
+        if let Some(marker) = marker {
+            terms.push(format!("{}{}", marker, word));
+        }
+        terms.push(word);
+    });
+    terms
+}
+
+/// Splits a search query into terms. Unlike `index_terms`, a marked word
+/// only yields its marked form: `#rust` looks for the hashtag, not the word.
+pub fn query_terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    split_words(text, |marker, word| {
+        let term = match marker {
+            Some(marker) => format!("{}{}", marker, word),
+            None => word,
+        };
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    });
+    terms
+}
+
+/// The distinct `#tags` in `text`, lowercased and without the marker.
+pub fn hashtags(text: &str) -> Vec<String> {
+    marked_words(text, '#')
+}
+
+/// The distinct `@usernames` in `text`, lowercased and without the marker.
+pub fn mentions(text: &str) -> Vec<String> {
+    marked_words(text, '@')
+}
+
+fn marked_words(text: &str, wanted: char) -> Vec<String> {
+    let mut words = Vec::new();
+    split_words(text, |marker, word| {
+        if marker == Some(wanted) && !words.contains(&word) {
+            words.push(word);
+        }
+    });
+    words
+}
+
+fn split_words(text: &str, mut emit: impl FnMut(Option<char>, String)) {
+    let mut word = String::new();
+    let mut marker = None;
+    let mut previous = None;
+
+    for c in text.to_lowercase().chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() || c == '_' {
+            if word.is_empty() {
+                marker = previous.filter(|p| *p == '#' || *p == '@');
+            }
+            if word.chars().count() < MAX_TERM_LEN {
+                word.push(c);
+            }
+        } else if !word.is_empty() {
+            emit(marker, std::mem::take(&mut word));
+        }
+        previous = Some(c);
+    }
+}
@@ -1,3 +1,6 @@
+pub const MAX_CONTENT_LENGTH: usize = 280;
+pub const MAX_MESSAGE_LENGTH: usize = 1000;
+
 pub fn is_valid_username(username: &str) -> bool {
     if username.is_empty() || username.len() > 20 {
         return false;
@@ -8,9 +11,55 @@ pub fn is_valid_username(username: &str) -> bool {
 }
 
 pub fn is_valid_content(content: &str) -> bool {
-    !content.trim().is_empty() && content.len() <= 280
+    !content.trim().is_empty() && content.len() <= MAX_CONTENT_LENGTH
 }
 
 pub fn sanitize_content(content: &str) -> String {
     content.trim().to_string()
-}
\ No newline at end of file
+}
+
+pub fn is_valid_bio(bio: &str) -> bool {
+    bio.len() <= 500
+}
+
+pub fn is_valid_url(url: &str) -> bool {
+    url.len() <= 512
+}
+
+/// Free-text notes attached to reports, moderation decisions and appeals.
+pub fn is_valid_note(note: &str) -> bool {
+    note.len() <= 500
+}
+
+pub fn is_valid_message(content: &str) -> bool {
+    !content.trim().is_empty() && content.len() <= MAX_MESSAGE_LENGTH
+}
+
+/// Media types that can be uploaded, with the largest size allowed for each.
+pub const MEDIA_TYPES: [(&str, u64); 6] = [
+    ("image/jpeg", 10 * 1024 * 1024),
+    ("image/png", 10 * 1024 * 1024),
+    ("image/gif", 10 * 1024 * 1024),
+    ("image/webp", 10 * 1024 * 1024),
+    ("video/mp4", 50 * 1024 * 1024),
+    ("video/webm", 50 * 1024 * 1024),
+];
+
+/// The largest upload allowed for `mime_type`, or `None` if it can't be
+/// uploaded at all.
+pub fn max_media_size(mime_type: &str) -> Option<u64> {
+    MEDIA_TYPES.iter().find(|(allowed, _)| *allowed == mime_type).map(|(_, max)| *max)
+}
+
+/// Identifies an allowed media type from the file's leading bytes.
+pub fn sniff_media_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0xFF, 0xD8, 0xFF, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some("image/png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some("video/mp4"),
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => Some("video/webm"),
+        _ => None,
+    }
+}
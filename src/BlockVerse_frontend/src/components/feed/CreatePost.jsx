@@ -3,37 +3,46 @@ import { Image, Video, Smile, Send } from 'lucide-react';
 import { useAuth } from '../../hooks/useAuth';
 import LoadingSpinner from '../common/LoadingSpinner';
 import toast from 'react-hot-toast';
+import { formatError } from '../../utils/formatters';
+import { api } from '../../services/api';
 
 const CreatePost = ({ onPostCreated }) => {
     const { actor, user } = useAuth();
     const [content, setContent] = useState('');
-    const [mediaUrl, setMediaUrl] = useState('');
+    const [mediaFile, setMediaFile] = useState(null);
     const [isPosting, setIsPosting] = useState(false);
     const [showMediaInput, setShowMediaInput] = useState(false);
 
     const handleSubmit = async (e) => {
         e.preventDefault();
 
-        if (!content.trim() && !mediaUrl.trim()) {
+        if (!content.trim() && !mediaFile) {
             toast.error('Post cannot be empty');
             return;
         }
 
         try {
             setIsPosting(true);
-            const result = await actor.create_post(
-                content.trim(),
-                mediaUrl.trim() ? [mediaUrl.trim()] : []
-            );
+            let mediaAssetId = [];
+            if (mediaFile) {
+                const upload = await api.uploadMedia(actor, mediaFile);
+                if ('Err' in upload) {
+                    toast.error(formatError(upload.Err));
+                    return;
+                }
+                mediaAssetId = [upload.Ok.id];
+            }
+
+            const result = await api.createPost(actor, content.trim(), mediaAssetId);
 
             if ('Ok' in result) {
                 setContent('');
-                setMediaUrl('');
+                setMediaFile(null);
                 setShowMediaInput(false);
                 onPostCreated(result.Ok);
                 toast.success('Post created successfully!');
             } else {
-                toast.error(result.Err);
+                toast.error(formatError(result.Err));
             }
         } catch (error) {
             console.error('Error creating post:', error);
@@ -66,11 +75,10 @@ const CreatePost = ({ onPostCreated }) => {
                         {showMediaInput && (
                             <div className="mt-4">
                                 <input
-                                    type="url"
-                                    value={mediaUrl}
-                                    onChange={(e) => setMediaUrl(e.target.value)}
-                                    placeholder="Enter image or video URL"
-                                    className="w-full bg-gray-700 text-white placeholder-gray-400 px-4 py-2 rounded-lg border border-gray-600 focus:border-purple-500 focus:outline-none"
+                                    type="file"
+                                    accept="image/jpeg,image/png,image/gif,image/webp,video/mp4,video/webm"
+                                    onChange={(e) => setMediaFile(e.target.files[0] || null)}
+                                    className="w-full bg-gray-700 text-white px-4 py-2 rounded-lg border border-gray-600 focus:border-purple-500 focus:outline-none"
                                 />
                             </div>
                         )}
@@ -106,7 +114,7 @@ const CreatePost = ({ onPostCreated }) => {
 
                                 <button
                                     type="submit"
-                                    disabled={isPosting || (!content.trim() && !mediaUrl.trim())}
+                                    disabled={isPosting || (!content.trim() && !mediaFile)}
                                     className="bg-purple-600 hover:bg-purple-700 disabled:bg-gray-600 disabled:cursor-not-allowed text-white px-6 py-2 rounded-full font-semibold transition-colors flex items-center space-x-2"
                                 >
                                     {isPosting ? (
@@ -4,35 +4,41 @@ import CreatePost from './CreatePost';
 import PostCard from './PostCard';
 import LoadingSpinner from '../common/LoadingSpinner';
 import toast from 'react-hot-toast';
+import { formatError } from '../../utils/formatters';
 
 const Feed = () => {
     const { actor, principal } = useAuth();
     const [posts, setPosts] = useState([]);
     const [loading, setLoading] = useState(true);
-    const [page, setPage] = useState(0);
+    const [cursor, setCursor] = useState([]);
     const [hasMore, setHasMore] = useState(true);
 
     useEffect(() => {
         loadFeed();
     }, [actor, principal]);
 
-    const loadFeed = async (pageNum = 0) => {
+    const loadFeed = async (pageCursor = []) => {
         if (!actor || !principal) return;
 
         try {
             setLoading(true);
             const limit = 10;
-            const offset = pageNum * limit;
 
-            const feedPosts = await actor.get_feed(principal, limit, offset);
+            const result = await actor.get_feed(principal, pageCursor, limit);
+            if ('Err' in result) {
+                toast.error(formatError(result.Err));
+                return;
+            }
+            const { items: feedPosts, next_cursor } = result.Ok;
 
-            if (pageNum === 0) {
+            if (pageCursor.length === 0) {
                 setPosts(feedPosts);
             } else {
                 setPosts(prev => [...prev, ...feedPosts]);
             }
 
-            setHasMore(feedPosts.length === limit);
+            setCursor(next_cursor);
+            setHasMore(next_cursor.length > 0);
         } catch (error) {
             console.error('Error loading feed:', error);
             toast.error('Failed to load feed');
@@ -54,9 +60,7 @@ const Feed = () => {
     };
 
     const loadMore = () => {
-        const nextPage = page + 1;
-        setPage(nextPage);
-        loadFeed(nextPage);
+        loadFeed(cursor);
     };
 
     if (loading && posts.length === 0) {
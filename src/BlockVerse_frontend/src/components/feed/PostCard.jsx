@@ -1,10 +1,43 @@
-import React, { useState } from 'react';
+import React, { useEffect, useState } from 'react';
 import { Link } from 'react-router-dom';
 import { Heart, MessageCircle, Repeat, Share, MoreHorizontal } from 'lucide-react';
 import { formatDistanceToNow } from 'date-fns';
 import { useAuth } from '../../hooks/useAuth';
 import TipButton from '../payments/TipButton';
 import toast from 'react-hot-toast';
+import { formatError } from '../../utils/formatters';
+import { api } from '../../services/api';
+
+const PostMedia = ({ assetId }) => {
+    const { actor } = useAuth();
+    const [media, setMedia] = useState(null);
+
+    useEffect(() => {
+        let loaded = null;
+        let cancelled = false;
+        api.loadAssetUrl(actor, assetId).then((result) => {
+            loaded = result;
+            if (!result) return;
+            if (cancelled) {
+                URL.revokeObjectURL(result.url);
+            } else {
+                setMedia({ url: result.url, isVideo: result.mimeType.startsWith('video/') });
+            }
+        });
+        return () => {
+            cancelled = true;
+            if (loaded) URL.revokeObjectURL(loaded.url);
+        };
+    }, [actor, assetId]);
+
+    if (!media) return null;
+
+    return media.isVideo ? (
+        <video src={media.url} controls className="rounded-lg max-w-full h-auto border border-gray-600" />
+    ) : (
+        <img src={media.url} alt="Post media" className="rounded-lg max-w-full h-auto border border-gray-600" />
+    );
+};
 
 const PostCard = ({ post, onUpdate }) => {
     const { actor, user } = useAuth();
@@ -30,7 +63,7 @@ const PostCard = ({ post, onUpdate }) => {
                 }
                 onUpdate(updatedPost);
             } else {
-                toast.error(result.Err);
+                toast.error(formatError(result.Err));
             }
         } catch (error) {
             console.error('Error liking post:', error);
@@ -46,7 +79,7 @@ const PostCard = ({ post, onUpdate }) => {
             if ('Ok' in result) {
                 toast.success('Post shared successfully!');
             } else {
-                toast.error(result.Err);
+                toast.error(formatError(result.Err));
             }
         } catch (error) {
             console.error('Error sharing post:', error);
@@ -83,6 +116,9 @@ const PostCard = ({ post, onUpdate }) => {
                         <span className="text-gray-500 text-sm">
                             {formatTime(post.created_at)}
                         </span>
+                        {post.edited_at.length > 0 && (
+                            <span className="text-gray-500 text-sm">(edited)</span>
+                        )}
                         <div className="ml-auto">
                             <button className="text-gray-400 hover:text-white transition-colors">
                                 <MoreHorizontal className="w-5 h-5" />
@@ -98,14 +134,24 @@ const PostCard = ({ post, onUpdate }) => {
                     )}
 
                     <div className="mb-4">
-                        <p className="text-white text-base leading-relaxed whitespace-pre-wrap">
-                            {post.content}
-                        </p>
+                        {post.deleted_at.length > 0 ? (
+                            <p className="text-gray-500 italic">This post was deleted</p>
+                        ) : (
+                            <p className="text-white text-base leading-relaxed whitespace-pre-wrap">
+                                {post.content}
+                            </p>
+                        )}
+
+                        {post.media_asset_id.length > 0 && (
+                            <div className="mt-3">
+                                <PostMedia assetId={post.media_asset_id[0]} />
+                            </div>
+                        )}
 
-                        {post.media_url && (
+                        {post.media_url.length > 0 && (
                             <div className="mt-3">
                                 <img
-                                    src={post.media_url}
+                                    src={post.media_url[0]}
                                     alt="Post media"
                                     className="rounded-lg max-w-full h-auto border border-gray-600"
                                     onError={(e) => {
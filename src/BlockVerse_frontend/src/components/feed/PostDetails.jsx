@@ -24,14 +24,16 @@ const PostDetails = () => {
 
             const [postResult, commentsResult] = await Promise.all([
                 actor.get_post(postId),
-                actor.get_post_comments(postId)
+                actor.get_post_comments(postId, [], 50)
             ]);
 
             if (postResult.length > 0) {
                 setPost(postResult[0]);
             }
 
-            setComments(commentsResult);
+            if ('Ok' in commentsResult) {
+                setComments(commentsResult.Ok.items);
+            }
         } catch (error) {
             console.error('Error loading post details:', error);
         } finally {
@@ -46,7 +48,7 @@ const PostDetails = () => {
 
         setSubmitting(true);
         try {
-            const result = await actor.create_comment(postId, newComment.trim());
+            const result = await actor.create_comment(postId, newComment.trim(), []);
 
             if (result.Ok) {
                 setComments(prev => [...prev, result.Ok]);
@@ -113,7 +115,11 @@ const PostDetails = () => {
 
                 <div className="comments-list">
                     {comments.map((comment) => (
-                        <div key={comment.id} className="comment-card">
+                        <div
+                            key={comment.id}
+                            className="comment-card"
+                            style={{ marginLeft: `${comment.depth * 24}px` }}
+                        >
                             <Link to={`/profile/${comment.author}`} className="comment-author">
                                 <img
                                     src={`/api/avatar/${comment.author}`}
@@ -125,7 +131,9 @@ const PostDetails = () => {
                                 </span>
                             </Link>
 
-                            <p className="comment-content">{comment.content}</p>
+                            <p className="comment-content">
+                                {comment.deleted_at.length > 0 ? <em>Comment deleted</em> : comment.content}
+                            </p>
 
                             <div className="comment-actions">
                                 <span className="comment-time">
@@ -135,6 +143,11 @@ const PostDetails = () => {
                                     <FiHeart />
                                     <span>{comment.likes_count}</span>
                                 </button>
+                                {comment.replies_count > 0 && (
+                                    <span className="comment-replies">
+                                        {comment.replies_count.toString()} {comment.replies_count === 1n ? 'reply' : 'replies'}
+                                    </span>
+                                )}
                             </div>
                         </div>
                     ))}
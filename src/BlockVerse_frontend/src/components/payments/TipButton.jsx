@@ -4,6 +4,7 @@ import { useAuth } from '../../hooks/useAuth';
 import Modal from '../common/Modal';
 import LoadingSpinner from '../common/LoadingSpinner';
 import toast from 'react-hot-toast';
+import { formatError } from '../../utils/formatters';
 
 const TipButton = ({ userId }) => {
     const { actor, user } = useAuth();
@@ -33,7 +34,7 @@ const TipButton = ({ userId }) => {
                 setShowModal(false);
                 setAmount('');
             } else {
-                toast.error(result.Err);
+                toast.error(formatError(result.Err));
             }
         } catch (error) {
             console.error('Error sending tip:', error);
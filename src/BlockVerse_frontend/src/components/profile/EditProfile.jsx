@@ -4,6 +4,7 @@ import { useAuth } from '../../hooks/useAuth';
 import Modal from '../common/Modal';
 import LoadingSpinner from '../common/LoadingSpinner';
 import toast from 'react-hot-toast';
+import { formatError } from '../../utils/formatters';
 
 const EditProfile = ({ user, onClose, onUpdate }) => {
     const { actor } = useAuth();
@@ -23,7 +24,7 @@ const EditProfile = ({ user, onClose, onUpdate }) => {
                 onClose();
                 toast.success('Profile updated successfully!');
             } else {
-                toast.error(result.Err);
+                toast.error(formatError(result.Err));
             }
         } catch (error) {
             console.error('Error updating profile:', error);
@@ -3,6 +3,7 @@ import { UserPlus, UserMinus } from 'lucide-react';
 import { useAuth } from '../../hooks/useAuth';
 import LoadingSpinner from '../common/LoadingSpinner';
 import toast from 'react-hot-toast';
+import { formatError } from '../../utils/formatters';
 
 const FollowButton = ({ userId }) => {
     const { actor, user } = useAuth();
@@ -21,8 +22,7 @@ const FollowButton = ({ userId }) => {
         }
 
         try {
-            const following = await actor.get_user_following(user.id);
-            setIsFollowing(following.includes(userId));
+            setIsFollowing(await actor.is_following(user.id, userId));
         } catch (error) {
             console.error('Error checking follow status:', error);
         } finally {
@@ -43,7 +43,7 @@ const FollowButton = ({ userId }) => {
                 setIsFollowing(!isFollowing);
                 toast.success(isFollowing ? 'Unfollowed successfully' : 'Following successfully');
             } else {
-                toast.error(result.Err);
+                toast.error(formatError(result.Err));
             }
         } catch (error) {
             console.error('Error following/unfollowing user:', error);
@@ -31,8 +31,10 @@ const ProfilePage = () => {
                 setUser(userResult[0]);
 
                 // Load user's posts
-                const userPosts = await actor.get_user_posts(userId);
-                setPosts(userPosts);
+                const postsResult = await actor.get_user_posts(userId, [], 20);
+                if ('Ok' in postsResult) {
+                    setPosts(postsResult.Ok.items);
+                }
             }
         } catch (error) {
             console.error('Error loading profile:', error);
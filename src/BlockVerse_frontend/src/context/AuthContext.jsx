@@ -185,23 +185,37 @@ const idlFactory = ({ IDL }) => {
         'following_count': IDL.Nat64,
         'posts_count': IDL.Nat64,
         'balance': IDL.Nat64,
+        'is_private': IDL.Bool,
         'created_at': IDL.Nat64,
         'updated_at': IDL.Nat64,
     });
 
+    const Visibility = IDL.Variant({
+        'Public': IDL.Null,
+        'FollowersOnly': IDL.Null,
+        'MentionedOnly': IDL.Null,
+        'Unlisted': IDL.Null,
+    });
+
     const Post = IDL.Record({
         'id': IDL.Text,
         'author': IDL.Principal,
         'content': IDL.Text,
         'media_url': IDL.Opt(IDL.Text),
+        'media_asset_id': IDL.Opt(IDL.Text),
         'likes_count': IDL.Nat64,
         'comments_count': IDL.Nat64,
         'shares_count': IDL.Nat64,
         'is_shared': IDL.Bool,
         'original_post_id': IDL.Opt(IDL.Text),
         'share_comment': IDL.Opt(IDL.Text),
+        'mentions': IDL.Vec(IDL.Principal),
+        'hashtags': IDL.Vec(IDL.Text),
+        'visibility': Visibility,
         'created_at': IDL.Nat64,
         'updated_at': IDL.Nat64,
+        'edited_at': IDL.Opt(IDL.Nat64),
+        'deleted_at': IDL.Opt(IDL.Nat64),
     });
 
     const Comment = IDL.Record({
@@ -209,19 +223,87 @@ const idlFactory = ({ IDL }) => {
         'post_id': IDL.Text,
         'author': IDL.Principal,
         'content': IDL.Text,
+        'mentions': IDL.Vec(IDL.Principal),
+        'hashtags': IDL.Vec(IDL.Text),
         'likes_count': IDL.Nat64,
         'created_at': IDL.Nat64,
+        'parent_comment_id': IDL.Opt(IDL.Text),
+        'depth': IDL.Nat32,
+        'replies_count': IDL.Nat64,
+        'edited_at': IDL.Opt(IDL.Nat64),
+        'deleted_at': IDL.Opt(IDL.Nat64),
+    });
+
+    const EntityKind = IDL.Variant({
+        'User': IDL.Null,
+        'Username': IDL.Null,
+        'Post': IDL.Null,
+        'Comment': IDL.Null,
+        'Like': IDL.Null,
+        'Role': IDL.Null,
+        'Report': IDL.Null,
+        'Decision': IDL.Null,
+        'Appeal': IDL.Null,
+        'Conversation': IDL.Null,
+        'FollowRequest': IDL.Null,
+        'Asset': IDL.Null,
+        'Upload': IDL.Null,
+        'Withdrawal': IDL.Null,
+    });
+
+    const BlockVerseError = IDL.Variant({
+        'NotFound': IDL.Record({ 'kind': EntityKind, 'id': IDL.Text }),
+        'AlreadyExists': IDL.Record({ 'kind': EntityKind, 'id': IDL.Text }),
+        'AnonymousCaller': IDL.Null,
+        'Unauthorized': IDL.Text,
+        'Validation': IDL.Record({ 'field': IDL.Text, 'reason': IDL.Text }),
+        'InvalidOperation': IDL.Text,
+        'InsufficientBalance': IDL.Record({ 'balance': IDL.Nat64, 'required': IDL.Nat64 }),
+        'Ledger': IDL.Text,
+        'Suspended': IDL.Record({ 'until': IDL.Nat64 }),
+    });
+
+    const Asset = IDL.Record({
+        'id': IDL.Text,
+        'owner': IDL.Principal,
+        'mime_type': IDL.Text,
+        'size': IDL.Nat64,
+        'chunk_count': IDL.Nat32,
+        'sha256': IDL.Text,
+        'created_at': IDL.Nat64,
+    });
+
+    const RemoteActor = IDL.Record({
+        'user_id': IDL.Principal,
+        'uri': IDL.Text,
+        'inbox': IDL.Text,
+        'shared_inbox': IDL.Opt(IDL.Text),
+        'key_id': IDL.Text,
+        'public_key_pem': IDL.Text,
+        'fetched_at': IDL.Nat64,
+    });
+
+    const PostPage = IDL.Record({
+        'items': IDL.Vec(Post),
+        'next_cursor': IDL.Opt(IDL.Text),
     });
 
     return IDL.Service({
-        'create_user': IDL.Func([IDL.Text, IDL.Text, IDL.Text], [IDL.Variant({ 'Ok': User, 'Err': IDL.Text })], []),
+        'create_user': IDL.Func([IDL.Text, IDL.Text, IDL.Text], [IDL.Variant({ 'Ok': User, 'Err': BlockVerseError })], []),
         'get_user': IDL.Func([IDL.Principal], [IDL.Opt(User)], ['query']),
-        'create_post': IDL.Func([IDL.Text, IDL.Opt(IDL.Text)], [IDL.Variant({ 'Ok': Post, 'Err': IDL.Text })], []),
-        'get_feed': IDL.Func([IDL.Principal, IDL.Nat64, IDL.Nat64], [IDL.Vec(Post)], ['query']),
-        'like_post': IDL.Func([IDL.Text], [IDL.Variant({ 'Ok': IDL.Null, 'Err': IDL.Text })], []),
-        'create_comment': IDL.Func([IDL.Text, IDL.Text], [IDL.Variant({ 'Ok': Comment, 'Err': IDL.Text })], []),
-        'follow_user': IDL.Func([IDL.Principal], [IDL.Variant({ 'Ok': IDL.Null, 'Err': IDL.Text })], []),
-        'tip_user': IDL.Func([IDL.Principal, IDL.Nat64], [IDL.Variant({ 'Ok': IDL.Null, 'Err': IDL.Text })], []),
+        'get_remote_actor': IDL.Func([IDL.Principal], [IDL.Opt(RemoteActor)], ['query']),
+        'create_post': IDL.Func([IDL.Text, IDL.Opt(IDL.Text), IDL.Opt(Visibility)], [IDL.Variant({ 'Ok': Post, 'Err': BlockVerseError })], []),
+        'get_feed': IDL.Func([IDL.Principal, IDL.Opt(IDL.Text), IDL.Nat64], [IDL.Variant({ 'Ok': PostPage, 'Err': BlockVerseError })], ['query']),
+        'like_post': IDL.Func([IDL.Text], [IDL.Variant({ 'Ok': IDL.Null, 'Err': BlockVerseError })], []),
+        'create_comment': IDL.Func([IDL.Text, IDL.Text, IDL.Opt(IDL.Text)], [IDL.Variant({ 'Ok': Comment, 'Err': BlockVerseError })], []),
+        'follow_user': IDL.Func([IDL.Principal], [IDL.Variant({ 'Ok': IDL.Null, 'Err': BlockVerseError })], []),
+        'begin_upload': IDL.Func([IDL.Text, IDL.Nat64], [IDL.Variant({ 'Ok': IDL.Text, 'Err': BlockVerseError })], []),
+        'put_chunk': IDL.Func([IDL.Text, IDL.Nat32, IDL.Vec(IDL.Nat8)], [IDL.Variant({ 'Ok': IDL.Null, 'Err': BlockVerseError })], []),
+        'commit_upload': IDL.Func([IDL.Text], [IDL.Variant({ 'Ok': Asset, 'Err': BlockVerseError })], []),
+        'abort_upload': IDL.Func([IDL.Text], [IDL.Variant({ 'Ok': IDL.Null, 'Err': BlockVerseError })], []),
+        'get_asset': IDL.Func([IDL.Text], [IDL.Opt(Asset)], ['query']),
+        'get_asset_chunk': IDL.Func([IDL.Text, IDL.Nat32], [IDL.Variant({ 'Ok': IDL.Vec(IDL.Nat8), 'Err': BlockVerseError })], ['query']),
+        'tip_user': IDL.Func([IDL.Principal, IDL.Nat64], [IDL.Variant({ 'Ok': IDL.Null, 'Err': BlockVerseError })], []),
     });
 };
 
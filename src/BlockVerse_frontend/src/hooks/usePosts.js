@@ -1,6 +1,7 @@
 import { useState, useEffect, useCallback } from 'react';
 import { useAuth } from './useAuth';
 import toast from 'react-hot-toast';
+import { formatError } from '../utils/formatters';
 
 export const usePosts = (userId = null) => {
   const { actor, principal } = useAuth();
@@ -17,9 +18,11 @@ export const usePosts = (userId = null) => {
       
       let postsData;
       if (userId) {
-        postsData = await actor.get_user_posts(userId);
+        const result = await actor.get_user_posts(userId, [], 20);
+        postsData = 'Ok' in result ? result.Ok.items : [];
       } else {
-        postsData = await actor.get_feed(principal, 20, 0);
+        const result = await actor.get_feed(principal, [], 20);
+        postsData = 'Ok' in result ? result.Ok.items : [];
       }
       
       setPosts(postsData);
@@ -36,16 +39,16 @@ export const usePosts = (userId = null) => {
     loadPosts();
   }, [loadPosts]);
 
-  const createPost = async (content, mediaUrl = null) => {
+  const createPost = async (content, mediaAssetId = null) => {
     if (!actor) throw new Error('Not authenticated');
 
     try {
-      const result = await actor.create_post(content, mediaUrl ? [mediaUrl] : []);
+      const result = await actor.create_post(content, mediaAssetId ? [mediaAssetId] : [], []);
       if ('Ok' in result) {
         setPosts(prev => [result.Ok, ...prev]);
         return result.Ok;
       } else {
-        throw new Error(result.Err);
+        throw new Error(formatError(result.Err));
       }
     } catch (error) {
       toast.error('Failed to create post');
@@ -81,7 +84,7 @@ export const usePosts = (userId = null) => {
           return p;
         }));
       } else {
-        throw new Error(result.Err);
+        throw new Error(formatError(result.Err));
       }
     } catch (error) {
       toast.error('Failed to like post');
@@ -98,7 +101,7 @@ export const usePosts = (userId = null) => {
         setPosts(prev => [result.Ok, ...prev]);
         return result.Ok;
       } else {
-        throw new Error(result.Err);
+        throw new Error(formatError(result.Err));
       }
     } catch (error) {
       toast.error('Failed to share post');
@@ -1,5 +1,6 @@
 import { Actor, HttpAgent } from '@dfinity/agent';
 import { idlFactory } from '../../../declarations/BlockVerse_backend';
+import { APP_CONFIG } from '../utils/constants';
 
 const canisterId = process.env.REACT_APP_BLOCKVERSE_BACKEND_CANISTER_ID;
 
@@ -33,6 +34,10 @@ export const api = {
     return await actor.get_user(userId);
   },
 
+  async getUserByUsername(actor, username) {
+    return await actor.get_user_by_username(username);
+  },
+
   async updateUser(actor, bio, avatarUrl) {
     return await actor.update_user(bio, avatarUrl);
   },
@@ -45,21 +50,83 @@ export const api = {
     return await actor.unfollow_user(userId);
   },
 
+  async setAccountPrivate(actor, isPrivate) {
+    return await actor.set_account_private(isPrivate);
+  },
+
+  async getFollowRequests(actor, cursor = [], limit = 20) {
+    return await actor.get_follow_requests(cursor, limit);
+  },
+
+  async getSentFollowRequests(actor, cursor = [], limit = 20) {
+    return await actor.get_sent_follow_requests(cursor, limit);
+  },
+
+  async approveFollowRequest(actor, requester) {
+    return await actor.approve_follow_request(requester);
+  },
+
+  async rejectFollowRequest(actor, requester) {
+    return await actor.reject_follow_request(requester);
+  },
+
+  async cancelFollowRequest(actor, userId) {
+    return await actor.cancel_follow_request(userId);
+  },
+
+  async blockUser(actor, userId) {
+    return await actor.block_user(userId);
+  },
+
+  async unblockUser(actor, userId) {
+    return await actor.unblock_user(userId);
+  },
+
+  async getBlockedUsers(actor, cursor = [], limit = 20) {
+    return await actor.get_blocked_users(cursor, limit);
+  },
+
+  async muteUser(actor, userId) {
+    return await actor.mute_user(userId);
+  },
+
+  async unmuteUser(actor, userId) {
+    return await actor.unmute_user(userId);
+  },
+
+  async getMutedUsers(actor, cursor = [], limit = 20) {
+    return await actor.get_muted_users(cursor, limit);
+  },
+
   // Post methods
-  async createPost(actor, content, mediaUrl) {
-    return await actor.create_post(content, mediaUrl);
+  // `visibility` is `[]` for public or e.g. `[{ FollowersOnly: null }]`;
+  // `mediaAssetId` is `[]` or `[assetId]` from `uploadMedia`
+  async createPost(actor, content, mediaAssetId = [], visibility = []) {
+    return await actor.create_post(content, mediaAssetId, visibility);
   },
 
   async getPost(actor, postId) {
     return await actor.get_post(postId);
   },
 
-  async getUserPosts(actor, userId) {
-    return await actor.get_user_posts(userId);
+  async getUserPosts(actor, userId, cursor = [], limit = 20) {
+    return await actor.get_user_posts(userId, cursor, limit);
+  },
+
+  async getFeed(actor, userId, cursor = [], limit = 20) {
+    return await actor.get_feed(userId, cursor, limit);
+  },
+
+  async updatePost(actor, postId, content, mediaAssetId = []) {
+    return await actor.update_post(postId, content, mediaAssetId);
   },
 
-  async getFeed(actor, userId, limit, offset) {
-    return await actor.get_feed(userId, limit, offset);
+  async getPostRevisions(actor, postId, cursor = [], limit = 20) {
+    return await actor.get_post_revisions(postId, cursor, limit);
+  },
+
+  async deletePost(actor, postId) {
+    return await actor.delete_post(postId);
   },
 
   async likePost(actor, postId) {
@@ -71,12 +138,28 @@ export const api = {
   },
 
   // Comment methods
-  async createComment(actor, postId, content) {
-    return await actor.create_comment(postId, content);
+  async createComment(actor, postId, content, parentCommentId = []) {
+    return await actor.create_comment(postId, content, parentCommentId);
+  },
+
+  async getPostComments(actor, postId, cursor = [], limit = 20) {
+    return await actor.get_post_comments(postId, cursor, limit);
+  },
+
+  async updateComment(actor, commentId, content) {
+    return await actor.update_comment(commentId, content);
   },
 
-  async getPostComments(actor, postId) {
-    return await actor.get_post_comments(postId);
+  async deleteComment(actor, commentId) {
+    return await actor.delete_comment(commentId);
+  },
+
+  async likeComment(actor, commentId) {
+    return await actor.like_comment(commentId);
+  },
+
+  async unlikeComment(actor, commentId) {
+    return await actor.unlike_comment(commentId);
   },
 
   // Payment methods
@@ -88,12 +171,158 @@ export const api = {
     return await actor.get_user_balance(userId);
   },
 
+  // Notification methods
+  async getNotifications(actor, cursor = [], limit = 20) {
+    return await actor.get_notifications(cursor, limit);
+  },
+
+  async getUnreadNotificationCount(actor) {
+    return await actor.get_unread_notification_count();
+  },
+
+  async markNotificationsRead(actor, ids) {
+    return await actor.mark_notifications_read(ids);
+  },
+
+  async markAllNotificationsRead(actor) {
+    return await actor.mark_all_notifications_read();
+  },
+
+  // `kind` is a variant such as `{ Like: null }`
+  async setNotificationMuted(actor, kind, muted) {
+    return await actor.set_notification_muted(kind, muted);
+  },
+
+  async getMutedNotifications(actor) {
+    return await actor.get_muted_notifications();
+  },
+
+  // Direct message methods
+  async startConversation(actor, members) {
+    return await actor.start_conversation(members);
+  },
+
+  async sendMessage(actor, conversationId, content) {
+    return await actor.send_message(conversationId, content);
+  },
+
+  async getMessages(actor, conversationId, cursor = [], limit = 20) {
+    return await actor.get_messages(conversationId, cursor, limit);
+  },
+
+  async getConversations(actor, cursor = [], limit = 20) {
+    return await actor.get_conversations(cursor, limit);
+  },
+
+  async markConversationRead(actor, conversationId) {
+    return await actor.mark_conversation_read(conversationId);
+  },
+
+  async getReadReceipts(actor, conversationId) {
+    return await actor.get_read_receipts(conversationId);
+  },
+
+  async setConversationMuted(actor, conversationId, muted) {
+    return await actor.set_conversation_muted(conversationId, muted);
+  },
+
+  async leaveConversation(actor, conversationId) {
+    return await actor.leave_conversation(conversationId);
+  },
+
+  // `policy` is `{ Everyone: null }` or `{ Following: null }`
+  async setDmPolicy(actor, policy) {
+    return await actor.set_dm_policy(policy);
+  },
+
+  async getDmPolicy(actor) {
+    return await actor.get_dm_policy();
+  },
+
+  // Media methods
+  // Uploads a File in chunks and returns the committed asset. A failed
+  // upload is aborted so it doesn't count against the quota.
+  async uploadMedia(actor, file) {
+    const begun = await actor.begin_upload(file.type, BigInt(file.size));
+    if ('Err' in begun) return begun;
+
+    const uploadId = begun.Ok;
+    const chunkCount = Math.ceil(file.size / APP_CONFIG.MEDIA_CHUNK_SIZE);
+    for (let index = 0; index < chunkCount; index++) {
+      const start = index * APP_CONFIG.MEDIA_CHUNK_SIZE;
+      const chunk = file.slice(start, start + APP_CONFIG.MEDIA_CHUNK_SIZE);
+      const result = await actor.put_chunk(uploadId, index, new Uint8Array(await chunk.arrayBuffer()));
+      if ('Err' in result) {
+        await actor.abort_upload(uploadId);
+        return result;
+      }
+    }
+    return await actor.commit_upload(uploadId);
+  },
+
+  async abortUpload(actor, uploadId) {
+    return await actor.abort_upload(uploadId);
+  },
+
+  async getAsset(actor, assetId) {
+    return await actor.get_asset(assetId);
+  },
+
+  // Fetches an asset's content and returns `{ url, mimeType }` with an
+  // object URL for it, or null if the asset is gone. Revoke the URL once it
+  // is no longer shown.
+  async loadAssetUrl(actor, assetId) {
+    const asset = await actor.get_asset(assetId);
+    if (asset.length === 0) return null;
+
+    const chunks = [];
+    for (let index = 0; index < asset[0].chunk_count; index++) {
+      const result = await actor.get_asset_chunk(assetId, index);
+      if ('Err' in result) return null;
+      chunks.push(new Uint8Array(result.Ok));
+    }
+    const mimeType = asset[0].mime_type;
+    return { url: URL.createObjectURL(new Blob(chunks, { type: mimeType })), mimeType };
+  },
+
+  async deleteAsset(actor, assetId) {
+    return await actor.delete_asset(assetId);
+  },
+
+  async getMyAssets(actor, cursor = [], limit = 20) {
+    return await actor.get_my_assets(cursor, limit);
+  },
+
+  async getMediaUsage(actor) {
+    return await actor.get_media_usage();
+  },
+
+  // Federation methods
+  // Users on other ActivityPub servers appear as users named
+  // `name@server`; this returns the actor behind one, or an empty array
+  // for local users.
+  async getRemoteActor(actor, userId) {
+    return await actor.get_remote_actor(userId);
+  },
+
   // Search methods
-  async searchUsers(actor, query) {
-    return await actor.search_users(query);
+  async searchUsers(actor, query, cursor = [], limit = 20) {
+    return await actor.search_users(query, cursor, limit);
+  },
+
+  async searchPosts(actor, query, cursor = [], limit = 20) {
+    return await actor.search_posts(query, cursor, limit);
+  },
+
+  async getPostsByHashtag(actor, tag, cursor = [], limit = 20) {
+    return await actor.get_posts_by_hashtag(tag, cursor, limit);
+  },
+
+  async getMentions(actor, userId, cursor = [], limit = 20) {
+    return await actor.get_mentions(userId, cursor, limit);
   },
 
-  async searchPosts(actor, query) {
-    return await actor.search_posts(query);
+  async getTrendingHashtags(actor, windowNs) {
+    return await actor.get_trending_hashtags(windowNs);
   }
 };
\ No newline at end of file
@@ -19,5 +19,32 @@ export const paymentService = {
   async getBalance(actor, userId) {
     const balanceE8s = await actor.get_user_balance(userId);
     return this.convertE8sToICP(balanceE8s);
+  },
+
+  // Send ledger tokens here, then call claimDeposit to credit them.
+  async getDepositAccount(actor) {
+    return await actor.get_deposit_account();
+  },
+
+  async claimDeposit(actor) {
+    return await actor.claim_deposit();
+  },
+
+  async tipPost(actor, postId, amountICP) {
+    const amountE8s = this.convertICPToE8s(amountICP);
+    return await actor.tip_post(postId, amountE8s);
+  },
+
+  async getTransactions(actor, userId, cursor = [], limit = 20) {
+    return await actor.get_transactions(userId, cursor, limit);
+  },
+
+  async getTransactionTotals(actor, userId) {
+    return await actor.get_transaction_totals(userId);
+  },
+
+  async withdraw(actor, owner, amountICP) {
+    const amountE8s = this.convertICPToE8s(amountICP);
+    return await actor.withdraw({ owner, subaccount: [] }, amountE8s);
   }
 };
\ No newline at end of file
@@ -6,6 +6,7 @@ export const APP_CONFIG = {
   MAX_BIO_LENGTH: 160,
   POSTS_PER_PAGE: 10,
   MAX_MEDIA_SIZE: 10 * 1024 * 1024, // 10MB
+  MEDIA_CHUNK_SIZE: 256 * 1024, // must match the canister's chunk size
 };
 
 export const ROUTES = {
@@ -44,4 +44,32 @@ export const formatFileSize = (bytes) => {
 
 export const formatICP = (amount, decimals = 8) => {
   return Number(amount).toFixed(decimals).replace(/\.?0+$/, '');
-};
\ No newline at end of file
+};
+
+// Turns a backend `BlockVerseError` variant into a user-facing message.
+export const formatError = (err) => {
+  if (!err || typeof err !== 'object') return String(err ?? 'Unknown error');
+
+  const [kind, detail] = Object.entries(err)[0] ?? [];
+  switch (kind) {
+    case 'NotFound':
+      return `${Object.keys(detail.kind)[0]} not found`;
+    case 'AlreadyExists':
+      return `${Object.keys(detail.kind)[0]} already exists`;
+    case 'AnonymousCaller':
+      return 'Please sign in first';
+    case 'Unauthorized':
+    case 'InvalidOperation':
+      return detail;
+    case 'Ledger':
+      return `Ledger error: ${detail}`;
+    case 'Validation':
+      return `${detail.field}: ${detail.reason}`;
+    case 'InsufficientBalance':
+      return 'Insufficient balance';
+    case 'Suspended':
+      return `Your account is suspended until ${formatDateTime(detail.until)}`;
+    default:
+      return 'Something went wrong';
+  }
+};
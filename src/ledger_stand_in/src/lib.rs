@@ -0,0 +1,272 @@
+use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk_macros::{query, update};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+/// Fee charged on every transfer and approval, matching the ICP ledger's.
+const FEE: u64 = 10_000;
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+/// An account with the default subaccount spelled out, so both spellings
+/// of it share one balance.
+type AccountKey = (Principal, Vec<u8>);
+
+impl Account {
+    fn key(&self) -> AccountKey {
+        (self.owner, self.subaccount.clone().unwrap_or_else(|| vec![0; 32]))
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct ApproveArgs {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub spender: Account,
+    pub amount: Nat,
+    pub expected_allowance: Option<Nat>,
+    pub expires_at: Option<u64>,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub enum ApproveError {
+    BadFee { expected_fee: Nat },
+    InsufficientFunds { balance: Nat },
+    AllowanceChanged { current_allowance: Nat },
+    Expired { ledger_time: u64 },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferFromArgs {
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub from: Account,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(Debug, CandidType, Deserialize)]
+pub enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct AllowanceArgs {
+    pub account: Account,
+    pub spender: Account,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Allowance {
+    pub allowance: Nat,
+    pub expires_at: Option<u64>,
+}
+
+/// Everything lives on the heap: the stand-in is redeployed with
+/// `--mode reinstall` for every test run, never upgraded.
+#[derive(Default)]
+struct Ledger {
+    balances: HashMap<AccountKey, Nat>,
+    allowances: HashMap<(AccountKey, AccountKey), Nat>,
+    blocks: u64,
+    failing_transfers: u32,
+    /// Block of every `icrc1_transfer` that set a memo and a creation
+    /// time, so resubmitting it is answered with `Duplicate`
+    transfers: HashMap<(AccountKey, Vec<u8>, u64), Nat>,
+}
+
+impl Ledger {
+    fn balance(&self, account: &AccountKey) -> Nat {
+        self.balances.get(account).cloned().unwrap_or_default()
+    }
+
+    fn allowance(&self, account: &AccountKey, spender: &AccountKey) -> Nat {
+        self.allowances.get(&(account.clone(), spender.clone())).cloned().unwrap_or_default()
+    }
+
+    /// Debits `amount` plus the fee from `from` and credits `amount` to
+    /// `to`, or returns `from`'s balance if it doesn't cover both.
+    fn transfer(&mut self, from: &AccountKey, to: AccountKey, amount: Nat) -> Result<Nat, Nat> {
+        let balance = self.balance(from);
+        let debit = amount.clone() + Nat::from(FEE);
+        if balance < debit {
+            return Err(balance);
+        }
+        self.balances.insert(from.clone(), balance - debit);
+        let credited = self.balance(&to) + amount;
+        self.balances.insert(to, credited);
+        Ok(self.next_block())
+    }
+
+    fn next_block(&mut self) -> Nat {
+        self.blocks += 1;
+        Nat::from(self.blocks)
+    }
+}
+
+thread_local! {
+    static LEDGER: RefCell<Ledger> = RefCell::default();
+}
+
+/// Whether `fee`, if the caller set one, is the ledger's.
+fn fee_matches(fee: &Option<Nat>) -> bool {
+    fee.as_ref().is_none_or(|fee| *fee == FEE)
+}
+
+fn caller_account(subaccount: Option<Vec<u8>>) -> AccountKey {
+    Account { owner: ic_cdk::caller(), subaccount }.key()
+}
+
+#[query]
+fn icrc1_fee() -> Nat {
+    Nat::from(FEE)
+}
+
+#[query]
+fn icrc1_balance_of(account: Account) -> Nat {
+    LEDGER.with(|ledger| ledger.borrow().balance(&account.key()))
+}
+
+#[update]
+fn icrc1_transfer(arg: TransferArg) -> Result<Nat, TransferError> {
+    if !fee_matches(&arg.fee) {
+        return Err(TransferError::BadFee { expected_fee: Nat::from(FEE) });
+    }
+
+    LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        if ledger.failing_transfers > 0 {
+            ledger.failing_transfers -= 1;
+            return Err(TransferError::TemporarilyUnavailable);
+        }
+
+        let from = caller_account(arg.from_subaccount);
+        let dedup = arg
+            .memo
+            .zip(arg.created_at_time)
+            .map(|(memo, created_at_time)| (from.clone(), memo, created_at_time));
+        if let Some(duplicate_of) = dedup.as_ref().and_then(|dedup| ledger.transfers.get(dedup)) {
+            return Err(TransferError::Duplicate { duplicate_of: duplicate_of.clone() });
+        }
+
+        let block_index = ledger
+            .transfer(&from, arg.to.key(), arg.amount)
+            .map_err(|balance| TransferError::InsufficientFunds { balance })?;
+        if let Some(dedup) = dedup {
+            ledger.transfers.insert(dedup, block_index.clone());
+        }
+        Ok(block_index)
+    })
+}
+
+/// Sets the allowance outright; `expected_allowance` and `expires_at`
+/// are accepted but not enforced.
+#[update]
+fn icrc2_approve(args: ApproveArgs) -> Result<Nat, ApproveError> {
+    if !fee_matches(&args.fee) {
+        return Err(ApproveError::BadFee { expected_fee: Nat::from(FEE) });
+    }
+
+    LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        let account = caller_account(args.from_subaccount);
+        let balance = ledger.balance(&account);
+        if balance < FEE {
+            return Err(ApproveError::InsufficientFunds { balance });
+        }
+        ledger.balances.insert(account.clone(), balance - Nat::from(FEE));
+        ledger.allowances.insert((account, args.spender.key()), args.amount);
+        Ok(ledger.next_block())
+    })
+}
+
+#[query]
+fn icrc2_allowance(args: AllowanceArgs) -> Allowance {
+    let allowance = LEDGER.with(|ledger| ledger.borrow().allowance(&args.account.key(), &args.spender.key()));
+    Allowance { allowance, expires_at: None }
+}
+
+/// Spends from an allowance; the fee counts against it like the amount.
+#[update]
+fn icrc2_transfer_from(args: TransferFromArgs) -> Result<Nat, TransferFromError> {
+    if !fee_matches(&args.fee) {
+        return Err(TransferFromError::BadFee { expected_fee: Nat::from(FEE) });
+    }
+
+    LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        let (from, spender) = (args.from.key(), caller_account(args.spender_subaccount));
+        let allowance = ledger.allowance(&from, &spender);
+        let spent = args.amount.clone() + Nat::from(FEE);
+        if allowance < spent {
+            return Err(TransferFromError::InsufficientAllowance { allowance });
+        }
+
+        let block_index = ledger
+            .transfer(&from, args.to.key(), args.amount)
+            .map_err(|balance| TransferFromError::InsufficientFunds { balance })?;
+        ledger.allowances.insert((from, spender), allowance - spent);
+        Ok(block_index)
+    })
+}
+
+/// Creates `amount` tokens in `to`. Open to anyone.
+#[update]
+fn mint(to: Account, amount: Nat) -> Nat {
+    LEDGER.with(|ledger| {
+        let mut ledger = ledger.borrow_mut();
+        let credited = ledger.balance(&to.key()) + amount;
+        ledger.balances.insert(to.key(), credited);
+        ledger.next_block()
+    })
+}
+
+/// Makes the next `count` calls to `icrc1_transfer` fail with
+/// `TemporarilyUnavailable`.
+#[update]
+fn fail_next_transfers(count: u32) {
+    LEDGER.with(|ledger| ledger.borrow_mut().failing_transfers = count);
+}